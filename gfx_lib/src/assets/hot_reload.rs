@@ -0,0 +1,216 @@
+
+// Hot reload
+// Watched files are polled for modification time, changed ones are reloaded on rayon pool
+// and handed back to game, which swaps them into materials, objects and atlases
+
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    path::{ Path, PathBuf },
+    sync::Arc,
+    time::SystemTime,
+};
+use vulkano::{
+    device::Queue,
+    format::Format,
+    image::ImageViewAccess,
+};
+
+use crate::{
+    graphics::image::ImageContent,
+    loader::{ ObjectInfo, obj },
+    main_processor::settings::GameSettings,
+    sync::Loader,
+};
+
+/// Polls modification time of files
+pub struct FileWatcher {
+    files: HashMap<PathBuf, Option<SystemTime>>, // None if file is missing
+    interval: f32, // Seconds between polls
+    elapsed: f32,
+}
+impl FileWatcher {
+    pub fn new(interval: f32) -> Self { Self {
+        files: HashMap::new(),
+        interval,
+        elapsed: 0.0,
+    } }
+
+    pub fn watch(&mut self, path: &Path) {
+        self.files.insert(path.to_path_buf(), modified(path));
+    }
+    pub fn unwatch(&mut self, path: &Path) { self.files.remove(path); }
+
+    /// Files changed since last poll, files are checked once per interval
+    pub fn poll(&mut self, delta: f32) -> Vec<PathBuf> {
+        self.elapsed += delta;
+        if self.elapsed < self.interval { return vec![]; }
+        self.elapsed = 0.0;
+        self.poll_now()
+    }
+
+    /// Check files right away, removed files are reported once they appear again
+    pub fn poll_now(&mut self) -> Vec<PathBuf> {
+        let mut changed = vec![];
+        for (path, time) in self.files.iter_mut() {
+            let now = modified(path);
+            if now != *time {
+                *time = now;
+                if now.is_some() { changed.push(path.clone()); }
+            }
+        }
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// How watched file is reloaded
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReloadKind {
+    Texture(Format), // Image file uploaded into new image, KTX2 and DDS keep their format
+    Object(String), // Object with name from OBJ file
+    Settings(Vec<String>), // Layered with environment and these arguments, as on startup
+}
+
+/// Reloaded resource, game swaps it into whatever used old one
+pub enum Reloaded {
+    Texture(Arc<dyn ImageViewAccess + Send + Sync>), // Already uploaded
+    Object(String, ObjectInfo), // Turn into instance with `Renderer3D::generate_object`, then `ObjectInstance::swap_geometry`
+    Settings(GameSettings), // See `GameSettings::runtime_requests`
+}
+
+/// Reload in flight
+struct PendingReload {
+    source: PathBuf,
+    kind: ReloadKind,
+    loader: Loader<Reloaded>,
+    dirty: bool, // Changed again while loading, restarted once loader finishes
+}
+
+/// Watches source files and reloads them in background
+/// Reload is started only after previous reload of same file finished
+pub struct HotReloader {
+    queue: Arc<Queue>,
+    watcher: FileWatcher,
+    triggers: HashMap<PathBuf, Vec<(PathBuf, ReloadKind)>>, // Watched file -> reloads it starts
+    loading: Vec<PendingReload>,
+}
+impl HotReloader {
+    /// Files are checked every `interval` seconds
+    pub fn new(queue: Arc<Queue>, interval: f32) -> Self { Self {
+        queue,
+        watcher: FileWatcher::new(interval),
+        triggers: HashMap::new(),
+        loading: vec![],
+    } }
+
+    fn add(&mut self, trigger: &Path, source: &Path, kind: ReloadKind) {
+        self.watcher.watch(trigger);
+        self.triggers.entry(trigger.to_path_buf()).or_insert_with(Vec::new).push((source.to_path_buf(), kind));
+    }
+
+    pub fn watch_texture(&mut self, path: &Path, format: Format) {
+        self.add(path, path, ReloadKind::Texture(format));
+    }
+
+    /// Object is also reloaded when `.mtl` file next to OBJ changes
+    pub fn watch_object(&mut self, path: &Path, name: &str) {
+        self.add(path, path, ReloadKind::Object(name.into()));
+        self.add(&path.with_extension("mtl"), path, ReloadKind::Object(name.into()));
+    }
+
+    /// Reloaded settings go through `GameSettings::load_layered` with `args`, so overrides are kept
+    pub fn watch_settings(&mut self, path: &Path, args: &[String]) {
+        self.add(path, path, ReloadKind::Settings(args.to_vec()));
+    }
+
+    /// Poll watched files, returns reloads finished since last call with source path
+    /// Failed reloads (like half written file) are printed and skipped, next save retries
+    /// Change during reload (like OBJ then MTL export) drops its result and reloads again
+    pub fn update(&mut self, delta: f32) -> Vec<(PathBuf, Reloaded)> {
+        for changed in self.watcher.poll(delta) {
+            let reloads = self.triggers.get(&changed).cloned().unwrap_or_default();
+            for (source, kind) in reloads {
+                match self.loading.iter_mut().find(|r| r.source == source && r.kind == kind) {
+                    Some(pending) => pending.dirty = true,
+                    None => {
+                        let loader = self.start(&source, &kind);
+                        self.loading.push(PendingReload { source, kind, loader, dirty: false });
+                    },
+                }
+            }
+        }
+
+        let mut done = vec![];
+        let mut i = 0;
+        while i < self.loading.len() {
+            if !self.loading[i].loader.is_finished() { i += 1; continue; }
+            if self.loading[i].dirty {
+                let loader = self.start(&self.loading[i].source, &self.loading[i].kind);
+                self.loading[i].loader = loader;
+                self.loading[i].dirty = false;
+                i += 1;
+                continue;
+            }
+            let mut pending = self.loading.remove(i);
+            match pending.loader.try_take() {
+                Ok(reloaded) => done.push((pending.source, reloaded)),
+                Err(e) => println!("Unable to reload {:?}: {}", pending.source, e),
+            }
+        }
+        done
+    }
+
+    fn start(&self, path: &Path, kind: &ReloadKind) -> Loader<Reloaded> {
+        let path = path.to_path_buf();
+        match kind.clone() {
+            ReloadKind::Texture(format) => {
+                let queue = self.queue.clone();
+                Loader::with_try_closure(move || std::fs::read(&path))
+                    .and_then(move |bytes| {
+                        match ImageContent::load_image(queue, Cursor::new(bytes), format) {
+                            Ok(image) => image.map(Reloaded::Texture),
+                            Err(e) => Loader::with_error(e),
+                        }
+                    })
+            },
+            ReloadKind::Object(name) => Loader::with_try_closure(move || {
+                obj::load_objects(&path, vec![name.clone()])?
+                    .remove(&name)
+                    .map(|info| Reloaded::Object(name.clone(), info))
+                    .ok_or(obj::Error::NoObjectForName(name))
+            }),
+            ReloadKind::Settings(args) => Loader::with_try_closure(move || {
+                GameSettings::load_layered(&path, &args).map(Reloaded::Settings)
+            }),
+        }
+    }
+}
+
+mod test {
+
+    #[test] fn test_file_watcher() {
+        use super::FileWatcher;
+        use std::time::{ Duration, SystemTime };
+
+        let path = std::env::temp_dir().join("gfx_lib_watcher_test.txt");
+        std::fs::write(&path, "a").unwrap();
+
+        let mut watcher = FileWatcher::new(1.0);
+        watcher.watch(&path);
+        assert!(watcher.poll_now().is_empty());
+
+        // Modification time is moved explicitly, file systems may have coarse timestamps
+        let modified = filetime::FileTime::from_system_time(SystemTime::now() + Duration::from_secs(10));
+        filetime::set_file_mtime(&path, modified).unwrap();
+        assert!(watcher.poll(0.5).is_empty()); // Not polled before interval
+        assert_eq!(watcher.poll(0.5), vec![path.clone()]);
+        assert!(watcher.poll_now().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(watcher.poll_now().is_empty());
+    }
+}
@@ -0,0 +1,344 @@
+
+// Asset manager
+// Hands out reference counted handles to images, meshes, atlases and OBJ objects
+// Loads are deduplicated by path and parameters, asset is unloaded then last handle is dropped
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    ops::Deref,
+    path::Path,
+    sync::{ Arc, Weak, Mutex },
+};
+use vulkano::{
+    buffer::BufferAccess,
+    device::Queue,
+    format::Format,
+    image::{ ImageAccess, ImageViewAccess },
+    sampler::SamplerCreationError,
+};
+
+use crate::{
+    graphics::{
+        image::{
+            ImageContent,
+            compressed,
+            mipmap,
+            atlas::{ TextureAtlas, AtlasError },
+            sampler_pool::{ SamplerPool, SamplerParams },
+        },
+        renderer_3d::mesh::MeshAccess,
+    },
+    loader::{ ObjectInfo, VertexInfo, obj },
+    sync::Loader,
+    vfs::{ self, Vfs, VfsError },
+};
+
+pub mod hot_reload;
+
+pub type MeshHandle = Handle<Loader<Arc<dyn MeshAccess + Send + Sync>>>;
+pub type AtlasHandle = Handle<Loader<TextureAtlas>>;
+pub type ObjectHandle = Handle<Loader<ObjectInfo>>;
+
+pub enum AssetError {
+    Read(String, VfsError),
+    Atlas(AtlasError),
+    Sampler(SamplerCreationError),
+}
+impl std::error::Error for AssetError {}
+impl std::fmt::Debug for AssetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            AssetError::Read(path, e) => write!(f, "Unable to read {}: {}", path, e),
+            AssetError::Atlas(e) => write!(f, "Unable to build atlas: {:?}", e),
+            AssetError::Sampler(e) => write!(f, "Unable to create sampler: {}", e),
+        }
+    }
+}
+impl std::fmt::Display for AssetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        (self as &dyn std::fmt::Debug).fmt(f)
+    }
+}
+impl From<AtlasError> for AssetError {
+    fn from(e: AtlasError) -> Self { AssetError::Atlas(e) }
+}
+
+struct Asset<T> {
+    name: String, // Path or key asset was loaded with
+    value: T,
+}
+
+/// Shared reference to loaded asset, asset is unloaded then last handle is dropped
+pub struct Handle<T>(Arc<Asset<T>>);
+impl <T> Handle<T> {
+    pub fn name(&self) -> &str { &self.0.name }
+    /// Number of handles to this asset
+    pub fn handle_count(&self) -> usize { Arc::strong_count(&self.0) }
+    pub fn ptr_eq(a: &Handle<T>, b: &Handle<T>) -> bool { Arc::ptr_eq(&a.0, &b.0) }
+}
+impl <T> Clone for Handle<T> {
+    fn clone(&self) -> Self { Handle(self.0.clone()) }
+}
+impl <T> Deref for Handle<T> {
+    type Target = T;
+    fn deref(&self) -> &T { &self.0.value }
+}
+
+/// Memory taken by asset, None while it is still loading or if it failed
+pub trait AssetSize {
+    fn asset_bytes(&self) -> Option<u64>;
+    /// Asset will never load, so it is not counted as loading
+    fn is_failed(&self) -> bool { false }
+}
+impl AssetSize for ImageContent {
+    fn asset_bytes(&self) -> Option<u64> {
+        if !self.is_ready() { return None; }
+        Some(image_bytes(&self.get_image()))
+    }
+    fn is_failed(&self) -> bool { ImageContent::is_failed(self) }
+}
+impl AssetSize for TextureAtlas {
+    fn asset_bytes(&self) -> Option<u64> { Some(image_bytes(&self.get_image())) }
+}
+impl AssetSize for Arc<dyn MeshAccess + Send + Sync> {
+    fn asset_bytes(&self) -> Option<u64> {
+        let ibo = if self.has_ibo() { self.get_ibo().size() } else { 0 };
+        Some((self.get_vbo().size() + ibo) as u64)
+    }
+}
+impl AssetSize for ObjectInfo {
+    fn asset_bytes(&self) -> Option<u64> {
+        Some((self.vertices.len() * std::mem::size_of::<VertexInfo>() + self.indices.len() * 4) as u64)
+    }
+}
+impl <T: AssetSize> AssetSize for Loader<T> {
+    fn asset_bytes(&self) -> Option<u64> {
+        if !self.is_ready() { return None; }
+        self.try_get().ok().and_then(|v| v.asset_bytes())
+    }
+    fn is_failed(&self) -> bool {
+        self.is_finished() && self.try_get().map_or(true, |v| v.is_failed())
+    }
+}
+
+/// Every mip level of every layer
+fn image_bytes(image: &Arc<dyn ImageViewAccess + Send + Sync>) -> u64 {
+    let dims = image.dimensions();
+    let format = image.parent().format();
+    let layers: u64 = (0 .. image.parent().mipmap_levels()).map(|level| {
+        let [w, h] = mipmap::mip_dims([dims.width(), dims.height()], level);
+        // Block compressed formats are sized per 4x4 block
+        compressed::level_bytes(format, [w, h])
+            .unwrap_or(w as usize * h as usize * format.size().unwrap_or(4)) as u64
+    }).sum();
+    layers * dims.array_layers() as u64
+}
+
+/// Memory used by assets that are still referenced by handles
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct MemoryUsage {
+    pub images: u64,
+    pub meshes: u64,
+    pub atlases: u64,
+    pub objects: u64, // CPU side OBJ data
+    pub assets: usize, // Live assets
+    pub loading: usize, // Live assets that are not loaded yet
+    pub failed: usize, // Live assets that failed to load
+}
+impl MemoryUsage {
+    pub fn total(&self) -> u64 { self.images + self.meshes + self.atlases + self.objects }
+}
+
+/// Assets of one kind, keeps weak references so it does not keep assets alive
+struct Cache<K, T> {
+    entries: HashMap<K, Weak<Asset<T>>>,
+}
+impl <K: Hash + Eq, T> Cache<K, T> {
+    fn new() -> Self { Self { entries: HashMap::new() } }
+
+    fn get(&self, key: &K) -> Option<Handle<T>> {
+        self.entries.get(key).and_then(|w| w.upgrade()).map(Handle)
+    }
+
+    /// Store new asset, returns asset loaded by other thread in the meantime if there is one
+    fn insert(&mut self, key: K, name: String, value: T) -> Handle<T> {
+        if let Some(h) = self.get(&key) { return h; }
+        let asset = Arc::new(Asset { name, value });
+        self.entries.insert(key, Arc::downgrade(&asset));
+        Handle(asset)
+    }
+
+    fn prune(&mut self) { self.entries.retain(|_, w| w.strong_count() > 0); }
+
+    /// Bytes of live assets, and count of live, loading and failed ones
+    fn usage(&self) -> (u64, usize, usize, usize) where T: AssetSize {
+        let mut usage = (0, 0, 0, 0);
+        for asset in self.entries.values().filter_map(|w| w.upgrade()) {
+            usage.1 += 1;
+            match asset.value.asset_bytes() {
+                Some(bytes) => usage.0 += bytes,
+                None if asset.value.is_failed() => usage.3 += 1,
+                None => usage.2 += 1,
+            }
+        }
+        usage
+    }
+}
+
+struct Inner {
+    sampler_pool: SamplerPool,
+    images: Cache<(String, Format, SamplerParams), ImageContent>,
+    meshes: Cache<String, Loader<Arc<dyn MeshAccess + Send + Sync>>>,
+    atlases: Cache<String, Loader<TextureAtlas>>,
+    objects: Cache<(String, String), Loader<ObjectInfo>>,
+}
+
+/// Shared between resolvers and game states, clones refer to same manager
+/// Lock is not held while asset is created, so build functions may use manager too
+/// Paths are virtual, files are read through `Vfs`
+#[derive(Clone)]
+pub struct AssetManager {
+    queue: Arc<Queue>,
+    vfs: Arc<Vfs>,
+    inner: Arc<Mutex<Inner>>,
+}
+impl AssetManager {
+    /// Manager reading from working directory
+    pub fn new(queue: Arc<Queue>) -> Self {
+        Self::with_vfs(queue, Arc::new(Vfs::directory(Path::new(""))))
+    }
+
+    pub fn with_vfs(queue: Arc<Queue>, vfs: Arc<Vfs>) -> Self {
+        let sampler_pool = SamplerPool::new(queue.device().clone());
+        Self {
+            queue,
+            vfs,
+            inner: Arc::new(Mutex::new(Inner {
+                sampler_pool,
+                images: Cache::new(),
+                meshes: Cache::new(),
+                atlases: Cache::new(),
+                objects: Cache::new(),
+            })),
+        }
+    }
+
+    /// PNG image, file is read and decoded right away, upload runs on GPU
+    pub fn image(&self, path: &str, format: Format, sampler: SamplerParams) -> Result<Handle<ImageContent>, AssetError> {
+        let path = vfs::normalize(path);
+        let key = (path.clone(), format, sampler.clone());
+        if let Some(h) = self.inner.lock().unwrap().images.get(&key) { return Ok(h); }
+
+        let bytes = self.vfs.read(&path).map_err(|e| AssetError::Read(path.clone(), e))?;
+        let sampler = self.inner.lock().unwrap().sampler_pool.with_params(sampler).map_err(AssetError::Sampler)?;
+        let content = ImageContent::new_with_bytes(self.queue.clone(), sampler, std::io::Cursor::new(bytes), format);
+
+        Ok(self.inner.lock().unwrap().images.insert(key, path, content))
+    }
+
+    /// Mesh stored under `key`, `build` is called only if there is no live mesh for it
+    pub fn mesh<F>(&self, key: &str, build: F) -> MeshHandle
+        where F: FnOnce() -> Loader<Arc<dyn MeshAccess + Send + Sync>>
+    {
+        let key = key.to_string();
+        if let Some(h) = self.inner.lock().unwrap().meshes.get(&key) { return h; }
+        let mesh = build();
+        self.inner.lock().unwrap().meshes.insert(key.clone(), key, mesh)
+    }
+
+    /// Atlas stored under `key`, `build` is called only if there is no live atlas for it
+    pub fn atlas<F>(&self, key: &str, build: F) -> Result<AtlasHandle, AssetError>
+        where F: FnOnce() -> Result<Loader<TextureAtlas>, AtlasError>
+    {
+        let key = key.to_string();
+        if let Some(h) = self.inner.lock().unwrap().atlases.get(&key) { return Ok(h); }
+        let atlas = build()?;
+        Ok(self.inner.lock().unwrap().atlases.insert(key.clone(), key, atlas))
+    }
+
+    /// Object `name` from OBJ file, parsed on rayon pool
+    pub fn object(&self, path: &str, name: &str) -> ObjectHandle {
+        let path = vfs::normalize(path);
+        let key = (path.clone(), name.to_string());
+        if let Some(h) = self.inner.lock().unwrap().objects.get(&key) { return h; }
+
+        let (vfs, file, object) = (self.vfs.clone(), path.clone(), name.to_string());
+        let loader = Loader::with_try_closure(move || {
+            obj::load_objects_from(&vfs, &file, vec![object.clone()])?
+                .remove(&object)
+                .ok_or(obj::Error::NoObjectForName(object))
+        });
+        let name = format!("{}:{}", path, name);
+        self.inner.lock().unwrap().objects.insert(key, name, loader)
+    }
+
+    pub fn vfs(&self) -> &Arc<Vfs> { &self.vfs }
+
+    /// Forget assets which are no longer referenced
+    pub fn collect_garbage(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.images.prune();
+        inner.meshes.prune();
+        inner.atlases.prune();
+        inner.objects.prune();
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.collect_garbage();
+        let inner = self.inner.lock().unwrap();
+        let mut usage = MemoryUsage::default();
+        let mut add = |(bytes, assets, loading, failed): (u64, usize, usize, usize)| {
+            usage.assets += assets;
+            usage.loading += loading;
+            usage.failed += failed;
+            bytes
+        };
+        let images = add(inner.images.usage());
+        let meshes = add(inner.meshes.usage());
+        let atlases = add(inner.atlases.usage());
+        let objects = add(inner.objects.usage());
+        MemoryUsage { images, meshes, atlases, objects, ..usage }
+    }
+}
+
+mod test {
+
+    #[test] fn test_cache_handles() {
+        use super::Cache;
+
+        let mut cache: Cache<&str, u32> = Cache::new();
+        let a = cache.insert("a", "a".into(), 1);
+        let b = cache.get(&"a").unwrap();
+        assert!(super::Handle::ptr_eq(&a, &b));
+        assert_eq!(a.handle_count(), 2);
+
+        // Insert of existing key returns live asset
+        let c = cache.insert("a", "a".into(), 2);
+        assert_eq!(*c, 1);
+
+        drop((a, b, c));
+        assert!(cache.get(&"a").is_none());
+        cache.prune();
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test] fn test_usage_of_failed() {
+        use super::{ Cache, AssetSize };
+        use crate::sync::Loader;
+
+        struct Bytes(u64);
+        impl AssetSize for Bytes {
+            fn asset_bytes(&self) -> Option<u64> { Some(self.0) }
+        }
+
+        let mut cache: Cache<&str, Loader<Bytes>> = Cache::new();
+        let _a = cache.insert("a", "a".into(), Loader::ready(Bytes(10)));
+        let _b = cache.insert("b", "b".into(), Loader::with_error("Missing file"));
+        // Still loading until sender is dropped
+        let (done, wait) = std::sync::mpsc::channel::<()>();
+        let _c = cache.insert("c", "c".into(), Loader::with_closure(move || { let _ = wait.recv(); Bytes(1) }));
+        assert_eq!(cache.usage(), (10, 3, 1, 1));
+        drop(done);
+    }
+}
@@ -0,0 +1,34 @@
+
+// Packs asset directory into single pack archive read by `gfx_lib::vfs::PackArchive`
+// Usage: pack_assets <dir> <out.pack> [--compress]
+
+use std::path::PathBuf;
+use gfx_lib::vfs::{ PackWriter, VfsError };
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let compress = args.iter().any(|a| a == "--compress");
+    args.retain(|a| a != "--compress");
+    if args.len() != 2 {
+        eprintln!("Usage: pack_assets <dir> <out.pack> [--compress]");
+        std::process::exit(2);
+    }
+    let (dir, out) = (PathBuf::from(&args[0]), PathBuf::from(&args[1]));
+
+    let result = std::fs::File::create(&out)
+        .map_err(VfsError::from)
+        .and_then(|file| {
+            let mut writer = PackWriter::new(std::io::BufWriter::new(file))?;
+            let count = writer.add_dir(&dir, compress)?;
+            writer.finish()?;
+            Ok(count)
+        });
+
+    match result {
+        Ok(count) => println!("Packed {} files from {:?} into {:?}", count, dir, out),
+        Err(e) => {
+            eprintln!("Unable to pack {:?}: {}", dir, e);
+            std::process::exit(1);
+        },
+    }
+}
@@ -6,7 +6,6 @@ use std::{
     sync::Arc,
     ops::Index,
     io::Cursor,
-    path::{ PathBuf, Path },
     collections::{ BTreeMap, HashMap }
 };
 use crate::{
@@ -19,6 +18,7 @@ use crate::{
             sampler_pool::SamplerParams
         },
     },
+    vfs,
 };
 use vulkano::{
     format::Format,
@@ -33,7 +33,7 @@ use vulkano::{
         viewport::Viewport,
         GraphicsPipeline, GraphicsPipelineAbstract,
     },
-    sampler::Sampler,
+    sampler::{ Sampler, SamplerCreationError },
     descriptor::{
         descriptor_set::{ PersistentDescriptorSet, DescriptorSet },
     },
@@ -47,7 +47,12 @@ use crate::sync::Loader;
 use crate::graphics::image::ImageContent;
 use vulkano::device::Queue;
 use std::ops::Range;
-use crate::graphics::image::loader::PNGData;
+use crate::graphics::image::{
+    loader::{ PNGData, ImageError },
+    mipmap, readback,
+};
+use crate::assets::{ AssetManager, Handle };
+use crate::graphics::renderer_3d::mesh::{ MaterialData, same_image };
 
 
 pub mod rect_solver;
@@ -57,6 +62,9 @@ pub enum AtlasError {
     // Cant fill all images in selected bounds
     SolverError(rect_solver::SolverError),
     NameAlreadyInUse(String), // Then image with the same name already added
+    Image(ImageError), // Image data can't be decoded or uploaded
+    Sampler(SamplerCreationError), // Default sampler can't be created
+    MipmapsUnsupported(Format), // Mipmaps requested, but levels of format can't be generated
 }
 impl std::error::Error for AtlasError {}
 impl std::fmt::Debug for AtlasError {
@@ -64,6 +72,9 @@ impl std::fmt::Debug for AtlasError {
         match self {
             AtlasError::SolverError(e) => write!(f, "Solver Error: {:?}", e),
             AtlasError::NameAlreadyInUse(name) => write!(f, "Image with name \"{}\" already registered", name),
+            AtlasError::Image(e) => write!(f, "Image Error: {:?}", e),
+            AtlasError::Sampler(e) => write!(f, "Sampler Error: {}", e),
+            AtlasError::MipmapsUnsupported(format) => write!(f, "Mipmaps can't be generated for {:?}, use MipmapsCount::One or other format", format),
             _ => write!(f, "Error not described"),
         }
     }
@@ -73,9 +84,15 @@ impl std::fmt::Display for AtlasError {
         (self as &dyn std::fmt::Debug).fmt(fmt)
     }
 }
+impl From<SamplerCreationError> for AtlasError {
+    fn from(e: SamplerCreationError) -> Self { AtlasError::Sampler(e) }
+}
 impl From<rect_solver::SolverError> for AtlasError {
     fn from(e: rect_solver::SolverError) -> Self { AtlasError::SolverError(e) }
 }
+impl From<ImageError> for AtlasError {
+    fn from(e: ImageError) -> Self { AtlasError::Image(e) }
+}
 
 /// Atlas Builder Entry (image holder)
 /// Container in different structure just in case if some other info will be required
@@ -92,17 +109,18 @@ enum EntryImage {
 }
 impl EntryImage {
     /// Loads `EntryImage::Request`, changing it to be `EntryImage::ImageLoader`
-    fn load_requests(&mut self, queue: &Arc<Queue>, format: &Format) {
+    fn load_requests(&mut self, queue: &Arc<Queue>, format: &Format) -> Result<(), ImageError> {
         match self {
             EntryImage::Request(data) => {
                 println!("Make loader from request");
-                let (i, f) = data.take().unwrap().load_image(queue.clone(), format.clone());
+                let (i, f) = data.take().unwrap().load_image(queue.clone(), format.clone())?;
                 *self = EntryImage::ImageLoader(
                     Loader::with_gpu_future(i as Arc<dyn ImageViewAccess + Send + Sync>, f)
                 )
             },
             _ => (),
         }
+        Ok(())
     }
 }
 
@@ -120,6 +138,8 @@ pub struct AtlasBuilderResult(Result<AtlasBuilder, AtlasError>);
 impl AtlasBuilderResult {
     pub fn unwrap(self) -> AtlasBuilderEditor { AtlasBuilderEditor(self.0.unwrap()) }
     pub fn unwrap_and_next(self) -> AtlasBuilder { self.0.unwrap() }
+    /// Editor or error of added entry, for builders fed with data that may be missing or broken
+    pub fn result(self) -> Result<AtlasBuilderEditor, AtlasError> { self.0.map(AtlasBuilderEditor) }
 }
 
 /// `BuilderEntry` editor, result of `AtlasBuilderResult` unwrap
@@ -150,6 +170,8 @@ pub struct AtlasBuilder {
     can_rotate: bool,
     format: Format,
     sampler: Option<Arc<Sampler>>, // If None(default) will become simple linear
+    mipmaps: MipmapsCount,
+    mip_padding: u32, // Levels regions are padded and aligned for, deeper levels may bleed
     entries: Vec<BuilderEntry>,
 }
 impl AtlasBuilder {
@@ -159,8 +181,10 @@ impl AtlasBuilder {
             padding: [2; 2],
             background_color: [0.0; 4],
             can_rotate: true,
-            format: Format::R8G8B8A8Snorm,
+            format: Format::R8G8B8A8Srgb,
             sampler: None,
+            mipmaps: MipmapsCount::Log2,
+            mip_padding: 4,
             entries: Vec::new()
         }
     }
@@ -170,6 +194,20 @@ impl AtlasBuilder {
     pub fn set_padding(mut self, px: u32, py: u32) -> Self { self.padding = [px, py]; self }
     pub fn set_background_color(mut self, r: f32, g: f32, b: f32, a: f32) -> Self { self.background_color = [r,g,b,a]; self }
     pub fn set_format(mut self, format: Format) -> Self { self.format = format; self }
+    pub fn set_mipmaps(mut self, mipmaps: MipmapsCount) -> Self { self.mipmaps = mipmaps; self }
+    /// Regions are padded and aligned so first `levels` mip levels don't bleed between them
+    pub fn set_mip_padding(mut self, levels: u32) -> Self { self.mip_padding = levels; self }
+
+    /// Padding and alignment of regions, alignment is power of two
+    fn mip_aware_padding(&self) -> ([u32; 2], u32) {
+        let levels = match self.mipmaps {
+            MipmapsCount::One => 1,
+            MipmapsCount::Specific(n) => n.min(self.mip_padding),
+            MipmapsCount::Log2 => self.mip_padding,
+        }.max(1).min(16);
+        let align = 1 << (levels - 1);
+        ([self.padding[0].max(align / 2), self.padding[1].max(align / 2)], align)
+    }
 
     /// Add entry or return Err
     fn add_entry(mut self, entry: BuilderEntry) -> Result<Self, AtlasError> {
@@ -197,9 +235,12 @@ impl AtlasBuilder {
             image: loader.into(),
         }))
     }
-    /// Starts loading image for path
+    /// Starts loading image for path, decoding error is returned as `AtlasError::Image`
     pub fn add_data<S: Into<String>>(mut self, name: S, data: Cursor<Vec<u8>>) -> AtlasBuilderResult {
-        let data = ImageContent::load_image_data(data);
+        let data = match ImageContent::load_image_data(data) {
+            Ok(data) => data,
+            Err(e) => return AtlasBuilderResult(Err(e.into())),
+        };
         AtlasBuilderResult(self.add_entry(BuilderEntry {
             name: name.into(),
             dims: [data.dimensions.0, data.dimensions.1],
@@ -211,15 +252,25 @@ impl AtlasBuilder {
     pub fn build(mut self, frame: &mut Frame) -> Result<Loader<TextureAtlas>, AtlasError> {
         let format = self.format;
         let queue = frame.queue.clone();
-        let sampler = self.sampler.unwrap_or_else(||
-            frame.sampler_pool.with_params(SamplerParams::simple_repeat())
-        );
+        let (padding, align) = self.mip_aware_padding();
+        let mipmaps = self.mipmaps;
+        // Output image, mip levels are blitted from level 0, or filtered on CPU if format can't be blitted
+        let blit = mipmap::supports_blit(format);
+        match mipmaps {
+            MipmapsCount::One => (),
+            _ if blit || mipmap::supports_cpu(format) => (),
+            _ => return Err(AtlasError::MipmapsUnsupported(format)),
+        }
+        let sampler = match self.sampler {
+            Some(sampler) => sampler,
+            None => frame.sampler_pool.with_params(SamplerParams::simple_repeat())?,
+        };
         let background_color = self.background_color;
 
         // Use rect_solver to map all images into rectangles and bin them with params
         let (min_dims, mut rects) = {
             use rect_solver::{ Solver, SolverError, Rect };
-            let solver = Solver::with_params(self.max_dims, self.padding, self.can_rotate);
+            let solver = Solver::with_params(self.max_dims, padding, self.can_rotate).set_align(align);
             let mut rects = self.entries.drain(..)
                 .map(|x| {
                     let dims = x.dims;
@@ -264,7 +315,7 @@ impl AtlasBuilder {
                 _ => None,
             };
             if let Some(data) = data {
-                let (i, f) = data.load_image(queue.clone(), format.clone());
+                let (i, f) = data.load_image(queue.clone(), format.clone())?;
                 l.key.image = EntryImage::ImageLoader(
                     Loader::with_gpu_future(i as Arc<dyn ImageViewAccess + Send + Sync>, f)
                 );
@@ -272,45 +323,46 @@ impl AtlasBuilder {
         }
 
         // Transient image we render stuff into then copy it into `ImmutableImage` and delete this one
-        let transient_image = vulkano::image::StorageImage::new(
-            queue.device().clone(), dim, format, vec![queue.family()]
-        ).unwrap();
-
-        // Create copy task
-        let (output_image, image_copy_command) = {
-            let (image, init) = vulkano::image::ImmutableImage::uninitialized(
-                queue.device().clone(),
-                dim,
-                format,
-                MipmapsCount::One,
-                ImageUsage {
-                    sampled: true,
-                    transfer_destination: true,
-                    .. ImageUsage::none()
-                },
-                ImageLayout::ShaderReadOnlyOptimal,
-                vec![queue.family()]
-            ).unwrap();
-
-            let cb = AutoCommandBufferBuilder::new(queue.device().clone(), queue.family()).unwrap()
-                .copy_image(
-                    transient_image.clone(), [0, 0, 0], 0, 0,
-                    init, [0, 0, 0], 0, 0,
-                    dim.width_height_depth(), 1).unwrap()
-                .build().unwrap();
-            (image, cb)
-        };
+        // No storage usage, sRGB formats usually can't be storage images
+        let transient_image = vulkano::image::StorageImage::with_usage(
+            queue.device().clone(), dim, format,
+            ImageUsage {
+                color_attachment: true,
+                transfer_source: true,
+                sampled: true,
+                .. ImageUsage::none()
+            },
+            vec![queue.family()]
+        ).map_err(mipmap::upload_error)?;
+
+        let levels = mipmap::level_count(mipmaps, min_dims);
+        let (output_image, init) = vulkano::image::ImmutableImage::uninitialized(
+            queue.device().clone(),
+            dim,
+            format,
+            MipmapsCount::Specific(levels),
+            ImageUsage {
+                sampled: true,
+                transfer_source: blit,
+                transfer_destination: true,
+                .. ImageUsage::none()
+            },
+            ImageLayout::ShaderReadOnlyOptimal,
+            vec![queue.family()]
+        ).map_err(mipmap::upload_error)?;
+        let init = Arc::new(init);
 
 
-        Ok(Loader::with_closure(move || {
+        Ok(Loader::with_try_closure(move || -> Result<TextureAtlas, AtlasError> {
 
-            // Await on futures in `r.key.image`
+            // Await on futures in `r.key.image`, failed image fails atlas
             for r in rects.iter() {
                 match &r.key.image {
                     // No need to wait on ready image
                     EntryImage::Image(_) => (),
                     // Wait on loader
-                    EntryImage::ImageLoader(l) => l.wait(None).unwrap(),
+                    EntryImage::ImageLoader(l) => l.wait(None)
+                        .map_err(|e| ImageError::Upload(format!("\"{}\": {}", r.key.name, e)))?,
                     // Requests should be resolved by this point
                     EntryImage::Request(_) => panic!("Request was not resolved"),
                 }
@@ -327,26 +379,49 @@ impl AtlasBuilder {
             for r in rects.iter() {
                 let mut content = LocalImageContent::new(&r.key.image, sampler.clone());
                 let mut call = renderer.start_image_content(&mut content);
-                let mut inst = {
-                    let w = r.size[0] as f32;
-                    let h = r.size[1] as f32;
-                    let x = r.pos[0] as f32 + w*0.5;
-                    let y = r.pos[1] as f32 + h*0.5;
-                    let r = if r.rotated { 90.0 } else { 0.0 };
-                    let mut inst = Renderer2D::prepare_instance(x, y, w, h, r);
+                let w = r.size[0] as f32;
+                let h = r.size[1] as f32;
+                let x = r.pos[0] as f32 + w*0.5;
+                let y = r.pos[1] as f32 + h*0.5;
+                let angle = if r.rotated { 90.0 } else { 0.0 };
+                let inst = |w: f32, h: f32| {
+                    let mut inst = Renderer2D::prepare_instance(x, y, w, h, angle);
                     inst.set_color(1.0, 1.0, 1.0, 1.0);
                     inst
                 };
-                call.render_instance(inst);
+                // Stretched copy under region fills padding with its edge colors, so lower levels don't bleed background
+                if levels > 1 {
+                    call.render_instance(inst(w + padding[0] as f32 * 2.0, h + padding[1] as f32 * 2.0));
+                }
+                call.render_instance(inst(w, h));
             }
 
             future = renderer.end(future);
+
+            // Copy into level 0 of output, then fill remaining levels
+            let cb = AutoCommandBufferBuilder::primary_one_time_submit(queue.device().clone(), queue.family()).map_err(mipmap::upload_error)?
+                .copy_image(
+                    transient_image.clone(), [0, 0, 0], 0, 0,
+                    init.clone(), [0, 0, 0], 0, 0,
+                    dim.width_height_depth(), 1).map_err(mipmap::upload_error)?;
+            let cb = if levels > 1 && blit {
+                mipmap::record_blit_chain(cb, &queue, init, min_dims, levels, format)?
+            } else if levels > 1 {
+                // Rendered atlas is read back, it must be finished first
+                future.then_signal_fence_and_flush().map_err(mipmap::upload_error)?.wait(None).map_err(mipmap::upload_error)?;
+                future = Box::new(vulkano::sync::now(queue.device().clone()));
+                let base = readback::read_image(queue.clone(), transient_image.clone()).and_then(|r| r.to_rgba8())
+                    .map_err(mipmap::upload_error)?;
+                let texels = mipmap::cpu_chain(base, format, levels)?;
+                mipmap::record_uploads(cb, queue.device(), init, min_dims, texels.into_iter().skip(1).collect(), 1)?
+            } else { cb };
+
             future
-                .then_execute(queue, image_copy_command).unwrap()
-                .then_signal_fence_and_flush().unwrap()
-                .wait(None).unwrap();
+                .then_execute(queue, cb.build().map_err(mipmap::upload_error)?).map_err(mipmap::upload_error)?
+                .then_signal_fence_and_flush().map_err(mipmap::upload_error)?
+                .wait(None).map_err(mipmap::upload_error)?;
 
-            TextureAtlas::new(output_image, sampler, rects)
+            Ok(TextureAtlas::new(output_image, sampler, rects))
         }))
     }
 
@@ -424,19 +499,20 @@ impl ImageResolver for AtlasImageResolver {
     }
 }
 
-/// Resolves images from `directory` and loads them on the fly
+/// Resolves images from virtual `directory` and loads them on the fly
+/// Images are shared through `AssetManager`, so resolvers of same directory load each file once
 pub struct DirectoryImageResolver {
-    queue: Arc<Queue>,
-    sampler: Arc<Sampler>,
-    // base directory
-    base_path: PathBuf,
-    // Pooled images for (path, image, image_future)
-    pooled: BTreeMap<String, (Loader<Arc<dyn ImageViewAccess + Send + Sync>>, TextureRegion)>
+    assets: AssetManager,
+    sampler: SamplerParams,
+    // base directory, virtual path
+    base_path: String,
+    // Resolved regions, handles keep images loaded while resolver lives
+    pooled: BTreeMap<String, (Handle<ImageContent>, TextureRegion)>
 }
 impl DirectoryImageResolver {
-    pub fn new(path: &Path, queue: Arc<Queue>, sampler: Arc<Sampler>) -> Result<Box<Self>, std::io::Error> {
+    pub fn new(path: &str, assets: AssetManager, sampler: SamplerParams) -> Result<Box<Self>, std::io::Error> {
         Ok(Box::new(Self {
-            queue,
+            assets,
             sampler,
             base_path: path.into(),
             pooled: BTreeMap::new(),
@@ -446,26 +522,23 @@ impl DirectoryImageResolver {
 impl ImageResolver for DirectoryImageResolver {
     fn get(&mut self, usage: MaterialImageUsage, key: &String) -> Option<&TextureRegion> {
         if !self.pooled.contains_key(key) {
-            let path = {
-                let mut p = self.base_path.clone();
-                p.push(key);
-                p
-            };
-            let bytes = std::fs::read(path.clone()).unwrap_or_else(|_| panic!("No file for path: {:?}", path));
-            let loader = ImageContent::load_image(
-                self.queue.clone(),
-                Cursor::new(bytes),
-                Format::R8G8B8A8Srgb
-            );
-            let region = {
-                let image = loader.snapshot().unwrap();
-                TextureRegion::from_image(image, self.sampler.clone())
+            let image = match self.assets.image(&vfs::join(&self.base_path, key), Format::R8G8B8A8Srgb, self.sampler.clone()) {
+                Ok(image) => image,
+                Err(e) => {
+                    println!("DirectoryImageResolver::get -> {:?}, {}", usage, e);
+                    return None;
+                }
             };
-            self.pooled.insert(key.clone(), (loader, region));
+            let region = TextureRegion::from_image(image.snapshot()?, image.get_sampler());
+            self.pooled.insert(key.clone(), (image, region));
         }
 
         Some(&self.pooled.get(key).as_ref().unwrap().1)
     }
+
+    fn flush(&mut self) {
+        for (image, _) in self.pooled.values() { image.flush(); }
+    }
 }
 
 /// Contains `ImageAccess` Arc and info about regions inside said image
@@ -528,6 +601,24 @@ impl TextureAtlas {
     /// Return clone of `Arc` instance of image used in this `TextureAtlas`
     #[inline] pub fn get_image(&self) -> Arc<dyn ImageViewAccess + Send + Sync> { self.image.clone() }
 
+    /// Move material using this atlas onto rebuilt `new` atlas, used by hot reload
+    /// Region is found by UV, material using whole atlas image keeps its sampler and UV
+    pub fn rebind(&self, new: &TextureAtlas, material: &mut MaterialData) -> bool {
+        match material.diffuse_texture() {
+            Some(texture) if same_image(texture, &self.image) => (),
+            _ => return false,
+        }
+        let uv = [material.get_diffuse_remap_a(), material.get_diffuse_remap_b()];
+        let name = self.regions.iter()
+            .find(|(_, r)| r.uv_a == uv[0] && r.uv_b == uv[1])
+            .map(|(name, _)| name);
+        match name.and_then(|name| new.regions.get(name)) {
+            Some(region) => material.set_diffuse_region(region),
+            None => { material.replace_texture(&self.image, &new.image); },
+        }
+        true
+    }
+
 }
 /// Index `TextureAtlas`, panics if no region for name
 impl <T: Into<String>> std::ops::Index<T> for TextureAtlas {
@@ -56,16 +56,24 @@ pub struct Solver {
     max_dims: u32,
     padding: [u32; 2],
     can_rotate: bool,
+    align: u32, // Footprint of rect with padding is multiple of this
 }
 impl Solver {
     pub fn with_params(max_dims: u32, padding: [u32; 2], can_rotate: bool) -> Self { Self {
         max_dims,
         padding,
         can_rotate,
+        align: 1,
     } }
 
+    /// Align footprints, so rects stay apart in lower mip levels
+    pub fn set_align(mut self, align: u32) -> Self {
+        self.align = align.max(1);
+        self
+    }
+
     /// Solve for given params, return true is succeeded, else false
-    fn solve_for<K>(dims: u32, pads: [u32; 2], can_rotate: bool, rects: &mut Vec<Rect<K>>) -> bool {
+    fn solve_for<K>(dims: u32, pads: [u32; 2], align: u32, can_rotate: bool, rects: &mut Vec<Rect<K>>) -> bool {
 
         let mut spaces = vec![Space { pos: (0, 0), size: (dims, dims) }];
 
@@ -80,9 +88,9 @@ impl Solver {
                 // Search for space to fit rect
                 let mut fits = true;
                 let mut rotated = false;
-                if rect.size[0] + px > space.size.0 || rect.size[1] + py > space.size.1 { fits = false; }
+                if round_up(rect.size[0] + px, align) > space.size.0 || round_up(rect.size[1] + py, align) > space.size.1 { fits = false; }
                 // Try rotated
-                if !fits && can_rotate && (rect.size[1] + px > space.size.0 || rect.size[0] + py > space.size.1) {
+                if !fits && can_rotate && (round_up(rect.size[1] + px, align) > space.size.0 || round_up(rect.size[0] + py, align) > space.size.1) {
                     fits = false;
                     rotated = true;
                 }
@@ -95,8 +103,8 @@ impl Solver {
 
                 // If space exits, put image in said space, then reduce and process remaining space
 
-                let rw = rect.size[0] + px;
-                let rh = rect.size[1] + py;
+                let rw = round_up(rect.size[0] + px, align);
+                let rh = round_up(rect.size[1] + py, align);
 
                 // if fully fits, remove space
                 if space.size.0 == rw && space.size.1 == rh {
@@ -146,10 +154,12 @@ impl Solver {
     pub fn solve<K>(&self, rects: &mut Vec<Rect<K>>) -> Result<[u32; 2], SolverError> {
         let mut total_rects_area = 0;
         for r in rects.iter() {
-            if r.size[0] + self.padding[0]*2 > self.max_dims || r.size[1] + self.padding[1]*2 > self.max_dims {
+            let w = round_up(r.size[0] + self.padding[0]*2, self.align);
+            let h = round_up(r.size[1] + self.padding[1]*2, self.align);
+            if w > self.max_dims || h > self.max_dims {
                 return Err(SolverError::ImageIsTooBig);
             }
-            total_rects_area += (r.size[0] + self.padding[0]*2) * (r.size[1] + self.padding[1]*2);
+            total_rects_area += w * h;
         }
 
         let given_area = self.max_dims * self.max_dims;
@@ -161,7 +171,7 @@ impl Solver {
 
         for p in pot .. max_pot+1 {
             let dim = 2.0f32.powf(p as f32) as u32;
-            if Self::solve_for(dim, self.padding, self.can_rotate, rects) {
+            if Self::solve_for(dim, self.padding, self.align, self.can_rotate, rects) {
                 return Ok([dim; 2])
             }
         }
@@ -251,4 +261,22 @@ impl Solver {
   }
 }
     */
-}
\ No newline at end of file
+}
+
+fn round_up(v: u32, align: u32) -> u32 { (v + align - 1) / align * align }
+
+mod test {
+
+    #[test] fn test_aligned_solve() {
+        use super::{ Solver, Rect };
+
+        let mut rects = vec![Rect::new(0, 5, 5), Rect::new(1, 3, 7), Rect::new(2, 8, 2)];
+        let dims = Solver::with_params(64, [1, 1], false).set_align(4).solve(&mut rects).unwrap();
+        assert!(dims[0] <= 64 && dims[1] <= 64);
+        for r in rects.iter() {
+            // Padded footprint starts on aligned boundary
+            assert_eq!((r.pos[0] - 1) % 4, 0);
+            assert_eq!((r.pos[1] - 1) % 4, 0);
+        }
+    }
+}
@@ -0,0 +1,389 @@
+
+// BC1 - BC7 block decoders, CPU fallback for devices without BC support
+// Every block is 4x4 texels, blocks on right and bottom edge are cut to image size
+// BC6H is HDR and has no RGBA8 fallback
+
+use vulkano::format::Format;
+
+use super::ImageError;
+
+type Texels = [[u8; 4]; 16];
+
+/// Decode blocks of image with `dims` into RGBA8
+pub fn decode(format: Format, dims: [u32; 2], data: &[u8]) -> Result<Vec<u8>, ImageError> {
+    let (block_size, decode_block): (usize, fn(&[u8], &mut Texels)) = match format {
+        Format::BC1_RGBUnormBlock | Format::BC1_RGBSrgbBlock => (8, bc1_opaque),
+        Format::BC1_RGBAUnormBlock | Format::BC1_RGBASrgbBlock => (8, bc1),
+        Format::BC2UnormBlock | Format::BC2SrgbBlock => (16, bc2),
+        Format::BC3UnormBlock | Format::BC3SrgbBlock => (16, bc3),
+        Format::BC4UnormBlock => (8, bc4_unorm),
+        Format::BC4SnormBlock => (8, bc4_snorm),
+        Format::BC5UnormBlock => (16, bc5_unorm),
+        Format::BC5SnormBlock => (16, bc5_snorm),
+        Format::BC7UnormBlock | Format::BC7SrgbBlock => (16, bc7),
+        Format::BC6HUfloatBlock | Format::BC6HSfloatBlock => return Err(ImageError::Unsupported("BC6H can't be decompressed on CPU".into())),
+        _ => return Err(ImageError::UnsupportedFormat(format)),
+    };
+
+    let (w, h) = (dims[0] as usize, dims[1] as usize);
+    let (bw, bh) = ((w + 3) / 4, (h + 3) / 4);
+    if data.len() < bw * bh * block_size { return Err(ImageError::Decode("block data is truncated".into())); }
+
+    let mut out = vec![0u8; w * h * 4];
+    let mut texels = [[0u8; 4]; 16];
+    for by in 0 .. bh {
+        for bx in 0 .. bw {
+            let at = (by * bw + bx) * block_size;
+            decode_block(&data[at .. at + block_size], &mut texels);
+            for (i, t) in texels.iter().enumerate() {
+                let (x, y) = (bx * 4 + i % 4, by * 4 + i / 4);
+                if x < w && y < h {
+                    let o = (y * w + x) * 4;
+                    out[o .. o + 4].copy_from_slice(t);
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn rgb565(c: u16) -> [u32; 3] {
+    let (r, g, b) = ((c >> 11) as u32 & 31, (c >> 5) as u32 & 63, c as u32 & 31);
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+/// Color part of BC1 - BC3
+/// Without `four_color` smaller first endpoint selects 3 colors and black, transparent if `punch_through`
+fn color_block(block: &[u8], texels: &mut Texels, four_color: bool, punch_through: bool) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+
+    let mut palette = [[0u8, 0, 0, 255]; 4];
+    for c in 0 .. 3 {
+        palette[0][c] = a[c] as u8;
+        palette[1][c] = b[c] as u8;
+        if four_color || c0 > c1 {
+            palette[2][c] = ((2 * a[c] + b[c]) / 3) as u8;
+            palette[3][c] = ((a[c] + 2 * b[c]) / 3) as u8;
+        } else {
+            palette[2][c] = ((a[c] + b[c]) / 2) as u8;
+        }
+    }
+    if !four_color && c0 <= c1 && punch_through { palette[3][3] = 0; }
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (i, t) in texels.iter_mut().enumerate() {
+        *t = palette[(indices >> (2 * i)) as usize & 3];
+    }
+}
+
+/// Single channel of BC3 alpha, BC4 and BC5, written into `channel` of texels
+fn channel_block(block: &[u8], texels: &mut Texels, channel: usize, signed: bool) {
+    let (a, b, lo, hi) = if signed {
+        ((block[0] as i8).max(-127) as i32, (block[1] as i8).max(-127) as i32, -127, 127)
+    } else {
+        (block[0] as i32, block[1] as i32, 0, 255)
+    };
+
+    let mut palette = [a, b, 0, 0, 0, 0, lo, hi];
+    if a > b {
+        for i in 1 .. 7 { palette[i + 1] = ((7 - i as i32) * a + i as i32 * b) / 7; }
+    } else {
+        for i in 1 .. 5 { palette[i + 1] = ((5 - i as i32) * a + i as i32 * b) / 5; }
+    }
+
+    let mut indices = 0u64;
+    for i in 0 .. 6 { indices |= (block[2 + i] as u64) << (8 * i); }
+    for (i, t) in texels.iter_mut().enumerate() {
+        // Signed values keep their two's complement byte
+        t[channel] = palette[(indices >> (3 * i)) as usize & 7] as u8;
+    }
+}
+
+fn bc1(block: &[u8], texels: &mut Texels) { color_block(block, texels, false, true) }
+fn bc1_opaque(block: &[u8], texels: &mut Texels) { color_block(block, texels, false, false) }
+
+fn bc2(block: &[u8], texels: &mut Texels) {
+    color_block(&block[8 ..], texels, true, false);
+    let alpha = u64::from_le_bytes([block[0], block[1], block[2], block[3], block[4], block[5], block[6], block[7]]);
+    for (i, t) in texels.iter_mut().enumerate() {
+        t[3] = ((alpha >> (4 * i)) & 15) as u8 * 17;
+    }
+}
+
+fn bc3(block: &[u8], texels: &mut Texels) {
+    color_block(&block[8 ..], texels, true, false);
+    channel_block(block, texels, 3, false);
+}
+
+/// BC4 and BC5 read as (r, 0, 0, 1) and (r, g, 0, 1), same as sampling them
+fn bc4_unorm(block: &[u8], texels: &mut Texels) {
+    *texels = [[0, 0, 0, 255]; 16];
+    channel_block(block, texels, 0, false);
+}
+fn bc4_snorm(block: &[u8], texels: &mut Texels) {
+    *texels = [[0, 0, 0, 127]; 16];
+    channel_block(block, texels, 0, true);
+}
+fn bc5_unorm(block: &[u8], texels: &mut Texels) {
+    *texels = [[0, 0, 0, 255]; 16];
+    channel_block(block, texels, 0, false);
+    channel_block(&block[8 ..], texels, 1, false);
+}
+fn bc5_snorm(block: &[u8], texels: &mut Texels) {
+    *texels = [[0, 0, 0, 127]; 16];
+    channel_block(block, texels, 0, true);
+    channel_block(&block[8 ..], texels, 1, true);
+}
+
+// ##########
+// BC7
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: usize,
+    rotation_bits: usize,
+    selection_bits: usize, // Mode 4 index selection, swaps color and alpha indices
+    color_bits: usize,
+    alpha_bits: usize, // 0 then alpha is opaque
+    endpoint_pbits: bool, // P-bit per endpoint
+    shared_pbits: bool, // P-bit per subset
+    index_bits: usize,
+    index2_bits: usize, // Secondary index, used by alpha unless selection swaps it
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 3, index2_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true, index_bits: 3, index2_bits: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index2_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index2_bits: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index2_bits: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index2_bits: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_pbits: true, shared_pbits: false, index_bits: 4, index2_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index2_bits: 0 },
+];
+
+/// Subset of each texel for 2 subset partitions, bit per texel
+const PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE, 0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+/// Subset of each texel for 3 subset partitions
+const PARTITIONS_3: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2], [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1], [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2], [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1], [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2], [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2], [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2], [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2], [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2], [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2], [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2], [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2], [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0], [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0], [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2], [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1], [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2], [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2], [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0], [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0], [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1], [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1], [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1], [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1], [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2], [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2], [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2], [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2], [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2], [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1], [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2], [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+/// Anchor texel of second subset in 2 subset partitions
+const ANCHORS_2: [usize; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15,  2,  8,  2,  2,  8,  8, 15,  2,  8,  2,  2,  8,  8,  2,  2,
+    15, 15,  6,  8,  2,  8, 15, 15,  2,  8,  2,  2,  2, 15, 15,  6,
+     6,  2,  6,  8, 15, 15,  2,  2, 15, 15, 15, 15, 15,  2,  2, 15,
+];
+/// Anchor texels of second and third subset in 3 subset partitions
+const ANCHORS_3: [[usize; 2]; 64] = [
+    [3, 15], [3, 8], [15, 8], [15, 3], [8, 15], [3, 15], [15, 3], [15, 8],
+    [8, 15], [8, 15], [6, 15], [6, 15], [6, 15], [5, 15], [3, 15], [3, 8],
+    [3, 15], [3, 8], [8, 15], [15, 3], [3, 15], [3, 8], [6, 15], [10, 8],
+    [5, 3], [8, 15], [8, 6], [6, 10], [8, 15], [5, 15], [15, 10], [15, 8],
+    [8, 15], [15, 3], [3, 15], [5, 10], [6, 10], [10, 8], [8, 9], [15, 10],
+    [15, 6], [3, 15], [15, 8], [5, 15], [15, 3], [15, 6], [15, 6], [15, 8],
+    [3, 15], [15, 3], [5, 15], [5, 15], [5, 15], [8, 15], [5, 15], [10, 15],
+    [5, 15], [10, 15], [8, 15], [13, 15], [15, 3], [12, 15], [3, 15], [3, 8],
+];
+
+const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Reads block from lowest bit up
+struct Bits<'a> { data: &'a [u8], pos: usize }
+impl <'a> Bits<'a> {
+    fn read(&mut self, count: usize) -> u32 {
+        let mut v = 0;
+        for i in 0 .. count {
+            v |= ((self.data[self.pos / 8] >> (self.pos % 8)) as u32 & 1) << i;
+            self.pos += 1;
+        }
+        v
+    }
+}
+
+fn interpolate(a: u32, b: u32, index: u32, bits: usize) -> u8 {
+    let w = match bits {
+        2 => WEIGHTS_2[index as usize],
+        3 => WEIGHTS_3[index as usize],
+        _ => WEIGHTS_4[index as usize],
+    };
+    (((64 - w) * a + w * b + 32) >> 6) as u8
+}
+
+fn bc7(block: &[u8], texels: &mut Texels) {
+    // Mode is position of lowest set bit, reserved mode decodes into transparent black
+    let mode = match (0 .. 8).find(|m| block[0] & (1 << m) != 0) {
+        Some(mode) => mode,
+        None => { *texels = [[0; 4]; 16]; return; }
+    };
+    let m = &BC7_MODES[mode];
+    let mut bits = Bits { data: block, pos: mode + 1 };
+
+    let partition = bits.read(m.partition_bits) as usize;
+    let rotation = bits.read(m.rotation_bits);
+    let selection = bits.read(m.selection_bits);
+
+    // All reds come first, then greens, blues and alphas
+    let count = m.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for c in 0 .. 4 {
+        let precision = if c < 3 { m.color_bits } else { m.alpha_bits };
+        for e in endpoints[.. count].iter_mut() { e[c] = bits.read(precision); }
+    }
+
+    let mut pbits = [0u32; 6];
+    if m.endpoint_pbits {
+        for p in pbits[.. count].iter_mut() { *p = bits.read(1); }
+    }
+    if m.shared_pbits {
+        for s in 0 .. m.subsets {
+            let p = bits.read(1);
+            pbits[s * 2] = p;
+            pbits[s * 2 + 1] = p;
+        }
+    }
+
+    // Expand endpoints into 8 bits, P-bit is extra lowest bit
+    let has_pbits = m.endpoint_pbits || m.shared_pbits;
+    for (e, p) in endpoints[.. count].iter_mut().zip(pbits.iter()) {
+        for c in 0 .. 4 {
+            let precision = if c < 3 { m.color_bits } else { m.alpha_bits };
+            if precision == 0 { e[c] = 255; continue; }
+            let (v, precision) = if has_pbits { (e[c] << 1 | p, precision + 1) } else { (e[c], precision) };
+            let v = v << (8 - precision);
+            e[c] = v | (v >> precision);
+        }
+    }
+
+    let subset = |i: usize| match m.subsets {
+        1 => 0,
+        2 => (PARTITIONS_2[partition] >> i) as usize & 1,
+        _ => PARTITIONS_3[partition][i] as usize,
+    };
+    // Anchor texels store their index with one bit less
+    let is_anchor = |i: usize| i == 0 || match m.subsets {
+        2 => i == ANCHORS_2[partition],
+        3 => ANCHORS_3[partition].contains(&i),
+        _ => false,
+    };
+
+    let mut index = [0u32; 16];
+    for (i, v) in index.iter_mut().enumerate() {
+        *v = bits.read(m.index_bits - is_anchor(i) as usize);
+    }
+    let mut index2 = [0u32; 16];
+    if m.index2_bits > 0 {
+        for (i, v) in index2.iter_mut().enumerate() {
+            *v = bits.read(m.index2_bits - (i == 0) as usize);
+        }
+    }
+
+    for (i, t) in texels.iter_mut().enumerate() {
+        let s = subset(i);
+        let (e0, e1) = (endpoints[s * 2], endpoints[s * 2 + 1]);
+        let (color, alpha) = if m.index2_bits == 0 {
+            ((index[i], m.index_bits), (index[i], m.index_bits))
+        } else if selection == 0 {
+            ((index[i], m.index_bits), (index2[i], m.index2_bits))
+        } else {
+            ((index2[i], m.index2_bits), (index[i], m.index_bits))
+        };
+        for c in 0 .. 4 {
+            let (index, index_bits) = if c < 3 { color } else { alpha };
+            t[c] = interpolate(e0[c], e1[c], index, index_bits);
+        }
+        match rotation {
+            1 => t.swap(0, 3),
+            2 => t.swap(1, 3),
+            3 => t.swap(2, 3),
+            _ => (),
+        }
+    }
+}
+
+mod test {
+
+    #[test] fn test_bc_blocks() {
+        use super::decode;
+        use vulkano::format::Format;
+
+        // Red and blue endpoints, texels use indices 0 - 3
+        let bc1 = [0x00, 0xF8, 0x1F, 0x00, 0xE4, 0, 0, 0];
+        let out = decode(Format::BC1_RGBAUnormBlock, [4, 1], &bc1).unwrap();
+        assert_eq!(out, vec![255, 0, 0, 255,  0, 0, 255, 255,  170, 0, 85, 255,  85, 0, 170, 255]);
+
+        // Swapped endpoints select 3 colors and transparent black
+        let bc1 = [0x1F, 0x00, 0x00, 0xF8, 0xE4, 0, 0, 0];
+        let out = decode(Format::BC1_RGBAUnormBlock, [4, 1], &bc1).unwrap();
+        assert_eq!(&out[8 ..], &[127, 0, 127, 255,  0, 0, 0, 0]);
+        let out = decode(Format::BC1_RGBUnormBlock, [4, 1], &bc1).unwrap();
+        assert_eq!(&out[12 ..], &[0, 0, 0, 255]);
+
+        // Texels 0 - 2 use indices 0, 1 and 2
+        let bc4 = [255, 0, 0b1000_1000, 0, 0, 0, 0, 0];
+        let out = decode(Format::BC4UnormBlock, [3, 1], &bc4).unwrap();
+        assert_eq!(out, vec![255, 0, 0, 255,  0, 0, 0, 255,  218, 0, 0, 255]);
+
+        // BC7 mode 6, texel 0 is first endpoint and texel 1 second
+        let mut bc7 = [0u8; 16];
+        {
+            let mut pos = 0;
+            let mut put = |v: u32, n: usize| for i in 0 .. n {
+                if v >> i & 1 != 0 { bc7[pos / 8] |= 1 << (pos % 8); }
+                pos += 1;
+            };
+            put(1 << 6, 7);
+            for &v in [127, 0, 64, 0, 0, 127, 127, 127].iter() { put(v, 7); }
+            put(1, 1); put(0, 1); // P-bits
+            put(0, 3); put(15, 4);
+        }
+        let out = decode(Format::BC7UnormBlock, [2, 1], &bc7).unwrap();
+        assert_eq!(out, vec![255, 129, 1, 255,  0, 0, 254, 254]);
+
+        assert!(decode(Format::BC6HUfloatBlock, [4, 4], &[0; 16]).is_err());
+        assert!(decode(Format::BC3UnormBlock, [8, 4], &[0; 16]).is_err());
+    }
+}
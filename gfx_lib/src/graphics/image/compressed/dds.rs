@@ -0,0 +1,186 @@
+
+// DDS container
+// Legacy header with FourCC or bit masks, or DX10 extension header with DXGI format
+// Data is stored per layer (each cube face is a layer), every layer with its full mip chain
+
+use vulkano::format::Format;
+
+use super::{ CompressedImage, ImageError, level_bytes, mip_dims, mipmap, u32_at };
+
+const MAGIC: &[u8; 4] = b"DDS ";
+const HEADER_SIZE: usize = 128; // With magic
+const DX10_HEADER_SIZE: usize = 20;
+
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x20000;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALLFACES: u32 = 0xFC00;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+const DX10_TEXTURE2D: u32 = 3;
+const DX10_TEXTURECUBE: u32 = 0x4;
+
+pub fn is_dds(bytes: &[u8]) -> bool { bytes.starts_with(MAGIC) }
+
+pub fn decode(bytes: &[u8]) -> Result<CompressedImage, ImageError> {
+    if bytes.len() < HEADER_SIZE || !is_dds(bytes) || u32_at(bytes, 4) != 124 {
+        return Err(ImageError::Decode("DDS header is truncated".into()));
+    }
+    let flags = u32_at(bytes, 8);
+    let height = u32_at(bytes, 12).max(1);
+    let width = u32_at(bytes, 16).max(1);
+    let levels = if flags & DDSD_MIPMAPCOUNT != 0 { u32_at(bytes, 28).max(1) } else { 1 };
+    let pf_flags = u32_at(bytes, 80);
+    let four_cc = &bytes[84 .. 88];
+    let caps2 = u32_at(bytes, 112);
+
+    if caps2 & DDSCAPS2_VOLUME != 0 { return Err(ImageError::Unsupported("DDS volume texture".into())); }
+
+    let (format, layers, cube, offset) = if pf_flags & DDPF_FOURCC != 0 && four_cc == b"DX10" {
+        if bytes.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+            return Err(ImageError::Decode("DDS DX10 header is truncated".into()));
+        }
+        let dxgi = u32_at(bytes, 128);
+        let format = dxgi_format(dxgi).ok_or_else(|| ImageError::Unsupported(format!("DXGI format {}", dxgi)))?;
+        if u32_at(bytes, 132) != DX10_TEXTURE2D { return Err(ImageError::Unsupported("DDS texture is not 2D".into())); }
+        let cube = u32_at(bytes, 136) & DX10_TEXTURECUBE != 0;
+        let array = u32_at(bytes, 140).max(1);
+        (format, if cube { array * 6 } else { array }, cube, HEADER_SIZE + DX10_HEADER_SIZE)
+    } else {
+        let format = legacy_format(pf_flags, four_cc, &bytes[88 .. 108])?;
+        let cube = caps2 & DDSCAPS2_CUBEMAP != 0;
+        if cube && caps2 & DDSCAPS2_CUBEMAP_ALLFACES != DDSCAPS2_CUBEMAP_ALLFACES {
+            return Err(ImageError::Unsupported("DDS cube map with missing faces".into()));
+        }
+        (format, if cube { 6 } else { 1 }, cube, HEADER_SIZE)
+    };
+
+    // Layer major order into level major
+    let levels = levels.min(mipmap::mip_levels([width, height]));
+    let mut out = vec![vec![]; levels as usize];
+    let mut pos = offset;
+    for _ in 0 .. layers {
+        for (level, data) in out.iter_mut().enumerate() {
+            let size = level_bytes(format, mip_dims([width, height], level as u32)).unwrap();
+            let chunk = bytes.get(pos .. pos + size).ok_or_else(|| ImageError::Decode("DDS data is truncated".into()))?;
+            data.extend_from_slice(chunk);
+            pos += size;
+        }
+    }
+    CompressedImage::new(format, [width, height], layers, cube, out)
+}
+
+/// Format of legacy pixel format, `masks` are bit count and RGBA masks
+fn legacy_format(flags: u32, four_cc: &[u8], masks: &[u8]) -> Result<Format, ImageError> {
+    if flags & DDPF_FOURCC != 0 {
+        return match four_cc {
+            b"DXT1" => Ok(Format::BC1_RGBAUnormBlock),
+            b"DXT2" | b"DXT3" => Ok(Format::BC2UnormBlock),
+            b"DXT4" | b"DXT5" => Ok(Format::BC3UnormBlock),
+            b"ATI1" | b"BC4U" => Ok(Format::BC4UnormBlock),
+            b"BC4S" => Ok(Format::BC4SnormBlock),
+            b"ATI2" | b"BC5U" => Ok(Format::BC5UnormBlock),
+            b"BC5S" => Ok(Format::BC5SnormBlock),
+            // D3DFMT_A16B16G16R16F and D3DFMT_A32B32G32R32F
+            &[113, 0, 0, 0] => Ok(Format::R16G16B16A16Sfloat),
+            &[116, 0, 0, 0] => Ok(Format::R32G32B32A32Sfloat),
+            _ => Err(ImageError::Unsupported(format!("DDS FourCC {:?}", String::from_utf8_lossy(four_cc)))),
+        };
+    }
+
+    let bits = u32_at(masks, 0);
+    let (r, g, b) = (u32_at(masks, 4), u32_at(masks, 8), u32_at(masks, 12));
+    let a = if flags & DDPF_ALPHAPIXELS != 0 { u32_at(masks, 16) } else { 0 };
+    if flags & DDPF_RGB != 0 {
+        match (bits, r, g, b, a) {
+            (32, 0xFF, 0xFF00, 0xFF0000, 0xFF000000) => return Ok(Format::R8G8B8A8Unorm),
+            (32, 0xFF0000, 0xFF00, 0xFF, 0xFF000000) => return Ok(Format::B8G8R8A8Unorm),
+            _ => (),
+        }
+    }
+    if flags & DDPF_LUMINANCE != 0 && bits == 8 && a == 0 { return Ok(Format::R8Unorm); }
+    Err(ImageError::Unsupported(format!("DDS pixel format of {} bits with masks {:x} {:x} {:x} {:x}", bits, r, g, b, a)))
+}
+
+fn dxgi_format(dxgi: u32) -> Option<Format> {
+    let format = match dxgi {
+        2 => Format::R32G32B32A32Sfloat,
+        10 => Format::R16G16B16A16Sfloat,
+        28 => Format::R8G8B8A8Unorm,
+        29 => Format::R8G8B8A8Srgb,
+        49 => Format::R8G8Unorm,
+        61 => Format::R8Unorm,
+        71 => Format::BC1_RGBAUnormBlock,
+        72 => Format::BC1_RGBASrgbBlock,
+        74 => Format::BC2UnormBlock,
+        75 => Format::BC2SrgbBlock,
+        77 => Format::BC3UnormBlock,
+        78 => Format::BC3SrgbBlock,
+        80 => Format::BC4UnormBlock,
+        81 => Format::BC4SnormBlock,
+        83 => Format::BC5UnormBlock,
+        84 => Format::BC5SnormBlock,
+        87 => Format::B8G8R8A8Unorm,
+        91 => Format::B8G8R8A8Srgb,
+        95 => Format::BC6HUfloatBlock,
+        96 => Format::BC6HSfloatBlock,
+        98 => Format::BC7UnormBlock,
+        99 => Format::BC7SrgbBlock,
+        _ => return None,
+    };
+    Some(format)
+}
+
+mod test {
+
+    /// Header with `four_cc`, `caps2` and optional DX10 header
+    fn header(w: u32, h: u32, levels: u32, four_cc: &[u8; 4], caps2: u32, dx10: Option<[u32; 5]>) -> Vec<u8> {
+        let mut out = vec![0u8; 128];
+        let mut put = |at: usize, v: u32| out[at .. at + 4].copy_from_slice(&v.to_le_bytes());
+        put(4, 124);
+        put(8, super::DDSD_MIPMAPCOUNT);
+        put(12, h);
+        put(16, w);
+        put(28, levels);
+        put(80, super::DDPF_FOURCC);
+        put(112, caps2);
+        out[.. 4].copy_from_slice(super::MAGIC);
+        out[84 .. 88].copy_from_slice(four_cc);
+        if let Some(dx10) = dx10 {
+            for v in dx10.iter() { out.extend_from_slice(&v.to_le_bytes()); }
+        }
+        out
+    }
+
+    #[test] fn test_dds() {
+        use super::decode;
+        use vulkano::format::Format;
+
+        // 8x4 DXT5 with 3 levels, 2 + 1 + 1 blocks
+        let mut dxt5 = header(8, 4, 3, b"DXT5", 0, None);
+        dxt5.extend((0 .. 4 * 16).map(|i| i as u8));
+        let image = decode(&dxt5).unwrap();
+        assert_eq!(image.format, Format::BC3UnormBlock);
+        assert_eq!(image.levels.iter().map(|l| l.len()).collect::<Vec<_>>(), vec![32, 16, 16]);
+        assert_eq!(image.levels[2][0], 48);
+
+        // BC7 cube from DX10 header, faces are reordered into level major
+        let mut cube = header(4, 4, 2, b"DX10", 0, Some([98, 3, 0x4, 1, 0]));
+        for face in 0 .. 6u8 {
+            cube.extend(vec![face; 16]);
+            cube.extend(vec![face + 100; 16]);
+        }
+        let image = decode(&cube).unwrap();
+        assert!(image.cube);
+        assert_eq!(image.layers, 6);
+        assert_eq!(image.levels[0][16 * 5], 5);
+        assert_eq!(image.levels[1][16 * 2], 102);
+
+        dxt5.truncate(128 + 40);
+        assert!(decode(&dxt5).is_err());
+        assert!(decode(&header(4, 4, 1, b"DX10", 0, Some([300, 3, 0, 1, 0]))).is_err());
+        assert!(decode(&header(4, 4, 1, b"ABCD", 0, None)).is_err());
+    }
+}
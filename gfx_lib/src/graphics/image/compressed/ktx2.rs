@@ -0,0 +1,152 @@
+
+// KTX2 container
+// Header with Vulkan format, index of levels from largest, each level holds all layers and faces
+// Levels may be zlib supercompressed, Basis Universal and zstd are not supported
+
+use vulkano::format::Format;
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+use super::{ CompressedImage, ImageError, level_bytes, mipmap, mip_dims, u32_at, u64_at };
+
+const IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+const HEADER_SIZE: usize = 80; // Up to level index
+const LEVEL_SIZE: usize = 24;
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_ZLIB: u32 = 3;
+
+pub fn is_ktx2(bytes: &[u8]) -> bool { bytes.starts_with(&IDENTIFIER) }
+
+pub fn decode(bytes: &[u8]) -> Result<CompressedImage, ImageError> {
+    if bytes.len() < HEADER_SIZE || !is_ktx2(bytes) {
+        return Err(ImageError::Decode("KTX2 header is truncated".into()));
+    }
+    let vk_format = u32_at(bytes, 12);
+    let width = u32_at(bytes, 20).max(1);
+    let height = u32_at(bytes, 24).max(1);
+    let layers = u32_at(bytes, 32).max(1);
+    let faces = u32_at(bytes, 36);
+    let levels = u32_at(bytes, 40).max(1);
+    let scheme = u32_at(bytes, 44);
+
+    if u32_at(bytes, 28) > 1 { return Err(ImageError::Unsupported("KTX2 3D texture".into())); }
+    if faces != 1 && faces != 6 { return Err(ImageError::Decode(format!("KTX2 with {} faces", faces))); }
+    if vk_format == 0 { return Err(ImageError::Unsupported("KTX2 without format, Basis Universal".into())); }
+    let format = vulkan_format(vk_format)
+        .ok_or_else(|| ImageError::Unsupported(format!("KTX2 Vulkan format {}", vk_format)))?;
+
+    let mut out = vec![];
+    for level in 0 .. levels.min(mipmap::mip_levels([width, height])) as usize {
+        let at = HEADER_SIZE + level * LEVEL_SIZE;
+        if bytes.len() < at + LEVEL_SIZE { return Err(ImageError::Decode("KTX2 level index is truncated".into())); }
+        let (offset, length) = (u64_at(bytes, at) as usize, u64_at(bytes, at + 8) as usize);
+        let data = offset.checked_add(length)
+            .and_then(|end| bytes.get(offset .. end))
+            .ok_or_else(|| ImageError::Decode(format!("KTX2 level {} is truncated", level)))?;
+
+        out.push(match scheme {
+            SUPERCOMPRESSION_NONE => data.to_vec(),
+            SUPERCOMPRESSION_ZLIB => {
+                // Size comes from file, so it is checked against what level can hold before anything is allocated
+                let expected = level_bytes(format, mip_dims([width, height], level as u32))
+                    .and_then(|size| size.checked_mul(layers as usize * faces as usize))
+                    .ok_or_else(|| ImageError::Decode(format!("KTX2 level {} is too large", level)))?;
+                if u64_at(bytes, at + 16) > expected as u64 {
+                    return Err(ImageError::Decode(format!("KTX2 level {} is larger then its dimensions", level)));
+                }
+                let mut level_data = Vec::with_capacity(u64_at(bytes, at + 16) as usize);
+                ZlibDecoder::new(data).take(expected as u64 + 1).read_to_end(&mut level_data)
+                    .map_err(|e| ImageError::Decode(format!("KTX2 level {}: {}", level, e)))?;
+                if level_data.len() > expected {
+                    return Err(ImageError::Decode(format!("KTX2 level {} is larger then its dimensions", level)));
+                }
+                level_data
+            },
+            _ => return Err(ImageError::Unsupported(format!("KTX2 supercompression scheme {}", scheme))),
+        });
+    }
+    CompressedImage::new(format, [width, height], layers * faces, faces == 6, out)
+}
+
+/// Formats KTX2 files may carry, vulkano keeps its own lookup private
+fn vulkan_format(vk_format: u32) -> Option<Format> {
+    let format = match vk_format {
+        9 => Format::R8Unorm,
+        15 => Format::R8Srgb,
+        16 => Format::R8G8Unorm,
+        37 => Format::R8G8B8A8Unorm,
+        43 => Format::R8G8B8A8Srgb,
+        44 => Format::B8G8R8A8Unorm,
+        50 => Format::B8G8R8A8Srgb,
+        97 => Format::R16G16B16A16Sfloat,
+        109 => Format::R32G32B32A32Sfloat,
+        131 => Format::BC1_RGBUnormBlock,
+        132 => Format::BC1_RGBSrgbBlock,
+        133 => Format::BC1_RGBAUnormBlock,
+        134 => Format::BC1_RGBASrgbBlock,
+        135 => Format::BC2UnormBlock,
+        136 => Format::BC2SrgbBlock,
+        137 => Format::BC3UnormBlock,
+        138 => Format::BC3SrgbBlock,
+        139 => Format::BC4UnormBlock,
+        140 => Format::BC4SnormBlock,
+        141 => Format::BC5UnormBlock,
+        142 => Format::BC5SnormBlock,
+        143 => Format::BC6HUfloatBlock,
+        144 => Format::BC6HSfloatBlock,
+        145 => Format::BC7UnormBlock,
+        146 => Format::BC7SrgbBlock,
+        _ => return None,
+    };
+    Some(format)
+}
+
+mod test {
+
+    /// KTX2 with `levels` stored in order of index, data follows index
+    fn ktx2(vk_format: u32, w: u32, h: u32, layers: u32, faces: u32, scheme: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = super::IDENTIFIER.to_vec();
+        for &v in [vk_format, 1, w, h, 0, layers, faces, levels.len() as u32, scheme].iter() {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out.resize(super::HEADER_SIZE, 0);
+        let mut offset = super::HEADER_SIZE + levels.len() * super::LEVEL_SIZE;
+        for l in levels.iter() {
+            for &v in [offset as u64, l.len() as u64, 0].iter() { out.extend_from_slice(&v.to_le_bytes()); }
+            offset += l.len();
+        }
+        for l in levels.iter() { out.extend_from_slice(l); }
+        out
+    }
+
+    #[test] fn test_ktx2() {
+        use super::decode;
+        use vulkano::format::Format;
+        use flate2::{ Compression, write::ZlibEncoder };
+        use std::io::Write;
+
+        // VK_FORMAT_BC1_RGBA_SRGB_BLOCK, 8x8 array of 2 layers
+        let image = decode(&ktx2(134, 8, 8, 2, 1, 0, &[vec![1; 64], vec![2; 16]])).unwrap();
+        assert_eq!(image.format, Format::BC1_RGBASrgbBlock);
+        assert_eq!(image.layers, 2);
+        assert_eq!(image.levels[1], vec![2; 16]);
+
+        // VK_FORMAT_R8G8B8A8_UNORM cube, zlib compressed
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&[7; 4 * 6]).unwrap();
+        let image = decode(&ktx2(37, 1, 1, 0, 6, 3, &[encoder.finish().unwrap()])).unwrap();
+        assert!(image.cube);
+        assert_eq!(image.levels[0], vec![7; 24]);
+
+        // Uncompressed length larger then 1x1 cube can hold is rejected before allocation
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&[7; 4 * 6]).unwrap();
+        let mut file = ktx2(37, 1, 1, 0, 6, 3, &[encoder.finish().unwrap()]);
+        file[super::HEADER_SIZE + 16 .. super::HEADER_SIZE + 24].copy_from_slice(&u64::max_value().to_le_bytes());
+        assert!(decode(&file).is_err());
+
+        assert!(decode(&ktx2(0, 4, 4, 1, 1, 0, &[vec![0; 16]])).is_err());
+        assert!(decode(&ktx2(37, 4, 4, 1, 1, 2, &[vec![0; 64]])).is_err());
+        assert!(decode(&ktx2(37, 4, 4, 1, 1, 0, &[vec![0; 63]])).is_err());
+    }
+}
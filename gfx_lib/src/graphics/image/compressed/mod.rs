@@ -0,0 +1,230 @@
+
+// Block compressed textures
+// KTX2 and DDS containers with BC1 - BC7 or raw texels, mip chains and layers are pre-baked
+// Data is uploaded as is, devices without BC support get image decompressed into RGBA8
+
+use vulkano::{
+    device::{ Device, Queue },
+    format::Format,
+    image::{ ImmutableImage, Dimensions, ImageUsage, ImageLayout, MipmapsCount },
+    buffer::{ CpuAccessibleBuffer, BufferUsage },
+    command_buffer::{ AutoCommandBufferBuilder, CommandBuffer },
+    sync::GpuFuture,
+};
+use std::sync::Arc;
+
+use super::{
+    loader::{ PNGData, ImageError, map_texels },
+    mipmap::{ self, mip_dims, upload_error },
+};
+
+mod bc;
+mod dds;
+mod ktx2;
+
+/// Is `bytes` a KTX2 or DDS file
+pub fn is_container(bytes: &[u8]) -> bool { dds::is_dds(bytes) || ktx2::is_ktx2(bytes) }
+
+/// Parse KTX2 or DDS file
+pub fn decode(bytes: &[u8]) -> Result<CompressedImage, ImageError> {
+    if dds::is_dds(bytes) { dds::decode(bytes) }
+    else if ktx2::is_ktx2(bytes) { ktx2::decode(bytes) }
+    else { Err(ImageError::UnknownFormat) }
+}
+
+/// Bytes of 4x4 block, None for uncompressed formats
+pub fn block_bytes(format: Format) -> Option<usize> {
+    match format {
+        Format::BC1_RGBUnormBlock | Format::BC1_RGBSrgbBlock | Format::BC1_RGBAUnormBlock | Format::BC1_RGBASrgbBlock
+        | Format::BC4UnormBlock | Format::BC4SnormBlock => Some(8),
+        Format::BC2UnormBlock | Format::BC2SrgbBlock | Format::BC3UnormBlock | Format::BC3SrgbBlock
+        | Format::BC5UnormBlock | Format::BC5SnormBlock | Format::BC6HUfloatBlock | Format::BC6HSfloatBlock
+        | Format::BC7UnormBlock | Format::BC7SrgbBlock => Some(16),
+        _ => None,
+    }
+}
+
+/// Bytes of texel for uncompressed formats containers may carry
+fn texel_bytes(format: Format) -> Option<usize> {
+    match format {
+        Format::R8Unorm | Format::R8Srgb => Some(1),
+        Format::R8G8Unorm => Some(2),
+        Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb | Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => Some(4),
+        Format::R16G16B16A16Sfloat => Some(8),
+        Format::R32G32B32A32Sfloat => Some(16),
+        _ => None,
+    }
+}
+
+/// Bytes of one layer of level with `dims`, None if format can't be loaded from container
+pub fn level_bytes(format: Format, dims: [u32; 2]) -> Option<usize> {
+    let (w, h) = (dims[0] as usize, dims[1] as usize);
+    match block_bytes(format) {
+        Some(block) => Some(((w + 3) / 4) * ((h + 3) / 4) * block),
+        None => texel_bytes(format).map(|texel| w * h * texel),
+    }
+}
+
+/// Can device sample `format`, BC formats need `texture_compression_bc` feature
+/// vulkano 0.16 can't query format features, raw formats Vulkan doesn't require to be sampled are decompressed
+pub fn is_supported(device: &Device, format: Format) -> bool {
+    match format {
+        format if block_bytes(format).is_some() => device.enabled_features().texture_compression_bc,
+        Format::R8Srgb => false,
+        format => texel_bytes(format).is_some(),
+    }
+}
+
+/// RGBA8 format texels of `format` are decompressed into
+fn rgba8_format(format: Format) -> Format {
+    match format {
+        Format::BC1_RGBSrgbBlock | Format::BC1_RGBASrgbBlock | Format::BC2SrgbBlock | Format::BC3SrgbBlock
+        | Format::BC7SrgbBlock | Format::R8Srgb | Format::R8G8B8A8Srgb | Format::B8G8R8A8Srgb => Format::R8G8B8A8Srgb,
+        Format::BC4SnormBlock | Format::BC5SnormBlock => Format::R8G8B8A8Snorm,
+        _ => Format::R8G8B8A8Unorm,
+    }
+}
+
+/// Image from KTX2 or DDS container, block compressed or raw, with every level and layer
+pub struct CompressedImage {
+    pub format: Format,
+    pub dimensions: [u32; 2],
+    pub layers: u32, // Array layers, each cube face is a layer
+    pub cube: bool,
+    pub levels: Vec<Vec<u8>>, // Each level holds all of its layers, one after another
+}
+impl CompressedImage {
+    /// Checks size of every level, extra bytes are cut off
+    pub fn new(format: Format, dimensions: [u32; 2], layers: u32, cube: bool, mut levels: Vec<Vec<u8>>) -> Result<Self, ImageError> {
+        if level_bytes(format, dimensions).is_none() { return Err(ImageError::UnsupportedFormat(format)); }
+        if levels.is_empty() || layers == 0 { return Err(ImageError::Decode("no image data".into())); }
+        if cube && (layers % 6 != 0 || dimensions[0] != dimensions[1]) {
+            return Err(ImageError::Decode("cube map needs 6 square faces".into()));
+        }
+
+        levels.truncate(mipmap::mip_levels(dimensions) as usize);
+        for (i, level) in levels.iter_mut().enumerate() {
+            let size = level_bytes(format, mip_dims(dimensions, i as u32)).unwrap() * layers as usize;
+            if level.len() < size { return Err(ImageError::Decode(format!("level {} is truncated", i))); }
+            level.truncate(size);
+        }
+        Ok(Self { format, dimensions, layers, cube, levels })
+    }
+
+    pub fn is_block_compressed(&self) -> bool { block_bytes(self.format).is_some() }
+
+    fn vulkan_dimensions(&self) -> Dimensions {
+        let [width, height] = self.dimensions;
+        match (self.cube, self.layers) {
+            (true, 6) => Dimensions::Cubemap { size: width },
+            (true, layers) => Dimensions::CubemapArray { size: width, array_layers: layers / 6 },
+            (false, 1) => Dimensions::Dim2d { width, height },
+            (false, array_layers) => Dimensions::Dim2dArray { width, height, array_layers },
+        }
+    }
+
+    /// One layer of level as RGBA8, block formats are decompressed
+    pub fn layer_rgba8(&self, level: u32, layer: u32) -> Result<Vec<u8>, ImageError> {
+        let dims = mip_dims(self.dimensions, level);
+        let size = level_bytes(self.format, dims).unwrap();
+        let data = self.levels.get(level as usize)
+            .and_then(|l| l.get(layer as usize * size .. (layer as usize + 1) * size))
+            .ok_or_else(|| ImageError::Decode(format!("no layer {} in level {}", layer, level)))?;
+
+        let out = match self.format {
+            Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb => data.to_vec(),
+            Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => map_texels(data, 4, |p| [p[2], p[1], p[0], p[3]]),
+            Format::R8Unorm | Format::R8Srgb => map_texels(data, 1, |p| [p[0], p[0], p[0], 255]),
+            Format::R8G8Unorm => map_texels(data, 2, |p| [p[0], p[1], 0, 255]),
+            format if block_bytes(format).is_some() => bc::decode(format, dims, data)?,
+            format => return Err(ImageError::UnsupportedFormat(format)),
+        };
+        Ok(out)
+    }
+
+    /// Base level of first layer, used by atlases and other CPU side processing
+    pub fn to_png_data(&self) -> Result<PNGData, ImageError> {
+        Ok(PNGData::new((self.dimensions[0], self.dimensions[1]), self.layer_rgba8(0, 0)?))
+    }
+
+    /// Every level and layer converted into RGBA8
+    pub fn decompress(&self) -> Result<CompressedImage, ImageError> {
+        let mut levels = Vec::with_capacity(self.levels.len());
+        for level in 0 .. self.levels.len() as u32 {
+            let mut data = vec![];
+            for layer in 0 .. self.layers {
+                data.extend(self.layer_rgba8(level, layer)?);
+            }
+            levels.push(data);
+        }
+        CompressedImage::new(rgba8_format(self.format), self.dimensions, self.layers, self.cube, levels)
+    }
+
+    /// Upload every level and layer, image is decompressed first then device can't sample its format
+    pub fn upload(self, queue: Arc<Queue>) -> Result<(Arc<ImmutableImage<Format>>, Box<dyn GpuFuture + Send + Sync>), ImageError> {
+        let image = if is_supported(queue.device(), self.format) { self } else { self.decompress()? };
+
+        let (output, init) = ImmutableImage::uninitialized(
+            queue.device().clone(),
+            image.vulkan_dimensions(),
+            image.format,
+            MipmapsCount::Specific(image.levels.len() as u32),
+            ImageUsage {
+                sampled: true,
+                transfer_destination: true,
+                .. ImageUsage::none()
+            },
+            ImageLayout::ShaderReadOnlyOptimal,
+            vec![queue.family()]
+        ).map_err(upload_error)?;
+        let init = Arc::new(init);
+
+        let mut cb = AutoCommandBufferBuilder::primary_one_time_submit(queue.device().clone(), queue.family()).map_err(upload_error)?;
+        for (level, data) in image.levels.into_iter().enumerate() {
+            let d = mip_dims(image.dimensions, level as u32);
+            let buffer = CpuAccessibleBuffer::from_iter(queue.device().clone(), BufferUsage::transfer_source(), data.into_iter())
+                .map_err(upload_error)?;
+            cb = cb
+                .copy_buffer_to_image_dimensions(buffer, init.clone(), [0, 0, 0], [d[0], d[1], 1], 0, image.layers, level as u32)
+                .map_err(upload_error)?;
+        }
+        let future = cb.build().map_err(upload_error)?
+            .execute(queue).map_err(upload_error)?;
+        Ok((output, Box::new(future)))
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u32_at(bytes, at) as u64 | (u32_at(bytes, at + 4) as u64) << 32
+}
+
+mod test {
+
+    #[test] fn test_compressed_image() {
+        use super::{ CompressedImage, level_bytes };
+        use vulkano::format::Format;
+
+        assert_eq!(level_bytes(Format::BC1_RGBAUnormBlock, [5, 4]), Some(16));
+        assert_eq!(level_bytes(Format::BC7UnormBlock, [1, 1]), Some(16));
+        assert_eq!(level_bytes(Format::R8G8Unorm, [3, 3]), Some(18));
+        assert_eq!(level_bytes(Format::D16Unorm, [1, 1]), None);
+
+        // 2x2 with 2 levels and 2 layers, extra byte is cut off
+        let image = CompressedImage::new(Format::B8G8R8A8Unorm, [2, 2], 2, false, vec![
+            (0 .. 32).collect(),
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
+        ]).unwrap();
+        assert_eq!(image.levels[1].len(), 8);
+        assert_eq!(image.layer_rgba8(1, 1).unwrap(), vec![7, 6, 5, 8]);
+
+        let rgba = image.decompress().unwrap();
+        assert_eq!(rgba.format, Format::R8G8B8A8Unorm);
+        assert_eq!(rgba.levels[0][.. 4], [2, 1, 0, 3]);
+
+        assert!(CompressedImage::new(Format::R8Unorm, [2, 2], 1, false, vec![vec![0; 3]]).is_err());
+        assert!(CompressedImage::new(Format::R8Unorm, [2, 1], 6, true, vec![vec![0; 12]]).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+
+// BMP decoder, "bmp" feature
+// Uncompressed 1, 4, 8, 16, 24 and 32 bit images, with bit field masks for 16 and 32 bit
+
+use super::{ PNGData, ImageError };
+
+const FILE_HEADER_SIZE: usize = 14;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+pub fn decode(bytes: &[u8]) -> Result<PNGData, ImageError> {
+    let truncated = || ImageError::Decode("truncated BMP".into());
+    let u16_at = |i: usize| bytes.get(i .. i + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(truncated);
+    let u32_at = |i: usize| bytes.get(i .. i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(truncated);
+
+    let data_offset = u32_at(10)? as usize;
+    let info_size = u32_at(FILE_HEADER_SIZE)? as usize;
+    let h = FILE_HEADER_SIZE;
+
+    // BITMAPCOREHEADER has 16 bit sizes and 3 byte palette entries
+    let (width, height, bpp, compression, palette_len, palette_entry) = if info_size == 12 {
+        (u16_at(h + 4)? as i32, u16_at(h + 6)? as i16 as i32, u16_at(h + 10)?, BI_RGB, 0, 3)
+    } else if info_size >= 40 {
+        (u32_at(h + 4)? as i32, u32_at(h + 8)? as i32, u16_at(h + 14)?, u32_at(h + 16)?, u32_at(h + 32)? as usize, 4)
+    } else {
+        return Err(ImageError::Unsupported(format!("BMP header size {}", info_size)));
+    };
+    if width <= 0 || height == 0 { return Err(ImageError::Decode("invalid dimensions".into())); }
+    let (width, top_down, height) = (width as usize, height < 0, height.abs() as usize);
+
+    // Channel masks, red, green, blue, alpha
+    let masks = match (compression, bpp) {
+        (BI_RGB, 16) => [0x7C00, 0x03E0, 0x001F, 0],
+        (BI_RGB, 32) => [0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0xFF00_0000],
+        (BI_RGB, _) => [0; 4],
+        (BI_BITFIELDS, 16) | (BI_BITFIELDS, 32) | (BI_ALPHABITFIELDS, 16) | (BI_ALPHABITFIELDS, 32) => {
+            // Masks follow 40 byte header, or are part of V4 and V5 headers
+            let m = h + 40;
+            let alpha = if info_size >= 56 || compression == BI_ALPHABITFIELDS { u32_at(m + 12)? } else { 0 };
+            [u32_at(m)?, u32_at(m + 4)?, u32_at(m + 8)?, alpha]
+        },
+        (c, b) => return Err(ImageError::Unsupported(format!("BMP compression {} with {} bits", c, b))),
+    };
+
+    let palette = if bpp <= 8 {
+        let len = if palette_len == 0 { 1 << bpp } else { palette_len };
+        let start = h + info_size;
+        let data = bytes.get(start .. start + len * palette_entry).ok_or_else(truncated)?;
+        data.chunks_exact(palette_entry).map(|p| [p[2], p[1], p[0], 255]).collect()
+    } else { vec![] };
+
+    let stride = (width * bpp as usize + 31) / 32 * 4;
+    let mut data = Vec::with_capacity(width * height * 4);
+    for y in 0 .. height {
+        let row = if top_down { y } else { height - 1 - y };
+        let start = data_offset + row * stride;
+        let line = bytes.get(start .. start + stride).ok_or_else(truncated)?;
+        for x in 0 .. width {
+            let texel = match bpp {
+                1 | 4 | 8 => {
+                    let bits = bpp as usize;
+                    let index = (line[x * bits / 8] >> (8 - bits - x * bits % 8)) & ((1 << bits) - 1) as u8;
+                    *palette.get(index as usize).ok_or_else(|| ImageError::Decode("palette index out of range".into()))?
+                },
+                16 => masked(u16::from_le_bytes([line[x * 2], line[x * 2 + 1]]) as u32, &masks),
+                24 => [line[x * 3 + 2], line[x * 3 + 1], line[x * 3], 255],
+                32 => masked(u32::from_le_bytes([line[x * 4], line[x * 4 + 1], line[x * 4 + 2], line[x * 4 + 3]]), &masks),
+                b => return Err(ImageError::Unsupported(format!("BMP with {} bits per pixel", b))),
+            };
+            data.extend_from_slice(&texel);
+        }
+    }
+
+    // Plain 32 bit files usually leave alpha byte zeroed
+    if bpp == 32 && compression == BI_RGB && data.chunks_exact(4).all(|p| p[3] == 0) {
+        for p in data.chunks_exact_mut(4) { p[3] = 255; }
+    }
+    Ok(PNGData::new((width as u32, height as u32), data))
+}
+
+/// Extract channels with masks, scaled to 8 bits, missing alpha is opaque
+fn masked(v: u32, masks: &[u32; 4]) -> [u8; 4] {
+    let channel = |mask: u32, default: u8| {
+        if mask == 0 { return default; }
+        let max = mask >> mask.trailing_zeros();
+        (((v & mask) >> mask.trailing_zeros()) as u64 * 255 / max as u64) as u8
+    };
+    [channel(masks[0], 0), channel(masks[1], 0), channel(masks[2], 0), channel(masks[3], 255)]
+}
+
+mod test {
+
+    /// BMP with 40 byte header, `pixels` must be padded rows
+    fn bmp(width: i32, height: i32, bpp: u16, palette: &[u8], pixels: &[u8]) -> Vec<u8> {
+        let offset = 14 + 40 + palette.len() as u32;
+        let mut out = b"BM".to_vec();
+        out.extend_from_slice(&(offset + pixels.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&40u32.to_le_bytes());
+        out.extend_from_slice(&width.to_le_bytes());
+        out.extend_from_slice(&height.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&bpp.to_le_bytes());
+        out.extend_from_slice(&[0; 24]); // No compression, sizes, resolution, full palette
+        out.extend_from_slice(palette);
+        out.extend_from_slice(pixels);
+        out
+    }
+
+    #[test] fn test_bmp() {
+        // 24 bit, bottom up, rows padded to 4 bytes
+        let data = super::decode(&bmp(1, 2, 24, &[], &[255, 0, 0, 0, 0, 0, 255, 0])).unwrap();
+        assert_eq!(data.data, vec![255, 0, 0, 255, 0, 0, 255, 255]);
+
+        // 1 bit palette, top down
+        let palette = [0, 0, 0, 0, 255, 255, 255, 0];
+        let data = super::decode(&bmp(3, -1, 1, &palette, &[0b1010_0000, 0, 0, 0])).unwrap();
+        assert_eq!(data.data, vec![255, 255, 255, 255, 0, 0, 0, 255, 255, 255, 255, 255]);
+
+        // 32 bit with zero alpha is opaque
+        let data = super::decode(&bmp(1, 1, 32, &[], &[1, 2, 3, 0])).unwrap();
+        assert_eq!(data.data, vec![3, 2, 1, 255]);
+
+        assert!(super::decode(&bmp(2, 2, 24, &[], &[0; 4])).is_err());
+        assert_eq!(super::masked(0x7FFF, &[0x7C00, 0x03E0, 0x001F, 0]), [255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,22 @@
+
+// JPEG decoder, "jpeg" feature
+
+use super::{ PNGData, ImageError, expand_to_rgba, map_texels };
+
+pub fn decode(bytes: &[u8]) -> Result<PNGData, ImageError> {
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    let pixels = decoder.decode().map_err(|e| ImageError::Decode(e.to_string()))?;
+    let info = decoder.info().ok_or_else(|| ImageError::Decode("missing frame header".into()))?;
+    let dimensions = (info.width as u32, info.height as u32);
+
+    let data = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => expand_to_rgba(&pixels, 1, 255),
+        jpeg_decoder::PixelFormat::RGB24 => expand_to_rgba(&pixels, 3, 255),
+        jpeg_decoder::PixelFormat::CMYK32 => map_texels(&pixels, 4, |p| {
+            let k = 255 - p[3] as u32;
+            let c = |v: u8| ((255 - v as u32) * k / 255) as u8;
+            [c(p[0]), c(p[1]), c(p[2]), 255]
+        }),
+    };
+    Ok(PNGData::new(dimensions, data))
+}
@@ -0,0 +1,322 @@
+
+
+use vulkano::{
+    device::Queue,
+    format::Format,
+    image::{ ImmutableImage, MipmapsCount },
+    sync::GpuFuture,
+};
+
+use super::{ readback::ReadbackError, mipmap, compressed };
+
+use std::{
+    io::{ Cursor, Write },
+    path::Path,
+    sync::Arc,
+};
+
+mod png;
+#[cfg(feature = "jpeg")]
+mod jpeg;
+#[cfg(feature = "tga")]
+mod tga;
+#[cfg(feature = "bmp")]
+mod bmp;
+
+pub enum ImageError {
+    Decode(String), // File is malformed
+    UnknownFormat, // Bytes are not of any supported image format
+    FeatureDisabled(&'static str), // Format is known, but its cargo feature is off
+    Unsupported(String), // Valid file using unsupported feature of format
+    UnsupportedFormat(Format), // No conversion into this format
+    Upload(String), // Unable to create image
+}
+impl std::error::Error for ImageError {}
+impl std::fmt::Debug for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            ImageError::Decode(e) => write!(f, "Unable to decode image: {}", e),
+            ImageError::UnknownFormat => write!(f, "Unknown image format"),
+            ImageError::FeatureDisabled(feature) => write!(f, "Image format requires \"{}\" feature", feature),
+            ImageError::Unsupported(e) => write!(f, "Unsupported image: {}", e),
+            ImageError::UnsupportedFormat(format) => write!(f, "Conversion into format {:?} is not supported", format),
+            ImageError::Upload(e) => write!(f, "Unable to upload image: {}", e),
+        }
+    }
+}
+impl std::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        (self as &dyn std::fmt::Debug).fmt(f)
+    }
+}
+
+/// Raw Image Loader, PNG, or JPEG, TGA and BMP with their features
+/// KTX2 and DDS are uploaded in format they carry, `format` is not used for them
+pub fn load_image_from_bytes(queue: Arc<Queue>, bytes: Cursor<Vec<u8>>, format: Format)
+    -> Result<(Arc<ImmutableImage<Format>>, Box<dyn GpuFuture + Send + Sync>), ImageError>
+{
+    if compressed::is_container(bytes.get_ref()) { return compressed::decode(bytes.get_ref())?.upload(queue); }
+    load_image_data_from_bytes(bytes)?.load_image(queue, format)
+}
+
+/// Raw Image Data, RGBA8
+/// 16 bit sources also keep full precision copy used for 16 bit and float formats
+pub struct PNGData {
+    pub dimensions: (u32, u32),
+    pub data: Vec<u8>,
+    wide: Option<Vec<u16>>, // RGBA16
+}
+impl PNGData {
+    pub fn new(dimensions: (u32, u32), data: Vec<u8>) -> Self {
+        Self { dimensions, data, wide: None }
+    }
+
+    /// From RGBA16 samples
+    pub fn with_rgba16(dimensions: (u32, u32), wide: Vec<u16>) -> Self {
+        let data = wide.iter().map(|&v| (v >> 8) as u8).collect();
+        Self { dimensions, data, wide: Some(wide) }
+    }
+
+    /// Upload with full mip chain
+    pub fn load_image(self, queue: Arc<Queue>, format: Format)
+        -> Result<(Arc<ImmutableImage<Format>>, Box<dyn GpuFuture + Send + Sync>), ImageError>
+    {
+        self.load_image_with_mips(queue, format, MipmapsCount::Log2)
+    }
+
+    /// Upload with `mips` levels, see `mipmap::upload`
+    pub fn load_image_with_mips(self, queue: Arc<Queue>, format: Format, mips: MipmapsCount)
+        -> Result<(Arc<ImmutableImage<Format>>, Box<dyn GpuFuture + Send + Sync>), ImageError>
+    {
+        mipmap::upload(self, queue, format, mips)
+    }
+
+    /// Next mip level, box filter over footprint of each texel, sRGB color is averaged in linear space
+    /// Odd axes get 3 weighted taps, so last column and row are not lost
+    pub fn downsample(&self, srgb: bool) -> PNGData {
+        let (w, h) = self.dimensions;
+        let (nw, nh) = ((w / 2).max(1), (h / 2).max(1));
+        let max = if self.wide.is_some() { 65535.0 } else { 255.0 };
+        let sample = |x: u32, y: u32, c: usize| {
+            let i = ((y.min(h - 1) * w + x.min(w - 1)) * 4) as usize + c;
+            let v = match &self.wide {
+                Some(wide) => wide[i] as f32,
+                None => self.data[i] as f32,
+            } / max;
+            if srgb && c < 3 { srgb_to_linear(v) } else { v }
+        };
+
+        let (fx, fy) = (footprints(w, nw), footprints(h, nh));
+        let mut out = Vec::with_capacity((nw * nh * 4) as usize);
+        for y in 0 .. nh as usize {
+            for x in 0 .. nw as usize {
+                for c in 0 .. 4 {
+                    let mut v = 0.0;
+                    for &(sy, wy) in fy[y].iter() {
+                        for &(sx, wx) in fx[x].iter() { v += sample(sx, sy, c) * wx * wy; }
+                    }
+                    out.push(if srgb && c < 3 { linear_to_srgb(v) } else { v });
+                }
+            }
+        }
+
+        match self.wide {
+            Some(_) => PNGData::with_rgba16((nw, nh), out.iter().map(|v| (v * 65535.0).round() as u16).collect()),
+            None => PNGData::new((nw, nh), out.iter().map(|v| (v * 255.0).round() as u8).collect()),
+        }
+    }
+
+    /// Texels in `format`, 8 bit formats take bytes as is (so Snorm and Uint get RGBA8 values)
+    pub fn convert(&self, format: Format) -> Result<Vec<u8>, ImageError> {
+        let wide = || match &self.wide {
+            Some(w) => w.clone(),
+            None => self.data.iter().map(|&v| v as u16 * 257).collect(),
+        };
+        let out = match format {
+            Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb | Format::R8G8B8A8Snorm | Format::R8G8B8A8Uint => self.data.clone(),
+            Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb | Format::B8G8R8A8Snorm =>
+                map_texels(&self.data, 4, |p| [p[2], p[1], p[0], p[3]]),
+            // Packed ABGR is RGBA in little endian memory
+            Format::A8B8G8R8UnormPack32 | Format::A8B8G8R8SrgbPack32 => self.data.clone(),
+            Format::R8Unorm | Format::R8Srgb | Format::R8Uint => self.data.chunks_exact(4)
+                .map(|p| p[0])
+                .collect(),
+            Format::R8G8Unorm => {
+                let mut out = Vec::with_capacity(self.data.len() / 2);
+                for p in self.data.chunks_exact(4) { out.extend_from_slice(&p[.. 2]); }
+                out
+            },
+            Format::R5G6B5UnormPack16 => {
+                let mut out = Vec::with_capacity(self.data.len() / 2);
+                let bits = |v: u8, max: u32| (v as u32 * max + 127) / 255;
+                for p in self.data.chunks_exact(4) {
+                    let texel = (bits(p[0], 31) << 11 | bits(p[1], 63) << 5 | bits(p[2], 31)) as u16;
+                    out.extend_from_slice(&texel.to_le_bytes());
+                }
+                out
+            },
+            Format::R16G16B16A16Sfloat => {
+                let wide = wide();
+                let mut out = Vec::with_capacity(wide.len() * 2);
+                for &v in wide.iter() { out.extend_from_slice(&f32_to_half(v as f32 / 65535.0).to_ne_bytes()); }
+                out
+            },
+            Format::R16G16B16A16Unorm | Format::R16G16B16A16Uint => {
+                let wide = wide();
+                let mut out = Vec::with_capacity(wide.len() * 2);
+                for v in wide.iter() { out.extend_from_slice(&v.to_ne_bytes()); }
+                out
+            },
+            Format::R32G32B32A32Sfloat => {
+                let wide = wide();
+                let mut out = Vec::with_capacity(wide.len() * 4);
+                for &v in wide.iter() { out.extend_from_slice(&(v as f32 / 65535.0).to_ne_bytes()); }
+                out
+            },
+            _ => return Err(ImageError::UnsupportedFormat(format)),
+        };
+        Ok(out)
+    }
+
+    /// Encode RGBA8 data as PNG
+    pub fn write_png<W: Write>(&self, w: W) -> Result<(), ::png::EncodingError> {
+        let mut encoder = ::png::Encoder::new(w, self.dimensions.0, self.dimensions.1);
+        encoder.set_color(::png::ColorType::RGBA);
+        encoder.set_depth(::png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.data)
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), ReadbackError> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_png(file).map_err(|e| ReadbackError::Encode(format!("{:?}", e)))
+    }
+}
+
+/// Prepare data for raw image loading, format is detected from bytes
+/// Only base level of KTX2 and DDS is used, decompressed into RGBA8
+pub fn load_image_data_from_bytes(bytes: Cursor<Vec<u8>>) -> Result<PNGData, ImageError> {
+    let bytes = bytes.into_inner();
+    if compressed::is_container(&bytes) { return compressed::decode(&bytes)?.to_png_data(); }
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") { return png::decode(&bytes); }
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) { return decode_jpeg(&bytes); }
+    if bytes.starts_with(b"BM") { return decode_bmp(&bytes); }
+    // TGA has no magic, header is checked instead
+    if looks_like_tga(&bytes) { return decode_tga(&bytes); }
+    Err(ImageError::UnknownFormat)
+}
+
+#[cfg(feature = "jpeg")]
+fn decode_jpeg(bytes: &[u8]) -> Result<PNGData, ImageError> { jpeg::decode(bytes) }
+#[cfg(not(feature = "jpeg"))]
+fn decode_jpeg(_: &[u8]) -> Result<PNGData, ImageError> { Err(ImageError::FeatureDisabled("jpeg")) }
+
+#[cfg(feature = "bmp")]
+fn decode_bmp(bytes: &[u8]) -> Result<PNGData, ImageError> { bmp::decode(bytes) }
+#[cfg(not(feature = "bmp"))]
+fn decode_bmp(_: &[u8]) -> Result<PNGData, ImageError> { Err(ImageError::FeatureDisabled("bmp")) }
+
+#[cfg(feature = "tga")]
+fn decode_tga(bytes: &[u8]) -> Result<PNGData, ImageError> { tga::decode(bytes) }
+#[cfg(not(feature = "tga"))]
+fn decode_tga(_: &[u8]) -> Result<PNGData, ImageError> { Err(ImageError::FeatureDisabled("tga")) }
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Known color map, image type and pixel depth in TGA header
+fn looks_like_tga(bytes: &[u8]) -> bool {
+    bytes.len() >= 18
+        && bytes[1] <= 1
+        && [1, 2, 3, 9, 10, 11].contains(&bytes[2])
+        && [8, 15, 16, 24, 32].contains(&bytes[16])
+}
+
+/// Map every `size` bytes of `data` into RGBA8 pixel, output is allocated once
+pub(super) fn map_texels<F>(data: &[u8], size: usize, f: F) -> Vec<u8>
+    where F: Fn(&[u8]) -> [u8; 4]
+{
+    let mut out = Vec::with_capacity(data.len() / size * 4);
+    for p in data.chunks_exact(size) { out.extend_from_slice(&f(p)); }
+    out
+}
+
+/// Source texels and their weights for each texel of axis shrunk from `size` to `new_size`
+fn footprints(size: u32, new_size: u32) -> Vec<Vec<(u32, f32)>> {
+    let scale = size as f32 / new_size as f32;
+    (0 .. new_size).map(|x| {
+        let (start, end) = (x as f32 * scale, (x + 1) as f32 * scale);
+        (start.floor() as u32 .. (end.ceil() as u32).min(size))
+            .map(|i| (i, (end.min(i as f32 + 1.0) - start.max(i as f32)) / scale))
+            .filter(|&(_, weight)| weight > 0.0)
+            .collect()
+    }).collect()
+}
+
+/// Half float of `v`, rounded to nearest
+fn f32_to_half(v: f32) -> u16 {
+    let bits = v.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    let mantissa = bits & 0x7F_FFFF;
+    if v.is_nan() { return sign | 0x7E00; }
+    if exp >= 0x1F { return sign | 0x7C00; }
+    if exp <= 0 {
+        // Subnormal or zero
+        if exp < -10 { return sign; }
+        let shift = (14 - exp) as u32;
+        return sign | (((mantissa | 0x80_0000) + (1 << (shift - 1))) >> shift) as u16;
+    }
+    // Carry of rounding goes into exponent, which is still correct
+    sign | (((exp as u32) << 10) + ((mantissa + 0x1000) >> 13)) as u16
+}
+
+/// Expand samples with 1 (grey), 2 (grey, alpha), 3 (RGB) or 4 channels into RGBA
+fn expand_to_rgba<T: Copy>(samples: &[T], channels: usize, opaque: T) -> Vec<T> {
+    let mut out = Vec::with_capacity(samples.len() / channels * 4);
+    for p in samples.chunks_exact(channels) {
+        match channels {
+            1 => out.extend_from_slice(&[p[0], p[0], p[0], opaque]),
+            2 => out.extend_from_slice(&[p[0], p[0], p[0], p[1]]),
+            3 => out.extend_from_slice(&[p[0], p[1], p[2], opaque]),
+            _ => out.extend_from_slice(&p[.. 4]),
+        }
+    }
+    out
+}
+
+mod test {
+
+    #[test] fn test_convert_formats() {
+        use super::PNGData;
+        use vulkano::format::Format;
+
+        let data = PNGData::new((1, 1), vec![10, 20, 30, 255]);
+        assert_eq!(data.convert(Format::B8G8R8A8Srgb).unwrap(), vec![30, 20, 10, 255]);
+        assert_eq!(data.convert(Format::R8Unorm).unwrap(), vec![10]);
+        assert_eq!(data.convert(Format::R16G16B16A16Unorm).unwrap().len(), 8);
+        assert!(data.convert(Format::D16Unorm).is_err());
+
+        let wide = PNGData::with_rgba16((1, 1), vec![0x1234, 0, 0xFFFF, 0xFFFF]);
+        assert_eq!(wide.data, vec![0x12, 0, 0xFF, 0xFF]);
+        assert_eq!(&wide.convert(Format::R16G16B16A16Unorm).unwrap()[.. 2], &0x1234u16.to_ne_bytes());
+
+        assert_eq!(data.convert(Format::R8G8Unorm).unwrap(), vec![10, 20]);
+        assert_eq!(data.convert(Format::A8B8G8R8UnormPack32).unwrap(), vec![10, 20, 30, 255]);
+        assert_eq!(PNGData::new((1, 1), vec![255, 0, 255, 255]).convert(Format::R5G6B5UnormPack16).unwrap(), 0xF81Fu16.to_le_bytes().to_vec());
+        assert_eq!(super::f32_to_half(1.0), 0x3C00);
+        assert_eq!(super::f32_to_half(0.5), 0x3800);
+        assert_eq!(super::f32_to_half(0.0), 0);
+        assert_eq!(super::f32_to_half(1.0 / 65535.0), 0x0100);
+
+        assert_eq!(super::expand_to_rgba(&[1u8, 2], 2, 255), vec![1, 1, 1, 2]);
+        assert!(match super::load_image_data_from_bytes(std::io::Cursor::new(vec![0; 4])) {
+            Err(super::ImageError::UnknownFormat) => true,
+            _ => false,
+        });
+    }
+}
@@ -0,0 +1,70 @@
+
+// PNG decoder
+// Palette and low bit depths are expanded by decoder, 16 bit samples are kept in full precision
+
+use super::{ PNGData, ImageError, expand_to_rgba };
+
+pub fn decode(bytes: &[u8]) -> Result<PNGData, ImageError> {
+    let mut decoder = ::png::Decoder::new(bytes);
+    decoder.set_transformations(::png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info().map_err(|e| ImageError::Decode(e.to_string()))?;
+
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer).map_err(|e| ImageError::Decode(e.to_string()))?;
+
+    let channels = match info.color_type {
+        ::png::ColorType::Grayscale => 1,
+        ::png::ColorType::GrayscaleAlpha => 2,
+        ::png::ColorType::RGB => 3,
+        ::png::ColorType::RGBA => 4,
+        ::png::ColorType::Indexed => return Err(ImageError::Unsupported("palette was not expanded".into())),
+    };
+    let dimensions = (info.width, info.height);
+
+    match info.bit_depth {
+        ::png::BitDepth::Eight => Ok(PNGData::new(dimensions, expand_to_rgba(&buffer, channels, 255))),
+        ::png::BitDepth::Sixteen => {
+            let samples: Vec<u16> = buffer.chunks_exact(2).map(|s| u16::from_be_bytes([s[0], s[1]])).collect();
+            Ok(PNGData::with_rgba16(dimensions, expand_to_rgba(&samples, channels, 0xFFFF)))
+        },
+        depth => Err(ImageError::Unsupported(format!("bit depth {:?} was not expanded", depth))),
+    }
+}
+
+mod test {
+
+    /// Encode `data` with color type and depth
+    fn encode(color: ::png::ColorType, depth: ::png::BitDepth, w: u32, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        {
+            let mut encoder = ::png::Encoder::new(&mut out, w, 1);
+            encoder.set_color(color);
+            encoder.set_depth(depth);
+            encoder.write_header().unwrap().write_image_data(data).unwrap();
+        }
+        out
+    }
+
+    #[test] fn test_png_color_types() {
+        use ::png::{ ColorType, BitDepth };
+
+        let grey = super::decode(&encode(ColorType::Grayscale, BitDepth::Eight, 2, &[0, 200])).unwrap();
+        assert_eq!(grey.data, vec![0, 0, 0, 255, 200, 200, 200, 255]);
+
+        let grey_alpha = super::decode(&encode(ColorType::GrayscaleAlpha, BitDepth::Eight, 1, &[50, 100])).unwrap();
+        assert_eq!(grey_alpha.data, vec![50, 50, 50, 100]);
+
+        let rgb = super::decode(&encode(ColorType::RGB, BitDepth::Eight, 1, &[1, 2, 3])).unwrap();
+        assert_eq!(rgb.data, vec![1, 2, 3, 255]);
+
+        // Two pixels of 2 bit grey in first bits of byte
+        let low = super::decode(&encode(ColorType::Grayscale, BitDepth::Two, 2, &[0b1101_0000])).unwrap();
+        assert_eq!(low.data, vec![255, 255, 255, 255, 85, 85, 85, 255]);
+
+        let wide = super::decode(&encode(ColorType::RGBA, BitDepth::Sixteen, 1, &[0x12, 0x34, 0, 0, 0xFF, 0xFF, 0x80, 0])).unwrap();
+        assert_eq!(wide.data, vec![0x12, 0, 0xFF, 0x80]);
+        assert_eq!(wide.wide, Some(vec![0x1234, 0, 0xFFFF, 0x8000]));
+
+        assert!(super::decode(&[0x89, b'P', b'N', b'G']).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+
+// TGA decoder, "tga" feature
+// Uncompressed and RLE true color, grey and color mapped images
+
+use super::{ PNGData, ImageError };
+
+const HEADER_SIZE: usize = 18;
+
+pub fn decode(bytes: &[u8]) -> Result<PNGData, ImageError> {
+    if bytes.len() < HEADER_SIZE { return Err(ImageError::Decode("truncated header".into())); }
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]) as usize;
+
+    let id_len = bytes[0] as usize;
+    let has_map = bytes[1] == 1;
+    let image_type = bytes[2];
+    let (map_first, map_len, map_depth) = (u16_at(3), u16_at(5), bytes[7]);
+    let (width, height, depth, descriptor) = (u16_at(12), u16_at(14), bytes[16], bytes[17]);
+
+    let rle = image_type >= 9;
+    let kind = image_type & 0x7;
+    if ![1, 2, 3].contains(&kind) { return Err(ImageError::Unsupported(format!("TGA image type {}", image_type))); }
+
+    // Color map, stored as RGBA
+    let mut offset = HEADER_SIZE + id_len;
+    let mut map = vec![];
+    if has_map {
+        let entry = (map_depth as usize + 7) / 8;
+        let data = bytes.get(offset .. offset + map_len * entry).ok_or_else(|| ImageError::Decode("truncated color map".into()))?;
+        map = data.chunks_exact(entry).map(|p| pixel(p, map_depth)).collect::<Result<Vec<_>, _>>()?;
+        offset += map_len * entry;
+    }
+
+    // Raw pixel bytes, with RLE packets expanded
+    let pixel_size = (depth as usize + 7) / 8;
+    let count = width * height;
+    let raw = if rle {
+        let mut raw = Vec::with_capacity(count * pixel_size);
+        let mut i = offset;
+        while raw.len() < count * pixel_size {
+            let header = *bytes.get(i).ok_or_else(|| ImageError::Decode("truncated RLE data".into()))?;
+            let run = (header & 0x7F) as usize + 1;
+            i += 1;
+            if header & 0x80 != 0 {
+                let p = bytes.get(i .. i + pixel_size).ok_or_else(|| ImageError::Decode("truncated RLE data".into()))?;
+                for _ in 0 .. run { raw.extend_from_slice(p); }
+                i += pixel_size;
+            } else {
+                let p = bytes.get(i .. i + run * pixel_size).ok_or_else(|| ImageError::Decode("truncated RLE data".into()))?;
+                raw.extend_from_slice(p);
+                i += run * pixel_size;
+            }
+        }
+        raw.truncate(count * pixel_size);
+        raw
+    } else {
+        bytes.get(offset .. offset + count * pixel_size).ok_or_else(|| ImageError::Decode("truncated pixel data".into()))?.to_vec()
+    };
+
+    let pixels = raw.chunks_exact(pixel_size)
+        .map(|p| match kind {
+            1 => {
+                let index = if pixel_size == 1 { p[0] as usize } else { u16::from_le_bytes([p[0], p[1]]) as usize };
+                index.checked_sub(map_first).and_then(|i| map.get(i).cloned())
+                    .ok_or_else(|| ImageError::Decode("color map index out of range".into()))
+            },
+            3 => Ok([p[0], p[0], p[0], if pixel_size > 1 { p[1] } else { 255 }]),
+            _ => pixel(p, depth),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Rows are stored bottom up unless descriptor says otherwise
+    let (right_to_left, top_down) = (descriptor & 0x10 != 0, descriptor & 0x20 != 0);
+    let mut data = Vec::with_capacity(count * 4);
+    for y in 0 .. height {
+        let row = if top_down { y } else { height - 1 - y };
+        for x in 0 .. width {
+            let col = if right_to_left { width - 1 - x } else { x };
+            data.extend_from_slice(&pixels[row * width + col]);
+        }
+    }
+    Ok(PNGData::new((width as u32, height as u32), data))
+}
+
+/// True color pixel (BGR order) into RGBA
+fn pixel(p: &[u8], depth: u8) -> Result<[u8; 4], ImageError> {
+    match depth {
+        15 | 16 => {
+            let v = u16::from_le_bytes([p[0], p[1]]);
+            let c = |shift: u16| (((v >> shift) & 0x1F) as u32 * 255 / 31) as u8;
+            Ok([c(10), c(5), c(0), 255])
+        },
+        24 => Ok([p[2], p[1], p[0], 255]),
+        32 => Ok([p[2], p[1], p[0], p[3]]),
+        d => Err(ImageError::Unsupported(format!("TGA pixel depth {}", d))),
+    }
+}
+
+mod test {
+
+    #[test] fn test_tga() {
+        // 2x2 RLE true color, bottom up: run of 2 red, then 2 raw pixels
+        let mut tga = vec![0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 24, 0];
+        tga.extend_from_slice(&[0x81, 0, 0, 255]);
+        tga.extend_from_slice(&[0x01, 255, 0, 0, 0, 255, 0]);
+        assert!(super::super::looks_like_tga(&tga));
+
+        let data = super::decode(&tga).unwrap();
+        assert_eq!(data.dimensions, (2, 2));
+        assert_eq!(data.data, vec![
+            0, 0, 255, 255,  0, 255, 0, 255, // Top row is last in file
+            255, 0, 0, 255,  255, 0, 0, 255,
+        ]);
+
+        tga.truncate(20);
+        assert!(super::decode(&tga).is_err());
+    }
+}
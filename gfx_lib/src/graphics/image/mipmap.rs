@@ -0,0 +1,216 @@
+
+// Mipmap generation
+// Levels are blitted on GPU then format is known to support linear blits, otherwise box filtered on CPU and uploaded
+// Blits go through scratch image, vulkano can't use one image as both source and destination of a command
+
+use vulkano::{
+    device::{ Device, Queue },
+    format::Format,
+    image::{ ImageAccess, ImmutableImage, StorageImage, Dimensions, ImageUsage, ImageLayout, MipmapsCount },
+    buffer::{ CpuAccessibleBuffer, BufferUsage },
+    command_buffer::{ AutoCommandBufferBuilder, CommandBuffer },
+    sampler::Filter,
+    sync::GpuFuture,
+};
+use std::sync::Arc;
+
+use super::loader::{ PNGData, ImageError };
+
+/// Number of levels in full chain
+pub fn mip_levels(dims: [u32; 2]) -> u32 {
+    32 - dims[0].max(dims[1]).max(1).leading_zeros()
+}
+
+/// Dimensions of `level`, never smaller then 1
+pub fn mip_dims(dims: [u32; 2], level: u32) -> [u32; 2] {
+    [(dims[0] >> level).max(1), (dims[1] >> level).max(1)]
+}
+
+/// Levels `mips` gives for image of `dims`, clamped to full chain
+pub fn level_count(mips: MipmapsCount, dims: [u32; 2]) -> u32 {
+    match mips {
+        MipmapsCount::One => 1,
+        MipmapsCount::Log2 => mip_levels(dims),
+        MipmapsCount::Specific(n) => n.max(1).min(mip_levels(dims)),
+    }
+}
+
+/// sRGB formats are filtered in linear space
+pub fn is_srgb(format: Format) -> bool {
+    match format {
+        Format::R8G8B8A8Srgb | Format::B8G8R8A8Srgb | Format::R8Srgb => true,
+        _ => false,
+    }
+}
+
+/// Can levels of `format` be generated with linear blits
+/// vulkano 0.16 can't query format features, so only formats Vulkan requires to blit with linear filter are listed
+pub fn supports_blit(format: Format) -> bool {
+    match format {
+        Format::R8Unorm | Format::R8G8Unorm
+        | Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb
+        | Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb
+        | Format::A8B8G8R8UnormPack32 | Format::A8B8G8R8SrgbPack32
+        | Format::R5G6B5UnormPack16 | Format::R16G16B16A16Sfloat => true,
+        _ => false,
+    }
+}
+
+/// Can levels of `format` be filtered on CPU, image is read back and uploaded through RGBA8
+pub fn supports_cpu(format: Format) -> bool {
+    super::readback::is_supported(format) && PNGData::new((1, 1), vec![0; 4]).convert(format).is_ok()
+}
+
+/// Texels of levels `0 .. levels` in `format`, filtered on CPU
+pub fn cpu_chain(base: PNGData, format: Format, levels: u32) -> Result<Vec<Vec<u8>>, ImageError> {
+    let srgb = is_srgb(format);
+    let mut out = vec![base.convert(format)?];
+    let mut level = base;
+    for _ in 1 .. levels {
+        level = level.downsample(srgb);
+        out.push(level.convert(format)?);
+    }
+    Ok(out)
+}
+
+/// Upload `data` into new image with `mips` levels
+pub fn upload(data: PNGData, queue: Arc<Queue>, format: Format, mips: MipmapsCount)
+    -> Result<(Arc<ImmutableImage<Format>>, Box<dyn GpuFuture + Send + Sync>), ImageError>
+{
+    let dims = [data.dimensions.0, data.dimensions.1];
+    let levels = level_count(mips, dims);
+    let gpu = levels > 1 && supports_blit(format);
+
+    // Only base level is uploaded then rest is blitted
+    let texels = if gpu { vec![data.convert(format)?] } else { cpu_chain(data, format, levels)? };
+
+    let (image, init) = ImmutableImage::uninitialized(
+        queue.device().clone(),
+        Dimensions::Dim2d { width: dims[0], height: dims[1] },
+        format,
+        MipmapsCount::Specific(levels),
+        ImageUsage {
+            sampled: true,
+            transfer_source: gpu,
+            transfer_destination: true,
+            .. ImageUsage::none()
+        },
+        ImageLayout::ShaderReadOnlyOptimal,
+        vec![queue.family()]
+    ).map_err(upload_error)?;
+    let init = Arc::new(init);
+
+    let cb = AutoCommandBufferBuilder::primary_one_time_submit(queue.device().clone(), queue.family()).map_err(upload_error)?;
+    let mut cb = record_uploads(cb, queue.device(), init.clone(), dims, texels, 0)?;
+    if gpu { cb = record_blit_chain(cb, &queue, init, dims, levels, format)?; }
+    let future = cb.build().map_err(upload_error)?
+        .execute(queue).map_err(upload_error)?;
+    Ok((image, Box::new(future)))
+}
+
+/// Record copies of `texels` into levels starting at `first_level`
+pub fn record_uploads<I>(mut builder: AutoCommandBufferBuilder, device: &Arc<Device>, image: I, dims: [u32; 2], texels: Vec<Vec<u8>>, first_level: u32)
+    -> Result<AutoCommandBufferBuilder, ImageError>
+    where I: ImageAccess + Clone + Send + Sync + 'static
+{
+    for (i, level) in texels.into_iter().enumerate() {
+        let level_index = first_level + i as u32;
+        let d = mip_dims(dims, level_index);
+        let buffer = CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::transfer_source(), level.into_iter())
+            .map_err(upload_error)?;
+        builder = builder
+            .copy_buffer_to_image_dimensions(buffer, image.clone(), [0, 0, 0], [d[0], d[1], 1], 0, 1, level_index)
+            .map_err(upload_error)?;
+    }
+    Ok(builder)
+}
+
+/// Record blits filling levels `1 .. levels` of `image` from level 0
+/// Image needs transfer source and destination usage
+pub fn record_blit_chain<I>(mut builder: AutoCommandBufferBuilder, queue: &Arc<Queue>, image: I, dims: [u32; 2], levels: u32, format: Format)
+    -> Result<AutoCommandBufferBuilder, ImageError>
+    where I: ImageAccess + Clone + Send + Sync + 'static
+{
+    if levels <= 1 { return Ok(builder); }
+
+    let half = mip_dims(dims, 1);
+    let scratch = StorageImage::with_usage(
+        queue.device().clone(),
+        Dimensions::Dim2d { width: half[0], height: half[1] },
+        format,
+        ImageUsage {
+            transfer_source: true,
+            transfer_destination: true,
+            .. ImageUsage::none()
+        },
+        vec![queue.family()]
+    ).map_err(upload_error)?;
+
+    for level in 1 .. levels {
+        let (src, dst) = (mip_dims(dims, level - 1), mip_dims(dims, level));
+        builder = builder
+            .blit_image(
+                image.clone(), [0, 0, 0], [src[0] as i32, src[1] as i32, 1], 0, level - 1,
+                scratch.clone(), [0, 0, 0], [dst[0] as i32, dst[1] as i32, 1], 0, 0,
+                1, Filter::Linear
+            ).map_err(upload_error)?
+            .copy_image(
+                scratch.clone(), [0, 0, 0], 0, 0,
+                image.clone(), [0, 0, 0], 0, level,
+                [dst[0], dst[1], 1], 1
+            ).map_err(upload_error)?;
+    }
+    Ok(builder)
+}
+
+pub(super) fn upload_error<E: std::fmt::Debug>(e: E) -> ImageError { ImageError::Upload(format!("{:?}", e)) }
+
+mod test {
+
+    #[test] fn test_mip_chain() {
+        use super::{ mip_levels, mip_dims, level_count, cpu_chain, supports_blit };
+        use crate::graphics::image::PNGData;
+        use vulkano::{ format::Format, image::MipmapsCount };
+
+        assert_eq!(mip_levels([1, 1]), 1);
+        assert_eq!(mip_levels([512, 512]), 10);
+        assert_eq!(mip_levels([1024, 3]), 11);
+        assert_eq!(mip_dims([1024, 3], 2), [256, 1]);
+        assert_eq!(level_count(MipmapsCount::Specific(40), [4, 4]), 3);
+        assert_eq!(level_count(MipmapsCount::One, [4, 4]), 1);
+
+        assert!(supports_blit(Format::R8G8B8A8Srgb));
+        assert!(!supports_blit(Format::R32G32B32A32Sfloat));
+        assert!(!supports_blit(Format::BC1_RGBAUnormBlock));
+
+        // Blit path still uploads base level through `convert`
+        for &format in [
+            Format::R8Unorm, Format::R8G8Unorm, Format::R8G8B8A8Unorm, Format::R8G8B8A8Srgb,
+            Format::B8G8R8A8Unorm, Format::B8G8R8A8Srgb, Format::A8B8G8R8UnormPack32, Format::A8B8G8R8SrgbPack32,
+            Format::R5G6B5UnormPack16, Format::R16G16B16A16Sfloat,
+        ].iter() {
+            assert!(supports_blit(format));
+            assert!(PNGData::new((1, 1), vec![0; 4]).convert(format).is_ok(), "{:?}", format);
+        }
+
+        // 2x2 black and white checker averages into grey
+        let base = PNGData::new((2, 2), vec![
+            0, 0, 0, 255,  255, 255, 255, 255,
+            255, 255, 255, 255,  0, 0, 0, 255,
+        ]);
+        let linear = cpu_chain(PNGData::new(base.dimensions, base.data.clone()), Format::R8G8B8A8Unorm, 2).unwrap();
+        assert_eq!(linear.len(), 2);
+        assert_eq!(linear[1], vec![128, 128, 128, 255]);
+
+        // In sRGB half intensity is brighter then half of encoded value
+        let srgb = cpu_chain(base, Format::R8G8B8A8Srgb, 2).unwrap();
+        assert_eq!(srgb[1][3], 255);
+        assert!(srgb[1][0] > 180 && srgb[1][0] < 195);
+
+        // Odd sizes keep last column, white stripe in the middle of 3x1 spreads over whole texel
+        let odd = cpu_chain(PNGData::new((3, 1), vec![
+            0, 0, 0, 255,  255, 255, 255, 255,  0, 0, 0, 255,
+        ]), Format::R8G8B8A8Unorm, 2).unwrap();
+        assert_eq!(odd[1], vec![85, 85, 85, 255]);
+    }
+}
@@ -17,21 +17,29 @@ use std::{
 };
 use vulkano::pipeline::GraphicsPipelineAbstract;
 use vulkano::sync::GpuFuture;
-use crate::sync::Loader;
+use crate::sync::{ Loader, LoaderError };
 
 mod loader;
 pub mod sampler_pool;
 pub mod atlas;
+pub mod readback;
+pub mod mipmap;
+pub mod compressed;
+
+pub use loader::{ PNGData, ImageError };
+pub use compressed::CompressedImage;
 
 #[derive(Debug)]
 pub enum AccessError {
     NotReadyError,
+    LoadFailed(LoaderError), // Image loader failed or was cancelled
     Panic
 }
 impl error::Error for AccessError {
     fn description(&self) -> &str {
         match self {
             AccessError::NotReadyError => "ImageContent not yet loaded. Use image.is_ready()",
+            AccessError::LoadFailed(_) => "ImageContent failed to load",
             AccessError::Panic => "Panic!",
         }
     }
@@ -64,19 +72,22 @@ impl Clone for ImageContent {
 /// Create new instance, check and access image
 impl ImageContent {
 
-    /// Load image info
-    pub fn load_image_data(bytes: Cursor<Vec<u8>>) -> loader::PNGData {
-        loader::load_png_data_from_bytes(bytes)
+    /// Load image info, decoded into RGBA
+    pub fn load_image_data(bytes: Cursor<Vec<u8>>) -> Result<loader::PNGData, ImageError> {
+        loader::load_image_data_from_bytes(bytes)
     }
 
-    /// Load image, from file bytes
-    pub fn load_image(queue: Arc<Queue>, bytes: Cursor<Vec<u8>>, format: Format) -> Loader<Arc<dyn ImageViewAccess + Send + Sync>> {
-        let (a, b) = loader::load_png_image_from_bytes(queue, bytes, format);
-        Loader::with_gpu_future(a, b)
+    /// Load image, from file bytes, converted into `format`
+    pub fn load_image(queue: Arc<Queue>, bytes: Cursor<Vec<u8>>, format: Format) -> Result<Loader<Arc<dyn ImageViewAccess + Send + Sync>>, ImageError> {
+        let (a, b) = loader::load_image_from_bytes(queue, bytes, format)?;
+        Ok(Loader::with_gpu_future(a, b))
     }
 
+    /// Image which can't be decoded becomes failed loader, see `access`
+    /// KTX2 and DDS keep their own format and levels
     pub fn new_with_bytes(queue: Arc<Queue>, sampler: Arc<Sampler>, bytes: Cursor<Vec<u8>>, format: Format) -> Self {
-        let image_loader = ImageContent::load_image(queue, bytes, format);
+        let image_loader = ImageContent::load_image(queue, bytes, format)
+            .unwrap_or_else(Loader::with_error);
 
         Self {
             sampler,
@@ -85,22 +96,25 @@ impl ImageContent {
         }
     }
     pub fn is_ready(&self) -> bool { self.image.is_ready() }
+    /// Image could not be decoded or uploaded, see `access` for error
+    pub fn is_failed(&self) -> bool { self.image.is_finished() && !self.image.is_ready() }
     pub fn recreate_uniform(&mut self) { self.uniform = None; }
 
     /// Return sampler
     pub fn get_sampler(&self) -> Arc<Sampler> { self.sampler.clone() }
     /// Return image with no check if it is ready to use
     pub fn get_image(&self) -> Arc<dyn ImageViewAccess + Send + Sync> { self.image.get_ref().clone() }
+    /// Image which may be still uploading, without waiting for it
+    pub fn snapshot(&self) -> Option<Arc<dyn ImageViewAccess + Send + Sync>> { self.image.snapshot() }
 
-    /// Wait for image to load
-    pub fn flush(&self) { while !self.is_ready() {} }
+    /// Wait for image to load, failed load is reported by `access`
+    pub fn flush(&self) { let _ = self.image.wait(None); }
 
+    /// Blocks until image is loaded
     pub fn access(&self) -> Result<Arc<dyn ImageViewAccess + Send + Sync>, AccessError> {
-//        if !self.is_ready() {
-//            Err(AccessError::NotReadyError)
-//        } else {
-            Ok(self.get_image())
-//        }
+        self.image.try_get()
+            .map(|image| image.clone())
+            .map_err(AccessError::LoadFailed)
     }
 }
 /// Content Access Interface for ImageContent
@@ -0,0 +1,232 @@
+
+// Render target readback
+// Copies image into host visible buffer and converts texels into RGBA8 for PNG export
+
+use vulkano::{
+    device::Queue,
+    format::Format,
+    image::ImageAccess,
+    buffer::{ CpuAccessibleBuffer, BufferUsage },
+    command_buffer::{ AutoCommandBufferBuilder, CommandBuffer },
+    sync::{ self, GpuFuture },
+};
+use std::{
+    path::Path,
+    sync::Arc,
+};
+
+use super::loader::{ PNGData, map_texels };
+
+pub enum ReadbackError {
+    UnsupportedFormat(Format), // No conversion into RGBA8 for this format
+    Allocation(String), // Unable to allocate host buffer
+    Copy(String), // Unable to record or submit copy command
+    NotReady, // Buffer still in use by GPU
+    Encode(String), // PNG encoder error
+    Io(std::io::Error),
+}
+impl std::error::Error for ReadbackError {}
+impl std::fmt::Debug for ReadbackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            ReadbackError::UnsupportedFormat(format) => write!(f, "Readback of format {:?} is not supported", format),
+            ReadbackError::Allocation(e) => write!(f, "Unable to allocate readback buffer: {}", e),
+            ReadbackError::Copy(e) => write!(f, "Unable to copy image: {}", e),
+            ReadbackError::NotReady => write!(f, "Readback buffer still in use by GPU"),
+            ReadbackError::Encode(e) => write!(f, "Unable to encode PNG: {}", e),
+            ReadbackError::Io(e) => write!(f, "IO Error: {:?}", e),
+        }
+    }
+}
+impl std::fmt::Display for ReadbackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        (self as &dyn std::fmt::Debug).fmt(f)
+    }
+}
+impl From<std::io::Error> for ReadbackError {
+    fn from(e: std::io::Error) -> Self { ReadbackError::Io(e) }
+}
+
+/// Copy of image texels in source format
+pub struct ImageReadback {
+    pub dimensions: (u32, u32),
+    pub format: Format,
+    pub data: Vec<u8>,
+}
+impl ImageReadback {
+    /// Convert texels into RGBA8, sRGB formats stay encoded, linear ones are written as is
+    pub fn to_rgba8(&self) -> Result<PNGData, ReadbackError> {
+        let data = convert_to_rgba8(self.format, &self.data)?;
+        Ok(PNGData::new(self.dimensions, data))
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), ReadbackError> {
+        self.to_rgba8()?.save_png(path)
+    }
+}
+
+/// Copy recorded, but not yet finished on GPU
+pub struct PendingReadback {
+    buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    dimensions: (u32, u32),
+    format: Format,
+}
+impl PendingReadback {
+    /// Read buffer, GPU future returned with this readback must be finished
+    pub fn read(&self) -> Result<ImageReadback, ReadbackError> {
+        let lock = self.buffer.read().map_err(|_| ReadbackError::NotReady)?;
+        Ok(ImageReadback {
+            dimensions: self.dimensions,
+            format: self.format,
+            data: lock.to_vec(),
+        })
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) { self.dimensions }
+    pub fn format(&self) -> Format { self.format }
+}
+
+/// Record copy of `image` after `future`
+/// Returned future must be flushed and finished before `PendingReadback::read`
+pub fn readback_after<F, I>(future: F, queue: Arc<Queue>, image: I) -> Result<(Box<dyn GpuFuture>, PendingReadback), ReadbackError>
+    where
+        F: GpuFuture + 'static,
+        I: ImageAccess + Send + Sync + 'static,
+{
+    let format = image.format();
+    let texel_size = match format.size() {
+        Some(size) if is_supported(format) => size,
+        _ => return Err(ReadbackError::UnsupportedFormat(format)),
+    };
+    let dims = ImageAccess::dimensions(&image).width_height();
+
+    let buffer = CpuAccessibleBuffer::from_iter(
+        queue.device().clone(),
+        BufferUsage::transfer_destination(),
+        (0 .. dims[0] as usize * dims[1] as usize * texel_size).map(|_| 0u8)
+    ).map_err(|e| ReadbackError::Allocation(format!("{:?}", e)))?;
+
+    let cb = AutoCommandBufferBuilder::primary_one_time_submit(queue.device().clone(), queue.family())
+        .map_err(|e| ReadbackError::Copy(format!("{:?}", e)))?
+        .copy_image_to_buffer(image, buffer.clone())
+        .map_err(|e| ReadbackError::Copy(format!("{:?}", e)))?
+        .build()
+        .map_err(|e| ReadbackError::Copy(format!("{:?}", e)))?;
+
+    let future = future.then_execute(queue, cb)
+        .map_err(|e| ReadbackError::Copy(format!("{:?}", e)))?;
+
+    Ok((Box::new(future), PendingReadback {
+        buffer,
+        dimensions: (dims[0], dims[1]),
+        format,
+    }))
+}
+
+/// Copy `image` into CPU memory, blocks until copy is finished
+pub fn read_image<I>(queue: Arc<Queue>, image: I) -> Result<ImageReadback, ReadbackError>
+    where I: ImageAccess + Send + Sync + 'static,
+{
+    let now = sync::now(queue.device().clone());
+    let (future, pending) = readback_after(now, queue, image)?;
+    future.then_signal_fence_and_flush()
+        .map_err(|e| ReadbackError::Copy(format!("{:?}", e)))?
+        .wait(None)
+        .map_err(|e| ReadbackError::Copy(format!("{:?}", e)))?;
+    pending.read()
+}
+
+/// Is there conversion from `format` into RGBA8
+pub fn is_supported(format: Format) -> bool {
+    match format {
+        Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb |
+        Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb |
+        Format::R8Unorm |
+        Format::A2B10G10R10UnormPack32 |
+        Format::R16G16B16A16Sfloat |
+        Format::D16Unorm => true,
+        _ => false,
+    }
+}
+
+/// Convert raw texels of `format` into RGBA8
+pub fn convert_to_rgba8(format: Format, data: &[u8]) -> Result<Vec<u8>, ReadbackError> {
+    let out = match format {
+        Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb => data.to_vec(),
+        Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => map_texels(data, 4, |p| [p[2], p[1], p[0], p[3]]),
+        Format::R8Unorm => map_texels(data, 1, |p| [p[0], p[0], p[0], 255]),
+        Format::A2B10G10R10UnormPack32 => map_texels(data, 4, |p| {
+            let v = u32::from_le_bytes([p[0], p[1], p[2], p[3]]);
+            let c = |shift: u32| unorm_to_u8(((v >> shift) & 0x3FF) as f32 / 1023.0);
+            [c(0), c(10), c(20), unorm_to_u8((v >> 30) as f32 / 3.0)]
+        }),
+        Format::R16G16B16A16Sfloat => map_texels(data, 8, |p| {
+            let c = |i: usize| unorm_to_u8(half_to_f32(u16::from_le_bytes([p[i * 2], p[i * 2 + 1]])));
+            [c(0), c(1), c(2), c(3)]
+        }),
+        Format::D16Unorm => map_texels(data, 2, |p| {
+            let v = unorm_to_u8(u16::from_le_bytes([p[0], p[1]]) as f32 / 65535.0);
+            [v, v, v, 255]
+        }),
+        _ => return Err(ReadbackError::UnsupportedFormat(format)),
+    };
+    Ok(out)
+}
+
+/// Clamp into [0, 1] and scale into byte
+fn unorm_to_u8(v: f32) -> u8 {
+    if v.is_nan() { return 0; }
+    (v.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+/// IEEE 754 half into f32
+fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = ((h >> 10) & 0x1F) as i32;
+    let mantissa = (h & 0x3FF) as f32;
+    match exp {
+        0 => sign * mantissa * 2f32.powi(-24), // Subnormal
+        0x1F => if mantissa == 0.0 { sign * std::f32::INFINITY } else { std::f32::NAN },
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exp - 15),
+    }
+}
+
+mod test {
+
+    #[test] fn test_half_to_f32() {
+        use super::half_to_f32;
+
+        assert_eq!(half_to_f32(0x0000), 0.0);
+        assert_eq!(half_to_f32(0x3C00), 1.0);
+        assert_eq!(half_to_f32(0x3800), 0.5);
+        assert_eq!(half_to_f32(0xC000), -2.0);
+        assert!(half_to_f32(0x7C00).is_infinite());
+        assert!(half_to_f32(0x7E00).is_nan());
+    }
+
+    #[test] fn test_convert_formats() {
+        use super::convert_to_rgba8;
+        use vulkano::format::Format;
+
+        // BGRA swizzle
+        let bgra = convert_to_rgba8(Format::B8G8R8A8Srgb, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(bgra, vec![3, 2, 1, 4]);
+
+        // A2B10G10R10: r = 1023, g = 0, b = 511, a = 3
+        let packed: u32 = 1023 | (0 << 10) | (511 << 20) | (3 << 30);
+        let rgb10 = convert_to_rgba8(Format::A2B10G10R10UnormPack32, &packed.to_le_bytes()).unwrap();
+        assert_eq!(rgb10, vec![255, 0, 127, 255]);
+
+        // Half floats: 1.0, 0.5, -1.0 (clamped), 2.0 (clamped)
+        let mut half = vec![];
+        for h in [0x3C00u16, 0x3800, 0xBC00, 0x4000].iter() { half.extend_from_slice(&h.to_le_bytes()); }
+        let f16 = convert_to_rgba8(Format::R16G16B16A16Sfloat, &half).unwrap();
+        assert_eq!(f16, vec![255, 128, 0, 255]);
+
+        // Depth into grayscale
+        let depth = convert_to_rgba8(Format::D16Unorm, &0xFFFFu16.to_le_bytes()).unwrap();
+        assert_eq!(depth, vec![255, 255, 255, 255]);
+
+        assert!(convert_to_rgba8(Format::R32Sfloat, &[0; 4]).is_err());
+    }
+}
@@ -1,22 +1,17 @@
-
 use std::{
-    mem::transmute,
     sync::Arc,
     collections::HashMap,
     hash::{Hash, Hasher},
 };
 use vulkano::{
     device::Device,
-    sampler::{ SamplerAddressMode, MipmapMode, Filter, Sampler, SamplerCreationError },
+    sampler::{ SamplerAddressMode, BorderColor, MipmapMode, Filter, Sampler, SamplerCreationError },
+    pipeline::depth_stencil::Compare,
 };
 
-fn hash_f32<H: Hasher>(f: f32, state: &mut H) {
-//    #![feature(float_to_from_bytes)]
-    let bytes = f.to_be_bytes();
-    bytes.hash(state);
-}
-
-#[derive(Clone)]
+/// Sampler description, key of `SamplerPool`
+/// Start from preset and change it with `set_*` methods
+#[derive(Clone, Debug)]
 pub struct SamplerParams {
     mag_filter: Filter,
     min_filter: Filter,
@@ -24,10 +19,12 @@ pub struct SamplerParams {
     u_addr: SamplerAddressMode,
     v_addr: SamplerAddressMode,
     w_addr: SamplerAddressMode,
+    border_color: BorderColor, // Used by every axis with ClampToBorder
     mip_lod_bias: f32,
-    max_anisotropy: f32,
+    max_anisotropy: f32, // 1.0 disables anisotropic filtering, pool clamps it to device limit
     min_lod: f32,
     max_lod: f32,
+    compare: Option<Compare>, // Depth compare, for shadow maps
 }
 impl SamplerParams {
     pub fn simple_repeat() -> Self { Self {
@@ -37,50 +34,101 @@ impl SamplerParams {
         u_addr: SamplerAddressMode::Repeat,
         v_addr: SamplerAddressMode::Repeat,
         w_addr: SamplerAddressMode::Repeat,
+        border_color: BorderColor::FloatTransparentBlack,
         mip_lod_bias: 0.0,
         max_anisotropy: 1.0,
         min_lod: 0.0,
         max_lod: 1_000.0,
+        compare: None,
     } }
 
-    fn generate_sampler(&self, device: Arc<Device>) -> Result<Arc<Sampler>, SamplerCreationError> {
-        Sampler::new(device,
-            self.mag_filter, self.min_filter, self.mipmap_mode, self.u_addr, self.v_addr,
-            self.w_addr, self.mip_lod_bias, self.max_anisotropy, self.min_lod, self.max_lod
+    /// Linear filtering, clamped to edge
+    pub fn simple_clamp() -> Self { Self::simple_repeat().set_address_mode(SamplerAddressMode::ClampToEdge) }
+
+    /// Depth compare with base level of shadow map, everything outside of map passes
+    pub fn shadow(compare: Compare) -> Self {
+        Self::simple_repeat()
+            .set_mipmap_mode(MipmapMode::Nearest)
+            .set_address_mode(SamplerAddressMode::ClampToBorder(BorderColor::FloatOpaqueWhite))
+            .set_lod(0.0, 0.0)
+            .set_compare(Some(compare))
+    }
+
+    pub fn set_filter(self, filter: Filter) -> Self { self.set_filters(filter, filter) }
+    pub fn set_filters(mut self, mag: Filter, min: Filter) -> Self { self.mag_filter = mag; self.min_filter = min; self }
+    pub fn set_mipmap_mode(mut self, mode: MipmapMode) -> Self { self.mipmap_mode = mode; self }
+
+    /// Same address mode for every axis
+    pub fn set_address_mode(self, mode: SamplerAddressMode) -> Self { self.set_address_modes(mode, mode, mode) }
+    /// Border color of ClampToBorder mode becomes border color of all axes, see `set_border_color`
+    pub fn set_address_modes(mut self, u: SamplerAddressMode, v: SamplerAddressMode, w: SamplerAddressMode) -> Self {
+        for mode in [u, v, w].iter() {
+            if let SamplerAddressMode::ClampToBorder(color) = mode { self.border_color = *color; }
+        }
+        self.u_addr = u;
+        self.v_addr = v;
+        self.w_addr = w;
+        self
+    }
+    /// Vulkan allows one border color per sampler, it is used by every axis with ClampToBorder
+    pub fn set_border_color(mut self, color: BorderColor) -> Self { self.border_color = color; self }
+
+    pub fn set_lod(mut self, min: f32, max: f32) -> Self { self.min_lod = min; self.max_lod = max; self }
+    pub fn set_lod_bias(mut self, bias: f32) -> Self { self.mip_lod_bias = bias; self }
+    /// Max anisotropy, 1.0 disables it
+    pub fn set_anisotropy(mut self, max: f32) -> Self { self.max_anisotropy = max.max(1.0); self }
+    pub fn set_compare(mut self, compare: Option<Compare>) -> Self { self.compare = compare; self }
+
+    /// Mode with border color of these params
+    fn address(&self, mode: SamplerAddressMode) -> SamplerAddressMode {
+        match mode {
+            SamplerAddressMode::ClampToBorder(_) => SamplerAddressMode::ClampToBorder(self.border_color),
+            mode => mode,
+        }
+    }
+
+    /// Anisotropy clamped to device limit, or disabled without `sampler_anisotropy` feature
+    fn clamped(mut self, device: &Device) -> Self {
+        self.max_anisotropy = if device.enabled_features().sampler_anisotropy {
+            self.max_anisotropy.max(1.0).min(device.physical_device().limits().max_sampler_anisotropy())
+        } else { 1.0 };
+        self
+    }
+
+    /// Compared and hashed fields, floats by their bits so `Eq` and `Hash` agree
+    fn key(&self) -> impl Eq + Hash {
+        (
+            self.mag_filter, self.min_filter, self.mipmap_mode,
+            self.address(self.u_addr), self.address(self.v_addr), self.address(self.w_addr),
+            self.mip_lod_bias.to_bits(), self.max_anisotropy.to_bits(),
+            self.min_lod.to_bits(), self.max_lod.to_bits(),
+            self.compare.map(|c| c as u32),
         )
     }
+
+    fn generate_sampler(&self, device: Arc<Device>) -> Result<Arc<Sampler>, SamplerCreationError> {
+        let (u, v, w) = (self.address(self.u_addr), self.address(self.v_addr), self.address(self.w_addr));
+        match self.compare {
+            Some(compare) => Sampler::compare(device,
+                self.mag_filter, self.min_filter, self.mipmap_mode, u, v, w,
+                self.mip_lod_bias, self.max_anisotropy, self.min_lod, self.max_lod, compare
+            ),
+            None => Sampler::new(device,
+                self.mag_filter, self.min_filter, self.mipmap_mode, u, v, w,
+                self.mip_lod_bias, self.max_anisotropy, self.min_lod, self.max_lod
+            ),
+        }
+    }
 }
 impl Hash for SamplerParams {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.mag_filter.hash(state);
-        self.min_filter.hash(state);
-        self.mipmap_mode.hash(state);
-        self.u_addr.hash(state);
-        self.v_addr.hash(state);
-        self.w_addr.hash(state);
-        hash_f32(self.mip_lod_bias, state);
-        hash_f32(self.max_anisotropy, state);
-        hash_f32(self.min_lod, state);
-        hash_f32(self.max_lod, state);
-    }
+    fn hash<H: Hasher>(&self, state: &mut H) { self.key().hash(state); }
 }
 impl PartialEq for SamplerParams {
-    fn eq(&self, o: &SamplerParams) -> bool {
-        if self.mag_filter.ne(&o.mag_filter) { false }
-        else if self.min_filter.ne(&o.min_filter) { false }
-        else if self.mipmap_mode.ne(&o.mipmap_mode) { false }
-        else if self.u_addr.ne(&o.u_addr) { false }
-        else if self.v_addr.ne(&o.w_addr) { false }
-        else if self.w_addr.ne(&o.v_addr) { false }
-        else if self.mip_lod_bias.ne(&o.mip_lod_bias) { false }
-        else if self.max_anisotropy.ne(&o.max_anisotropy) { false }
-        else if self.min_lod.ne(&o.min_lod) { false }
-        else if self.max_lod.ne(&o.max_lod) { false }
-        else { true }
-    }
+    fn eq(&self, o: &SamplerParams) -> bool { self.key() == o.key() }
 }
 impl Eq for SamplerParams {}
 
+/// Creates one sampler per unique `SamplerParams`
 pub struct SamplerPool {
     device: Arc<Device>,
     samplers: HashMap<SamplerParams, Arc<Sampler>>,
@@ -92,14 +140,53 @@ impl SamplerPool {
             samplers: HashMap::new(),
         }
     }
-    pub fn with_params(&mut self, params: SamplerParams) -> Arc<Sampler> {
+    /// Sampler for params, failed creation is not cached
+    pub fn with_params(&mut self, params: SamplerParams) -> Result<Arc<Sampler>, SamplerCreationError> {
+        let params = params.clamped(&self.device);
         if let Some(sampler) = self.samplers.get(&params) {
-            sampler.clone()
+            Ok(sampler.clone())
         } else {
-            // TODO: Propagate error in a wrapper
-            let sampler = params.generate_sampler(self.device.clone()).unwrap();
+            let sampler = params.generate_sampler(self.device.clone())?;
             self.samplers.insert(params, sampler.clone());
-            sampler
+            Ok(sampler)
         }
     }
 }
+
+mod test {
+
+    #[test] fn test_params_eq_hash() {
+        use super::SamplerParams;
+        use vulkano::sampler::{ SamplerAddressMode, BorderColor };
+        use std::{ collections::hash_map::DefaultHasher, hash::{ Hash, Hasher } };
+
+        let hash = |p: &SamplerParams| {
+            let mut h = DefaultHasher::new();
+            p.hash(&mut h);
+            h.finish()
+        };
+
+        let a = SamplerParams::simple_repeat()
+            .set_address_modes(SamplerAddressMode::Repeat, SamplerAddressMode::ClampToEdge, SamplerAddressMode::Repeat);
+        let b = SamplerParams::simple_repeat()
+            .set_address_modes(SamplerAddressMode::Repeat, SamplerAddressMode::Repeat, SamplerAddressMode::ClampToEdge);
+        assert!(a != b);
+        assert!(a == a.clone());
+        assert_eq!(hash(&a), hash(&a.clone()));
+
+        // Border color is shared by all axes, order of calls doesn't matter
+        let border = SamplerAddressMode::ClampToBorder(BorderColor::FloatOpaqueWhite);
+        let c = SamplerParams::simple_repeat().set_address_mode(border);
+        let d = SamplerParams::simple_repeat()
+            .set_border_color(BorderColor::FloatOpaqueWhite)
+            .set_address_modes(border, SamplerAddressMode::ClampToBorder(BorderColor::IntOpaqueBlack), border)
+            .set_border_color(BorderColor::FloatOpaqueWhite);
+        assert!(c == d);
+        assert_eq!(hash(&c), hash(&d));
+
+        // NaN is equal to itself, so pool can find it again
+        let nan = SamplerParams::simple_repeat().set_lod_bias(std::f32::NAN);
+        assert!(nan == nan.clone());
+        assert!(SamplerParams::simple_repeat().set_anisotropy(0.0) == SamplerParams::simple_repeat());
+    }
+}
@@ -5,15 +5,14 @@ use std::{
 };
 
 use vulkano::{
+    device::Device,
     sampler::Sampler,
     image::ImageViewAccess,
-    command_buffer::{AutoCommandBufferBuilder, CommandBuffer},
     descriptor::{
         DescriptorSet, PipelineLayoutAbstract,
         descriptor_set::PersistentDescriptorSet,
     },
     buffer::{ CpuAccessibleBuffer, BufferUsage, BufferAccess },
-    sync::GpuFuture,
 };
 
 use crate::{
@@ -22,7 +21,7 @@ use crate::{
     graphics::object::ScreenInstance
 };
 use vulkano::buffer::BufferSlice;
-use crate::utils::with;
+use crate::utils::FrameRing;
 
 /// Errors
 pub enum Render2DCacheError {
@@ -55,36 +54,35 @@ impl std::fmt::Display for Render2DCacheError {
 }
 
 /// Instance buffer, witch can be created and modified only when necessary. Simple instance caching
+/// Instances are kept on CPU and uploaded on next `access` after change,
+/// into buffer GPU is not reading, one per frame in flight
 pub struct Render2DCache {
-    // Associated Instance buffer
-    buffer: Arc<CpuAccessibleBuffer<[ScreenInstance]>>,
+    device: Arc<Device>,
+    // Instances, written into buffer then dirty
+    instances: Vec<ScreenInstance>,
+    dirty: bool,
+    // Associated Instance buffers, per frame in flight
+    buffers: FrameRing<Arc<CpuAccessibleBuffer<[ScreenInstance]>>>,
     // Currently used buffer slice, slice is non if no data in buffer
     slice: Option<Arc<dyn BufferAccess + Send + Sync>>,
     // Associated Image uniform
     image: Option<(Arc<dyn ImageViewAccess + Send + Sync>, Arc<Sampler>)>,
     // Uniform with texture
     uniform_set: Option<Arc<dyn DescriptorSet + Send + Sync>>,
-    // Current position in buffer
-    pos: usize,
     // Buffer Capacity
     capacity: usize,
 }
 impl Render2DCache {
     pub fn new(frame: &mut Frame, capacity: usize) -> Self {
-        let buffer = unsafe {
-            CpuAccessibleBuffer::uninitialized_array(
-                frame.queue.device().clone(),
-                capacity,
-                BufferUsage::all(),
-            ).unwrap()
-        };
-
+        let device = frame.queue.device().clone();
         Self {
-            buffer,
+            buffers: FrameRing::new(Self::create_buffer(&device, capacity)),
+            device,
+            instances: Vec::with_capacity(capacity),
+            dirty: false,
             slice: None,
             image: None,
             uniform_set: None,
-            pos: 0,
             capacity,
         }
     }
@@ -92,8 +90,27 @@ impl Render2DCache {
     // ##############
     // Locals
 
-    fn update_slice(&mut self) {
-        let slice = BufferSlice::from_typed_buffer_access(self.buffer.clone()).slice(0 .. self.pos).unwrap();
+    fn create_buffer(device: &Arc<Device>, capacity: usize) -> Arc<CpuAccessibleBuffer<[ScreenInstance]>> {
+        unsafe {
+            CpuAccessibleBuffer::uninitialized_array(device.clone(), capacity.max(1), BufferUsage::all()).unwrap()
+        }
+    }
+
+    /// Write instances into buffer GPU is not using, buffers still read by frames in flight are left as is
+    fn upload(&mut self) {
+        self.dirty = false;
+        if self.instances.is_empty() {
+            self.slice = None;
+            return;
+        }
+
+        let (device, capacity) = (&self.device, self.capacity);
+        let buffer = self.buffers.next(|b| b.write().is_ok(), || Self::create_buffer(device, capacity)).clone();
+        {
+            let mut writer = buffer.write().unwrap();
+            writer[.. self.instances.len()].copy_from_slice(&self.instances);
+        }
+        let slice = BufferSlice::from_typed_buffer_access(buffer).slice(0 .. self.instances.len()).unwrap();
         self.slice = Some(Arc::new(slice));
     }
 
@@ -101,7 +118,7 @@ impl Render2DCache {
     // Globals
 
     /// Return current position in buffer (length of used buffer), less or equal to capacity
-    #[inline] pub fn position(&self) -> usize { self.pos }
+    #[inline] pub fn position(&self) -> usize { self.instances.len() }
     #[inline] pub fn capacity(&self) -> usize { self.capacity }
 
     /// Set new image descriptor, ezpz
@@ -110,52 +127,31 @@ impl Render2DCache {
         self.uniform_set = None; // Reset uniform to update it with new texture
     }
 
-    /// Reallocate buffer with different capacity. Slow, blocking.
+    /// Reallocate buffers with different capacity, old buffers are dropped after GPU is done with them
     /// If not all data can fit in new capacity, excess will be trimmed
-    /// All errors currently just panic
     pub fn realloc(&mut self, frame: &mut Frame, new_capacity: usize) {
         // noice
         if new_capacity == self.capacity { return }
 
-        let new_buffer = unsafe {
-            CpuAccessibleBuffer::uninitialized_array(
-                frame.queue.device().clone(),
-                new_capacity,
-                BufferUsage::all()
-            ).unwrap()
-        };
-
-        let mut cbb = AutoCommandBufferBuilder::primary_one_time_submit(
-            frame.queue.device().clone(), frame.queue.family()
-        ).unwrap()
-            .copy_buffer(self.buffer.clone(), new_buffer.clone()).unwrap()
-            .build().unwrap();
-        cbb.execute(frame.queue.clone()).unwrap().flush().unwrap();
-
-        // New buffer is ready, drop old arc
-        self.buffer = new_buffer;
+        self.buffers = FrameRing::new(Self::create_buffer(frame.queue.device(), new_capacity));
         self.capacity = new_capacity;
-        self.pos = self.pos.min(self.capacity);
-        self.update_slice();
+        self.instances.truncate(new_capacity);
+        self.dirty = true;
     }
 
     /// Appends new instance to end of the buffer and increase position
     pub fn append(&mut self, instance: ScreenInstance) -> Result<(), Render2DCacheError> {
-        if self.pos >= self.capacity { return Err(Render2DCacheError::CapacityOverflow(self.capacity)) }
-
-        // Writer dropped right after setting new value, releasing self
-        self.buffer.write().unwrap()[self.pos] = instance;
-
-        self.pos += 1;
-        self.update_slice();
+        if self.instances.len() >= self.capacity { return Err(Render2DCacheError::CapacityOverflow(self.capacity)) }
+        self.instances.push(instance);
+        self.dirty = true;
         Ok(())
     }
 
     /// Replace instance at position with different
     pub fn set(&mut self, pos: usize, instance: ScreenInstance) -> Result<(), Render2DCacheError> {
-        if pos >= self.pos { return Err(Render2DCacheError::OutOfBounds(pos, self.pos)) }
-        let mut writer = self.buffer.write().unwrap();
-        writer[pos] = instance;
+        if pos >= self.instances.len() { return Err(Render2DCacheError::OutOfBounds(pos, self.instances.len())) }
+        self.instances[pos] = instance;
+        self.dirty = true;
         Ok(())
     }
 
@@ -168,6 +164,7 @@ impl Render2DCache {
     {
 
         assert!(self.image.is_some(), "Texture not set!");
+        if self.dirty { self.upload(); }
 
         if self.uniform_set.is_none() {
             let img= self.image.as_ref().unwrap();
@@ -4,17 +4,21 @@ use crate::graphics::object::{
     ScreenVertex, ScreenInstance
 };
 use crate::graphics::image::ImageContentAbstract;
+use crate::utils::FrameRing;
+use crate::main_processor::profiler::RenderStats;
 
 use vulkano::{
     device::{ Queue },
-    format::Format,
+    format::{ Format, ClearValue },
 
-    image::{ ImageAccess, ImageViewAccess },
+    image::{ ImageAccess, ImageViewAccess, ImmutableImage, Dimensions },
+    sampler::Sampler,
 
     buffer:: { BufferAccess, ImmutableBuffer, BufferUsage, CpuAccessibleBuffer },
 
     descriptor::{
-        DescriptorSet
+        DescriptorSet,
+        descriptor_set::PersistentDescriptorSet,
     },
 
     framebuffer::{ RenderPassAbstract, Subpass, FramebufferBuilder, Framebuffer },
@@ -28,6 +32,7 @@ use cgmath::{Matrix4, SquareMatrix, vec3};
 use vulkano::buffer::BufferSlice;
 
 pub mod cache;
+pub mod pixel_font;
 
 use cache::{ Render2DCache, Render2DCacheError };
 
@@ -128,37 +133,72 @@ pub struct Renderer2D {
 
     ibo_start: usize,
     ibo_data: Vec<ScreenInstance>,
-    ibo: Arc<CpuAccessibleBuffer<[ScreenInstance]>>,
+    ibo: FrameRing<Arc<CpuAccessibleBuffer<[ScreenInstance]>>>, // Instance buffer per frame in flight
+    ibo_capacity: usize,
 
     pub clear_color: [f32; 4],
+    clear: bool, // Clear output on begin, otherwise draw over it
 
     viewport_mat: Matrix4<f32>,
 
+    white: Option<Arc<dyn DescriptorSet + Send + Sync>>, // Untextured draws, created on first use
+    stats: RenderStats,
+
     // Render CBB
     cbb: Option<AutoCommandBufferBuilder>,
 }
 impl Renderer2D {
+    /// Renderer clearing output with `clear_color` on `begin`
     pub fn new(queue: Arc<Queue>, output_format: Format, capacity: usize) -> Self {
+        Self::with_clear(queue, output_format, capacity, true)
+    }
+
+    /// Renderer drawing over existing content of output, for overlays
+    pub fn new_overlay(queue: Arc<Queue>, output_format: Format, capacity: usize) -> Self {
+        Self::with_clear(queue, output_format, capacity, false)
+    }
+
+    fn with_clear(queue: Arc<Queue>, output_format: Format, capacity: usize, clear: bool) -> Self {
 //        assert!(capacity >= 1000, "Recommended capacity at least 1000 instances");
         let default_capacity = capacity;
 
-        let render_pass = Arc::new(vulkano::ordered_passes_renderpass!(queue.device().clone(),
-            attachments: {
-                image: {
-                    load: Clear,
-                    store: Store,
-                    format: output_format,
-                    samples: 1,
-                }
-            },
-            passes: [
-                {
-                    color: [image],
-                    depth_stencil: {},
-                    input: []
-                }
-            ]
-        ).unwrap()) as Arc<dyn RenderPassAbstract + Send + Sync>;
+        let render_pass = if clear {
+            Arc::new(vulkano::ordered_passes_renderpass!(queue.device().clone(),
+                attachments: {
+                    image: {
+                        load: Clear,
+                        store: Store,
+                        format: output_format,
+                        samples: 1,
+                    }
+                },
+                passes: [
+                    {
+                        color: [image],
+                        depth_stencil: {},
+                        input: []
+                    }
+                ]
+            ).unwrap()) as Arc<dyn RenderPassAbstract + Send + Sync>
+        } else {
+            Arc::new(vulkano::ordered_passes_renderpass!(queue.device().clone(),
+                attachments: {
+                    image: {
+                        load: Load,
+                        store: Store,
+                        format: output_format,
+                        samples: 1,
+                    }
+                },
+                passes: [
+                    {
+                        color: [image],
+                        depth_stencil: {},
+                        input: []
+                    }
+                ]
+            ).unwrap()) as Arc<dyn RenderPassAbstract + Send + Sync>
+        };
 
         let flat_pipeline = {
             let vs = vs::Shader::load(queue.device().clone())
@@ -193,9 +233,7 @@ impl Renderer2D {
             a
         };
 
-        let ibo = unsafe {
-            CpuAccessibleBuffer::uninitialized_array(queue.device().clone(), default_capacity, BufferUsage::all()).unwrap()
-        };
+        let ibo = FrameRing::new(Self::create_ibo(&queue, default_capacity));
 
         Self {
             queue,
@@ -204,17 +242,51 @@ impl Renderer2D {
             dyn_state: DynamicState::none(),
 
             clear_color: [1.0; 4],
+            clear,
             viewport_mat: Matrix4::identity(),
 
             vbo,
             ibo_start: 0,
             ibo_data: Vec::with_capacity(default_capacity),
             ibo,
+            ibo_capacity: default_capacity,
+
+            white: None,
+            stats: RenderStats::default(),
 
             cbb: None,
         }
     }
 
+    fn create_ibo(queue: &Arc<Queue>, capacity: usize) -> Arc<CpuAccessibleBuffer<[ScreenInstance]>> {
+        unsafe {
+            CpuAccessibleBuffer::uninitialized_array(queue.device().clone(), capacity, BufferUsage::all()).unwrap()
+        }
+    }
+
+    /// Uniform with 1x1 white texture, instances drawn with it are filled with their color
+    pub fn white_uniform(&mut self) -> Arc<dyn DescriptorSet + Send + Sync> {
+        if self.white.is_none() {
+            let (image, future) = ImmutableImage::from_iter(
+                [255u8; 4].iter().cloned(),
+                Dimensions::Dim2d { width: 1, height: 1 },
+                Format::R8G8B8A8Unorm,
+                self.queue.clone()
+            ).unwrap();
+            future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
+
+            let sampler = Sampler::simple_repeat_linear_no_mipmap(self.queue.device().clone());
+            self.white = Some(Arc::new(PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+                .add_sampled_image(image, sampler).unwrap()
+                .build().unwrap()
+            ));
+        }
+        self.white.clone().unwrap()
+    }
+
+    /// Stats of current or last finished pass
+    pub fn stats(&self) -> RenderStats { self.stats }
+
     /// Set ortho-window viewport
     /// LeftTop: [0.0, 0.0]
     /// RightBottom: [w, h]
@@ -253,13 +325,17 @@ impl Renderer2D {
             .build().unwrap()
         );
 
+        // Instance buffer of previous frames may be still in use by GPU
+        let (queue, capacity) = (&self.queue, self.ibo_capacity);
+        self.ibo.next(|ibo| ibo.write().is_ok(), || Self::create_ibo(queue, capacity));
+
         self.ibo_start = 0;
         self.ibo_data.clear();
+        self.stats = RenderStats::default();
+        let clear_value = if self.clear { self.clear_color.into() } else { ClearValue::None };
         self.cbb = Some(
             AutoCommandBufferBuilder::primary_one_time_submit(self.queue.device().clone(), self.queue.family()).unwrap()
-                .begin_render_pass(fb.clone(), false, vec![
-                    self.clear_color.into()
-                ]).unwrap()
+                .begin_render_pass(fb.clone(), false, vec![clear_value]).unwrap()
         );
     }
 
@@ -282,6 +358,8 @@ impl Renderer2D {
         let (buff, tex) = cache.access(&self.pipeline, 0);
         // skip drawing if buffer slice is non (no instances)
         if buff.is_some() {
+            self.stats.draw_calls += 1;
+            self.stats.triangles += 2 * buff.as_ref().unwrap().size() as u64 / std::mem::size_of::<ScreenInstance>() as u64;
             self.cbb = Some(self.cbb.take().unwrap()
                 .draw(self.pipeline.clone(), &self.dyn_state,
                       vec![self.vbo.clone(), buff.unwrap()], (tex), vs::ty::PushData {
@@ -299,16 +377,18 @@ impl Renderer2D {
         if self.ibo_data.len() > 0 {
             // write ibo_data to buffer
             {
-                let mut writer = self.ibo.write().unwrap();
+                let mut writer = self.ibo.current().write().unwrap();
                 for i in 0 .. self.ibo_data.len() {
                     writer[i + self.ibo_start] = self.ibo_data[i];
                 }
             }
 
-            let slice = BufferSlice::from_typed_buffer_access(self.ibo.clone())
+            let slice = BufferSlice::from_typed_buffer_access(self.ibo.current().clone())
                 .slice(self.ibo_start .. self.ibo_start+self.ibo_data.len())
                 .unwrap();
             self.ibo_start += self.ibo_data.len();
+            self.stats.draw_calls += 1;
+            self.stats.triangles += 2 * self.ibo_data.len() as u64;
 
             cbb = cbb.draw(self.pipeline.clone(), &self.dyn_state,
                            vec![self.vbo.clone(), Arc::new(slice)],
@@ -330,6 +410,18 @@ impl Renderer2D {
             .build().unwrap();
         Box::new(prev_future.then_execute(self.queue.clone(), cb).unwrap())
     }
+
+    /// Same as `end` for futures which are not `Send`, like one given to `GameListener::update`
+    pub fn end_after<F>(&mut self, prev_future: F) -> Box<dyn GpuFuture>
+        where F: GpuFuture + 'static
+    {
+        assert!(self.cbb.is_some(), "First need to begin renderer");
+
+        let cb = self.cbb.take().unwrap()
+            .end_render_pass().unwrap()
+            .build().unwrap();
+        Box::new(prev_future.then_execute(self.queue.clone(), cb).unwrap())
+    }
 }
 
 
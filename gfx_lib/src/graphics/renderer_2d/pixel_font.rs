@@ -0,0 +1,116 @@
+
+// Built-in 5x7 pixel font
+// Text is drawn as one `ScreenInstance` per lit dot, so it needs no texture and works with `white_uniform`
+// Covers upper case letters, digits and common punctuation, lower case is drawn upper case
+
+use crate::graphics::object::ScreenInstance;
+
+pub const GLYPH_WIDTH: usize = 5;
+pub const GLYPH_HEIGHT: usize = 7;
+const ADVANCE: usize = GLYPH_WIDTH + 1; // Glyph and one dot of spacing
+
+/// Rows of glyph from top, bit 4 is leftmost column, unknown chars are drawn as '?'
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '"' => [0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '\'' => [0x04, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+/// Width of `text` drawn with dots of `pixel` size
+pub fn text_width(text: &str, pixel: f32) -> f32 {
+    let chars = text.chars().count();
+    if chars == 0 { 0.0 } else { (chars * ADVANCE - 1) as f32 * pixel }
+}
+
+/// Number of chars that fit into `width`
+pub fn chars_fitting(width: f32, pixel: f32) -> usize {
+    ((width / pixel + 1.0) / ADVANCE as f32).max(0.0) as usize
+}
+
+/// Dots of `text` with top left corner at `x`, `y`
+pub fn text_instances(text: &str, x: f32, y: f32, pixel: f32, color: [f32; 4]) -> Vec<ScreenInstance> {
+    let mut out = vec![];
+    for (i, c) in text.chars().enumerate() {
+        let left = x + (i * ADVANCE) as f32 * pixel;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0 .. GLYPH_WIDTH {
+                if bits & (0x10 >> col) == 0 { continue; }
+                let mut dot = ScreenInstance::new();
+                dot.set_transform(
+                    left + (col as f32 + 0.5) * pixel,
+                    y + (row as f32 + 0.5) * pixel,
+                    pixel, pixel, cgmath::Rad(0.0),
+                );
+                dot.set_color(color[0], color[1], color[2], color[3]);
+                out.push(dot);
+            }
+        }
+    }
+    out
+}
+
+mod test {
+
+    #[test] fn test_pixel_font() {
+        use super::{ glyph, text_width, chars_fitting, text_instances };
+
+        assert_eq!(text_instances("I", 0.0, 0.0, 2.0, [1.0; 4]).len(), 11);
+        assert_eq!(text_instances(" ", 0.0, 0.0, 2.0, [1.0; 4]).len(), 0);
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph('~'), glyph('?'));
+
+        assert_eq!(text_width("", 2.0), 0.0);
+        assert_eq!(text_width("AB", 2.0), 22.0);
+        assert_eq!(chars_fitting(22.0, 2.0), 2);
+        assert_eq!(chars_fitting(21.0, 2.0), 1);
+    }
+}
@@ -20,6 +20,8 @@ use cgmath::{ Matrix4, SquareMatrix };
 use crate::graphics::renderer_3d::mesh::{
     Vertex3D, MeshAccess, MaterialMeshSlice, MaterialData, ObjectInstance, MaterialDrawMode
 };
+use crate::utils::FrameRing;
+use crate::main_processor::profiler::RenderStats;
 
 
 // Pass for baking geometry and material data
@@ -184,6 +186,22 @@ void main() {
     }
 }
 
+/// View projection uniform buffer with its descriptor set
+type MatrixUniform<M> = (Arc<CpuAccessibleBuffer<M>>, Arc<dyn DescriptorSet + Send + Sync>);
+
+fn create_matrix_uniform<M>(queue: &Arc<Queue>, pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>, data: M) -> MatrixUniform<M>
+    where M: Send + Sync + 'static
+{
+    let buffer = CpuAccessibleBuffer::from_data(queue.device().clone(), BufferUsage::uniform_buffer(), data).unwrap();
+    let set = Arc::new(
+        PersistentDescriptorSet::start(pipeline.clone(), 0)
+            .add_buffer(buffer.clone()).unwrap()
+            .build().unwrap()
+    );
+    (buffer, set)
+}
+
+
 /// Depth bake for `ShadowMapping`
 pub struct DepthPass {
     queue: Arc<Queue>,
@@ -194,8 +212,7 @@ pub struct DepthPass {
 
     // Matrixes Uniform
     uniform_dirty: bool, // True then uniform requires update
-    uniform_mat_set: Arc<dyn DescriptorSet + Send + Sync>, // desc set
-    uniform_matrixes_buffer: Arc<CpuAccessibleBuffer<depth_vs::ty::Matrixes>>, // Buffer with `matrixes`
+    uniforms: FrameRing<MatrixUniform<depth_vs::ty::Matrixes>>, // Buffer with `matrixes` and its desc set, per frame in flight
 }
 impl DepthPass {
     pub fn new<R>(queue: Arc<Queue>, subpass: Subpass<R>) -> Self
@@ -221,20 +238,9 @@ impl DepthPass {
                 .unwrap()) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>
         };
 
-        let uniform_matrixes_buffer = {
-            CpuAccessibleBuffer::from_data(
-                queue.device().clone(), BufferUsage::uniform_buffer(),
-                depth_vs::ty::Matrixes {
-                    vp: Matrix4::identity().into()
-                }
-            ).unwrap()
-        };
-
-        let uniform_set = Arc::new(
-            PersistentDescriptorSet::start(pipeline.clone(), 0)
-                .add_buffer(uniform_matrixes_buffer.clone()).unwrap()
-                .build().unwrap()
-        );
+        let uniforms = FrameRing::new(create_matrix_uniform(&queue, &pipeline, depth_vs::ty::Matrixes {
+            vp: Matrix4::identity().into()
+        }));
 
         Self {
             queue,
@@ -243,8 +249,7 @@ impl DepthPass {
             view_projection: Matrix4::identity(),
 
             uniform_dirty: true,
-            uniform_mat_set: uniform_set,
-            uniform_matrixes_buffer
+            uniforms,
         }
     }
 
@@ -258,8 +263,12 @@ impl DepthPass {
     pub fn render<'f>(&mut self, dyn_state: &DynamicState, mut cbb: AutoCommandBufferBuilder, matrices: (Matrix4<f32>, Matrix4<f32>), mat: &mut MaterialMeshSlice) -> AutoCommandBufferBuilder {
         if self.uniform_dirty {
             self.uniform_dirty = false;
-            let mut writer = self.uniform_matrixes_buffer.write().unwrap();
-            writer.vp = self.view_projection.into();
+            let (queue, pipeline) = (&self.queue, &self.pipeline);
+            let (buffer, _) = self.uniforms.next(
+                |(buffer, _)| buffer.write().is_ok(),
+                || create_matrix_uniform(queue, pipeline, depth_vs::ty::Matrixes { vp: Matrix4::identity().into() })
+            );
+            buffer.write().unwrap().vp = self.view_projection.into();
         }
 
         let push = vs::ty::PushData {
@@ -272,14 +281,14 @@ impl DepthPass {
                 self.pipeline.clone(), dyn_state,
                 vec![mat.vbo_slice.clone()],
                 mat.ibo_slice.clone().unwrap(),
-                (self.uniform_mat_set.clone()),
+                (self.uniforms.current().1.clone()),
                 (push)
             ).unwrap()
         } else {
             cbb.draw(
                 self.pipeline.clone(), dyn_state,
                 vec![mat.vbo_slice.clone()],
-                (self.uniform_mat_set.clone()),
+                (self.uniforms.current().1.clone()),
                 (push)
             ).unwrap()
         }
@@ -297,8 +306,7 @@ pub struct FlatPass {
 
     // Matrixes Uniform
     uniform_dirty: bool, // True then uniform requires update
-    uniform_mat_set: Arc<dyn DescriptorSet + Send + Sync>, // desc set
-    uniform_matrixes_buffer: Arc<CpuAccessibleBuffer<vs::ty::Matrixes>>, // Buffer with `matrixes`
+    uniforms: FrameRing<MatrixUniform<vs::ty::Matrixes>>, // Buffer with `matrixes` and its desc set, per frame in flight
 }
 impl FlatPass {
     pub fn new<R>(queue: Arc<Queue>, subpass: Subpass<R>) -> Self
@@ -325,20 +333,9 @@ impl FlatPass {
                 .unwrap()) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>
         };
 
-        let uniform_matrixes_buffer = {
-            CpuAccessibleBuffer::from_data(
-                queue.device().clone(), BufferUsage::uniform_buffer(),
-                vs::ty::Matrixes {
-                    vp: Matrix4::identity().into()
-                }
-            ).unwrap()
-        };
-
-        let uniform_set = Arc::new(
-            PersistentDescriptorSet::start(pipeline.clone(), 0)
-                .add_buffer(uniform_matrixes_buffer.clone()).unwrap()
-                .build().unwrap()
-        );
+        let uniforms = FrameRing::new(create_matrix_uniform(&queue, &pipeline, vs::ty::Matrixes {
+            vp: Matrix4::identity().into()
+        }));
 
         Self {
             queue,
@@ -347,8 +344,7 @@ impl FlatPass {
             view_projection: Matrix4::identity(),
 
             uniform_dirty: true,
-            uniform_mat_set: uniform_set,
-            uniform_matrixes_buffer
+            uniforms,
         }
     }
 
@@ -362,8 +358,12 @@ impl FlatPass {
     pub fn render<'f>(&mut self, dyn_state: &DynamicState, mut cbb: AutoCommandBufferBuilder, matrices: (Matrix4<f32>, Matrix4<f32>), mat: &mut MaterialMeshSlice) -> AutoCommandBufferBuilder {
         if self.uniform_dirty {
             self.uniform_dirty = false;
-            let mut writer = self.uniform_matrixes_buffer.write().unwrap();
-            writer.vp = self.view_projection.into();
+            let (queue, pipeline) = (&self.queue, &self.pipeline);
+            let (buffer, _) = self.uniforms.next(
+                |(buffer, _)| buffer.write().is_ok(),
+                || create_matrix_uniform(queue, pipeline, vs::ty::Matrixes { vp: Matrix4::identity().into() })
+            );
+            buffer.write().unwrap().vp = self.view_projection.into();
         }
 
         let push = vs::ty::PushData {
@@ -376,14 +376,14 @@ impl FlatPass {
                 self.pipeline.clone(), dyn_state,
                 vec![mat.vbo_slice.clone()],
                 mat.ibo_slice.clone().unwrap(),
-                (self.uniform_mat_set.clone(), mat.material.get_uniform(&self.pipeline, 1)),
+                (self.uniforms.current().1.clone(), mat.material.get_uniform(&self.pipeline, 1)),
                 (push)
             ).unwrap()
         } else {
             cbb.draw(
                 self.pipeline.clone(), dyn_state,
                 vec![mat.vbo_slice.clone()],
-                (self.uniform_mat_set.clone(), mat.material.get_uniform(&self.pipeline, 1)),
+                (self.uniforms.current().1.clone(), mat.material.get_uniform(&self.pipeline, 1)),
                 (push)
             ).unwrap()
         }
@@ -404,8 +404,7 @@ pub struct TexPass {
 
     // Matrixes Uniform
     uniform_dirty: bool, // True then uniform requires update
-    uniform_mat_set: Arc<dyn DescriptorSet + Send + Sync>, // desc set
-    uniform_matrixes_buffer: Arc<CpuAccessibleBuffer<vs::ty::Matrixes>>, // Buffer with `matrixes`
+    uniforms: FrameRing<MatrixUniform<vs::ty::Matrixes>>, // Buffer with `matrixes` and its desc set, per frame in flight
 }
 impl TexPass {
     pub fn new<R>(queue: Arc<Queue>, subpass: Subpass<R>) -> Self
@@ -439,20 +438,9 @@ impl TexPass {
             0.0, 1.0, 0.0, 0.0
         ).unwrap();
 
-        let uniform_matrixes_buffer = {
-            CpuAccessibleBuffer::from_data(
-                queue.device().clone(), BufferUsage::uniform_buffer(),
-                vs::ty::Matrixes {
-                    vp: Matrix4::identity().into()
-                }
-            ).unwrap()
-        };
-
-        let uniform_set = Arc::new(
-            PersistentDescriptorSet::start(pipeline.clone(), 0)
-                .add_buffer(uniform_matrixes_buffer.clone()).unwrap()
-                .build().unwrap()
-        );
+        let uniforms = FrameRing::new(create_matrix_uniform(&queue, &pipeline, vs::ty::Matrixes {
+            vp: Matrix4::identity().into()
+        }));
 
         Self {
             queue,
@@ -463,8 +451,7 @@ impl TexPass {
             view_projection: Matrix4::identity(),
 
             uniform_dirty: true,
-            uniform_mat_set: uniform_set,
-            uniform_matrixes_buffer
+            uniforms,
         }
     }
 
@@ -478,8 +465,12 @@ impl TexPass {
     pub fn render<'f>(&mut self, dyn_state: &DynamicState, mut cbb: AutoCommandBufferBuilder, matrices: (Matrix4<f32>, Matrix4<f32>), mat: &mut MaterialMeshSlice) -> AutoCommandBufferBuilder {
         if self.uniform_dirty {
             self.uniform_dirty = false;
-            let mut writer = self.uniform_matrixes_buffer.write().unwrap();
-            writer.vp = self.view_projection.into();
+            let (queue, pipeline) = (&self.queue, &self.pipeline);
+            let (buffer, _) = self.uniforms.next(
+                |(buffer, _)| buffer.write().is_ok(),
+                || create_matrix_uniform(queue, pipeline, vs::ty::Matrixes { vp: Matrix4::identity().into() })
+            );
+            buffer.write().unwrap().vp = self.view_projection.into();
         }
 
         let push = vs::ty::PushData {
@@ -492,14 +483,14 @@ impl TexPass {
                 self.pipeline.clone(), dyn_state,
                 vec![mat.vbo_slice.clone()],
                 mat.ibo_slice.clone().unwrap(),
-                (self.uniform_mat_set.clone(), mat.material.get_uniform(&self.pipeline, 1)),
+                (self.uniforms.current().1.clone(), mat.material.get_uniform(&self.pipeline, 1)),
                 (push)
             ).unwrap()
         } else {
             cbb.draw(
                 self.pipeline.clone(), dyn_state,
                 vec![mat.vbo_slice.clone()],
-                (self.uniform_mat_set.clone(), mat.material.get_uniform(&self.pipeline, 1)),
+                (self.uniforms.current().1.clone(), mat.material.get_uniform(&self.pipeline, 1)),
                 (push)
             ).unwrap()
         }
@@ -514,6 +505,8 @@ pub struct GeometryPass {
     depth_pass: DepthPass,
     flat_pass: FlatPass,
     tex_pass: TexPass,
+
+    stats: RenderStats,
 }
 impl GeometryPass {
     pub fn new<R>(queue: Arc<Queue>, depth_subpass: Subpass<R>, mat_subpass: Subpass<R>) -> Self
@@ -529,10 +522,15 @@ impl GeometryPass {
 
             depth_pass,
             flat_pass,
-            tex_pass
+            tex_pass,
+
+            stats: RenderStats::default(),
         }
     }
 
+    /// Stats since last call
+    pub fn take_stats(&mut self) -> RenderStats { std::mem::replace(&mut self.stats, RenderStats::default()) }
+
     pub fn set_view_projection(&mut self, vp: Matrix4<f32>) {
         self.view_projection = vp;
         self.depth_pass.set_view_projection(vp);
@@ -548,6 +546,8 @@ impl GeometryPass {
             let matrices = (i.model_matrix(), i.normal_matrix());
             for m in i.materials.iter_mut() {
                 if m.material.is_cast_shadow() {
+                    self.stats.draw_calls += 1;
+                    self.stats.triangles += m.triangle_count();
                     cbb = self.depth_pass.render(dyn_state, cbb, matrices, m);
                 }
             }
@@ -561,9 +561,15 @@ impl GeometryPass {
     pub fn bake_materials<'f>(&mut self, mut cbb: AutoCommandBufferBuilder, dyn_state: &DynamicState, geometry: &mut Vec<ObjectInstance>) -> AutoCommandBufferBuilder {
 
         let vp = self.view_projection;
-        for i in geometry.iter_mut().filter(|x| x.mesh_data.ready_for_use() && x.mesh_data.visible_in(vp * x.model_matrix())) {
+        for i in geometry.iter_mut().filter(|x| x.mesh_data.ready_for_use()) {
+            if !i.mesh_data.visible_in(vp * i.model_matrix()) {
+                self.stats.culled_objects += 1;
+                continue;
+            }
             let matrices = (i.model_matrix(), i.normal_matrix());
             for m in i.materials.iter_mut() {
+                self.stats.draw_calls += 1;
+                self.stats.triangles += m.triangle_count();
                 match m.material.mode() {
                     MaterialDrawMode::NoTexture => cbb = self.flat_pass.render(dyn_state, cbb, matrices, m),
                     MaterialDrawMode::WithDiffuse => cbb = self.tex_pass.render(dyn_state, cbb, matrices, m),
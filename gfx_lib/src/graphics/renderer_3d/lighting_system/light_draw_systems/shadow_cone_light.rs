@@ -215,36 +215,31 @@ impl ShadedConeLight {
         assert!(cone.image.is_some());
 
         // Prepare or generate buffer
-        if cone.data_buffer.is_none() {
-            let buffer = CpuAccessibleBuffer::from_data(
-                self.queue.device().clone(), BufferUsage::uniform_buffer(),
-                fs::ty::LightData {
-                    _dummy0: [0; 4].into(),
-                    shadow_biased: (Matrix4::new(
-                        0.5, 0.0, 0.0, 0.0,
-                        0.0, 0.5, 0.0, 0.0,
-                        0.0, 0.0, 1.0, 0.0,
-                        0.5, 0.5, 0.0, 1.0,
-                    ) * cone.vp).into(),
-                    light_pos: cone.pos.into(),
-                    light_col: cone.col.into(),
-                    light_pow: cone.dist.into(),
-                }
-            ).unwrap();
-            cone.data_buffer = Some(buffer);
-        }
-        else if cone.data_changed {
-            // Update information in buffer then requested
-            let mut writer = cone.data_buffer.as_ref().unwrap().write().unwrap();
-            writer.shadow_biased = (Matrix4::new(
-                0.5, 0.0, 0.0, 0.0,
-                0.0, 0.5, 0.0, 0.0,
-                0.0, 0.0, 1.0, 0.0,
-                0.5, 0.5, 0.0, 1.0,
-            ) * cone.vp).into();
-            writer.light_pos = cone.pos.into();
-            writer.light_col = cone.col.into();
-            writer.light_pow = cone.dist.into();
+        if cone.data_changed || cone.data_buffer.is_none() {
+            let data = fs::ty::LightData {
+                _dummy0: [0; 4].into(),
+                shadow_biased: (Matrix4::new(
+                    0.5, 0.0, 0.0, 0.0,
+                    0.0, 0.5, 0.0, 0.0,
+                    0.0, 0.0, 1.0, 0.0,
+                    0.5, 0.5, 0.0, 1.0,
+                ) * cone.vp).into(),
+                light_pos: cone.pos.into(),
+                light_col: cone.col.into(),
+                light_pow: cone.dist.into(),
+            };
+
+            // Update information in buffer then requested, buffer used by frame in flight is replaced
+            let written = match cone.data_buffer.as_ref().map(|b| b.write()) {
+                Some(Ok(mut writer)) => { *writer = data; true },
+                _ => false,
+            };
+            if !written {
+                cone.data_buffer = Some(CpuAccessibleBuffer::from_data(
+                    self.queue.device().clone(), BufferUsage::uniform_buffer(), data
+                ).unwrap());
+                cone.data_set = None;
+            }
             cone.data_changed = false;
         }
 
@@ -10,6 +10,7 @@ use cgmath::{ Matrix4, SquareMatrix };
 use vulkano::command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, DynamicState};
 use vulkano::image::{AttachmentImage};
 use std::cell::RefCell;
+use crate::main_processor::profiler::RenderStats;
 
 use crate::graphics::renderer_3d::{
     mesh::{ Vertex3D, ObjectInstance },
@@ -44,6 +45,8 @@ pub struct LightingPass {
     // Lights with shadows
     shadow_cone_light: ShadedConeLight,
 
+    stats: RenderStats, // Light draws, shadow mapping keeps own stats
+
 
     // camera view_projection
     view_projection: Matrix4<f32>,
@@ -90,10 +93,19 @@ impl LightingPass {
             point_light,
             shadow_cone_light,
 
+            stats: RenderStats::default(),
+
             view_projection: Matrix4::identity(),
         }
     }
 
+    /// Stats of light draws and shadow mapping since last call
+    pub fn take_stats(&mut self) -> RenderStats {
+        let mut stats = std::mem::replace(&mut self.stats, RenderStats::default());
+        stats.add(&self.shadow_mapper.take_stats());
+        stats
+    }
+
     /// Create and enable new light source
     pub fn create_source(&mut self, kind: LightKind) -> Arc<RefCell<LightSource>>{
         let source = Arc::new(RefCell::new(LightSource::new(kind)));
@@ -174,6 +186,9 @@ impl LightingPass {
         for s in self.sources.iter_mut() {
             let mut source = &mut s.borrow_mut();
             if source.active {
+                // Every light is single full screen quad
+                self.stats.draw_calls += 1;
+                self.stats.triangles += 2;
                 match &mut source.kind {
                     LightKind::Ambient => unsafe {
                         cbb = cbb.execute_commands(self.ambient_light.render(
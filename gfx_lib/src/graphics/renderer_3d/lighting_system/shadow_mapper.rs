@@ -13,6 +13,7 @@ use crate::graphics::renderer_3d::{
 };
 use cgmath::{Matrix4, Point3, vec3};
 use vulkano::buffer::BufferAccess;
+use crate::main_processor::profiler::RenderStats;
 
 mod depth_vs {
     vulkano_shaders::shader! {
@@ -50,6 +51,8 @@ pub struct ShadowMapping {
     // Generate Depth Buffer
     pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
     dyn_state: DynamicState,
+
+    stats: RenderStats,
 }
 impl ShadowMapping {
 
@@ -98,15 +101,28 @@ impl ShadowMapping {
             render_pass,
             pipeline,
             dyn_state: DynamicState::none(),
+
+            stats: RenderStats::default(),
         }
     }
 
+    /// Stats since last call
+    pub fn take_stats(&mut self) -> RenderStats { std::mem::replace(&mut self.stats, RenderStats::default()) }
+
 
     fn create_source_info(&self, resolution: [u32; 2]) -> (Arc<dyn FramebufferAbstract + Send + Sync>, Arc<AttachmentImage>) {
-        let img = AttachmentImage::sampled(
+        // Transfer source allows reading shadow map back for debugging
+        let usage = ImageUsage {
+            depth_stencil_attachment: true,
+            sampled: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        };
+        let img = AttachmentImage::with_usage(
             self.queue.device().clone(),
             resolution,
-            Format::D16Unorm
+            Format::D16Unorm,
+            usage
         ).unwrap();
 
         let framebuffer = Arc::new(Framebuffer::start(self.render_pass.clone())
@@ -142,6 +158,7 @@ impl ShadowMapping {
                     false,
                     vec![1.0f32.into()]
                 ).unwrap();
+                self.stats.shadow_maps += 1;
 
                 self.dyn_state.viewports = Some(vec![Viewport {
                     origin: [0.0, 0.0],
@@ -156,6 +173,8 @@ impl ShadowMapping {
                             mvp: mvp.into()
                         };
                         for mat in i.materials.iter().filter(|x| x.material.is_cast_shadow()) {
+                            self.stats.draw_calls += 1;
+                            self.stats.triangles += mat.triangle_count();
                             if mat.ibo_slice.is_some() {
                                 cbb = cbb.draw_indexed(self.pipeline.clone(), &self.dyn_state,
                                                        vec![mat.vbo_slice.clone()],
@@ -193,7 +193,7 @@ impl MaterialData {
         self.flat_shading = flag;
         self.material_dirty = true;
     }
-    pub fn set_uv_remap(&mut self, remap: [[f32; 2]; 2]) { self.diffuse_remap = remap; }
+    pub fn set_uv_remap(&mut self, remap: [[f32; 2]; 2]) { self.diffuse_remap = remap; self.material_dirty = true; }
     pub fn set_cast_shadow(&mut self, flag: bool) { self.cast_shadow = flag; }
 
     // Generate MaterialColor structure
@@ -224,6 +224,23 @@ impl MaterialData {
         self.material_dirty = true;
     }
 
+    pub fn diffuse_texture(&self) -> Option<&Arc<dyn ImageViewAccess + Send + Sync>> {
+        self.diffuse_texture.as_ref().map(|(t, _)| t)
+    }
+
+    /// Replace diffuse texture if it is `old`, sampler and UV remap are kept
+    pub fn replace_texture(&mut self, old: &Arc<dyn ImageViewAccess + Send + Sync>, new: &Arc<dyn ImageViewAccess + Send + Sync>) -> bool {
+        let sampler = match &self.diffuse_texture {
+            Some((texture, sampler)) if same_image(texture, old) => sampler.clone(),
+            _ => return false,
+        };
+        self.set_diffuse_texture_with_sampler(new.clone(), sampler);
+        true
+    }
+
+    /// Rebuild descriptor set on next `get_uniform`
+    pub fn recreate_uniform(&mut self) { self.recreate = true; }
+
     #[inline] pub fn mode(&self) -> MaterialDrawMode {
         let diff = self.diffuse_texture.is_some();
         if diff { MaterialDrawMode::WithDiffuse }
@@ -245,8 +262,19 @@ impl MaterialData {
 
         // Update buffer if required
         if self.material_dirty {
-            let mut writer = self.material_buffer.as_ref().unwrap().write().unwrap();
-            *writer = self.get_material_color();
+            let color = self.get_material_color();
+            let written = match self.material_buffer.as_ref().unwrap().write() {
+                Ok(mut writer) => { *writer = color; true },
+                Err(_) => false,
+            };
+            if !written {
+                // Still used by frame in flight, material changes are rare, so just reallocate
+                self.material_buffer = Some(CpuAccessibleBuffer::from_data(pipeline.device().clone(),
+                    BufferUsage::uniform_buffer(),
+                    color
+                ).unwrap());
+                self.recreate = true;
+            }
             self.material_dirty = false;
         }
 
@@ -277,6 +305,11 @@ impl MaterialData {
     }
 }
 
+/// Compare images by address, vtable of same image may differ
+pub fn same_image(a: &Arc<dyn ImageViewAccess + Send + Sync>, b: &Arc<dyn ImageViewAccess + Send + Sync>) -> bool {
+    Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+}
+
 /// Slice of some mesh with material attached
 #[derive(Clone)]
 pub struct MaterialMeshSlice {
@@ -284,6 +317,15 @@ pub struct MaterialMeshSlice {
     pub ibo_slice: Option<Arc<MeshIBOType>>,
     pub material: MaterialData
 }
+impl MaterialMeshSlice {
+    /// Triangles drawn with this slice, meshes are triangle lists
+    pub fn triangle_count(&self) -> u64 {
+        match &self.ibo_slice {
+            Some(ibo) => ibo.len() as u64 / 3,
+            None => (self.vbo_slice.size() / std::mem::size_of::<Vertex3D>()) as u64 / 3,
+        }
+    }
+}
 
 /// Draw instance for mesh data
 /// contains transformation of matrices
@@ -316,6 +358,17 @@ impl ObjectInstance {
         rot: [0.0, 0.0, 0.0],
     }}
 
+    /// Take mesh and materials of reloaded object, transform is kept
+    pub fn swap_geometry(&mut self, reloaded: ObjectInstance) {
+        self.mesh_data = reloaded.mesh_data;
+        self.materials = reloaded.materials;
+    }
+
+    /// Replace texture in every material using `old`, returns number of replaced
+    pub fn replace_texture(&mut self, old: &Arc<dyn ImageViewAccess + Send + Sync>, new: &Arc<dyn ImageViewAccess + Send + Sync>) -> usize {
+        self.materials.iter_mut().filter(|m| m.material.replace_texture(old, new)).count()
+    }
+
     pub fn set_pos(&mut self, x: f32, y: f32, z: f32) {
         self.pos = [x, y, z];
         self.dirty = true;
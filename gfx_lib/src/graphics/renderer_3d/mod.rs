@@ -14,6 +14,11 @@ use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
 use lighting_system::lighting_pass::LightingPass;
 use lighting_system::{ LightSource, LightKind, ShadowKind };
 use std::cell::RefCell;
+use std::time::{ Duration, Instant };
+use crate::main_processor::{
+    profiler::{ Profiler, RenderStats },
+    gpu_timer::GpuTimer,
+};
 
 mod geometry_pass;
 
@@ -37,6 +42,71 @@ use crate::graphics::renderer_3d::mesh::ImmutableMeshData;
 const DIFFUSE_FORMAT: Format = Format::A2B10G10R10UnormPack32;
 const DEPTH_FORMAT: Format = Format::D16Unorm;
 
+/// Deferred render pass, `$gbuffer_store` is store op of G-Buffer attachments (Store or DontCare)
+macro_rules! deferred_render_pass {
+    ($device:expr, $output_format:expr, $gbuffer_store:ident) => {
+        Arc::new(vulkano::ordered_passes_renderpass!($device,
+            attachments: {
+                final_color: {
+                    load: Clear,
+                    store: Store,
+                    format: $output_format,
+                    samples: 1,
+                },
+                // Will be bound to `self.diffuse_buffer`.
+                diffuse: {
+                    load: Clear,
+                    store: $gbuffer_store,
+                    format: DIFFUSE_FORMAT,
+                    samples: 1,
+                },
+                // Will be bound to `self.normals_buffer`.
+                normals: {
+                    load: Clear,
+                    store: $gbuffer_store,
+                    format: Format::R16G16B16A16Sfloat,
+                    samples: 1,
+                },
+                // Depth used for geometry pass
+                transient_depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: DEPTH_FORMAT,
+                    samples: 1,
+                },
+                // Buffered depth
+                depth: {
+                    load: Clear,
+                    store: $gbuffer_store,
+                    format: DEPTH_FORMAT,
+                    samples: 1,
+                }
+            },
+            passes: [
+                // Write to the diffuse, normals and depth attachments.
+                {
+                    color: [ diffuse ],
+                    depth_stencil: { transient_depth },
+                    input: []
+                },
+                // Write depth again, for shadow mapping
+                {
+                    color: [ normals ],
+                    depth_stencil: { depth },
+                    input: []
+                },
+
+                // Apply lighting by reading these three attachments and writing to `final_color`.
+                {
+                    color: [ final_color ],
+                    depth_stencil: {},
+                    input: [ diffuse, normals, depth ]
+                }
+            ]
+        ).unwrap()) as Arc<dyn RenderPassAbstract + Send + Sync>
+    };
+}
+
 pub struct Renderer3D {
     // Geometry to draw
     pub render_geometry: Vec<ObjectInstance>,
@@ -47,6 +117,7 @@ pub struct Renderer3D {
     dyn_state: DynamicState,
 
     // FB Attachments,
+    atch_usage: ImageUsage,
     diffuse_buffer: Arc<AttachmentImage>,
     normal_buffer: Arc<AttachmentImage>,
     transient_depth_buffer: Arc<AttachmentImage>,
@@ -55,15 +126,30 @@ pub struct Renderer3D {
     // Passes
     geom_pass: GeometryPass,
     lighting_pass: LightingPass,
+
+    // Last rendered frame
+    stats: RenderStats,
+    timings: [(&'static str, Duration); 3], // CPU time of command recording per pass
+    gpu_timer: GpuTimer, // GPU time of shadow mapping and main render pass
 }
 /// Comms with game_listener
 impl Renderer3D {
 
     pub fn generate_mesh_from_data(&self, data: Vec<Vertex3D>, indices: Option<Vec<u32>>) -> Loader<Arc<dyn MeshAccess + Send + Sync + 'static>> {
-        ImmutableMeshData::from_data(self.queue.clone(), data, indices).into()
+        Self::load_mesh(self.queue.clone(), data, indices)
     }
 
-    pub fn generate_object(&self, mut object: ObjectInfo, mut image_resolver: Box<dyn ImageResolver + Send + 'static>) -> Loader<ObjectInstance> {
+    pub fn generate_object(&self, object: ObjectInfo, image_resolver: Box<dyn ImageResolver + Send + 'static>) -> Loader<ObjectInstance> {
+        Self::load_object(self.queue.clone(), object, image_resolver)
+    }
+
+    /// Same as `generate_mesh_from_data`, without renderer, for `LoadPlanner` jobs
+    pub fn load_mesh(queue: Arc<Queue>, data: Vec<Vertex3D>, indices: Option<Vec<u32>>) -> Loader<Arc<dyn MeshAccess + Send + Sync + 'static>> {
+        ImmutableMeshData::from_data(queue, data, indices).into()
+    }
+
+    /// Same as `generate_object`, without renderer, for `LoadPlanner` jobs
+    pub fn load_object(queue: Arc<Queue>, mut object: ObjectInfo, mut image_resolver: Box<dyn ImageResolver + Send + 'static>) -> Loader<ObjectInstance> {
 
         fn generate_material(material: &MaterialInfo, resolver: &mut Box<dyn ImageResolver + Send + 'static>) -> MaterialData {
             let mut data = MaterialData::new();
@@ -78,15 +164,15 @@ impl Renderer3D {
             data
         }
 
-        let mut mesh_loader = self.generate_mesh_from_data(
+        let mesh_loader = Self::load_mesh(
+            queue.clone(),
             object.vertices.iter()
                 .map(|x| x.clone().into())
                 .collect(),
             if object.indices.is_empty() { None } else { Some(object.indices.iter().cloned().collect()) }
         );
-        let queue = self.queue.clone();
-        Loader::with_closure(move || {
-            let mesh = mesh_loader.take();
+        // Materials are built once mesh is ready, without blocking on it
+        mesh_loader.and_then(move |mesh| {
             // Object instance that we are building
             let mut inst = ObjectInstance::new(mesh.clone());
             // Futures to wait on before loading is complete
@@ -121,11 +207,9 @@ impl Renderer3D {
                 }
             } }
 
-            // Wait on GpuFutures
-            future.flush().unwrap();
+            // Ready once images are resolved and GPU finished uploading index buffers
             image_resolver.flush();
-
-            inst
+            Loader::with_gpu_future(inst, future)
         })
     }
 
@@ -140,70 +224,34 @@ impl Renderer3D {
 /// Comms with main loop
 impl Renderer3D {
     pub fn new(queue: Arc<Queue>, output_format: Format) -> Self {
-        let render_pass = Arc::new(vulkano::ordered_passes_renderpass!(queue.device().clone(),
-            attachments: {
-                final_color: {
-                    load: Clear,
-                    store: Store,
-                    format: output_format,
-                    samples: 1,
-                },
-                // Will be bound to `self.diffuse_buffer`.
-                diffuse: {
-                    load: Clear,
-                    store: DontCare,
-                    format: DIFFUSE_FORMAT,
-                    samples: 1,
-                },
-                // Will be bound to `self.normals_buffer`.
-                normals: {
-                    load: Clear,
-                    store: DontCare,
-                    format: Format::R16G16B16A16Sfloat,
-                    samples: 1,
-                },
-                // Depth used for geometry pass
-                transient_depth: {
-                    load: Clear,
-                    store: DontCare,
-                    format: DEPTH_FORMAT,
-                    samples: 1,
-                },
-                // Buffered depth
-                depth: {
-                    load: Clear,
-                    store: DontCare,
-                    format: DEPTH_FORMAT,
-                    samples: 1,
-                }
-            },
-            passes: [
-                // Write to the diffuse, normals and depth attachments.
-                {
-                    color: [ diffuse ],
-                    depth_stencil: { transient_depth },
-                    input: []
-                },
-                // Write depth again, for shadow mapping
-                {
-                    color: [ normals ],
-                    depth_stencil: { depth },
-                    input: []
-                },
+        Self::with_options(queue, output_format, false)
+    }
 
-                // Apply lighting by reading these three attachments and writing to `final_color`.
-                {
-                    color: [ final_color ],
-                    depth_stencil: {},
-                    input: [ diffuse, normals, depth ]
-                }
-            ]
-        ).unwrap()) as Arc<dyn RenderPassAbstract + Send + Sync>;
+    /// G-Buffer (diffuse, normals, depth) is stored after rendering and can be read back
+    /// Slower than `new`, because attachments can no longer be transient
+    pub fn new_with_gbuffer_readback(queue: Arc<Queue>, output_format: Format) -> Self {
+        Self::with_options(queue, output_format, true)
+    }
 
-        let atch_usage = ImageUsage {
-            transient_attachment: true,
-            input_attachment: true,
-            ..ImageUsage::none()
+    fn with_options(queue: Arc<Queue>, output_format: Format, gbuffer_readback: bool) -> Self {
+        let render_pass = if gbuffer_readback {
+            deferred_render_pass!(queue.device().clone(), output_format, Store)
+        } else {
+            deferred_render_pass!(queue.device().clone(), output_format, DontCare)
+        };
+
+        let atch_usage = if gbuffer_readback {
+            ImageUsage {
+                input_attachment: true,
+                transfer_source: true,
+                ..ImageUsage::none()
+            }
+        } else {
+            ImageUsage {
+                transient_attachment: true,
+                input_attachment: true,
+                ..ImageUsage::none()
+            }
         };
 
         let diffuse_buffer = AttachmentImage::with_usage(
@@ -229,6 +277,7 @@ impl Renderer3D {
             queue.clone(),
             Subpass::from(render_pass.clone(), 2).unwrap()
         );
+        let gpu_timer = GpuTimer::new(queue.clone());
 
 
         Self {
@@ -238,6 +287,7 @@ impl Renderer3D {
             render_pass,
             dyn_state: DynamicState::none(),
 
+            atch_usage,
             diffuse_buffer,
             normal_buffer,
             transient_depth_buffer,
@@ -245,9 +295,37 @@ impl Renderer3D {
 
             geom_pass,
             lighting_pass,
+
+            stats: RenderStats::default(),
+            timings: [
+                ("shadow_mapping", Duration::default()),
+                ("geometry_pass", Duration::default()),
+                ("lighting_pass", Duration::default()),
+            ],
+            gpu_timer,
         }
     }
 
+    /// Draw calls, triangles, culled objects and shadow maps of last `render`
+    pub fn stats(&self) -> RenderStats { self.stats }
+    /// CPU time spent recording commands of each pass in last `render`
+    pub fn timings(&self) -> &[(&'static str, Duration)] { &self.timings }
+
+    /// GPU time of shadow mapping and main pass (geometry and lighting subpasses) of latest timed `render`
+    pub fn gpu_timings(&self) -> &[(&'static str, Duration)] { self.gpu_timer.timings() }
+
+    /// Add stats and timings of last `render` into frame profiler
+    pub fn report(&self, profiler: &mut Profiler) {
+        for (name, time) in self.timings.iter() { profiler.record(name, *time); }
+        self.gpu_timer.report(profiler);
+        profiler.add_stats(&self.stats);
+    }
+
+    /// G-Buffer attachments, contain data of last frame only if created with `new_with_gbuffer_readback`
+    pub fn diffuse_buffer(&self) -> Arc<AttachmentImage> { self.diffuse_buffer.clone() }
+    pub fn normal_buffer(&self) -> Arc<AttachmentImage> { self.normal_buffer.clone() }
+    pub fn depth_buffer(&self) -> Arc<AttachmentImage> { self.depth_buffer.clone() }
+
     pub fn set_view_projection(&mut self, view_projection: Matrix4<f32>) {
         self.geom_pass.set_view_projection(view_projection);
         self.lighting_pass.set_view_projection(view_projection);
@@ -263,11 +341,7 @@ impl Renderer3D {
         let img_dims = ImageAccess::dimensions(&final_image).width_height();
         if ImageAccess::dimensions(&self.depth_buffer).width_height() != img_dims {
 
-            let atch_usage = ImageUsage {
-                transient_attachment: true,
-                input_attachment: true,
-                ..ImageUsage::none()
-            };
+            let atch_usage = self.atch_usage;
 
             self.diffuse_buffer = AttachmentImage::with_usage(
                 self.queue.device().clone(),
@@ -312,7 +386,9 @@ impl Renderer3D {
 
         // Prepare shadow map
         // Perform updating of lighting and wait on it
+        let start = Instant::now();
         let shadow_cb = self.lighting_pass.update(&self.render_geometry);
+        self.timings[0].1 = start.elapsed();
 
         let framebuffer = Arc::new(
             Framebuffer::start(self.render_pass.clone())
@@ -336,22 +412,31 @@ impl Renderer3D {
             ]).unwrap();
 
         // Do geometry depth only
+        let start = Instant::now();
         main_cbb = self.geom_pass.bake_materials(main_cbb, &self.dyn_state, &mut self.render_geometry);
 
         // Do geometry pass
         main_cbb = main_cbb.next_subpass(false).unwrap();
         main_cbb = self.geom_pass.bake_depth_normal(main_cbb, &self.dyn_state, &mut self.render_geometry);
 
+        self.timings[1].1 = start.elapsed();
+
         // Do Lighting
+        let start = Instant::now();
         main_cbb = main_cbb.next_subpass(true).unwrap();
         main_cbb = self.lighting_pass.render(main_cbb, &self.dyn_state);
+        self.timings[2].1 = start.elapsed();
+
+        self.stats = self.geom_pass.take_stats();
+        self.stats.add(&self.lighting_pass.take_stats());
 
         let main_cb = main_cbb.end_render_pass().unwrap().build().unwrap();
 
-        Box::new(prev_future
-                     .then_execute(self.queue.clone(), shadow_cb).unwrap()
-                     .then_execute(self.queue.clone(), main_cb).unwrap()
-        )
+        let future = self.gpu_timer.start(prev_future)
+            .then_execute(self.queue.clone(), shadow_cb).unwrap();
+        let future = self.gpu_timer.end("shadow_mapping", future)
+            .then_execute(self.queue.clone(), main_cb).unwrap();
+        self.gpu_timer.end("main_pass", future)
     }
 
 }
\ No newline at end of file
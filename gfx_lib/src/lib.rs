@@ -1,7 +1,9 @@
 
+pub mod assets;
 pub mod graphics;
 pub mod loader;
 pub mod main_processor;
 pub mod sync;
 pub mod utils;
+pub mod vfs;
 pub mod view;
@@ -1,4 +1,7 @@
-use std::path::Path;
+use std::{
+    io::Cursor,
+    path::Path,
+};
 
 use crate::loader::{
     obj::Error::NoObjectForName,
@@ -6,11 +9,13 @@ use crate::loader::{
     MaterialSlice, MaterialInfo,
     VertexInfo
 };
+use crate::vfs::{ self, Vfs, VfsError };
 use std::collections::BTreeMap;
 
 pub enum Error {
     LoadingError(tobj::LoadError),
     NoObjectForName(String),
+    Vfs(VfsError),
 }
 impl std::error::Error for Error {}
 impl std::fmt::Debug for Error {
@@ -18,6 +23,7 @@ impl std::fmt::Debug for Error {
         match self {
             Error::LoadingError(err) => write!(f, "Unable to load files: {:?}", err),
             Error::NoObjectForName(name) => write!(f, "No object for name: {}", name),
+            Error::Vfs(err) => write!(f, "Unable to read file: {}", err),
         }
     }
 }
@@ -29,11 +35,29 @@ impl std::fmt::Display for Error {
 impl From<tobj::LoadError> for Error {
     fn from(o: tobj::LoadError) -> Self { Error::LoadingError(o) }
 }
+impl From<VfsError> for Error {
+    fn from(o: VfsError) -> Self { Error::Vfs(o) }
+}
 
 
 /// Load objects with names into map (name, object) or error
-pub fn load_objects<T: Into<String>>(path: &Path, mut names: Vec<T>) -> Result<BTreeMap<String, ObjectInfo>, Error> {
-    let (models, materials) = tobj::load_obj(path)?;
+pub fn load_objects<T: Into<String>>(path: &Path, names: Vec<T>) -> Result<BTreeMap<String, ObjectInfo>, Error> {
+    let vfs = Vfs::directory(path.parent().unwrap_or_else(|| Path::new("")));
+    let file = path.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+    load_objects_from(&vfs, &file, names)
+}
+
+/// Same as `load_objects`, OBJ and MTL files it references are read through `vfs`
+pub fn load_objects_from<T: Into<String>>(vfs: &Vfs, path: &str, mut names: Vec<T>) -> Result<BTreeMap<String, ObjectInfo>, Error> {
+    let bytes = vfs.read(path)?;
+    let dir = vfs::parent(path);
+    let (models, materials) = tobj::load_obj_buf(&mut Cursor::new(bytes), |mtl| {
+        // MTL paths are relative to OBJ file
+        match vfs.read(&vfs::join(&dir, &mtl.to_string_lossy())) {
+            Ok(mtl) => tobj::load_mtl_buf(&mut Cursor::new(mtl)),
+            Err(_) => Err(tobj::LoadError::OpenFileFailed),
+        }
+    })?;
 
     names.drain(..) // .iter()
         // Extract strings
@@ -0,0 +1,207 @@
+
+// Screenshots and frame sequence capture
+// Presented image is copied into host buffer before present, once GPU is done with it
+// conversion and PNG encoding run on rayon thread pool so main loop is not blocked
+
+use std::{
+    path::PathBuf,
+    sync::{ Arc, Mutex, Condvar },
+};
+use vulkano::{
+    device::Queue,
+    image::ImageAccess,
+    sync::GpuFuture,
+};
+
+use crate::graphics::image::readback::{ self, PendingReadback, ReadbackError };
+
+/// Frame sequence written by `FrameRequest::StartCapture`
+struct CaptureSequence {
+    dir: PathBuf,
+    period: f32, // Seconds between captured frames
+    accumulated: f32,
+    next_index: u32,
+}
+
+/// Copy recorded into frame, with files it should be written to
+pub struct PendingCapture {
+    readback: PendingReadback,
+    paths: Vec<PathBuf>,
+}
+
+/// Captures presented frames, owned by main loop
+/// Requests are applied to next presented frame
+pub struct FrameCapture {
+    screenshots: Vec<PathBuf>,
+    sequence: Option<CaptureSequence>,
+    pending: Vec<PendingCapture>, // Waiting for GPU
+    encoding: Arc<(Mutex<usize>, Condvar)>, // Number of running encode tasks
+}
+impl FrameCapture {
+    pub fn new() -> Self { Self {
+        screenshots: vec![],
+        sequence: None,
+        pending: vec![],
+        encoding: Arc::new((Mutex::new(0), Condvar::new())),
+    } }
+
+    pub fn screenshot(&mut self, path: PathBuf) { self.screenshots.push(path) }
+
+    /// Start writing `dir/frame_000000.png` sequence with `fps` frames per second of frame time
+    /// Frames are repeated if game runs slower than `fps`, and skipped if faster
+    pub fn start_sequence(&mut self, dir: PathBuf, fps: f32) {
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            println!("Unable to create capture directory {:?}: {}", dir, e);
+            return;
+        }
+        let period = 1.0 / fps.max(1.0);
+        self.sequence = Some(CaptureSequence {
+            dir,
+            period,
+            accumulated: period, // First frame is captured right away
+            next_index: 0,
+        });
+    }
+
+    pub fn stop_sequence(&mut self) { self.sequence = None; }
+    pub fn is_capturing(&self) -> bool { self.sequence.is_some() }
+
+    /// Files frame with `delta` should be written into, advances sequence
+    fn frame_paths(&mut self, delta: f32) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.screenshots.drain(..).collect();
+        if let Some(seq) = &mut self.sequence {
+            seq.accumulated += delta;
+            while seq.accumulated >= seq.period {
+                seq.accumulated -= seq.period;
+                paths.push(seq.dir.join(format!("frame_{:06}.png", seq.next_index)));
+                seq.next_index += 1;
+            }
+        }
+        paths
+    }
+
+    /// Record copy of `image` after `future` if frame should be captured
+    /// Returned capture must be passed to `push_pending` if frame was submitted, or to `retry` if it was out of date
+    pub fn capture_frame<I>(&mut self, delta: f32, future: Box<dyn GpuFuture>, queue: Arc<Queue>, image: I)
+        -> Result<(Box<dyn GpuFuture>, Option<PendingCapture>), ReadbackError>
+        where I: ImageAccess + Send + Sync + 'static
+    {
+        let paths = self.frame_paths(delta);
+        if paths.is_empty() { return Ok((future, None)); }
+
+        // Not an error for frame itself, capture is just stopped
+        let format = image.format();
+        if !readback::is_supported(format) {
+            println!("Unable to capture frame: {}", ReadbackError::UnsupportedFormat(format));
+            self.sequence = None;
+            return Ok((future, None));
+        }
+
+        let (future, readback) = readback::readback_after(future, queue, image)?;
+        Ok((future, Some(PendingCapture { readback, paths })))
+    }
+
+    pub fn push_pending(&mut self, capture: PendingCapture) { self.pending.push(capture) }
+
+    /// Capture of frame that was not presented (swapchain out of date), its files are written from next frame
+    pub fn retry(&mut self, capture: PendingCapture) { self.requeue(capture.paths) }
+
+    fn requeue(&mut self, mut paths: Vec<PathBuf>) {
+        paths.append(&mut self.screenshots);
+        self.screenshots = paths;
+    }
+
+    /// Start encoding of captures GPU is done with, called once per frame
+    pub fn poll(&mut self) {
+        let mut i = 0;
+        while i < self.pending.len() {
+            match self.pending[i].readback.read() {
+                Ok(image) => {
+                    let capture = self.pending.remove(i);
+                    self.encode(image, capture.paths);
+                },
+                Err(ReadbackError::NotReady) => i += 1,
+                Err(e) => {
+                    println!("Unable to read captured frame: {}", e);
+                    self.pending.remove(i);
+                },
+            }
+        }
+    }
+
+    fn encode(&self, image: readback::ImageReadback, paths: Vec<PathBuf>) {
+        *self.encoding.0.lock().unwrap() += 1;
+        let encoding = self.encoding.clone();
+        rayon::spawn(move || {
+            if let Err(e) = write_png(&image, &paths) {
+                println!("Unable to save captured frame: {}", e);
+            }
+            let (count, done) = &*encoding;
+            *count.lock().unwrap() -= 1;
+            done.notify_all();
+        });
+    }
+
+    /// Encode remaining captures and wait until all files are written
+    /// GPU must be done with every submitted frame
+    pub fn finish(&mut self) {
+        self.sequence = None;
+        self.poll();
+        if !self.pending.is_empty() {
+            println!("Dropping {} captured frames still in use by GPU", self.pending.len());
+            self.pending.clear();
+        }
+
+        let (count, done) = &*self.encoding;
+        let mut count = count.lock().unwrap();
+        while *count > 0 {
+            count = done.wait(count).unwrap();
+        }
+    }
+}
+
+/// Same image may be written into multiple files when sequence repeats frame
+fn write_png(image: &readback::ImageReadback, paths: &[PathBuf]) -> Result<(), ReadbackError> {
+    let rgba = image.to_rgba8()?;
+    let first = &paths[0];
+    rgba.save_png(first)?;
+    for p in paths[1 ..].iter() {
+        std::fs::copy(first, p)?;
+    }
+    Ok(())
+}
+
+mod test {
+
+    #[test] fn test_sequence_frame_paths() {
+        use super::FrameCapture;
+        use std::path::PathBuf;
+
+        let dir = std::env::temp_dir().join("gfx_lib_capture_test");
+        let mut capture = FrameCapture::new();
+        capture.start_sequence(dir.clone(), 10.0);
+
+        // First frame is captured immediately
+        assert_eq!(capture.frame_paths(0.0), vec![dir.join("frame_000000.png")]);
+        // Faster frames are skipped until period is accumulated
+        assert!(capture.frame_paths(0.05).is_empty());
+        assert_eq!(capture.frame_paths(0.05).len(), 1);
+        // Slow frame is repeated
+        assert_eq!(capture.frame_paths(0.25), vec![
+            dir.join("frame_000002.png"),
+            dir.join("frame_000003.png"),
+        ]);
+
+        capture.screenshot(PathBuf::from("shot.png"));
+        capture.stop_sequence();
+        assert_eq!(capture.frame_paths(1.0), vec![PathBuf::from("shot.png")]);
+        assert!(capture.frame_paths(1.0).is_empty());
+
+        // Paths of frame that was not presented go to next frame
+        capture.screenshot(PathBuf::from("next.png"));
+        capture.requeue(vec![PathBuf::from("lost.png")]);
+        assert_eq!(capture.frame_paths(0.0), vec![PathBuf::from("lost.png"), PathBuf::from("next.png")]);
+
+        let _ = std::fs::remove_dir(&dir);
+    }
+}
@@ -0,0 +1,102 @@
+
+// Game clock
+// Splits variable frame time into fixed simulation ticks and keeps interpolation alpha for rendering
+
+/// Fixed timestep accumulator with pause and time scale
+#[derive(Debug, Copy, Clone)]
+pub struct GameClock {
+    tick: f32, // Duration of single fixed tick in seconds
+    max_ticks: u32, // Max ticks per frame, rest of accumulated time is dropped
+    accumulator: f32,
+    time_scale: f32,
+    paused: bool,
+
+    ticks: u64, // Fixed ticks since start
+    time: f64, // Scaled game time since start, without time dropped by `max_ticks`
+}
+impl GameClock {
+
+    /// `tick_rate` fixed ticks per second, at most `max_ticks` per frame
+    pub fn new(tick_rate: f32, max_ticks: u32) -> Self { Self {
+        tick: 1.0 / tick_rate.max(1.0),
+        max_ticks: max_ticks.max(1),
+        accumulator: 0.0,
+        time_scale: 1.0,
+        paused: false,
+
+        ticks: 0,
+        time: 0.0,
+    } }
+
+    /// Accumulate frame time and return how many fixed ticks should run this frame
+    pub fn advance(&mut self, delta: f32) -> u32 {
+        if self.paused { return 0; }
+
+        let scaled = delta.max(0.0) * self.time_scale;
+        self.accumulator += scaled;
+        let mut time = scaled as f64;
+
+        let mut count = (self.accumulator / self.tick) as u32;
+        self.accumulator -= count as f32 * self.tick;
+        if count > self.max_ticks {
+            // Too far behind (breakpoint, long load), drop time instead of spiraling
+            time -= ((count - self.max_ticks) as f32 * self.tick) as f64;
+            count = self.max_ticks;
+        }
+        self.ticks += count as u64;
+        self.time += time;
+        count
+    }
+
+    /// Position between last and next fixed tick in [0, 1), used to interpolate rendered state
+    pub fn alpha(&self) -> f32 { (self.accumulator / self.tick).min(1.0) }
+
+    /// Duration of fixed tick, passed into `GameListener::fixed_update`
+    pub fn tick_delta(&self) -> f32 { self.tick }
+    pub fn set_tick_rate(&mut self, tick_rate: f32) { self.tick = 1.0 / tick_rate.max(1.0); }
+
+    pub fn ticks(&self) -> u64 { self.ticks }
+    pub fn time(&self) -> f64 { self.time }
+
+    pub fn is_paused(&self) -> bool { self.paused }
+    pub fn set_paused(&mut self, paused: bool) { self.paused = paused; }
+
+    pub fn time_scale(&self) -> f32 { self.time_scale }
+    pub fn set_time_scale(&mut self, scale: f32) { self.time_scale = scale.max(0.0); }
+}
+
+mod test {
+
+    #[test] fn test_clock_accumulator() {
+        use super::GameClock;
+
+        let mut clock = GameClock::new(10.0, 5);
+        assert_eq!(clock.advance(0.05), 0);
+        assert!((clock.alpha() - 0.5).abs() < 1e-4);
+        assert_eq!(clock.advance(0.06), 1);
+        assert!((clock.alpha() - 0.1).abs() < 1e-4);
+        assert_eq!(clock.ticks(), 1);
+
+        // Long frame is clamped to max ticks and leftover is dropped
+        assert_eq!(clock.advance(10.0), 5);
+        assert!(clock.alpha() < 1.0);
+        // Game time has only simulated and pending time
+        assert!((clock.time() - 0.61).abs() < 1e-4);
+    }
+
+    #[test] fn test_clock_pause_and_scale() {
+        use super::GameClock;
+
+        let mut clock = GameClock::new(10.0, 100);
+        clock.set_paused(true);
+        assert_eq!(clock.advance(1.0), 0);
+        assert_eq!(clock.time(), 0.0);
+
+        clock.set_paused(false);
+        clock.set_time_scale(2.0);
+        assert_eq!(clock.advance(0.525), 10);
+
+        clock.set_time_scale(0.0);
+        assert_eq!(clock.advance(1.0), 0);
+    }
+}
@@ -0,0 +1,250 @@
+// GPU pass timings from timestamp queries
+// vulkano 0.16 writes timestamps only through `UnsafeCommandBufferBuilder` and can't read query results,
+// so timestamps are written by small command buffers submitted between passes,
+// and results are read with `vkGetQueryPoolResults` loaded through vulkano's loader
+
+use std::{
+    os::raw::{ c_char, c_void },
+    sync::{ Arc, atomic::{ AtomicBool, Ordering } },
+    time::Duration,
+};
+use vulkano::{
+    VulkanObject, OomError,
+    buffer::BufferAccess,
+    command_buffer::{
+        CommandBuffer, CommandBufferExecError,
+        pool::standard::StandardCommandPoolAlloc,
+        sys::{ UnsafeCommandBuffer, UnsafeCommandBufferBuilder, Kind, Flags },
+    },
+    device::{ Device, DeviceOwned, Queue },
+    image::{ ImageAccess, ImageLayout },
+    instance::loader,
+    query::{ UnsafeQueryPool, QueryType },
+    sync::{ AccessCheckError, AccessFlagBits, GpuFuture, PipelineStages },
+};
+
+use super::settings::MAX_FRAMES_IN_FLIGHT;
+use super::profiler::Profiler;
+
+/// Timestamps one frame can write, first one starts the frame, every other ends a pass
+pub const MAX_TIMESTAMPS: u32 = 16;
+/// Frames in flight and one more, so set being reused is finished by GPU
+const FRAME_SETS: u32 = MAX_FRAMES_IN_FLIGHT + 1;
+
+const QUERY_RESULT_64_BIT: u32 = 0x1;
+const VK_SUCCESS: i32 = 0;
+
+type GetDeviceProcAddr = extern "system" fn(usize, *const c_char) -> Option<extern "system" fn()>;
+type GetQueryPoolResults = extern "system" fn(usize, u64, u32, u32, usize, *mut c_void, u64, u32) -> i32;
+
+/// Device, pool and loaded function, None if queue family has no timestamps
+struct Queries {
+    queue: Arc<Queue>,
+    pool: Arc<UnsafeQueryPool>,
+    get_results: GetQueryPoolResults,
+    period: f64, // Nanoseconds per tick
+    mask: u64, // Valid bits of timestamp
+}
+
+/// Times passes submitted on one queue, results are read `FRAME_SETS` frames late without waiting
+/// Does nothing if device can't write timestamps
+pub struct GpuTimer {
+    queries: Option<Queries>,
+    set: u32, // Set of current frame
+    passes: Vec<Vec<&'static str>>, // Passes ended in every set
+    started: Vec<bool>, // Set was reset and has start timestamp
+    last: Vec<(&'static str, Duration)>, // Latest read timings
+}
+impl GpuTimer {
+    pub fn new(queue: Arc<Queue>) -> Self {
+        let queries = match Self::create_queries(queue) {
+            Ok(queries) => queries,
+            Err(e) => {
+                println!("GPU timings are disabled: {}", e);
+                None
+            },
+        };
+        Self {
+            queries,
+            set: 0,
+            passes: vec![vec![]; FRAME_SETS as usize],
+            started: vec![false; FRAME_SETS as usize],
+            last: vec![],
+        }
+    }
+
+    fn create_queries(queue: Arc<Queue>) -> Result<Option<Queries>, String> {
+        let bits = match queue.family().timestamp_valid_bits() {
+            Some(bits) if bits > 0 => bits,
+            _ => return Ok(None),
+        };
+        let device = queue.device().clone();
+        let period = device.physical_device().limits().timestamp_period() as f64;
+
+        // `queries_range` needs one slot past the range
+        let pool = UnsafeQueryPool::new(device.clone(), QueryType::Timestamp, FRAME_SETS * MAX_TIMESTAMPS + 1)
+            .map_err(|e| format!("{:?}", e))?;
+
+        let loader = loader::auto_loader().map_err(|e| format!("{:?}", e))?;
+        let get_results = unsafe {
+            let instance = device.instance().internal_object();
+            let get_device_proc_addr: Option<GetDeviceProcAddr> = std::mem::transmute(
+                loader.get_instance_proc_addr(instance, b"vkGetDeviceProcAddr\0".as_ptr() as *const c_char)
+            );
+            let get_device_proc_addr = get_device_proc_addr.ok_or("No vkGetDeviceProcAddr")?;
+            match get_device_proc_addr(device.internal_object(), b"vkGetQueryPoolResults\0".as_ptr() as *const c_char) {
+                Some(f) => std::mem::transmute::<extern "system" fn(), GetQueryPoolResults>(f),
+                None => return Err(String::from("No vkGetQueryPoolResults")),
+            }
+        };
+
+        Ok(Some(Queries {
+            queue,
+            pool: Arc::new(pool),
+            get_results,
+            period,
+            mask: if bits >= 64 { !0 } else { (1 << bits) - 1 },
+        }))
+    }
+
+    pub fn is_supported(&self) -> bool { self.queries.is_some() }
+
+    /// Start new frame after `future`, reads timings of frame that used same queries
+    pub fn start<F>(&mut self, future: F) -> Box<dyn GpuFuture>
+        where F: GpuFuture + 'static
+    {
+        let queries = match &self.queries {
+            Some(queries) => queries,
+            None => return Box::new(future),
+        };
+
+        self.set = (self.set + 1) % FRAME_SETS;
+        let set = self.set as usize;
+        if self.started[set] {
+            if let Some(timings) = queries.read(self.set, &self.passes[set]) { self.last = timings; }
+        }
+        self.passes[set].clear();
+
+        let first = self.set * MAX_TIMESTAMPS;
+        match queries.commands(Some((first, MAX_TIMESTAMPS)), first) {
+            Ok(cb) => {
+                self.started[set] = true;
+                Box::new(future.then_execute(queries.queue.clone(), cb).unwrap())
+            },
+            Err(_) => {
+                self.started[set] = false;
+                Box::new(future)
+            },
+        }
+    }
+
+    /// End pass `name` submitted after previous `start` or `end`
+    pub fn end<F>(&mut self, name: &'static str, future: F) -> Box<dyn GpuFuture>
+        where F: GpuFuture + 'static
+    {
+        let set = self.set as usize;
+        let queries = match &self.queries {
+            Some(queries) if self.started[set] && (self.passes[set].len() as u32) < MAX_TIMESTAMPS - 1 => queries,
+            _ => return Box::new(future),
+        };
+
+        let query = self.set * MAX_TIMESTAMPS + self.passes[set].len() as u32 + 1;
+        match queries.commands(None, query) {
+            Ok(cb) => {
+                self.passes[set].push(name);
+                Box::new(future.then_execute(queries.queue.clone(), cb).unwrap())
+            },
+            Err(_) => Box::new(future),
+        }
+    }
+
+    /// Latest finished frame timings, in order passes were ended
+    pub fn timings(&self) -> &[(&'static str, Duration)] { &self.last }
+
+    /// Add latest timings into GPU scopes of profiler
+    pub fn report(&self, profiler: &mut Profiler) {
+        for (name, time) in self.last.iter() { profiler.record_gpu(name, *time); }
+    }
+}
+impl Queries {
+
+    /// Command buffer resetting `reset` range and writing timestamp into `query` after all previous work
+    fn commands(&self, reset: Option<(u32, u32)>, query: u32) -> Result<QueryCommands, OomError> {
+        let device = self.queue.device().clone();
+        let pool = Device::standard_command_pool(&device, self.queue.family());
+        unsafe {
+            let mut builder = UnsafeCommandBufferBuilder::new(&pool, Kind::primary(), Flags::OneTimeSubmit)?;
+            if let Some((first, count)) = reset {
+                builder.reset_query_pool(self.pool.queries_range(first, count).unwrap());
+            }
+            builder.write_timestamp(
+                self.pool.query(query).unwrap(),
+                PipelineStages { bottom_of_pipe: true, .. PipelineStages::none() }
+            );
+            Ok(QueryCommands {
+                inner: builder.build()?,
+                _pool: self.pool.clone(),
+                device,
+                submitted: AtomicBool::new(false),
+            })
+        }
+    }
+
+    /// Durations between timestamps of `set`, None if any is not written yet
+    fn read(&self, set: u32, passes: &[&'static str]) -> Option<Vec<(&'static str, Duration)>> {
+        let count = passes.len() + 1;
+        let mut data = vec![0u64; count];
+        let result = (self.get_results)(
+            self.queue.device().internal_object(),
+            self.pool.internal_object(),
+            set * MAX_TIMESTAMPS, count as u32,
+            count * 8, data.as_mut_ptr() as *mut c_void, 8,
+            QUERY_RESULT_64_BIT,
+        );
+        if result != VK_SUCCESS { return None; }
+
+        Some(passes.iter().enumerate().map(|(i, name)| {
+            let ticks = data[i + 1].wrapping_sub(data[i]) & self.mask;
+            (*name, Duration::from_nanos((ticks as f64 * self.period) as u64))
+        }).collect())
+    }
+}
+
+/// One time command buffer without resources, only keeps query pool it writes into alive
+struct QueryCommands {
+    inner: UnsafeCommandBuffer<StandardCommandPoolAlloc>,
+    _pool: Arc<UnsafeQueryPool>, // Not dropped before command buffer is
+    device: Arc<Device>,
+    submitted: AtomicBool,
+}
+unsafe impl DeviceOwned for QueryCommands {
+    fn device(&self) -> &Arc<Device> { &self.device }
+}
+unsafe impl CommandBuffer for QueryCommands {
+    type PoolAlloc = StandardCommandPoolAlloc;
+
+    fn inner(&self) -> &UnsafeCommandBuffer<StandardCommandPoolAlloc> { &self.inner }
+
+    fn lock_submit(&self, _future: &dyn GpuFuture, _queue: &Queue) -> Result<(), CommandBufferExecError> {
+        if self.submitted.swap(true, Ordering::SeqCst) {
+            Err(CommandBufferExecError::OneTimeSubmitAlreadySubmitted)
+        } else {
+            Ok(())
+        }
+    }
+
+    unsafe fn unlock(&self) {}
+
+    // No buffers or images are used, access is decided by previous futures
+    fn check_buffer_access(&self, _buffer: &dyn BufferAccess, _exclusive: bool, _queue: &Queue)
+        -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError>
+    {
+        Err(AccessCheckError::Unknown)
+    }
+
+    fn check_image_access(&self, _image: &dyn ImageAccess, _layout: ImageLayout, _exclusive: bool, _queue: &Queue)
+        -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError>
+    {
+        Err(AccessCheckError::Unknown)
+    }
+}
@@ -0,0 +1,266 @@
+
+// Headless mode: no window, no swapchain, frames are rendered into offscreen image
+
+use std::sync::Arc;
+use std::time::Instant;
+use vulkano::{
+    instance::{ Instance, InstanceExtensions },
+    device::{ Queue, QueuesIter, Device, DeviceExtensions },
+    format::Format,
+    image::{ AttachmentImage, ImageUsage },
+    sync::{ self, GpuFuture },
+};
+
+use crate::graphics::image::sampler_pool::SamplerPool;
+use super::{
+    Frame, FrameImage, FrameRequest, GameListener, ApplicationState,
+    KeyboardState, MouseState,
+    settings::{ self, GameSettings },
+    recording::{ InputEvent, InputPlayer },
+    profiler::Profiler,
+    capture::FrameCapture,
+    state_stack::StateStack,
+};
+
+/// Format of offscreen output image
+pub const HEADLESS_FORMAT: Format = Format::R8G8B8A8Srgb;
+/// Delta passed to listener on every headless frame if `GameSettings::fixed_delta` is not set
+pub const HEADLESS_DELTA: f32 = 1.0 / 60.0;
+
+macro_rules! new_frame {
+    ($runner:expr) => {
+        Frame {
+            queue: $runner.config.main_queue.clone(),
+            image: $runner.config.image.clone() as Arc<dyn FrameImage>,
+            sampler_pool: &mut $runner.sampler_pool,
+            keyboard: &mut $runner.keyboard,
+            mouse: &mut $runner.mouse,
+            profiler: &mut $runner.profiler,
+            top_state: true,
+            clock: $runner.application_state.clock,
+            requests: vec![],
+        }
+    };
+}
+
+/// Device and output image used instead of swapchain
+pub struct HeadlessConfig {
+    image: Arc<AttachmentImage>,
+
+    main_queue: Arc<Queue>,
+    queues: QueuesIter,
+}
+impl HeadlessConfig {
+
+    /// Output image has dimensions of `settings.window_size`
+    pub fn create(instance: &Arc<Instance>, settings: &GameSettings) -> Result<Self, String> {
+        let physical = settings.select_physical_device(instance)?;
+
+        let queue_family = match physical.queue_families().find(|&q| q.supports_graphics()) {
+            Some(q) => q,
+            None => return Err(String::from("No graphics queue available (headless)")),
+        };
+
+        let (device, mut queues) = match Device::new(
+            physical, physical.supported_features(), &DeviceExtensions::none(),
+            [(queue_family, 0.5)].iter().cloned()
+        ) {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{:?}", e)),
+        };
+
+        let main_queue = queues.next().unwrap();
+
+        let (w, h) = settings.window_size;
+        let image = Self::create_image(&device, [w, h])?;
+
+        Ok(Self {
+            image,
+
+            main_queue,
+            queues,
+        })
+    }
+
+    fn create_image(device: &Arc<Device>, dimensions: [u32; 2]) -> Result<Arc<AttachmentImage>, String> {
+        let usage = ImageUsage {
+            color_attachment: true,
+            sampled: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        };
+        AttachmentImage::with_usage(device.clone(), dimensions, HEADLESS_FORMAT, usage)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    pub fn device(&self) -> Arc<Device> { self.main_queue.device().clone() }
+    pub fn queue(&self) -> Arc<Queue> { self.main_queue.clone() }
+    pub fn image(&self) -> Arc<AttachmentImage> { self.image.clone() }
+}
+
+/// Drives `GameListener` without window, frame by frame
+/// Every frame is waited for, so output image and readbacks are valid after `run_frame`
+pub struct HeadlessRunner {
+    application_state: ApplicationState,
+    config: HeadlessConfig,
+    keyboard: KeyboardState,
+    mouse: MouseState,
+    profiler: Profiler,
+    capture: FrameCapture,
+    sampler_pool: SamplerPool,
+    listener: Option<StateStack>,
+    last_sync: Option<Box<dyn GpuFuture>>,
+    delta: f32,
+    replay: Option<InputPlayer>,
+}
+impl HeadlessRunner {
+
+    /// Create device and output image of `settings.window_size`, and init listener
+    pub fn new<F>(settings: &GameSettings, mut init_listener: F) -> Result<Self, String>
+        where F: FnMut(&mut Frame) -> Box<dyn GameListener>
+    {
+        let instance = settings::create_instance(&InstanceExtensions::none())?;
+        let (w, h) = settings.window_size;
+        let config = HeadlessConfig::create(&instance, settings)?;
+
+        let mut runner = Self {
+            application_state: ApplicationState::new(settings),
+            sampler_pool: SamplerPool::new(config.device()),
+            last_sync: Some(Box::new(sync::now(config.device()))),
+            config,
+            keyboard: KeyboardState::new(),
+            mouse: MouseState::new(),
+            profiler: Profiler::new(),
+            capture: FrameCapture::new(),
+            listener: None,
+            delta: settings.fixed_delta.unwrap_or(HEADLESS_DELTA),
+            replay: None,
+        };
+
+        let mut init_frame = new_frame!(runner);
+        let first = init_listener(&mut init_frame);
+        let mut l = StateStack::new(&mut init_frame, first);
+        l.dimensions_changed(&mut init_frame, w, h);
+        runner.application_state.accept(init_frame);
+        runner.apply_requests();
+        runner.listener = Some(l);
+
+        Ok(runner)
+    }
+
+    /// Update and draw single frame, blocks until GPU work is finished
+    /// Returns false if listener requested exit
+    pub fn run_frame(&mut self) -> Result<bool, String> {
+        if !self.application_state.running { return Ok(false); }
+
+        let mut listener = self.listener.take().unwrap();
+        let last_sync = self.last_sync.take().unwrap();
+
+        let (delta, events) = match self.replay.as_mut().and_then(|r| r.next_frame()) {
+            Some(f) => (f.delta, f.events),
+            None => (self.delta, vec![]),
+        };
+
+        let ticks = self.application_state.clock.advance(delta);
+        let fixed_start = Instant::now();
+        for _ in 0 .. ticks {
+            let mut frame = new_frame!(self);
+            listener.fixed_update(self.application_state.clock.tick_delta(), &mut frame);
+            self.application_state.accept(frame);
+        }
+        if ticks > 0 { self.profiler.record("fixed_update", fixed_start.elapsed()); }
+
+        let mut frame = new_frame!(self);
+        let update_start = Instant::now();
+        let future = listener.update(delta, &mut frame, last_sync);
+        frame.profiler.record("update", update_start.elapsed());
+        self.application_state.accept(frame);
+        self.listener = Some(listener);
+
+        // Includes waiting for GPU, unlike windowed loop
+        let submit_start = Instant::now();
+        let (future, captured) = self.capture.capture_frame(delta, future, self.config.queue(), self.config.image())
+            .map_err(|e| format!("Unable to capture frame: {}", e))?;
+        match future.then_signal_fence_and_flush() {
+            Ok(future) => {
+                future.wait(None).map_err(|e| format!("{:?}", e))?;
+                self.last_sync = Some(Box::new(future));
+            },
+            Err(e) => return Err(format!("{:?}", e)),
+        }
+        if let Some(c) = captured { self.capture.push_pending(c); }
+        self.capture.poll();
+        self.profiler.record("submit", submit_start.elapsed());
+
+        // Replayed input for next frame, same order as windowed main loop
+        self.keyboard.end_frame();
+        self.mouse.end_frame();
+        let mut listener = self.listener.take().unwrap();
+        let mut frame = new_frame!(self);
+        let mut resized = None;
+        for e in events.iter() {
+            match e {
+                InputEvent::CloseRequested => self.application_state.running = false,
+                &InputEvent::Resized(w, h) => resized = Some([w as u32, h as u32]),
+                e => e.dispatch(&mut frame, &mut listener),
+            }
+        }
+        self.application_state.accept(frame);
+        self.apply_requests();
+        self.listener = Some(listener);
+        if let Some(dims) = resized { self.resize(dims)?; }
+
+        self.mouse.update(delta);
+        self.profiler.end_frame(delta);
+
+        Ok(self.application_state.running)
+    }
+
+    /// Recreate output image for replayed resize, there is no hidpi scaling so logical size is used as is
+    fn resize(&mut self, dimensions: [u32; 2]) -> Result<(), String> {
+        if dimensions[0] == 0 || dimensions[1] == 0 || dimensions == self.config.image.dimensions() { return Ok(()); }
+        self.config.image = HeadlessConfig::create_image(&self.config.device(), dimensions)?;
+
+        let listener = self.listener.as_mut().unwrap();
+        let mut frame = new_frame!(self);
+        listener.dimensions_changed(&mut frame, dimensions[0], dimensions[1]);
+        self.application_state.accept(frame);
+        Ok(())
+    }
+
+    /// Only capture requests are applied, there is no window for the rest
+    fn apply_requests(&mut self) {
+        for r in self.application_state.window_requests.drain(..) {
+            match r {
+                FrameRequest::Screenshot(path) => self.capture.screenshot(path),
+                FrameRequest::StartCapture(dir, fps) => self.capture.start_sequence(dir, fps),
+                FrameRequest::StopCapture => self.capture.stop_sequence(),
+                _ => (),
+            }
+        }
+    }
+
+    /// Run up to `frames` frames, returns number of frames actually drawn
+    pub fn run(&mut self, frames: u32) -> Result<u32, String> {
+        let mut drawn = 0;
+        while drawn < frames && self.application_state.running {
+            self.run_frame()?;
+            drawn += 1;
+        }
+        Ok(drawn)
+    }
+
+    /// Feed recorded input and deltas instead of `HEADLESS_DELTA`, until recording is over
+    pub fn set_replay(&mut self, replay: InputPlayer) { self.replay = Some(replay); }
+    pub fn is_replaying(&self) -> bool { self.replay.as_ref().map(|r| !r.is_finished()).unwrap_or(false) }
+
+    pub fn queue(&self) -> Arc<Queue> { self.config.queue() }
+    /// Output image, contains result of last drawn frame
+    pub fn image(&self) -> Arc<AttachmentImage> { self.config.image() }
+    pub fn sampler_pool(&mut self) -> &mut SamplerPool { &mut self.sampler_pool }
+    pub fn profiler(&self) -> &Profiler { &self.profiler }
+}
+/// Captured frames are still being written on rayon pool
+impl Drop for HeadlessRunner {
+    fn drop(&mut self) { self.capture.finish(); }
+}
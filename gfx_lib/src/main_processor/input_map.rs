@@ -0,0 +1,465 @@
+
+// Input action mapping
+// Named actions and axes on top of KeyboardState and MouseState, so bindings can be changed by player
+
+use std::collections::BTreeMap;
+use winit::{ VirtualKeyCode, MouseButton, ModifiersState };
+use serializer::{ Data, DataObject, DataObtainError, Peek, PeekResult, Persistent, PersistentError };
+
+use super::{ Frame, KeyboardState, MouseState };
+
+/// Layout version of serialized bindings
+pub const INPUT_MAP_VERSION: u16 = 1;
+
+/// Physical button
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InputSource {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+impl InputSource {
+    fn is_down(&self, keyboard: &KeyboardState, mouse: &MouseState) -> bool {
+        match self {
+            InputSource::Key(key) => keyboard.state_of(*key),
+            InputSource::Mouse(button) => mouse.state_of(*button),
+        }
+    }
+    fn pressed(&self, keyboard: &KeyboardState, mouse: &MouseState) -> bool {
+        match self {
+            InputSource::Key(key) => keyboard.pressed_this_frame(*key),
+            InputSource::Mouse(button) => mouse.pressed_this_frame(*button),
+        }
+    }
+    fn released(&self, keyboard: &KeyboardState, mouse: &MouseState) -> bool {
+        match self {
+            InputSource::Key(key) => keyboard.released_this_frame(*key),
+            InputSource::Mouse(button) => mouse.released_this_frame(*button),
+        }
+    }
+}
+
+/// Button with required modifiers (chord), extra modifiers are allowed
+/// unless action bound to same button with more of held modifiers takes the press, e.g. Shift+F12 over F12
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Binding {
+    pub source: InputSource,
+    pub modifiers: ModifiersState,
+}
+impl Binding {
+    pub fn key(key: VirtualKeyCode) -> Self { Self {
+        source: InputSource::Key(key),
+        modifiers: ModifiersState::default(),
+    } }
+    pub fn mouse(button: MouseButton) -> Self { Self {
+        source: InputSource::Mouse(button),
+        modifiers: ModifiersState::default(),
+    } }
+    #[inline] pub fn with_shift(mut self) -> Self { self.modifiers.shift = true; self }
+    #[inline] pub fn with_ctrl(mut self) -> Self { self.modifiers.ctrl = true; self }
+    #[inline] pub fn with_alt(mut self) -> Self { self.modifiers.alt = true; self }
+    #[inline] pub fn with_logo(mut self) -> Self { self.modifiers.logo = true; self }
+
+    fn modifiers_held(&self, keyboard: &KeyboardState) -> bool {
+        let held = keyboard.modifiers();
+        (!self.modifiers.shift || held.shift) && (!self.modifiers.ctrl || held.ctrl)
+            && (!self.modifiers.alt || held.alt) && (!self.modifiers.logo || held.logo)
+    }
+
+    fn modifier_count(&self) -> usize {
+        let m = self.modifiers;
+        m.shift as usize + m.ctrl as usize + m.alt as usize + m.logo as usize
+    }
+}
+
+/// Source of axis value
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AxisSource {
+    Buttons { positive: Binding, negative: Binding }, // +1, -1 or 0
+    MouseX, // Cursor speed
+    MouseY,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AxisBinding {
+    pub source: AxisSource,
+    pub scale: f32,
+}
+impl AxisBinding {
+    pub fn buttons(positive: Binding, negative: Binding) -> Self { Self {
+        source: AxisSource::Buttons { positive, negative },
+        scale: 1.0,
+    } }
+    pub fn keys(positive: VirtualKeyCode, negative: VirtualKeyCode) -> Self {
+        Self::buttons(Binding::key(positive), Binding::key(negative))
+    }
+    pub fn mouse_x(scale: f32) -> Self { Self { source: AxisSource::MouseX, scale } }
+    pub fn mouse_y(scale: f32) -> Self { Self { source: AxisSource::MouseY, scale } }
+}
+
+/// Named actions and axes, each with any number of bindings
+#[derive(Debug, Clone, Default)]
+pub struct InputMap {
+    actions: BTreeMap<String, Vec<Binding>>,
+    axes: BTreeMap<String, Vec<AxisBinding>>,
+}
+/// Binding
+impl InputMap {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn bind_action<S: Into<String>>(&mut self, name: S, binding: Binding) -> &mut Self {
+        self.actions.entry(name.into()).or_insert_with(Vec::new).push(binding);
+        self
+    }
+    pub fn bind_axis<S: Into<String>>(&mut self, name: S, binding: AxisBinding) -> &mut Self {
+        self.axes.entry(name.into()).or_insert_with(Vec::new).push(binding);
+        self
+    }
+
+    /// Remove all bindings of action or axis, used before rebinding
+    pub fn clear_action(&mut self, name: &str) { self.actions.remove(name); }
+    pub fn clear_axis(&mut self, name: &str) { self.axes.remove(name); }
+
+    pub fn action_bindings(&self, name: &str) -> &[Binding] {
+        self.actions.get(name).map(|b| b.as_slice()).unwrap_or(&[])
+    }
+    pub fn axis_bindings(&self, name: &str) -> &[AxisBinding] {
+        self.axes.get(name).map(|b| b.as_slice()).unwrap_or(&[])
+    }
+}
+/// Queries, "this frame" is time between two `GameListener::update` calls
+impl InputMap {
+    pub fn is_down(&self, frame: &Frame, action: &str) -> bool {
+        self.state_query(frame, action, |b, k, m| b.source.is_down(k, m))
+    }
+    pub fn pressed_this_frame(&self, frame: &Frame, action: &str) -> bool {
+        self.state_query(frame, action, |b, k, m| b.source.pressed(k, m))
+    }
+    pub fn released_this_frame(&self, frame: &Frame, action: &str) -> bool {
+        self.state_query(frame, action, |b, k, m| b.source.released(k, m))
+    }
+
+    /// Sum of all bindings of axis
+    pub fn axis(&self, frame: &Frame, axis: &str) -> f32 {
+        let (keyboard, mouse) = (frame.keyboard(), frame.mouse());
+        self.axis_bindings(axis).iter().map(|b| {
+            let value = match &b.source {
+                AxisSource::Buttons { positive, negative } => {
+                    let held = |b: &Binding| b.source.is_down(keyboard, mouse) && b.modifiers_held(keyboard);
+                    (if held(positive) { 1.0 } else { 0.0 }) - (if held(negative) { 1.0 } else { 0.0 })
+                },
+                AxisSource::MouseX => mouse.speed()[0],
+                AxisSource::MouseY => mouse.speed()[1],
+            };
+            value * b.scale
+        }).sum()
+    }
+
+    fn state_query<F>(&self, frame: &Frame, action: &str, query: F) -> bool
+        where F: Fn(&Binding, &KeyboardState, &MouseState) -> bool
+    {
+        self.query_with(frame.keyboard(), frame.mouse(), action, query)
+    }
+
+    fn query_with<F>(&self, keyboard: &KeyboardState, mouse: &MouseState, action: &str, query: F) -> bool
+        where F: Fn(&Binding, &KeyboardState, &MouseState) -> bool
+    {
+        self.action_bindings(action).iter()
+            .any(|b| b.modifiers_held(keyboard) && !self.is_shadowed(b, keyboard) && query(b, keyboard, mouse))
+    }
+
+    /// Some action has binding of same button with more modifiers, all of them held
+    fn is_shadowed(&self, binding: &Binding, keyboard: &KeyboardState) -> bool {
+        self.actions.values().flatten().any(|other| {
+            other.source == binding.source && other.modifier_count() > binding.modifier_count()
+                && other.modifiers_held(keyboard)
+        })
+    }
+}
+
+// Names of keys used in serialized bindings and input recordings
+macro_rules! key_names {
+    ( $( $key:ident ),* $(,)? ) => {
+        pub fn key_name(key: VirtualKeyCode) -> Option<&'static str> {
+            match key {
+                $( VirtualKeyCode::$key => Some(stringify!($key)), )*
+                _ => None,
+            }
+        }
+        pub fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+            match name {
+                $( stringify!($key) => Some(VirtualKeyCode::$key), )*
+                _ => None,
+            }
+        }
+    };
+}
+key_names!(
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Escape, Space, Return, Tab, Back, Insert, Delete, Home, End, PageUp, PageDown,
+    Left, Right, Up, Down,
+    LShift, RShift, LControl, RControl, LAlt, RAlt, LWin, RWin,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    Minus, Equals, Grave, Comma, Period, Slash, Backslash, Semicolon, Apostrophe, LBracket, RBracket,
+    Add, Subtract, Multiply, Divide, Decimal, NumpadEnter, Numlock,
+    Capital, Scroll, Pause, Snapshot, Sysrq, Apps, Compose,
+);
+
+pub fn mouse_name(button: MouseButton) -> String {
+    match button {
+        MouseButton::Left => "Left".to_string(),
+        MouseButton::Right => "Right".to_string(),
+        MouseButton::Middle => "Middle".to_string(),
+        MouseButton::Other(id) => format!("Other{}", id),
+    }
+}
+pub fn mouse_from_name(name: &str) -> Option<MouseButton> {
+    match name {
+        "Left" => Some(MouseButton::Left),
+        "Right" => Some(MouseButton::Right),
+        "Middle" => Some(MouseButton::Middle),
+        _ if name.starts_with("Other") => name[5 ..].parse().ok().map(MouseButton::Other),
+        _ => None,
+    }
+}
+
+fn invalid(key: &str, value: String) -> PersistentError {
+    PersistentError::InvalidField(key.to_string(), DataObtainError::StringParseError(value, key.to_string()))
+}
+
+fn string_field(val: &Data, key: &str) -> Option<String> {
+    match val.obj_get(key) {
+        PeekResult::Ok(d) | PeekResult::Lossy(d) => match Peek::<String, DataObtainError>::peek(d) {
+            PeekResult::Ok(s) | PeekResult::Lossy(s) => Some(s),
+            PeekResult::Err(_) => None,
+        },
+        PeekResult::Err(_) => None,
+    }
+}
+
+/// { "key": "W" } or { "mouse": "Left" }, with optional "mods": "ctrl+shift"
+impl Persistent for Binding {
+    fn read(&mut self, val: Data) -> Result<(), PersistentError> {
+        self.source = if let Some(name) = string_field(&val, "key") {
+            InputSource::Key(key_from_name(&name).ok_or_else(|| invalid("key", name))?)
+        } else if let Some(name) = string_field(&val, "mouse") {
+            InputSource::Mouse(mouse_from_name(&name).ok_or_else(|| invalid("mouse", name))?)
+        } else {
+            return Err(PersistentError::UnableToDeserialize);
+        };
+
+        self.modifiers = ModifiersState::default();
+        if let Some(mods) = string_field(&val, "mods") {
+            for m in mods.split('+').filter(|m| !m.is_empty()) {
+                match m {
+                    "shift" => self.modifiers.shift = true,
+                    "ctrl" => self.modifiers.ctrl = true,
+                    "alt" => self.modifiers.alt = true,
+                    "logo" => self.modifiers.logo = true,
+                    _ => return Err(invalid("mods", mods.clone())),
+                }
+            }
+        }
+        Ok(())
+    }
+    fn write(&self) -> Data {
+        let mods: Vec<&str> = [
+            (self.modifiers.shift, "shift"), (self.modifiers.ctrl, "ctrl"),
+            (self.modifiers.alt, "alt"), (self.modifiers.logo, "logo"),
+        ].iter().filter(|(on, _)| *on).map(|(_, name)| *name).collect();
+
+        let mut data = match self.source {
+            // Keys outside of name table can't be saved, they are written as None and skipped on read
+            InputSource::Key(key) => match key_name(key) {
+                Some(name) => DataObject! { key => name },
+                None => return Data::None,
+            },
+            InputSource::Mouse(button) => DataObject! { mouse => mouse_name(button) },
+        };
+        if !mods.is_empty() {
+            if let Data::Object(map) = &mut data { map.insert("mods".to_string(), mods.join("+").into()); }
+        }
+        data
+    }
+}
+
+fn read_binding(val: Data) -> Result<Binding, PersistentError> {
+    let mut binding = Binding::key(VirtualKeyCode::Escape);
+    binding.read(val)?;
+    Ok(binding)
+}
+
+/// { "positive": Binding, "negative": Binding, "scale": f32 } or { "mouse": "x" | "y", "scale": f32 }
+impl Persistent for AxisBinding {
+    fn read(&mut self, val: Data) -> Result<(), PersistentError> {
+        self.source = match string_field(&val, "mouse").as_ref().map(|s| s.as_str()) {
+            Some("x") => AxisSource::MouseX,
+            Some("y") => AxisSource::MouseY,
+            Some(other) => return Err(invalid("mouse", other.to_string())),
+            None => {
+                let positive = val.obj_get("positive").unwrap_or(Data::None);
+                let negative = val.obj_get("negative").unwrap_or(Data::None);
+                AxisSource::Buttons { positive: read_binding(positive)?, negative: read_binding(negative)? }
+            },
+        };
+        self.scale = match val.obj_get("scale") {
+            PeekResult::Ok(d) | PeekResult::Lossy(d) => d.peek().unwrap_or(1.0),
+            PeekResult::Err(_) => 1.0,
+        };
+        Ok(())
+    }
+    fn write(&self) -> Data {
+        match &self.source {
+            // Whole binding is skipped then either key can't be saved
+            AxisSource::Buttons { positive, negative } => match (positive.write(), negative.write()) {
+                (Data::None, _) | (_, Data::None) => Data::None,
+                (positive, negative) => DataObject! {
+                    positive => positive,
+                    negative => negative,
+                    scale => self.scale
+                },
+            },
+            AxisSource::MouseX => DataObject! { mouse => "x", scale => self.scale },
+            AxisSource::MouseY => DataObject! { mouse => "y", scale => self.scale },
+        }
+    }
+}
+
+/// { "ver": 1, "actions": { name: [Binding] }, "axes": { name: [AxisBinding] } }
+/// Reading replaces all bindings
+impl Persistent for InputMap {
+    fn read(&mut self, val: Data) -> Result<(), PersistentError> {
+        let ver: u16 = match val.obj_get("ver") {
+            PeekResult::Ok(d) | PeekResult::Lossy(d) => d.peek().unwrap_or(0),
+            PeekResult::Err(_) => 0,
+        };
+        if ver == 0 || ver > INPUT_MAP_VERSION { return Err(PersistentError::UnknownVersion(ver)); }
+
+        let mut actions = BTreeMap::new();
+        if let PeekResult::Ok(Data::Object(map)) = val.obj_get("actions") {
+            for (name, list) in map {
+                let bindings = list_items(list).into_iter()
+                    .filter(|d| d.has_data())
+                    .map(read_binding)
+                    .collect::<Result<Vec<_>, _>>()?;
+                actions.insert(name, bindings);
+            }
+        }
+
+        let mut axes = BTreeMap::new();
+        if let PeekResult::Ok(Data::Object(map)) = val.obj_get("axes") {
+            for (name, list) in map {
+                let mut bindings = vec![];
+                for d in list_items(list).into_iter().filter(|d| d.has_data()) {
+                    let mut binding = AxisBinding::mouse_x(1.0);
+                    binding.read(d)?;
+                    bindings.push(binding);
+                }
+                axes.insert(name, bindings);
+            }
+        }
+
+        self.actions = actions;
+        self.axes = axes;
+        Ok(())
+    }
+    fn write(&self) -> Data {
+        let actions = self.actions.iter()
+            .map(|(name, list)| (name.clone(), Data::Array(list.iter().map(|b| b.write()).collect())))
+            .collect();
+        let axes = self.axes.iter()
+            .map(|(name, list)| (name.clone(), Data::Array(list.iter().map(|b| b.write()).collect())))
+            .collect();
+        DataObject! {
+            ver => INPUT_MAP_VERSION,
+            actions => Data::Object(actions),
+            axes => Data::Object(axes)
+        }
+    }
+}
+
+fn list_items(list: Data) -> Vec<Data> {
+    match list {
+        Data::Array(items) => items,
+        Data::None => vec![],
+        other => vec![other],
+    }
+}
+
+mod test {
+
+    #[test] fn test_chord_priority() {
+        use super::{ InputMap, Binding };
+        use crate::main_processor::{ KeyboardState, MouseState };
+        use winit::{ VirtualKeyCode, ElementState };
+
+        let mut map = InputMap::new();
+        map.bind_action("screenshot", Binding::key(VirtualKeyCode::F12))
+            .bind_action("capture", Binding::key(VirtualKeyCode::F12).with_shift())
+            .bind_action("jump", Binding::key(VirtualKeyCode::Space));
+        let pressed = |map: &InputMap, k: &KeyboardState, action: &str|
+            map.query_with(k, &MouseState::new(), action, |b, k, m| b.source.pressed(k, m));
+
+        let mut keyboard = KeyboardState::new();
+        keyboard.key_event(VirtualKeyCode::F12, ElementState::Pressed);
+        assert!(pressed(&map, &keyboard, "screenshot"));
+        assert!(!pressed(&map, &keyboard, "capture"));
+
+        // Shift+F12 goes only to more specific chord, other buttons still allow extra modifiers
+        keyboard.end_frame();
+        keyboard.key_event(VirtualKeyCode::F12, ElementState::Released);
+        keyboard.key_event(VirtualKeyCode::LShift, ElementState::Pressed);
+        keyboard.key_event(VirtualKeyCode::F12, ElementState::Pressed);
+        keyboard.key_event(VirtualKeyCode::Space, ElementState::Pressed);
+        assert!(pressed(&map, &keyboard, "capture"));
+        assert!(!pressed(&map, &keyboard, "screenshot"));
+        assert!(pressed(&map, &keyboard, "jump"));
+    }
+
+    #[test] fn test_input_map_round_trip() {
+        use super::{ InputMap, Binding, AxisBinding };
+        use serializer::{ Data, Persistent };
+        use winit::{ VirtualKeyCode, MouseButton };
+
+        let mut map = InputMap::new();
+        map.bind_action("jump", Binding::key(VirtualKeyCode::Space))
+            .bind_action("jump", Binding::mouse(MouseButton::Other(4)))
+            .bind_action("save", Binding::key(VirtualKeyCode::S).with_ctrl())
+            .bind_axis("move_forward", AxisBinding::keys(VirtualKeyCode::W, VirtualKeyCode::S))
+            .bind_axis("look_x", AxisBinding::mouse_x(0.5));
+
+        let json = serde_json::to_string(&map.write()).unwrap();
+        let data: Data = serde_json::from_str(&json).unwrap();
+        let mut read = InputMap::new();
+        read.read(data).unwrap();
+
+        assert_eq!(read.action_bindings("jump"), map.action_bindings("jump"));
+        assert_eq!(read.action_bindings("save"), map.action_bindings("save"));
+        assert!(read.action_bindings("save")[0].modifiers.ctrl);
+        assert_eq!(read.axis_bindings("move_forward"), map.axis_bindings("move_forward"));
+        assert_eq!(read.axis_bindings("look_x"), map.axis_bindings("look_x"));
+
+        // Keys outside of name table are skipped, rest of bindings is kept
+        let mut map = InputMap::new();
+        map.bind_action("mail", Binding::key(VirtualKeyCode::Mail))
+            .bind_action("mail", Binding::key(VirtualKeyCode::M))
+            .bind_axis("zoom", AxisBinding::keys(VirtualKeyCode::Mail, VirtualKeyCode::Minus))
+            .bind_axis("zoom", AxisBinding::keys(VirtualKeyCode::Equals, VirtualKeyCode::Minus));
+
+        let json = serde_json::to_string(&map.write()).unwrap();
+        let mut read = InputMap::new();
+        read.read(serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(read.action_bindings("mail"), &[Binding::key(VirtualKeyCode::M)]);
+        assert_eq!(read.axis_bindings("zoom"), &[AxisBinding::keys(VirtualKeyCode::Equals, VirtualKeyCode::Minus)]);
+    }
+
+    #[test] fn test_key_names() {
+        use super::{ key_name, key_from_name };
+        use winit::VirtualKeyCode;
+
+        assert_eq!(key_name(VirtualKeyCode::LShift), Some("LShift"));
+        assert_eq!(key_from_name("F11"), Some(VirtualKeyCode::F11));
+        assert_eq!(key_from_name("NotAKey"), None);
+    }
+}
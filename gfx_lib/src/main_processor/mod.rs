@@ -6,14 +6,18 @@ use vulkano_win::{ VkSurfaceBuild };
 use vulkano::{
     instance::{ Instance, QueueFamily, PhysicalDevice, MemoryType, ApplicationInfo },
     device::{ Queue, QueuesIter, Device, DeviceExtensions, DeviceOwned },
-    swapchain::{ self, Surface, Swapchain, SurfaceTransform, PresentMode, AcquireError, SwapchainAcquireFuture},
-    image::{ SwapchainImage },
-    sync::{ self, GpuFuture, FlushError },
+    swapchain::{ self, Surface, Swapchain, SurfaceTransform, CompositeAlpha, PresentMode, SupportedPresentModes, ColorSpace, AcquireError, SwapchainAcquireFuture, PresentFuture },
+    format::Format,
+    image::{ SwapchainImage, ImageAccess, ImageViewAccess, ImageUsage },
+    sync::{ self, GpuFuture, FlushError, FenceSignalFuture },
 };
-use winit::{EventsLoop, dpi::{LogicalPosition, LogicalSize}, VirtualKeyCode, ElementState, Window, MouseButton};
+use winit::{EventsLoop, dpi::{LogicalPosition, LogicalSize}, VirtualKeyCode, ElementState, Window, MouseButton, Icon, ModifiersState};
 
 use std::sync::Arc;
 use std::cell::{RefCell, Ref};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::Instant;
 
 use crate::loader;
 use crate::graphics::{
@@ -25,8 +29,23 @@ use crate::graphics::{
 };
 
 pub mod settings;
+pub mod headless;
+pub mod clock;
+pub mod input_map;
+pub mod recording;
+pub mod profiler;
+pub mod gpu_timer;
+pub mod capture;
+pub mod state_stack;
+use clock::GameClock;
+use state_stack::{ GameState, StateStack };
+use capture::FrameCapture;
+use profiler::Profiler;
+use recording::{ InputEvent, InputRecording, InputPlayer };
 use settings::{
     GameSettings,
+    WindowMode,
+    ColorMode,
     WindowInfo
 };
 use cgmath::Matrix4;
@@ -38,19 +57,77 @@ use crate::graphics::image::sampler_pool::SamplerPool;
 pub trait GameListener {
     fn dimensions_changed(&mut self, frame: &mut Frame, width: u32, height: u32) {}
 
+    /// Called `GameSettings::tick_rate` times per second of scaled game time, before `update`
+    /// Not called while game clock is paused
+    fn fixed_update(&mut self, tick: f32, frame: &mut Frame) {}
+
+    /// Called once per presented frame with real (unscaled) frame time
+    /// Use `frame.clock().alpha()` to interpolate state between fixed ticks
     fn update(&mut self, delta: f32, frame: &mut Frame, future: Box<dyn GpuFuture>) -> Box<dyn GpuFuture>;
 
     fn key_pressed(&mut self, frame: &mut Frame, keycode: VirtualKeyCode) { }
     fn key_released(&mut self, frame: &mut Frame, keycode: VirtualKeyCode) { }
 
     fn mouse_wheel(&mut self, frame: &mut Frame, x: f32, y: f32) { }
+    /// Touchpad scroll in logical pixels, by default converted into lines
+    fn mouse_wheel_pixels(&mut self, frame: &mut Frame, x: f32, y: f32) {
+        self.mouse_wheel(frame, x / PIXELS_PER_WHEEL_LINE, y / PIXELS_PER_WHEEL_LINE)
+    }
+
+    fn mouse_pressed(&mut self, frame: &mut Frame, button: MouseButton) { }
+    fn mouse_released(&mut self, frame: &mut Frame, button: MouseButton) { }
+    /// New cursor position in logical pixels
+    fn cursor_moved(&mut self, frame: &mut Frame, x: f32, y: f32) { }
+
+    /// Text input, already with layout and modifiers applied
+    fn received_character(&mut self, frame: &mut Frame, c: char) { }
+
+    fn focus_changed(&mut self, frame: &mut Frame, focused: bool) { }
+    /// Window was minimised or restored, `update` is not called while minimised
+    fn minimised(&mut self, frame: &mut Frame, minimised: bool) { }
+    fn file_dropped(&mut self, frame: &mut Frame, path: PathBuf) { }
+
+    /// State was pushed onto `state_stack::StateStack`, called before first `dimensions_changed`
+    fn enter(&mut self, frame: &mut Frame) { }
+    /// State was popped or replaced
+    fn exit(&mut self, frame: &mut Frame) { }
+    /// Overlay states let state under them update and render, events still go only to top state
+    fn is_overlay(&self) -> bool { false }
 }
 
+/// Pixels of touchpad scroll reported as one wheel line
+pub const PIXELS_PER_WHEEL_LINE: f32 = 20.0;
+/// Sleep between event polls while window is minimised
+const MINIMISED_SLEEP_MS: u64 = 10;
+
 /// Requests on to do to some parts of backend from window user
 /// Ex: Window settings, ...
+#[derive(Debug)]
 pub enum FrameRequest {
     ExitApplication,
     HoldCursor(Option<bool>), // If none => switch state
+    SetVSync(Option<bool>), // If none => switch state, ignored in headless mode
+
+    // Window requests, ignored in headless mode
+    SetWindowMode(WindowMode),
+    SetWindowSize(u32, u32), // Logical size, applied to windowed mode only
+    SetWindowTitle(String),
+    SetWindowIcon(Option<Icon>), // See `settings::load_icon`
+    SaveSettings, // Save current settings (with runtime window changes) into file they were loaded from
+
+    // Game clock
+    PauseClock(Option<bool>), // If none => switch state
+    SetTimeScale(f32),
+
+    // Capture of next presented frames into PNG files, see `capture::FrameCapture`
+    Screenshot(PathBuf),
+    StartCapture(PathBuf, f32), // Directory for numbered frames, frames per second
+    StopCapture,
+
+    // State stack, applied right after callback that requested them
+    PushState(GameState),
+    PopState, // Application exits then last state is popped
+    ReplaceState(GameState), // Pop top state and push new one
 }
 
 /// Holds state of application
@@ -60,35 +137,64 @@ pub enum FrameRequest {
 struct ApplicationState {
     running: bool,
     hold_cursor: bool,
+    vsync: bool,
+    vsync_changed: bool, // Swapchain must be rebuilt with new present mode
+    window_requests: Vec<FrameRequest>, // Requests applied to window by main loop
+    clock: GameClock,
 }
-impl Default for ApplicationState {
-    fn default() -> Self { Self {
+impl ApplicationState {
+    fn new(settings: &GameSettings) -> Self { Self {
         running: true,
         hold_cursor: false,
+        vsync: true,
+        vsync_changed: false,
+        window_requests: vec![],
+        clock: {
+            let mut clock = GameClock::new(settings.tick_rate, settings.max_ticks_per_frame);
+            clock.set_time_scale(settings.time_scale);
+            clock
+        },
     } }
-}
-impl ApplicationState {
-    fn accept(&mut self, frame: Frame) {
-        for r in frame.requests.iter() {
+
+    fn accept(&mut self, mut frame: Frame) {
+        for r in frame.requests.drain(..) {
             use FrameRequest::*;
             match r {
                 ExitApplication => self.running = false,
                 HoldCursor(flag) => self.hold_cursor = flag.unwrap_or(!self.hold_cursor),
+                SetVSync(flag) => {
+                    let vsync = flag.unwrap_or(!self.vsync);
+                    self.vsync_changed |= vsync != self.vsync;
+                    self.vsync = vsync;
+                },
+                PauseClock(flag) => {
+                    let paused = flag.unwrap_or(!self.clock.is_paused());
+                    self.clock.set_paused(paused);
+                },
+                SetTimeScale(scale) => self.clock.set_time_scale(scale),
+                r => self.window_requests.push(r),
             }
         }
     }
 }
 
+/// Output image of `Frame`, swapchain image when windowed or `AttachmentImage` when headless
+pub trait FrameImage: ImageAccess + ImageViewAccess + Send + Sync {}
+impl <T> FrameImage for T where T: ImageAccess + ImageViewAccess + Send + Sync {}
+
 /// Frame info (draw geometry, clicked buttons)
 pub struct Frame<'v> {
     pub queue: Arc<Queue>, // Main Queue
-    pub image: Arc<SwapchainImage<Window>>, // Output image, None in init frame
+    pub image: Arc<dyn FrameImage>, // Output image, first swapchain image in init frame
     pub sampler_pool: &'v mut SamplerPool, // Samplet pool
 
+    clock: GameClock, // State of game clock at frame start
     requests: Vec<FrameRequest>,
 
     keyboard: &'v mut KeyboardState,
     mouse: &'v mut MouseState,
+    profiler: &'v mut Profiler,
+    top_state: bool, // Frame is given to top state of stack
 }
 /// Init and interaction with IO
 impl <'v> Frame<'v> {
@@ -97,33 +203,62 @@ impl <'v> Frame<'v> {
     pub fn cursor_btn(&self, button: winit::MouseButton) -> bool { self.mouse.state_of(button) }
 
     pub fn key_state(&self, keycode: VirtualKeyCode) -> bool { self.keyboard.state_of(keycode) }
+
+    /// Raw input state, see `input_map::InputMap` for named actions
+    pub fn keyboard(&self) -> &KeyboardState { &self.keyboard }
+    pub fn mouse(&self) -> &MouseState { &self.mouse }
+
+    /// Game clock, `alpha` is valid for rendering in `GameListener::update`
+    pub fn clock(&self) -> &GameClock { &self.clock }
+
+    /// Frame profiler, main loop times `fixed_update`, `update` and `submit` scopes
+    /// Renderers report their own scopes and stats into it
+    pub fn profiler(&mut self) -> &mut Profiler { &mut self.profiler }
+
+    /// False then frame is given to state under overlay, it should not react on input
+    pub fn is_top_state(&self) -> bool { self.top_state }
 }
 /// Frame requests
 impl <'v> Frame<'v> {
     pub fn request(&mut self, request: FrameRequest) { self.requests.push(request) }
 }
 
+/// Fence of presented frame, shared between in flight queue and next frame future
+type FrameFence = Arc<FenceSignalFuture<PresentFuture<Box<dyn GpuFuture>, Window>>>;
+
 /// Start Listener in one function
 pub fn start_with_settings_and_listener<F>(
-    settings: GameSettings,
+    mut settings: GameSettings,
     mut init_listener: F) -> Result<(), String>
     where F: FnMut(&mut Frame) -> Box<dyn GameListener>
 {
-    let mut application_state = ApplicationState::default();
-    let mut window = settings.generate_window()?;
-    let mut swapchain = SwapchainConfig::create(&window)?;
+    let mut replay = InputPlayer::from_settings(&settings).map_err(|e| format!("Unable to load input replay: {}", e))?;
+    let mut recording = settings.record_input.as_ref().map(|_| InputRecording::new(settings.window_size));
+
+    let mut application_state = ApplicationState::new(&settings);
+    let mut window = match &replay {
+        Some(replay) => replay.session_settings(&settings).generate_window()?,
+        None => settings.generate_window()?,
+    };
+    let mut swapchain = SwapchainConfig::create(&window, &settings)?;
+    application_state.vsync = swapchain.is_vsync();
     let mut keyboard = KeyboardState::new();
     let mut mouse = MouseState::new();
+    let mut profiler = Profiler::new();
+    let mut capture = FrameCapture::new();
     let mut sampler_pool = SamplerPool::new(swapchain.device());
 
     macro_rules! new_frame {
         ($img_idx:expr) => {
             Frame {
                 queue: swapchain.main_queue.clone(),
-                image: swapchain.images[$img_idx].clone(),
+                image: swapchain.images[$img_idx].clone() as Arc<dyn FrameImage>,
                 sampler_pool: &mut sampler_pool,
                 keyboard: &mut keyboard,
                 mouse: &mut mouse,
+                profiler: &mut profiler,
+                top_state: true,
+                clock: application_state.clock,
                 requests: vec![],
             }
         };
@@ -132,109 +267,206 @@ pub fn start_with_settings_and_listener<F>(
 
     let mut listener = {
         let mut init_frame = new_frame!();
-        let mut l = init_listener(&mut init_frame);
+        let first = init_listener(&mut init_frame);
+        let mut l = StateStack::new(&mut init_frame, first);
         let dims = swapchain.swapchain.dimensions();
         l.dimensions_changed(&mut init_frame, dims[0], dims[1]);
         application_state.accept(init_frame);
         l
     };
 
+    // Fences of presented frames GPU may be still working on
+    let frames_in_flight = settings.frames_in_flight.max(1).min(settings::MAX_FRAMES_IN_FLIGHT) as usize;
+    let wait_for_gpu = settings.waits_for_gpu(swapchain.device().physical_device());
+    let mut in_flight: VecDeque<FrameFence> = VecDeque::with_capacity(frames_in_flight);
+
     let mut last_sync = Box::new(sync::now(swapchain.device())) as Box<dyn GpuFuture>;
     let mut prev_time = time::precise_time_ns();
+    let mut minimised = false;
     while application_state.running {
         let time = time::precise_time_ns();
-        let delta = (time - prev_time) as f32 / 1e9;
+        let mut delta = settings.fixed_delta.unwrap_or((time - prev_time) as f32 / 1e9);
         prev_time = time;
 
-        // Do swapchain maintenance
-        swapchain.update_if_required(&window);
-
-        // Do acquire swapchain image
-        let (image_num, acquire_future) = match swapchain.acquire() {
-            Ok(r) => r,
-            Err(AcquireError::OutOfDate) => {
-                swapchain.recreate();
-                continue;
-            },
-            Err(e) => return Err(format!("{:?}", e)),
-        };
+        // Replayed frame overrides delta, its events are fed after update
+        let mut replayed_events = None;
+
+        // Zero sized window has nothing to present into, rendering is paused until it is restored
+        let is_minimised = window.window().get_inner_size()
+            .map(|s| s.width < 1.0 || s.height < 1.0)
+            .unwrap_or(false);
+        if is_minimised != minimised {
+            minimised = is_minimised;
+            let mut frame = new_frame!();
+            listener.minimised(&mut frame, minimised);
+            application_state.accept(frame);
+        }
 
+        let mut frame = if minimised {
+            std::thread::sleep(std::time::Duration::from_millis(MINIMISED_SLEEP_MS));
+            new_frame!()
+        } else {
+            // Do swapchain maintenance, listener is notified only after swapchain got new dimensions
+            if let Some(dims) = swapchain.update_if_required(&window) {
+                let mut frame = new_frame!();
+                listener.dimensions_changed(&mut frame, dims[0], dims[1]);
+                application_state.accept(frame);
+            }
 
-        let mut frame = new_frame!(image_num);
+            // Don't record more than `frames_in_flight` frames ahead of GPU
+            while in_flight.len() >= frames_in_flight {
+                in_flight.pop_front().unwrap().wait(None).map_err(|e| format!("{:?}", e))?;
+            }
+            last_sync.cleanup_finished();
+            capture.poll();
+
+            // Do acquire swapchain image
+            let (image_num, acquire_future) = match swapchain.acquire() {
+                Ok(r) => r,
+                Err(AcquireError::OutOfDate) => {
+                    swapchain.recreate();
+                    continue;
+                },
+                Err(e) => return Err(format!("{:?}", e)),
+            };
 
-        // Do update and drawing using future to receive next GpuFuture
-        let future = listener.update(delta, &mut frame, Box::new(last_sync.join(acquire_future)));
+            // Replayed frame is taken only then its update is sure to run, so it isn't lost on swapchain recreation
+            if let Some(player) = &mut replay {
+                match player.next_frame() {
+                    Some(f) => {
+                        delta = f.delta;
+                        replayed_events = Some(f.events);
+                    },
+                    None => {
+                        println!("Input replay finished");
+                        replay = None;
+                    },
+                }
+            }
 
-        // Present future to swapchain
-        match future.then_swapchain_present(
-            swapchain.main_queue.clone(),
-            swapchain.swapchain.clone(),
-            image_num
-        ).then_signal_fence_and_flush() {
-            Ok(future) => {
-                // This wait is required when using NVIDIA or running on macOS. See https://github.com/vulkano-rs/vulkano/issues/1247
-                future.wait(None).unwrap();
-                last_sync = Box::new(future) as Box<_>;
+            // Run fixed ticks for time accumulated by clock
+            let ticks = application_state.clock.advance(delta);
+            let fixed_start = Instant::now();
+            for _ in 0 .. ticks {
+                let mut frame = new_frame!(image_num);
+                listener.fixed_update(application_state.clock.tick_delta(), &mut frame);
+                application_state.accept(frame);
             }
-            Err(FlushError::OutOfDate) => {
-                swapchain.recreate();
-                last_sync = Box::new(sync::now(swapchain.device().clone())) as Box<_>;
+            if ticks > 0 { profiler.record("fixed_update", fixed_start.elapsed()); }
+
+            let mut frame = new_frame!(image_num);
+
+            // Do update and drawing using future to receive next GpuFuture
+            let update_start = Instant::now();
+            let future = listener.update(delta, &mut frame, Box::new(last_sync.join(acquire_future)));
+            frame.profiler.record("update", update_start.elapsed());
+
+            // Copy image before it is presented
+            let submit_start = Instant::now();
+            let (future, captured) = capture.capture_frame(
+                delta, future, swapchain.main_queue.clone(), swapchain.images[image_num].clone()
+            ).map_err(|e| format!("Unable to capture frame: {}", e))?;
+
+            // Present future to swapchain
+            match future.then_swapchain_present(
+                swapchain.main_queue.clone(),
+                swapchain.swapchain.clone(),
+                image_num
+            ).then_signal_fence_and_flush() {
+                Ok(future) => {
+                    let future = Arc::new(future);
+                    if wait_for_gpu {
+                        // Fallback for drivers that require it (NVIDIA, macOS). See https://github.com/vulkano-rs/vulkano/issues/1247
+                        future.wait(None).map_err(|e| format!("{:?}", e))?;
+                    } else {
+                        in_flight.push_back(future.clone());
+                    }
+                    last_sync = Box::new(future) as Box<_>;
+                    if let Some(c) = captured { capture.push_pending(c); }
+                }
+                Err(FlushError::OutOfDate) => {
+                    if let Some(c) = captured { capture.retry(c); }
+                    swapchain.recreate();
+                    last_sync = Box::new(sync::now(swapchain.device().clone())) as Box<_>;
+                }
+                Err(e) => return Err(format!("{:?}", e)),
             }
-            Err(e) => return Err(format!("{:?}", e)),
+            frame.profiler.record("submit", submit_start.elapsed());
+            frame
+        };
+
+        // Everything after this point is input for next frame
+        frame.keyboard.end_frame();
+        frame.mouse.end_frame();
+
+        // Collect Events
+        let mut events = vec![];
+        window.event_loop.poll_events(|e| if let Some(e) = InputEvent::from_winit(&e) { events.push(e) });
+        if replay.is_some() || replayed_events.is_some() {
+            // Window still maintains swapchain and can be closed, but input comes from recording
+            // Replay is paused while window is minimised, live input is dropped meanwhile
+            events.retain(|e| e.is_window_event());
+            if let Some(replayed) = replayed_events {
+                // Replayed resize resizes window, swapchain and listener follow it as with live resize
+                for e in replayed.iter() {
+                    if let &InputEvent::Resized(w, h) = e { window.window().set_inner_size(LogicalSize::new(w, h)); }
+                }
+                events.extend(replayed);
+            }
+        }
+        if let Some(recording) = &mut recording {
+            // Minimised frames have no update, their events belong to last updated frame
+            if minimised { recording.extend_last_frame(&events) } else { recording.push_frame(delta, &events) }
         }
 
         // Process Events
-        window.event_loop.poll_events(|e| {
-            use winit::Event;
+        for e in events.iter() {
             match e {
-                Event::DeviceEvent { event, .. } => {
-                    use winit::DeviceEvent;
-                    match event {
-                        DeviceEvent::MouseMotion { delta } => { frame.mouse.move_event(delta) },
-                        _ => (),
-                    }
-                },
-                Event::WindowEvent { event, .. } => {
-                    use winit::WindowEvent;
-                    match event {
-                        WindowEvent::Resized(s) => {
-                            listener.dimensions_changed(&mut frame, s.width as u32, s.height as u32);
-                            swapchain.recreate();
-                        },
-                        WindowEvent::CloseRequested => application_state.running = false,
-                        WindowEvent::CursorMoved { position, .. } => frame.mouse.pos_event(position),
-                        WindowEvent::MouseWheel { delta, .. } => {
-                            match delta {
-                                winit::MouseScrollDelta::LineDelta(x, y) => {
-                                    if x != 0.0 || y != 0.0 { listener.mouse_wheel(&mut frame, x, y) }
-                                },
-                                winit::MouseScrollDelta::PixelDelta(p) => {
-                                    panic!("Currently not supported");
-                                },
-                            }
-                        },
-                        WindowEvent::MouseInput { button, state, .. } => frame.mouse.key_event(button, state),
-                        WindowEvent::KeyboardInput { input: winit::KeyboardInput { virtual_keycode: Some(keycode), state, .. }, .. } => {
-                            frame.keyboard.key_event(keycode, state);
-                            match state {
-                                ElementState::Pressed => listener.key_pressed(&mut frame, keycode),
-                                ElementState::Released => listener.key_released(&mut frame, keycode),
-                            }
-                        }
-                        _ => (),
-                    }
-                },
-                _ => (),
+                InputEvent::Resized(..) => swapchain.recreate(),
+                InputEvent::CloseRequested => application_state.running = false,
+                e => e.dispatch(&mut frame, &mut listener),
             }
-        });
+        }
 
         // Also move frame to release mouse and keyboard fields for modding
         application_state.accept(frame);
 
         // Update mouse position after events but before setting forced, centred position
         mouse.update(delta);
+        if !minimised { profiler.end_frame(delta); }
 
         // Apply application state
+        if application_state.vsync_changed {
+            application_state.vsync_changed = false;
+            swapchain.set_vsync(&window, application_state.vsync)?;
+            application_state.vsync = swapchain.is_vsync();
+        }
+        for r in application_state.window_requests.drain(..) {
+            let w = window.window();
+            match r {
+                FrameRequest::SetWindowMode(mode) => {
+                    settings.window_mode = mode;
+                    settings.apply_window_mode(w);
+                    swapchain.recreate();
+                },
+                FrameRequest::SetWindowSize(width, height) => {
+                    settings.window_size = (width, height);
+                    if let WindowMode::Windowed(_) = settings.window_mode {
+                        w.set_inner_size(settings.window_size.into());
+                        swapchain.recreate();
+                    }
+                },
+                FrameRequest::SetWindowTitle(title) => w.set_title(&title),
+                FrameRequest::SetWindowIcon(icon) => w.set_window_icon(icon),
+                FrameRequest::SaveSettings => if let Err(e) = settings.save_back() {
+                    println!("Unable to save settings: {}", e);
+                },
+                FrameRequest::Screenshot(path) => capture.screenshot(path),
+                FrameRequest::StartCapture(dir, fps) => capture.start_sequence(dir, fps),
+                FrameRequest::StopCapture => capture.stop_sequence(),
+                _ => (),
+            }
+        }
         {
             let window = window.window();
             window.hide_cursor(application_state.hold_cursor);
@@ -246,6 +478,30 @@ pub fn start_with_settings_and_listener<F>(
         }
     }
 
+    // Let GPU finish frames still in flight, so captured images can be written
+    for f in in_flight.drain(..) { f.wait(None).map_err(|e| format!("{:?}", e))?; }
+    capture.finish();
+
+    if let (Some(recording), Some(path)) = (&recording, &settings.record_input) {
+        if let Err(e) = recording.save(path) { println!("Unable to save input recording: {}", e); }
+    }
+
+    Ok(())
+}
+
+/// Start Listener without window, rendering `frames` frames into offscreen image
+/// Stops early if listener requests `FrameRequest::ExitApplication`
+pub fn start_headless_with_settings_and_listener<F>(
+    mut settings: GameSettings,
+    frames: u32,
+    init_listener: F) -> Result<(), String>
+    where F: FnMut(&mut Frame) -> Box<dyn GameListener>
+{
+    let replay = InputPlayer::from_settings(&settings).map_err(|e| format!("Unable to load input replay: {}", e))?;
+    if let Some(replay) = &replay { settings = replay.session_settings(&settings); }
+    let mut runner = headless::HeadlessRunner::new(&settings, init_listener)?;
+    if let Some(replay) = replay { runner.set_replay(replay); }
+    runner.run(frames)?;
     Ok(())
 }
 
@@ -257,11 +513,16 @@ pub struct SwapchainConfig {
     main_queue: Arc<Queue>,
     queues: QueuesIter,
 
+    usage: ImageUsage,
+    alpha: CompositeAlpha,
+    vsync_mode: PresentMode, // Mode used then vsync is switched on, FifoRelaxed if supported
+    no_vsync_mode: PresentMode, // Mode used then vsync is switched off
+
     recreate: bool
 }
 impl SwapchainConfig {
 
-    pub fn create(backend: &WindowInfo) -> Result<Self, String> {
+    pub fn create(backend: &WindowInfo, settings: &GameSettings) -> Result<Self, String> {
 
         // Dims
         let mut dimensions = if let Some(dimensions) = backend.window().get_inner_size() {
@@ -271,15 +532,14 @@ impl SwapchainConfig {
             return Err(String::from("Window already closed (swapchain)"));
         };
 
-        let physical = {
-            let dev = PhysicalDevice::enumerate(&backend.instance).next().unwrap();
-//            println!("Using device: {} (type: {:?})", dev.name(), dev.ty());
-            dev
-        };
+        let physical = settings.select_physical_device(&backend.instance)?;
 
-        let queue_family = physical.queue_families().find(|&q|
+        let queue_family = match physical.queue_families().find(|&q|
             q.supports_graphics() && backend.surface.is_supported(q).unwrap_or(false)
-        ).unwrap();
+        ) {
+            Some(q) => q,
+            None => return Err(format!("Device {} can't present to window", physical.name())),
+        };
 
         let device_ext = DeviceExtensions { khr_swapchain: true, .. DeviceExtensions::none() };
 
@@ -290,18 +550,29 @@ impl SwapchainConfig {
 
         let main_queue = queues.next().unwrap();
 
-        let (mut swapchain, mut images) = {
-            let caps = backend.surface.capabilities(physical).unwrap();
+        let caps = backend.surface.capabilities(physical).map_err(|e| format!("{:?}", e))?;
+        let present_mode = choose_present_mode(settings.present_mode, &caps.present_modes);
+        let vsync_mode = choose_present_mode(PresentMode::Relaxed, &caps.present_modes);
+        let no_vsync_mode = if is_vsync(settings.present_mode) {
+            choose_present_mode(PresentMode::Immediate, &caps.present_modes)
+        } else {
+            present_mode
+        };
+        let usage = caps.supported_usage_flags;
+        let alpha = caps.supported_composite_alpha.iter().next().unwrap();
 
-            let usage = caps.supported_usage_flags;
-            let format = caps.supported_formats[0].0;
-            let alpha = caps.supported_composite_alpha.iter().next().unwrap();
+        let (mut swapchain, mut images) = {
+            let format = choose_format(settings.color_mode, &caps.supported_formats);
 
-            let image_count = caps.min_image_count;
+            let max_images = caps.max_image_count.unwrap_or(std::u32::MAX);
+            let image_count = settings.swapchain_images
+                .unwrap_or(caps.min_image_count)
+                .max(caps.min_image_count)
+                .min(max_images);
             match Swapchain::new(device.clone(), backend.surface.clone(),
                                  image_count, format, dimensions, 1,
                                  usage, &main_queue, SurfaceTransform::Identity,
-                                 alpha, PresentMode::Immediate,
+                                 alpha, present_mode,
                                  true, None) {
                 Ok(s) => s,
                 Err(e) => return Err(format!("{:?}", e)),
@@ -315,6 +586,11 @@ impl SwapchainConfig {
             main_queue,
             queues,
 
+            usage,
+            alpha,
+            vsync_mode,
+            no_vsync_mode,
+
             recreate: false
         })
     }
@@ -325,36 +601,112 @@ impl SwapchainConfig {
 
     pub fn recreate(&mut self) { self.recreate = true; }
 
-    pub fn update_if_required(&mut self, backend: &WindowInfo) {
+    /// Recreate swapchain if requested, returns new dimensions if they changed
+    pub fn update_if_required(&mut self, backend: &WindowInfo) -> Option<[u32; 2]> {
         if self.recreate {
             let window = backend.window();
             let dimensions = if let Some(dimensions) = window.get_inner_size() {
                 let dimensions: (u32, u32) = dimensions.to_physical(window.get_hidpi_factor()).into();
                 [dimensions.0, dimensions.1]
             } else {
-                return;
+                return None;
             };
+            if dimensions[0] == 0 || dimensions[1] == 0 { return None; } // Minimised, keep recreate flag
+
+            let old_dimensions = self.swapchain.dimensions();
             let (new_swapchain, new_images) = self.swapchain.recreate_with_dimension(dimensions).unwrap();
             self.swapchain = new_swapchain;
             self.images = new_images;
             self.recreate = false;
+            if old_dimensions != dimensions { return Some(dimensions); }
         }
+        None
     }
 
     pub fn acquire(&self) -> Result<(usize, SwapchainAcquireFuture<Window>), AcquireError> {
         swapchain::acquire_next_image(self.swapchain.clone(), None)
     }
 
+    pub fn present_mode(&self) -> PresentMode { self.swapchain.present_mode() }
+    pub fn is_vsync(&self) -> bool { is_vsync(self.present_mode()) }
+
+    /// Rebuild swapchain with vsync or non vsync mode, old swapchain is handed over to new one
+    /// FifoRelaxed is preferred for vsync, late frames are presented at once instead of waiting for next blank
+    pub fn set_vsync(&mut self, backend: &WindowInfo, vsync: bool) -> Result<(), String> {
+        let mode = if vsync { self.vsync_mode } else { self.no_vsync_mode };
+        if mode == self.present_mode() { return Ok(()); }
+
+        let dimensions = match backend.window().get_inner_size() {
+            Some(dimensions) => {
+                let dimensions: (u32, u32) = dimensions.to_physical(backend.window().get_hidpi_factor()).into();
+                [dimensions.0, dimensions.1]
+            },
+            None => return Ok(()),
+        };
+
+        let old = &self.swapchain;
+        let (new_swapchain, new_images) = Swapchain::new(
+            self.device(), backend.surface.clone(),
+            old.num_images(), old.format(), dimensions, 1,
+            self.usage, &self.main_queue, SurfaceTransform::Identity,
+            self.alpha, mode,
+            true, Some(old)
+        ).map_err(|e| format!("{:?}", e))?;
+
+        self.swapchain = new_swapchain;
+        self.images = new_images;
+        self.recreate = false;
+        Ok(())
+    }
+
+}
+
+/// Fifo and FifoRelaxed wait for vertical blank
+fn is_vsync(mode: PresentMode) -> bool {
+    match mode {
+        PresentMode::Fifo | PresentMode::Relaxed => true,
+        _ => false,
+    }
+}
+
+/// Requested mode if supported, otherwise closest supported one. Fifo is always supported
+fn choose_present_mode(requested: PresentMode, supported: &SupportedPresentModes) -> PresentMode {
+    let fallback: &[PresentMode] = match requested {
+        PresentMode::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox],
+        PresentMode::Mailbox => &[PresentMode::Mailbox, PresentMode::Immediate],
+        PresentMode::Relaxed => &[PresentMode::Relaxed],
+        PresentMode::Fifo => &[],
+    };
+    fallback.iter().cloned()
+        .find(|&m| supported.supports(m))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+/// Prefer 8 bit BGRA/RGBA formats in SrgbNonLinear colour space with requested encoding
+fn choose_format(mode: ColorMode, supported: &[(Format, ColorSpace)]) -> Format {
+    let preferred: &[Format] = match mode {
+        ColorMode::Srgb => &[Format::B8G8R8A8Srgb, Format::R8G8B8A8Srgb, Format::A8B8G8R8SrgbPack32],
+        ColorMode::Unorm => &[Format::B8G8R8A8Unorm, Format::R8G8B8A8Unorm, Format::A8B8G8R8UnormPack32],
+    };
+    preferred.iter()
+        .filter_map(|f| supported.iter().find(|(sf, cs)| sf == f && *cs == ColorSpace::SrgbNonLinear))
+        .map(|(f, _)| *f)
+        .next()
+        .unwrap_or(supported[0].0)
 }
 
 /// Current state of keyboard keys being pressed
 pub struct KeyboardState {
     keys: [bool; 255],
+    pressed: [bool; 255], // Went down since last `end_frame`, latched so tap inside one frame is not lost
+    released: [bool; 255], // Went up since last `end_frame`
 }
 impl KeyboardState {
 
     pub fn new() -> Self { Self {
-        keys: [false; 255]
+        keys: [false; 255],
+        pressed: [false; 255],
+        released: [false; 255],
     } }
 
     /// Set from ElementState
@@ -368,24 +720,51 @@ impl KeyboardState {
 
     /// Then Key Pressed
     pub fn key_down(&mut self, keycode: VirtualKeyCode) {
+        // Repeated events of held key are not presses
+        if !self.keys[keycode as usize] { self.pressed[keycode as usize] = true; }
         self.keys[keycode as usize] = true;
     }
 
     /// Then Key Released
     pub fn key_up(&mut self, keycode: VirtualKeyCode) {
+        if self.keys[keycode as usize] { self.released[keycode as usize] = true; }
         self.keys[keycode as usize] = false;
     }
 
     pub fn state_of(&self, keycode: VirtualKeyCode) -> bool { self.keys[keycode as usize] }
 
+    /// Key went down since last `end_frame`, true even if it is already released
+    pub fn pressed_this_frame(&self, keycode: VirtualKeyCode) -> bool { self.pressed[keycode as usize] }
+    /// Key went up since last `end_frame`
+    pub fn released_this_frame(&self, keycode: VirtualKeyCode) -> bool { self.released[keycode as usize] }
+
+    /// Modifiers from held keys, left and right are same
+    pub fn modifiers(&self) -> ModifiersState {
+        let any = |a: VirtualKeyCode, b: VirtualKeyCode| self.state_of(a) || self.state_of(b);
+        ModifiersState {
+            shift: any(VirtualKeyCode::LShift, VirtualKeyCode::RShift),
+            ctrl: any(VirtualKeyCode::LControl, VirtualKeyCode::RControl),
+            alt: any(VirtualKeyCode::LAlt, VirtualKeyCode::RAlt),
+            logo: any(VirtualKeyCode::LWin, VirtualKeyCode::RWin),
+        }
+    }
+
+    /// Forget presses and releases, so next frame can tell what changed
+    pub fn end_frame(&mut self) {
+        self.pressed = [false; 255];
+        self.released = [false; 255];
+    }
+
 }
 
 /// Save mouse state, position, button states and move delta
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct MouseState {
     // Buttons
     buttons: [bool; 3],
     other_buttons: Vec<u8>, // Other currently down buttons with u8 ID
+    pressed: Vec<MouseButton>, // Went down since last `end_frame`, latched so click inside one frame is not lost
+    released: Vec<MouseButton>, // Went up since last `end_frame`
 
     // Cursor Position and Move Speed
     position: [f32; 2], // Current LogicalPosition
@@ -398,12 +777,21 @@ impl Default for MouseState {
     fn default() -> Self { Self {
         buttons: [false; 3],
         other_buttons: Vec::new(),
+        pressed: Vec::new(),
+        released: Vec::new(),
 
         position: [0.0; 2],
         speed_updated: 2,
         speed: [0.0; 2],
     }}
 }
+/// Per frame latches `pressed` and `released` are not compared, only lasting state is
+impl PartialEq for MouseState {
+    fn eq(&self, o: &MouseState) -> bool {
+        self.buttons == o.buttons && self.other_buttons == o.other_buttons
+            && self.position == o.position && self.speed_updated == o.speed_updated && self.speed == o.speed
+    }
+}
 impl MouseState {
 
     pub fn new() -> Self { MouseState::default() }
@@ -435,6 +823,9 @@ impl MouseState {
 
     /// New Mouse Button Pressed
     pub fn key_event(&mut self, button: MouseButton, state: ElementState) {
+        let down = self.state_of(button);
+        if state == ElementState::Pressed && !down && !self.pressed.contains(&button) { self.pressed.push(button); }
+        if state == ElementState::Released && down && !self.released.contains(&button) { self.released.push(button); }
         match button {
             MouseButton::Left => self.buttons[0] = state == ElementState::Pressed,
             MouseButton::Right => self.buttons[1] = state == ElementState::Pressed,
@@ -458,6 +849,20 @@ impl MouseState {
         }
     }
 
+    /// Button went down since last `end_frame`, true even if it is already released
+    pub fn pressed_this_frame(&self, button: MouseButton) -> bool { self.pressed.contains(&button) }
+    /// Button went up since last `end_frame`
+    pub fn released_this_frame(&self, button: MouseButton) -> bool { self.released.contains(&button) }
+
+    pub fn position(&self) -> [f32; 2] { self.position }
+    pub fn speed(&self) -> [f32; 2] { self.speed }
+
+    /// Forget presses and releases, so next frame can tell what changed
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+    }
+
 }
 
 mod test {
@@ -486,6 +891,67 @@ mod test {
         assert_eq!(state.state_of(VirtualKeyCode::W), false);
     }
 
+    #[test] fn test_keyboard_frame_edges() {
+        use crate::main_processor::KeyboardState;
+        use winit::{VirtualKeyCode};
+
+        let mut state = KeyboardState::new();
+
+        state.key_event(VirtualKeyCode::LControl, ElementState::Pressed);
+        state.key_event(VirtualKeyCode::S, ElementState::Pressed);
+        assert!(state.pressed_this_frame(VirtualKeyCode::S));
+        assert!(state.modifiers().ctrl);
+        assert!(!state.modifiers().shift);
+
+        state.end_frame();
+        assert!(state.state_of(VirtualKeyCode::S));
+        assert!(!state.pressed_this_frame(VirtualKeyCode::S));
+
+        state.key_event(VirtualKeyCode::S, ElementState::Released);
+        assert!(state.released_this_frame(VirtualKeyCode::S));
+        state.end_frame();
+        assert!(!state.released_this_frame(VirtualKeyCode::S));
+
+        // Tap inside one frame, key repeat is not a press
+        state.key_event(VirtualKeyCode::P, ElementState::Pressed);
+        state.key_event(VirtualKeyCode::P, ElementState::Released);
+        assert!(!state.state_of(VirtualKeyCode::P));
+        assert!(state.pressed_this_frame(VirtualKeyCode::P));
+        assert!(state.released_this_frame(VirtualKeyCode::P));
+        state.end_frame();
+        state.key_event(VirtualKeyCode::W, ElementState::Pressed);
+        state.end_frame();
+        state.key_event(VirtualKeyCode::W, ElementState::Pressed);
+        assert!(!state.pressed_this_frame(VirtualKeyCode::W));
+        assert!(!state.pressed_this_frame(VirtualKeyCode::P));
+    }
+
+    #[test] fn test_mouse_frame_edges() {
+        use crate::main_processor::MouseState;
+        use winit::MouseButton;
+
+        let mut state = MouseState::new();
+
+        // Click inside one frame
+        state.key_event(MouseButton::Left, ElementState::Pressed);
+        state.key_event(MouseButton::Left, ElementState::Released);
+        assert!(!state.state_of(MouseButton::Left));
+        assert!(state.pressed_this_frame(MouseButton::Left));
+        assert!(state.released_this_frame(MouseButton::Left));
+        state.end_frame();
+        assert!(!state.pressed_this_frame(MouseButton::Left));
+
+        // Held button is pressed only in its first frame
+        state.key_event(MouseButton::Other(4), ElementState::Pressed);
+        assert!(state.pressed_this_frame(MouseButton::Other(4)));
+        state.end_frame();
+        state.key_event(MouseButton::Other(4), ElementState::Pressed);
+        assert!(!state.pressed_this_frame(MouseButton::Other(4)));
+        state.key_event(MouseButton::Other(4), ElementState::Released);
+        assert!(state.released_this_frame(MouseButton::Other(4)));
+        assert!(!state.released_this_frame(MouseButton::Right));
+    }
+
     #[test] fn test_mouse_state() {
         use crate::main_processor::MouseState;
         use winit::{ MouseButton, dpi::LogicalPosition };
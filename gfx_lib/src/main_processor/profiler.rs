@@ -0,0 +1,320 @@
+
+// Frame profiler
+// CPU timings of named scopes, rolling frame time statistics and counters reported by renderers
+// GPU pass timings come from `gpu_timer::GpuTimer` of renderers, a few frames late
+
+use std::{
+    collections::{ BTreeMap, VecDeque },
+    sync::Arc,
+    time::{ Duration, Instant },
+};
+use vulkano::{
+    device::Queue,
+    format::Format,
+    image::ImageViewAccess,
+    sync::GpuFuture,
+};
+
+use crate::graphics::{
+    renderer_2d::{ Renderer2D, pixel_font },
+    object::ScreenInstance,
+};
+use super::FrameImage;
+
+/// Number of frames kept for frame time statistics
+pub const FRAME_HISTORY: usize = 240;
+/// Weight of newest frame in scope average
+const SCOPE_SMOOTHING: f32 = 0.1;
+/// Size of overlay font dot in pixels
+const TEXT_PIXEL: f32 = 2.0;
+/// Counter lines are short, GPU scope lines are cut
+const MAX_LINE_CHARS: usize = 32;
+const MAX_TEXT_LINES: usize = 12;
+/// Frame graph, scope bars and text
+const MAX_INSTANCES: usize = FRAME_HISTORY + 64
+    + MAX_TEXT_LINES * MAX_LINE_CHARS * pixel_font::GLYPH_WIDTH * pixel_font::GLYPH_HEIGHT;
+
+/// Counters reported by renderers for single frame
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    pub triangles: u64,
+    pub culled_objects: u32, // Objects outside of view frustum
+    pub shadow_maps: u32, // Shadow maps rendered
+}
+impl RenderStats {
+    pub fn add(&mut self, other: &RenderStats) {
+        self.draw_calls += other.draw_calls;
+        self.triangles += other.triangles;
+        self.culled_objects += other.culled_objects;
+        self.shadow_maps += other.shadow_maps;
+    }
+}
+
+/// Timing of named scope in milliseconds
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ScopeTiming {
+    pub last: f32, // Last frame scope was recorded in
+    pub average: f32, // Exponential moving average
+    pub max: f32, // Max since creation or `reset`
+}
+
+/// Collects timings and stats of frames, owned by main loop and accessed via `Frame::profiler`
+pub struct Profiler {
+    frame_times: VecDeque<f32>, // Seconds
+    frame_scopes: BTreeMap<&'static str, f32>, // Scopes of current frame
+    scopes: BTreeMap<&'static str, ScopeTiming>,
+    frame_gpu_scopes: BTreeMap<&'static str, f32>,
+    gpu_scopes: BTreeMap<&'static str, ScopeTiming>,
+    frame_stats: RenderStats,
+    last_stats: RenderStats,
+}
+impl Profiler {
+    pub fn new() -> Self { Self {
+        frame_times: VecDeque::with_capacity(FRAME_HISTORY),
+        frame_scopes: BTreeMap::new(),
+        scopes: BTreeMap::new(),
+        frame_gpu_scopes: BTreeMap::new(),
+        gpu_scopes: BTreeMap::new(),
+        frame_stats: RenderStats::default(),
+        last_stats: RenderStats::default(),
+    } }
+
+    /// Add CPU time of scope to current frame, same scope may be recorded multiple times per frame
+    pub fn record(&mut self, name: &'static str, duration: Duration) {
+        let ms = duration.as_secs_f32() * 1000.0;
+        *self.frame_scopes.entry(name).or_insert(0.0) += ms;
+    }
+
+    /// Add GPU time of pass to current frame, passes are timed on GPU a few frames before they are reported
+    pub fn record_gpu(&mut self, name: &'static str, duration: Duration) {
+        let ms = duration.as_secs_f32() * 1000.0;
+        *self.frame_gpu_scopes.entry(name).or_insert(0.0) += ms;
+    }
+
+    /// Measure `f` as scope `name`
+    pub fn time<R, F>(&mut self, name: &'static str, f: F) -> R
+        where F: FnOnce() -> R
+    {
+        let start = Instant::now();
+        let result = f();
+        self.record(name, start.elapsed());
+        result
+    }
+
+    pub fn add_stats(&mut self, stats: &RenderStats) { self.frame_stats.add(stats); }
+
+    /// Close current frame, called by main loop after frame was submitted
+    pub fn end_frame(&mut self, frame_time: f32) {
+        if self.frame_times.len() == FRAME_HISTORY { self.frame_times.pop_front(); }
+        self.frame_times.push_back(frame_time);
+
+        merge_scopes(&mut self.scopes, std::mem::replace(&mut self.frame_scopes, BTreeMap::new()));
+        merge_scopes(&mut self.gpu_scopes, std::mem::replace(&mut self.frame_gpu_scopes, BTreeMap::new()));
+
+        self.last_stats = std::mem::replace(&mut self.frame_stats, RenderStats::default());
+    }
+
+    /// Forget collected history, timings and stats
+    pub fn reset(&mut self) {
+        self.frame_times.clear();
+        self.frame_scopes.clear();
+        self.scopes.clear();
+        self.frame_gpu_scopes.clear();
+        self.gpu_scopes.clear();
+        self.frame_stats = RenderStats::default();
+        self.last_stats = RenderStats::default();
+    }
+}
+/// Fold scopes of finished frame into rolling timings
+fn merge_scopes(scopes: &mut BTreeMap<&'static str, ScopeTiming>, frame: BTreeMap<&'static str, f32>) {
+    for (name, ms) in frame {
+        let timing = scopes.entry(name).or_insert(ScopeTiming { last: ms, average: ms, max: ms });
+        timing.last = ms;
+        timing.average += (ms - timing.average) * SCOPE_SMOOTHING;
+        timing.max = timing.max.max(ms);
+    }
+}
+
+/// Statistics
+impl Profiler {
+    /// Frame times in seconds, oldest first
+    pub fn frame_times(&self) -> &VecDeque<f32> { &self.frame_times }
+
+    pub fn average_frame_time(&self) -> f32 {
+        if self.frame_times.is_empty() { return 0.0; }
+        self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32
+    }
+
+    /// Frame time below which `p` (0 ..= 1) of frames are
+    pub fn percentile(&self, p: f32) -> f32 {
+        if self.frame_times.is_empty() { return 0.0; }
+        let mut sorted: Vec<f32> = self.frame_times.iter().cloned().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let idx = ((sorted.len() - 1) as f32 * p.max(0.0).min(1.0)).round() as usize;
+        sorted[idx]
+    }
+
+    /// Count of frames in `buckets` of `bucket_ms` width, last bucket also counts all longer frames
+    pub fn histogram(&self, bucket_ms: f32, buckets: usize) -> Vec<u32> {
+        let mut counts = vec![0; buckets.max(1)];
+        let last = counts.len() - 1;
+        for t in self.frame_times.iter() {
+            let idx = (t * 1000.0 / bucket_ms) as usize;
+            counts[idx.min(last)] += 1;
+        }
+        counts
+    }
+
+    pub fn scope(&self, name: &str) -> Option<ScopeTiming> { self.scopes.get(name).cloned() }
+    pub fn scopes(&self) -> &BTreeMap<&'static str, ScopeTiming> { &self.scopes }
+    pub fn gpu_scope(&self, name: &str) -> Option<ScopeTiming> { self.gpu_scopes.get(name).cloned() }
+    pub fn gpu_scopes(&self) -> &BTreeMap<&'static str, ScopeTiming> { &self.gpu_scopes }
+
+    /// Renderer counters of last finished frame
+    pub fn last_stats(&self) -> RenderStats { self.last_stats }
+}
+
+/// Frame time graph, CPU and GPU scope bars, render counters and GPU pass times drawn over frame image
+pub struct ProfilerOverlay {
+    renderer: Renderer2D,
+    pub visible: bool, // Hidden by default
+    pub target_frame_time: f32, // Frame time drawn as half of graph height
+}
+impl ProfilerOverlay {
+    pub fn new(queue: Arc<Queue>, output_format: Format) -> Self { Self {
+        renderer: Renderer2D::new_overlay(queue, output_format, MAX_INSTANCES),
+        visible: false,
+        target_frame_time: 1.0 / 60.0,
+    } }
+
+    pub fn render<F>(&mut self, profiler: &Profiler, image: Arc<dyn FrameImage>, future: F) -> Box<dyn GpuFuture>
+        where F: GpuFuture + 'static
+    {
+        if !self.visible { return Box::new(future); }
+
+        let dims = ImageViewAccess::dimensions(&image).width_height();
+        let (bar_w, graph_h, pad) = (2.0, 100.0, 8.0);
+        let graph_w = bar_w * FRAME_HISTORY as f32;
+
+        let rect = |x: f32, y: f32, w: f32, h: f32, col: [f32; 4]| {
+            let mut instance = ScreenInstance::new();
+            instance.set_transform(x + w / 2.0, y + h / 2.0, w, h, cgmath::Rad(0.0));
+            instance.set_color(col[0], col[1], col[2], col[3]);
+            instance
+        };
+
+        let mut instances = vec![];
+
+        // Counters of last frame, then GPU passes, under scope bars
+        let stats = profiler.last_stats();
+        let mut lines = vec![
+            format!("DRAW CALLS {}", stats.draw_calls),
+            format!("TRIANGLES {}", stats.triangles),
+            format!("CULLED {}", stats.culled_objects),
+            format!("SHADOW MAPS {}", stats.shadow_maps),
+        ];
+        for (name, timing) in profiler.gpu_scopes().iter().take(MAX_TEXT_LINES - lines.len()) {
+            let mut line = format!("GPU {} {:.2} MS", name, timing.last);
+            line.truncate(MAX_LINE_CHARS);
+            lines.push(line);
+        }
+        let line_h = (pixel_font::GLYPH_HEIGHT + 3) as f32 * TEXT_PIXEL;
+        let text_y = pad + graph_h + 36.0;
+
+        // Background and target line
+        instances.push(rect(pad, pad, graph_w, text_y - pad + lines.len() as f32 * line_h + 4.0, [0.0, 0.0, 0.0, 0.6]));
+        instances.push(rect(pad, pad + graph_h / 2.0, graph_w, 1.0, [0.2, 0.8, 0.2, 0.8]));
+
+        // Frame times, newest on the right, over budget frames are red
+        let offset = FRAME_HISTORY - profiler.frame_times().len();
+        for (i, t) in profiler.frame_times().iter().enumerate() {
+            let h = (t / self.target_frame_time * graph_h / 2.0).min(graph_h);
+            let col = if *t > self.target_frame_time * 1.05 { [0.9, 0.2, 0.2, 0.9] } else { [0.9, 0.9, 0.9, 0.9] };
+            instances.push(rect(pad + (offset + i) as f32 * bar_w, pad + graph_h - h, bar_w, h, col));
+        }
+
+        // Last frame scopes stacked on single bar, width relative to target frame time
+        let mut x = pad;
+        let scale = graph_w / (self.target_frame_time * 1000.0);
+        for (i, timing) in profiler.scopes().values().enumerate() {
+            let w = timing.last * scale;
+            instances.push(rect(x, pad + graph_h + 6.0, w, 10.0, SCOPE_COLORS[i % SCOPE_COLORS.len()]));
+            x += w;
+        }
+
+        // GPU passes of latest timed frame on bar under it, same scale
+        let mut x = pad;
+        for (i, timing) in profiler.gpu_scopes().values().enumerate() {
+            let w = timing.last * scale;
+            instances.push(rect(x, pad + graph_h + 20.0, w, 10.0, SCOPE_COLORS[i % SCOPE_COLORS.len()]));
+            x += w;
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            instances.extend(pixel_font::text_instances(line, pad + 4.0, text_y + i as f32 * line_h, TEXT_PIXEL, [0.9, 0.9, 0.9, 1.0]));
+        }
+
+        self.renderer.set_viewport_window(dims[0] as f32, dims[1] as f32);
+        self.renderer.begin(image);
+        {
+            let white = self.renderer.white_uniform();
+            let mut call = self.renderer.start_image_uniform(white);
+            call.render_instances_vec(instances);
+        }
+        self.renderer.end_after(future)
+    }
+}
+
+const SCOPE_COLORS: [[f32; 4]; 6] = [
+    [0.9, 0.5, 0.1, 0.9],
+    [0.2, 0.6, 0.9, 0.9],
+    [0.6, 0.9, 0.3, 0.9],
+    [0.9, 0.3, 0.7, 0.9],
+    [0.9, 0.9, 0.3, 0.9],
+    [0.5, 0.4, 0.9, 0.9],
+];
+
+mod test {
+
+    #[test] fn test_profiler_statistics() {
+        use super::{ Profiler, RenderStats };
+        use std::time::Duration;
+
+        let mut profiler = Profiler::new();
+        for i in 0 .. 10 {
+            profiler.record("update", Duration::from_millis(2));
+            profiler.record("update", Duration::from_millis(1));
+            profiler.add_stats(&RenderStats { draw_calls: 3, triangles: 100, ..RenderStats::default() });
+            profiler.add_stats(&RenderStats { draw_calls: 1, shadow_maps: 1, ..RenderStats::default() });
+            profiler.record_gpu("main_pass", Duration::from_micros(500));
+            profiler.end_frame(if i == 9 { 0.050 } else { 0.010 });
+        }
+
+        let update = profiler.scope("update").unwrap();
+        assert!((update.last - 3.0).abs() < 1e-3);
+        assert!((update.average - 3.0).abs() < 1e-3);
+        assert!((profiler.gpu_scope("main_pass").unwrap().last - 0.5).abs() < 1e-3);
+        assert!(profiler.scope("main_pass").is_none());
+
+        let stats = profiler.last_stats();
+        assert_eq!(stats.draw_calls, 4);
+        assert_eq!(stats.triangles, 100);
+        assert_eq!(stats.shadow_maps, 1);
+
+        // 10 ms buckets: nine frames in [10, 20), one in last bucket
+        assert_eq!(profiler.histogram(10.0, 3), vec![0, 9, 1]);
+        assert!((profiler.percentile(0.5) - 0.010).abs() < 1e-6);
+        assert!((profiler.percentile(1.0) - 0.050).abs() < 1e-6);
+        assert!((profiler.average_frame_time() - 0.014).abs() < 1e-6);
+
+        profiler.record("update", Duration::from_millis(2));
+        profiler.add_stats(&RenderStats { draw_calls: 1, ..RenderStats::default() });
+        profiler.reset();
+        profiler.end_frame(0.010);
+        assert_eq!(profiler.last_stats(), RenderStats::default());
+        assert!(profiler.scope("update").is_none());
+        assert!(profiler.gpu_scope("main_pass").is_none());
+        assert_eq!(profiler.frame_times().len(), 1);
+    }
+}
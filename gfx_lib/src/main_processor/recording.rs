@@ -0,0 +1,394 @@
+
+// Input recording and replay
+// Every input fed into KeyboardState, MouseState and GameListener goes through InputEvent,
+// so it can be written into file with frame deltas and fed back instead of winit events
+
+use std::path::{ Path, PathBuf };
+use winit::{ Event, WindowEvent, DeviceEvent, ElementState, MouseScrollDelta, VirtualKeyCode, MouseButton, dpi::LogicalPosition };
+use serializer::{ Data, DataObject, DataObtainError, Peek, PeekResult, Persistent, PersistentError };
+
+use super::{ Frame, GameListener, input_map, settings::GameSettings };
+
+/// Layout version of recording file
+pub const RECORDING_VERSION: u16 = 1;
+
+pub enum RecordingError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Persistent(PersistentError),
+}
+impl std::error::Error for RecordingError {}
+impl std::fmt::Debug for RecordingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            RecordingError::Io(e) => write!(f, "IO Error: {:?}", e),
+            RecordingError::Json(e) => write!(f, "Invalid recording file: {}", e),
+            RecordingError::Persistent(e) => write!(f, "Invalid recording: {:?}", e),
+        }
+    }
+}
+impl std::fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        (self as &dyn std::fmt::Debug).fmt(f)
+    }
+}
+impl From<std::io::Error> for RecordingError {
+    fn from(e: std::io::Error) -> Self { RecordingError::Io(e) }
+}
+impl From<serde_json::Error> for RecordingError {
+    fn from(e: serde_json::Error) -> Self { RecordingError::Json(e) }
+}
+impl From<PersistentError> for RecordingError {
+    fn from(e: PersistentError) -> Self { RecordingError::Persistent(e) }
+}
+
+/// Single input, as main loop feeds it into input state and listener
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputEvent {
+    Key(VirtualKeyCode, bool), // Key, is pressed
+    MouseButton(MouseButton, bool), // Button, is pressed
+    MouseMotion(f64, f64), // Raw device delta
+    CursorMoved(f64, f64), // Logical position
+    Wheel(f32, f32), // Lines
+    WheelPixels(f64, f64), // Touchpad scroll in logical pixels
+    Character(char),
+    Focused(bool),
+    DroppedFile(PathBuf),
+
+    // Window events, not fed into listener, replayed resize changes window size
+    Resized(f64, f64), // Logical size
+    CloseRequested,
+}
+impl InputEvent {
+
+    /// Convert winit event, None if event is not an input
+    pub fn from_winit(event: &Event) -> Option<Self> {
+        match event {
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => Some(InputEvent::MouseMotion(delta.0, delta.1)),
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::Resized(size) => Some(InputEvent::Resized(size.width, size.height)),
+                WindowEvent::CloseRequested => Some(InputEvent::CloseRequested),
+                WindowEvent::CursorMoved { position, .. } => Some(InputEvent::CursorMoved(position.x, position.y)),
+                WindowEvent::MouseWheel { delta: MouseScrollDelta::LineDelta(x, y), .. } => Some(InputEvent::Wheel(*x, *y)),
+                WindowEvent::MouseWheel { delta: MouseScrollDelta::PixelDelta(p), .. } => Some(InputEvent::WheelPixels(p.x, p.y)),
+                WindowEvent::ReceivedCharacter(c) => Some(InputEvent::Character(*c)),
+                WindowEvent::Focused(focused) => Some(InputEvent::Focused(*focused)),
+                WindowEvent::DroppedFile(path) => Some(InputEvent::DroppedFile(path.clone())),
+                WindowEvent::MouseInput { button, state, .. } => Some(InputEvent::MouseButton(*button, *state == ElementState::Pressed)),
+                WindowEvent::KeyboardInput { input: winit::KeyboardInput { virtual_keycode: Some(keycode), state, .. }, .. } =>
+                    Some(InputEvent::Key(*keycode, *state == ElementState::Pressed)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Resize and close are handled by main loop, not by listener
+    pub fn is_window_event(&self) -> bool {
+        match self {
+            InputEvent::Resized(..) | InputEvent::CloseRequested => true,
+            _ => false,
+        }
+    }
+
+    /// Feed input event into frame input state and listener
+    pub fn dispatch(&self, frame: &mut Frame, listener: &mut dyn GameListener) {
+        match self {
+            &InputEvent::Key(keycode, pressed) => {
+                frame.keyboard.key_event(keycode, element_state(pressed));
+                if pressed {
+                    listener.key_pressed(frame, keycode)
+                } else {
+                    listener.key_released(frame, keycode)
+                }
+            },
+            &InputEvent::MouseButton(button, pressed) => {
+                frame.mouse.key_event(button, element_state(pressed));
+                if pressed {
+                    listener.mouse_pressed(frame, button)
+                } else {
+                    listener.mouse_released(frame, button)
+                }
+            },
+            &InputEvent::MouseMotion(x, y) => frame.mouse.move_event((x, y)),
+            &InputEvent::CursorMoved(x, y) => {
+                frame.mouse.pos_event(LogicalPosition::new(x, y));
+                listener.cursor_moved(frame, x as f32, y as f32)
+            },
+            &InputEvent::Wheel(x, y) => if x != 0.0 || y != 0.0 { listener.mouse_wheel(frame, x, y) },
+            &InputEvent::WheelPixels(x, y) => if x != 0.0 || y != 0.0 { listener.mouse_wheel_pixels(frame, x as f32, y as f32) },
+            &InputEvent::Character(c) => listener.received_character(frame, c),
+            &InputEvent::Focused(focused) => listener.focus_changed(frame, focused),
+            InputEvent::DroppedFile(path) => listener.file_dropped(frame, path.clone()),
+            InputEvent::Resized(..) | InputEvent::CloseRequested => (),
+        }
+    }
+}
+
+fn element_state(pressed: bool) -> ElementState {
+    if pressed { ElementState::Pressed } else { ElementState::Released }
+}
+
+/// Events received after frame with `delta` was updated
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputFrame {
+    pub delta: f32,
+    pub events: Vec<InputEvent>,
+}
+
+/// Recorded session, replays start with same `window_size` and resize window as it was resized
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputRecording {
+    pub window_size: (u32, u32),
+    pub frames: Vec<InputFrame>,
+}
+impl InputRecording {
+    pub fn new(window_size: (u32, u32)) -> Self { Self {
+        window_size,
+        frames: vec![],
+    } }
+
+    pub fn push_frame(&mut self, delta: f32, events: &[InputEvent]) {
+        self.frames.push(InputFrame { delta, events: events.to_vec() });
+    }
+
+    /// Events received without update, e.g. while window is minimised
+    /// They are fed after last recorded update, as they were; events before first frame are dropped
+    pub fn extend_last_frame(&mut self, events: &[InputEvent]) {
+        if let Some(last) = self.frames.last_mut() { last.events.extend_from_slice(events); }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RecordingError> {
+        let data: Data = serde_json::from_slice(&std::fs::read(path)?)?;
+        let mut recording = Self::new((0, 0));
+        recording.read(data)?;
+        Ok(recording)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RecordingError> {
+        std::fs::write(path, serde_json::to_vec(&self.write())?)?;
+        Ok(())
+    }
+}
+
+/// Feeds recorded frames one by one
+pub struct InputPlayer {
+    recording: InputRecording,
+    next: usize,
+}
+impl InputPlayer {
+    pub fn new(recording: InputRecording) -> Self { Self { recording, next: 0 } }
+
+    /// Load `settings.replay_input` if set
+    pub fn from_settings(settings: &GameSettings) -> Result<Option<Self>, RecordingError> {
+        match &settings.replay_input {
+            Some(path) => Ok(Some(Self::new(InputRecording::load(path)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Settings to run replay with, window size is switched to recorded one
+    /// Persisted settings are not changed, so `FrameRequest::SaveSettings` doesn't save recorded size
+    pub fn session_settings(&self, settings: &GameSettings) -> GameSettings {
+        let mut session = settings.clone();
+        if self.recording.window_size != (0, 0) { session.window_size = self.recording.window_size; }
+        session
+    }
+
+    /// Next recorded frame, None then recording is over
+    pub fn next_frame(&mut self) -> Option<InputFrame> {
+        let frame = self.recording.frames.get(self.next).cloned();
+        if frame.is_some() { self.next += 1; }
+        frame
+    }
+
+    pub fn is_finished(&self) -> bool { self.next >= self.recording.frames.len() }
+    pub fn window_size(&self) -> (u32, u32) { self.recording.window_size }
+}
+
+fn peek_field<T>(val: &Data, key: &str) -> Result<T, PersistentError>
+    where Data: Peek<T, DataObtainError>
+{
+    match val.obj_get(key) {
+        PeekResult::Ok(d) | PeekResult::Lossy(d) => match d.peek() {
+            PeekResult::Ok(v) | PeekResult::Lossy(v) => Ok(v),
+            PeekResult::Err(e) => Err(PersistentError::InvalidField(key.to_string(), e)),
+        },
+        PeekResult::Err(e) => Err(PersistentError::InvalidField(key.to_string(), e)),
+    }
+}
+
+fn item<T>(items: &[Data], i: usize) -> Result<T, PersistentError>
+    where Data: Peek<T, DataObtainError>
+{
+    match items.get(i).cloned().unwrap_or(Data::None).peek() {
+        PeekResult::Ok(v) | PeekResult::Lossy(v) => Ok(v),
+        PeekResult::Err(e) => Err(PersistentError::InvalidField(format!("event[{}]", i), e)),
+    }
+}
+
+/// Discriminant of `key`, written for keys without name in `input_map` key table
+fn key_code(key: VirtualKeyCode) -> u32 { key as u32 }
+
+fn key_from_code(code: u32) -> Option<VirtualKeyCode> {
+    // VirtualKeyCode is `repr(u32)` without gaps, `Cut` is the last one
+    if code <= VirtualKeyCode::Cut as u32 {
+        Some(unsafe { std::mem::transmute::<u32, VirtualKeyCode>(code) })
+    } else {
+        None
+    }
+}
+
+/// Events are written as short arrays: ["key", "W", true], ["motion", x, y], ["close"]
+/// Keys without name are written by code: ["key", 149, true]
+impl Persistent for InputEvent {
+    fn read(&mut self, val: Data) -> Result<(), PersistentError> {
+        let items = match val {
+            Data::Array(items) => items,
+            _ => return Err(PersistentError::UnableToDeserialize),
+        };
+        let string = |i: usize| -> Result<String, PersistentError> { item(&items, i) };
+        let float = |i: usize| -> Result<f64, PersistentError> { item(&items, i) };
+        let flag = |i: usize| -> Result<bool, PersistentError> { item(&items, i) };
+
+        *self = match string(0)?.as_str() {
+            "key" => {
+                let name = string(1)?;
+                let key = input_map::key_from_name(&name)
+                    .or_else(|| name.parse().ok().and_then(key_from_code))
+                    .ok_or(PersistentError::UnableToDeserialize)?;
+                InputEvent::Key(key, flag(2)?)
+            },
+            "button" => InputEvent::MouseButton(
+                input_map::mouse_from_name(&string(1)?).ok_or(PersistentError::UnableToDeserialize)?,
+                flag(2)?
+            ),
+            "motion" => InputEvent::MouseMotion(float(1)?, float(2)?),
+            "cursor" => InputEvent::CursorMoved(float(1)?, float(2)?),
+            "wheel" => InputEvent::Wheel(float(1)? as f32, float(2)? as f32),
+            "wheel_px" => InputEvent::WheelPixels(float(1)?, float(2)?),
+            "char" => InputEvent::Character(string(1)?.chars().next().ok_or(PersistentError::UnableToDeserialize)?),
+            "focus" => InputEvent::Focused(flag(1)?),
+            "drop" => InputEvent::DroppedFile(PathBuf::from(string(1)?)),
+            "resized" => InputEvent::Resized(float(1)?, float(2)?),
+            "close" => InputEvent::CloseRequested,
+            _ => return Err(PersistentError::UnableToDeserialize),
+        };
+        Ok(())
+    }
+    fn write(&self) -> Data {
+        let items: Vec<Data> = match self {
+            InputEvent::Key(key, pressed) => vec![
+                "key".into(),
+                match input_map::key_name(*key) {
+                    Some(name) => name.into(),
+                    None => key_code(*key).into(),
+                },
+                (*pressed).into()
+            ],
+            InputEvent::MouseButton(button, pressed) => vec![
+                "button".into(), input_map::mouse_name(*button).into(), (*pressed).into()
+            ],
+            InputEvent::MouseMotion(x, y) => vec!["motion".into(), (*x).into(), (*y).into()],
+            InputEvent::CursorMoved(x, y) => vec!["cursor".into(), (*x).into(), (*y).into()],
+            InputEvent::Wheel(x, y) => vec!["wheel".into(), (*x).into(), (*y).into()],
+            InputEvent::WheelPixels(x, y) => vec!["wheel_px".into(), (*x).into(), (*y).into()],
+            InputEvent::Character(c) => vec!["char".into(), c.to_string().into()],
+            InputEvent::Focused(focused) => vec!["focus".into(), (*focused).into()],
+            InputEvent::DroppedFile(path) => vec!["drop".into(), path.to_string_lossy().into_owned().into()],
+            InputEvent::Resized(w, h) => vec!["resized".into(), (*w).into(), (*h).into()],
+            InputEvent::CloseRequested => vec!["close".into()],
+        };
+        Data::Array(items)
+    }
+}
+
+/// { "ver": 1, "window_size": [w, h], "frames": [{ "delta": f32, "events": [InputEvent] }] }
+impl Persistent for InputRecording {
+    fn read(&mut self, val: Data) -> Result<(), PersistentError> {
+        let ver: u16 = peek_field(&val, "ver")?;
+        if ver == 0 || ver > RECORDING_VERSION { return Err(PersistentError::UnknownVersion(ver)); }
+
+        let size = val.obj_get("window_size").unwrap_or(Data::None);
+        let width: u32 = size.arr_get(0).unwrap_or(Data::None).peek().unwrap_or(0);
+        let height: u32 = size.arr_get(1).unwrap_or(Data::None).peek().unwrap_or(0);
+
+        let mut frames = vec![];
+        if let PeekResult::Ok(Data::Array(list)) = val.obj_get("frames") {
+            for f in list {
+                let mut events = vec![];
+                if let PeekResult::Ok(Data::Array(list)) = f.obj_get("events") {
+                    for e in list {
+                        let mut event = InputEvent::CloseRequested;
+                        event.read(e)?;
+                        events.push(event);
+                    }
+                }
+                frames.push(InputFrame { delta: peek_field(&f, "delta")?, events });
+            }
+        }
+
+        self.window_size = (width, height);
+        self.frames = frames;
+        Ok(())
+    }
+    fn write(&self) -> Data {
+        let frames = self.frames.iter()
+            .map(|f| DataObject! {
+                delta => f.delta,
+                events => Data::Array(f.events.iter().map(|e| e.write()).collect())
+            })
+            .collect::<Vec<Data>>();
+        DataObject! {
+            ver => RECORDING_VERSION,
+            window_size => vec![Data::from(self.window_size.0), Data::from(self.window_size.1)],
+            frames => frames
+        }
+    }
+}
+
+mod test {
+
+    #[test] fn test_recording_round_trip() {
+        use super::{ InputRecording, InputEvent, InputPlayer };
+        use serializer::{ Data, Persistent };
+        use winit::{ VirtualKeyCode, MouseButton };
+
+        let mut recording = InputRecording::new((640, 480));
+        recording.push_frame(1.0 / 60.0, &[
+            InputEvent::Key(VirtualKeyCode::W, true),
+            InputEvent::Key(VirtualKeyCode::Mail, true), // Not in key table, written by code
+            InputEvent::Resized(800.0, 600.0),
+            InputEvent::MouseButton(MouseButton::Other(3), false),
+            InputEvent::MouseMotion(1.5, -2.0),
+        ]);
+        recording.push_frame(0.02, &[
+            InputEvent::CursorMoved(10.0, 20.0),
+            InputEvent::Wheel(0.0, 1.0),
+            InputEvent::WheelPixels(0.0, -12.5),
+            InputEvent::Character('ё'),
+            InputEvent::Focused(false),
+            InputEvent::DroppedFile(std::path::PathBuf::from("data/level.obj")),
+            InputEvent::CloseRequested,
+        ]);
+        recording.extend_last_frame(&[InputEvent::Resized(1.0, 1.0), InputEvent::Focused(true)]);
+        assert_eq!(recording.frames[0].events.len(), 5);
+        assert_eq!(recording.frames[1].events.last(), Some(&InputEvent::Focused(true)));
+
+        let json = serde_json::to_string(&recording.write()).unwrap();
+        let data: Data = serde_json::from_str(&json).unwrap();
+        let mut read = InputRecording::new((0, 0));
+        read.read(data).unwrap();
+        assert_eq!(read, recording);
+
+        let mut player = InputPlayer::new(read);
+        assert_eq!(player.window_size(), (640, 480));
+        assert_eq!(player.next_frame().unwrap().delta, 1.0 / 60.0);
+        assert!(player.next_frame().is_some());
+        assert!(player.is_finished());
+        assert!(player.next_frame().is_none());
+
+        assert_eq!(super::key_from_code(super::key_code(VirtualKeyCode::Cut)), Some(VirtualKeyCode::Cut));
+        assert_eq!(super::key_from_code(VirtualKeyCode::Cut as u32 + 1), None);
+    }
+}
@@ -3,15 +3,21 @@
 
 use vulkano_win::{ self, VkSurfaceBuild };
 use vulkano::{
-    instance::{ Instance, PhysicalDevice, ApplicationInfo, Version },
+    instance::{ Instance, PhysicalDevice, PhysicalDeviceType, ApplicationInfo, Version },
     swapchain::{ PresentMode, Surface },
 };
 
-use winit::{EventsLoop, WindowBuilder, Window, dpi::{ LogicalPosition, LogicalSize }};
+use winit::{EventsLoop, WindowBuilder, Window, MonitorId, Icon, dpi::{ LogicalPosition, LogicalSize }};
 use std::sync::Arc;
 use std::borrow::Cow;
 use std::cell::RefCell;
+use std::io::Cursor;
+use std::path::{ Path, PathBuf };
 use vulkano::instance::InstanceExtensions;
+use serializer::{ Data, DataObject, DataObtainError, Peek, PeekResult, Persistent, PersistentError };
+
+use crate::graphics::image::ImageContent;
+use super::FrameRequest;
 
 pub const WINDOW_TITLE: &str = "API";
 const ENGINE_NAME: &str = "Insomnia";
@@ -21,24 +27,100 @@ const ENGINE_VER: Version = Version {
     patch: 0
 };
 
-#[derive(Debug, Copy, Clone)]
+/// Layout version of settings file, bump on change and append patch into `GameSettings::read`
+pub const SETTINGS_VERSION: u16 = 3;
+/// Upper bound of `GameSettings::frames_in_flight`
+pub const MAX_FRAMES_IN_FLIGHT: u32 = 3;
+/// Prefix of environment overrides, `INSOMNIA_WINDOW_SIZE=1280x720`
+pub const SETTINGS_ENV_PREFIX: &str = "INSOMNIA_";
+/// PCI vendor of NVIDIA devices, they need `wait_for_gpu`
+const NVIDIA_VENDOR_ID: u32 = 0x10DE;
+
+pub enum SettingsError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Persistent(PersistentError),
+    UnknownKey(String), // Override for setting that doesn't exist
+    InvalidValue(String, String), // Override (key, value) can't be parsed
+}
+impl std::error::Error for SettingsError {}
+impl std::fmt::Debug for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            SettingsError::Io(e) => write!(f, "IO Error: {:?}", e),
+            SettingsError::Json(e) => write!(f, "Settings file is not valid JSON: {:?}", e),
+            SettingsError::Persistent(e) => write!(f, "Unable to read settings: {:?}", e),
+            SettingsError::UnknownKey(key) => write!(f, "Unknown setting \"{}\"", key),
+            SettingsError::InvalidValue(key, value) => write!(f, "Invalid value \"{}\" for setting \"{}\"", value, key),
+        }
+    }
+}
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        (self as &dyn std::fmt::Debug).fmt(f)
+    }
+}
+impl From<std::io::Error> for SettingsError {
+    fn from(e: std::io::Error) -> Self { SettingsError::Io(e) }
+}
+impl From<serde_json::Error> for SettingsError {
+    fn from(e: serde_json::Error) -> Self { SettingsError::Json(e) }
+}
+impl From<PersistentError> for SettingsError {
+    fn from(e: PersistentError) -> Self { SettingsError::Persistent(e) }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum WindowMode {
     // Windowed with optional window position
     Windowed(Option<(u32, u32)>),
-    // Borderless full screen
+    // Borderless window covering whole monitor
     Borderless,
-    // Exclusive full screen
+    // Borderless fullscreen of winit on selected monitor, at native resolution of monitor
+    // Not exclusive: winit 0.19 can't switch video modes and vulkano 0.16 lacks VK_EXT_full_screen_exclusive
     Fullscreen
 }
 
-#[derive(Copy, Clone)]
+/// Preferred encoding of swapchain images
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorMode {
+    // Hardware converts linear output into sRGB
+    Srgb,
+    // Output is written as is
+    Unorm,
+}
+
+/// Not `Copy` as it owns device name and paths, functions take `&GameSettings` and main loops own their copy
+#[derive(Clone)]
 pub struct GameSettings {
     // Window settings
     pub window_size: (u32, u32),
     pub window_mode: WindowMode,
+    pub monitor: Option<usize>, // Index of monitor for Borderless and Fullscreen, None => primary
 
     // Vulkan settings
-    pub present_mode: PresentMode,
+    pub present_mode: PresentMode, // Falls back to Fifo if not supported
+    pub color_mode: ColorMode, // Falls back to first supported format
+    pub swapchain_images: Option<u32>, // None => minimal supported, clamped into supported range
+    pub device_name: Option<String>, // Part of physical device name, case insensitive
+    pub device_type: Option<PhysicalDeviceType>,
+
+    // Game clock settings
+    pub tick_rate: f32, // Fixed updates per second
+    pub max_ticks_per_frame: u32, // Time above this is dropped
+    pub time_scale: f32, // Initial time scale of game clock
+
+    // Frame pacing settings
+    pub frames_in_flight: u32, // Frames CPU may record ahead of GPU, clamped into [1, MAX_FRAMES_IN_FLIGHT]
+    pub wait_for_gpu: Option<bool>, // Wait for every frame after present, None => only on NVIDIA and macOS which require it (see vulkano#1247)
+
+    // Not persistent, file settings were loaded from and `FrameRequest::SaveSettings` writes to
+    pub settings_file: Option<PathBuf>,
+
+    // Not persistent, session only
+    pub fixed_delta: Option<f32>, // Frame delta used instead of measured time, for deterministic runs
+    pub record_input: Option<PathBuf>, // Input recording is written here on exit
+    pub replay_input: Option<PathBuf>, // Input recording fed instead of window input
 }
 
 /// Default settings
@@ -46,12 +128,345 @@ impl Default for GameSettings {
     fn default() -> Self { Self {
         window_size: (800, 600),
         window_mode: WindowMode::Windowed(None),
+        monitor: None,
         present_mode: PresentMode::Fifo,
+        color_mode: ColorMode::Srgb,
+        swapchain_images: None,
+        device_name: None,
+        device_type: None,
+        tick_rate: 60.0,
+        max_ticks_per_frame: 5,
+        time_scale: 1.0,
+        frames_in_flight: 2,
+        wait_for_gpu: None,
+        settings_file: None,
+        fixed_delta: None,
+        record_input: None,
+        replay_input: None,
     }}
 }
 
 /// Editor for settings
 impl GameSettings {
+    /// Read settings file over defaults
+    pub fn load(path: &Path) -> Result<Self, SettingsError> {
+        let data: Data = serde_json::from_slice(&std::fs::read(path)?)?;
+        let mut settings = Self::default();
+        settings.read(data)?;
+        settings.settings_file = Some(path.to_path_buf());
+        Ok(settings)
+    }
+
+    /// Write settings file, including fields that match default
+    pub fn save(&self, path: &Path) -> Result<(), SettingsError> {
+        let json = serde_json::to_string_pretty(&self.write())?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Save into file settings were loaded from, if any
+    pub fn save_back(&self) -> Result<(), SettingsError> {
+        match &self.settings_file {
+            Some(path) => self.save(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Requests applying settings that can change at runtime, used when settings file is reloaded
+    /// Device, swapchain and clock rate settings need restart
+    pub fn runtime_requests(&self) -> Vec<FrameRequest> {
+        let vsync = match self.present_mode {
+            PresentMode::Fifo | PresentMode::Relaxed => true,
+            _ => false,
+        };
+        vec![
+            FrameRequest::SetWindowMode(self.window_mode),
+            FrameRequest::SetWindowSize(self.window_size.0, self.window_size.1),
+            FrameRequest::SetVSync(Some(vsync)),
+            FrameRequest::SetTimeScale(self.time_scale),
+        ]
+    }
+
+    /// Settings file (if exists) with environment overrides, then command line overrides on top
+    /// Missing file is not an error, settings will be saved back into it
+    pub fn load_layered(path: &Path, args: &[String]) -> Result<Self, SettingsError> {
+        let mut settings = if path.exists() {
+            Self::load(path)?
+        } else {
+            let mut s = Self::default();
+            s.settings_file = Some(path.to_path_buf());
+            s
+        };
+        settings.apply_env()?;
+        settings.apply_args(args)?;
+        Ok(settings)
+    }
+
+    /// Apply `INSOMNIA_<KEY>=<value>` environment variables, other `INSOMNIA_` variables are ignored
+    pub fn apply_env(&mut self) -> Result<(), SettingsError> {
+        self.apply_vars(std::env::vars())
+    }
+
+    /// Apply variables as `apply_env` does, from any source
+    pub fn apply_vars<I>(&mut self, vars: I) -> Result<(), SettingsError>
+        where I: IntoIterator<Item = (String, String)>
+    {
+        for (key, value) in vars {
+            if key.starts_with(SETTINGS_ENV_PREFIX) {
+                let key = key[SETTINGS_ENV_PREFIX.len() ..].to_lowercase();
+                if is_setting_key(&key) { self.set_value(&key, &value)?; }
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply `--<key>=<value>` arguments, other arguments are ignored
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), SettingsError> {
+        for arg in args.iter().filter(|a| a.starts_with("--")) {
+            let mut split = arg[2 ..].splitn(2, '=');
+            let key = split.next().unwrap().replace('-', "_");
+            if let Some(value) = split.next() {
+                if is_setting_key(&key) { self.set_value(&key, value)?; }
+            }
+        }
+        Ok(())
+    }
+
+    /// Set single setting from string, `none` resets optional settings
+    /// Keys: window_size (WxH), window_mode (windowed[:X,Y] | borderless | fullscreen), monitor,
+    /// present_mode, color_mode, swapchain_images, device_name, device_type,
+    /// tick_rate, max_ticks_per_frame, time_scale, frames_in_flight, wait_for_gpu, fixed_delta, record_input, replay_input
+    pub fn set_value(&mut self, key: &str, value: &str) -> Result<(), SettingsError> {
+        let invalid = || SettingsError::InvalidValue(key.to_string(), value.to_string());
+        let is_none = value.eq_ignore_ascii_case("none");
+        match key {
+            "window_size" => self.window_size = parse_pair(value, 'x').ok_or_else(invalid)?,
+            "window_mode" => self.window_mode = parse_window_mode(value).ok_or_else(invalid)?,
+            "monitor" => self.monitor = if is_none { None } else { Some(value.parse().map_err(|_| invalid())?) },
+            "present_mode" => self.present_mode = present_mode_from_name(value).ok_or_else(invalid)?,
+            "color_mode" => self.color_mode = color_mode_from_name(value).ok_or_else(invalid)?,
+            "swapchain_images" => self.swapchain_images = if is_none { None } else { Some(value.parse().map_err(|_| invalid())?) },
+            "device_name" => self.device_name = if is_none { None } else { Some(value.to_string()) },
+            "device_type" => self.device_type = if is_none { None } else { Some(device_type_from_name(value).ok_or_else(invalid)?) },
+            "tick_rate" => self.tick_rate = value.parse().map_err(|_| invalid())?,
+            "max_ticks_per_frame" => self.max_ticks_per_frame = value.parse().map_err(|_| invalid())?,
+            "time_scale" => self.time_scale = value.parse().map_err(|_| invalid())?,
+            "frames_in_flight" => self.frames_in_flight = value.parse().map_err(|_| invalid())?,
+            "wait_for_gpu" => self.wait_for_gpu = if is_none { None } else { Some(value.parse().map_err(|_| invalid())?) },
+            "fixed_delta" => self.fixed_delta = if is_none { None } else { Some(value.parse().map_err(|_| invalid())?) },
+            "record_input" => self.record_input = if is_none { None } else { Some(PathBuf::from(value)) },
+            "replay_input" => self.replay_input = if is_none { None } else { Some(PathBuf::from(value)) },
+            _ => return Err(SettingsError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+}
+
+fn is_setting_key(key: &str) -> bool {
+    match key {
+        "window_size" | "window_mode" | "monitor" |
+        "present_mode" | "color_mode" | "swapchain_images" |
+        "device_name" | "device_type" |
+        "tick_rate" | "max_ticks_per_frame" | "time_scale" |
+        "frames_in_flight" | "wait_for_gpu" |
+        "fixed_delta" | "record_input" | "replay_input" => true,
+        _ => false,
+    }
+}
+
+/// "800x600" => (800, 600)
+fn parse_pair(value: &str, separator: char) -> Option<(u32, u32)> {
+    let mut split = value.splitn(2, separator);
+    let a = split.next()?.trim().parse().ok()?;
+    let b = split.next()?.trim().parse().ok()?;
+    Some((a, b))
+}
+
+/// "windowed", "windowed:100,50", "borderless", "fullscreen"
+fn parse_window_mode(value: &str) -> Option<WindowMode> {
+    let mut split = value.splitn(2, ':');
+    match split.next()?.to_lowercase().as_str() {
+        "windowed" => match split.next() {
+            Some(pos) => Some(WindowMode::Windowed(Some(parse_pair(pos, ',')?))),
+            None => Some(WindowMode::Windowed(None)),
+        },
+        "borderless" => Some(WindowMode::Borderless),
+        "fullscreen" => Some(WindowMode::Fullscreen),
+        _ => None,
+    }
+}
+
+fn present_mode_name(mode: PresentMode) -> &'static str {
+    match mode {
+        PresentMode::Immediate => "immediate",
+        PresentMode::Mailbox => "mailbox",
+        PresentMode::Fifo => "fifo",
+        PresentMode::Relaxed => "relaxed",
+    }
+}
+fn present_mode_from_name(name: &str) -> Option<PresentMode> {
+    match name.to_lowercase().as_str() {
+        "immediate" => Some(PresentMode::Immediate),
+        "mailbox" => Some(PresentMode::Mailbox),
+        "fifo" => Some(PresentMode::Fifo),
+        "relaxed" => Some(PresentMode::Relaxed),
+        _ => None,
+    }
+}
+
+fn color_mode_name(mode: ColorMode) -> &'static str {
+    match mode {
+        ColorMode::Srgb => "srgb",
+        ColorMode::Unorm => "unorm",
+    }
+}
+fn color_mode_from_name(name: &str) -> Option<ColorMode> {
+    match name.to_lowercase().as_str() {
+        "srgb" => Some(ColorMode::Srgb),
+        "unorm" => Some(ColorMode::Unorm),
+        _ => None,
+    }
+}
+
+fn device_type_name(ty: PhysicalDeviceType) -> &'static str {
+    match ty {
+        PhysicalDeviceType::IntegratedGpu => "integrated",
+        PhysicalDeviceType::DiscreteGpu => "discrete",
+        PhysicalDeviceType::VirtualGpu => "virtual",
+        PhysicalDeviceType::Cpu => "cpu",
+        PhysicalDeviceType::Other => "other",
+    }
+}
+fn device_type_from_name(name: &str) -> Option<PhysicalDeviceType> {
+    match name.to_lowercase().as_str() {
+        "integrated" => Some(PhysicalDeviceType::IntegratedGpu),
+        "discrete" => Some(PhysicalDeviceType::DiscreteGpu),
+        "virtual" => Some(PhysicalDeviceType::VirtualGpu),
+        "cpu" => Some(PhysicalDeviceType::Cpu),
+        "other" => Some(PhysicalDeviceType::Other),
+        _ => None,
+    }
+}
+
+/// Peek object field, Ok(None) if field is missing
+fn field<T>(val: &Data, key: &str) -> Result<Option<T>, PersistentError>
+    where Data: Peek<T, DataObtainError>
+{
+    match val.obj_get(key) {
+        PeekResult::Ok(data) | PeekResult::Lossy(data) => match Peek::<T, DataObtainError>::peek(data) {
+            PeekResult::Ok(v) | PeekResult::Lossy(v) => Ok(Some(v)),
+            PeekResult::Err(e) => Err(PersistentError::InvalidField(key.to_string(), e)),
+        },
+        PeekResult::Err(_) => Ok(None),
+    }
+}
+
+/// Same as `field`, but `null` is read as Some(None)
+fn opt_field<T>(val: &Data, key: &str) -> Result<Option<Option<T>>, PersistentError>
+    where Data: Peek<T, DataObtainError>
+{
+    match val.obj_get(key) {
+        PeekResult::Ok(Data::None) | PeekResult::Lossy(Data::None) => Ok(Some(None)),
+        _ => Ok(field(val, key)?.map(Some)),
+    }
+}
+
+/// Named enum field, parsed with `from_name`
+fn named_field<T>(val: &Data, key: &str, from_name: fn(&str) -> Option<T>) -> Result<Option<T>, PersistentError> {
+    match field::<String>(val, key)? {
+        Some(name) => match from_name(&name) {
+            Some(v) => Ok(Some(v)),
+            None => Err(PersistentError::InvalidField(key.to_string(), DataObtainError::StringParseError(name, key.to_string()))),
+        },
+        None => Ok(None),
+    }
+}
+
+/// { "mode": "windowed" | "borderless" | "fullscreen", "x": .., "y": .. }
+impl Persistent for WindowMode {
+    fn read(&mut self, val: Data) -> Result<(), PersistentError> {
+        let mode: String = field(&val, "mode")?.ok_or(PersistentError::UnableToDeserialize)?;
+        *self = match mode.as_str() {
+            "windowed" => {
+                let x: Option<u32> = field(&val, "x")?;
+                let y: Option<u32> = field(&val, "y")?;
+                WindowMode::Windowed(x.and_then(|x| y.map(|y| (x, y))))
+            },
+            "borderless" => WindowMode::Borderless,
+            "fullscreen" => WindowMode::Fullscreen,
+            _ => return Err(PersistentError::InvalidField("mode".to_string(), DataObtainError::StringParseError(mode, "WindowMode".to_string()))),
+        };
+        Ok(())
+    }
+    fn write(&self) -> Data {
+        match self {
+            WindowMode::Windowed(Some((x, y))) => DataObject! { mode => "windowed", x => *x, y => *y },
+            WindowMode::Windowed(None) => DataObject! { mode => "windowed" },
+            WindowMode::Borderless => DataObject! { mode => "borderless" },
+            WindowMode::Fullscreen => DataObject! { mode => "fullscreen" },
+        }
+    }
+}
+
+/// Missing fields keep current values, so older files are read over defaults
+impl Persistent for GameSettings {
+    fn read(&mut self, val: Data) -> Result<(), PersistentError> {
+        let ver: u16 = field(&val, "ver")?.unwrap_or(0);
+        if ver == 0 || ver > SETTINGS_VERSION { return Err(PersistentError::UnknownVersion(ver)); }
+
+        // Ver 1
+        if let (Some(w), Some(h)) = (field(&val, "window_width")?, field(&val, "window_height")?) {
+            self.window_size = (w, h);
+        }
+        if let PeekResult::Ok(mode) = val.obj_get("window_mode") { self.window_mode.read(mode)?; }
+        if let Some(v) = opt_field(&val, "monitor")? { self.monitor = v.map(|m: u32| m as usize); }
+        if let Some(v) = named_field(&val, "present_mode", present_mode_from_name)? { self.present_mode = v; }
+        if let Some(v) = named_field(&val, "color_mode", color_mode_from_name)? { self.color_mode = v; }
+        if let Some(v) = opt_field(&val, "swapchain_images")? { self.swapchain_images = v; }
+        if let Some(v) = opt_field(&val, "device_name")? { self.device_name = v; }
+        if let Some(v) = opt_field::<String>(&val, "device_type")? {
+            self.device_type = match v {
+                Some(name) => Some(device_type_from_name(&name).ok_or_else(|| PersistentError::InvalidField(
+                    "device_type".to_string(), DataObtainError::StringParseError(name, "PhysicalDeviceType".to_string())
+                ))?),
+                None => None,
+            };
+        }
+
+        // Ver 2, game clock
+        if ver >= 2 {
+            if let Some(v) = field(&val, "tick_rate")? { self.tick_rate = v; }
+            if let Some(v) = field(&val, "max_ticks_per_frame")? { self.max_ticks_per_frame = v; }
+            if let Some(v) = field(&val, "time_scale")? { self.time_scale = v; }
+        }
+
+        // Ver 3, frame pacing
+        if ver >= 3 {
+            if let Some(v) = field(&val, "frames_in_flight")? { self.frames_in_flight = v; }
+            if let Some(v) = opt_field(&val, "wait_for_gpu")? { self.wait_for_gpu = v; }
+        }
+
+        Ok(())
+    }
+    fn write(&self) -> Data {
+        DataObject! {
+            ver => SETTINGS_VERSION,
+            window_width => self.window_size.0,
+            window_height => self.window_size.1,
+            window_mode => self.window_mode.write(),
+            monitor => self.monitor.map(|m| m as u32),
+            present_mode => present_mode_name(self.present_mode),
+            color_mode => color_mode_name(self.color_mode),
+            swapchain_images => self.swapchain_images,
+            device_name => self.device_name.clone(),
+            device_type => self.device_type.map(device_type_name),
+            tick_rate => self.tick_rate,
+            max_ticks_per_frame => self.max_ticks_per_frame,
+            time_scale => self.time_scale,
+            frames_in_flight => self.frames_in_flight,
+            wait_for_gpu => self.wait_for_gpu,
+        }
+    }
 }
 
 /// Usage of selected settings
@@ -61,49 +476,36 @@ impl GameSettings {
         let mut event_loop = EventsLoop::new();
 
         // Create instance
-        let instance = {
-
-            let app_info = ApplicationInfo {
-                application_name: Some(Cow::Borrowed(WINDOW_TITLE)),
-                application_version: Some(Version{
-                    major: 0,
-                    minor: 1,
-                    patch: 0
-                }),
-                engine_name: Some(Cow::Borrowed(ENGINE_NAME)),
-                engine_version: Some(ENGINE_VER)
-            };
-
-            let extensions = vulkano_win::required_extensions();
-            Instance::new(Some(&app_info), &extensions, None).unwrap()
-        };
+        let instance = create_instance(&vulkano_win::required_extensions())?;
 
 
         let surface = {
             let mut wb = winit::WindowBuilder::new().with_title(WINDOW_TITLE);
+            let monitor = pick_monitor(event_loop.get_available_monitors(), event_loop.get_primary_monitor(), self.monitor);
 
             match self.window_mode {
-                WindowMode::Windowed(pos) => {
-//                    if let Some(p) = pos { window.set_position(LogicalPosition::new(p.0 as _, p.1 as _)); }
-//                    let size = LogicalSize::from_physical(self.window_size.into(), window.get_hidpi_factor());
-//                    window.set_inner_size(size);
+                WindowMode::Windowed(_) => {
                     wb = wb.with_dimensions(self.window_size.into());
                 },
                 WindowMode::Borderless => {
-//                    let dims = window.get_current_monitor().get_dimensions();
-                    wb = wb.with_dimensions(LogicalSize::from_physical(event_loop.get_primary_monitor().get_dimensions(), event_loop.get_primary_monitor().get_hidpi_factor()))
+                    wb = wb.with_dimensions(LogicalSize::from_physical(monitor.get_dimensions(), monitor.get_hidpi_factor()))
                         .with_decorations(false);
-                }
-                WindowMode::Fullscreen => return Err(String::from("Fullscreen currently not supported")),
+                },
+                WindowMode::Fullscreen => {
+                    wb = wb.with_fullscreen(Some(monitor));
+                },
             }
 
-            wb.build_vk_surface(&event_loop, instance.clone()).unwrap()
+            match wb.build_vk_surface(&event_loop, instance.clone()) {
+                Ok(s) => s,
+                Err(e) => return Err(format!("{:?}", e)),
+            }
         };
         let window = surface.window();
 
         // Extra settings for window
         match self.window_mode {
-            WindowMode::Borderless => window.set_position((0, 0).into()),
+            WindowMode::Windowed(Some(_)) | WindowMode::Borderless => self.apply_window_mode(window),
             _ => (),
         }
 
@@ -117,6 +519,91 @@ impl GameSettings {
     }
 }
 
+/// Runtime window changes
+impl GameSettings {
+    /// Apply `window_mode`, `window_size` and `monitor` to existing window
+    /// Window will send resize event, so swapchain is recreated by main loop
+    pub fn apply_window_mode(&self, window: &Window) {
+        let monitor = pick_monitor(window.get_available_monitors(), window.get_primary_monitor(), self.monitor);
+        match self.window_mode {
+            WindowMode::Windowed(pos) => {
+                window.set_fullscreen(None);
+                window.set_decorations(true);
+                window.set_inner_size(self.window_size.into());
+                if let Some((x, y)) = pos {
+                    window.set_position(LogicalPosition::new(x as f64, y as f64));
+                }
+            },
+            WindowMode::Borderless => {
+                let hidpi = monitor.get_hidpi_factor();
+                window.set_fullscreen(None);
+                window.set_decorations(false);
+                window.set_position(monitor.get_position().to_logical(hidpi));
+                window.set_inner_size(LogicalSize::from_physical(monitor.get_dimensions(), hidpi));
+            },
+            // Window is resized to cover monitor, video mode is not changed
+            WindowMode::Fullscreen => window.set_fullscreen(Some(monitor)),
+        }
+    }
+}
+
+/// Monitor with `index`, primary if None or out of range
+fn pick_monitor<I>(mut monitors: I, primary: MonitorId, index: Option<usize>) -> MonitorId
+    where I: Iterator<Item = MonitorId>
+{
+    index.and_then(|i| monitors.nth(i)).unwrap_or(primary)
+}
+
+/// Load window icon from image bytes
+pub fn load_icon(bytes: Cursor<Vec<u8>>) -> Result<Icon, String> {
+    let data = ImageContent::load_image_data(bytes).map_err(|e| e.to_string())?;
+    Icon::from_rgba(data.data, data.dimensions.0, data.dimensions.1).map_err(|e| format!("{:?}", e))
+}
+
+/// Device selection
+impl GameSettings {
+    /// First physical device matching `device_name` and `device_type`
+    pub fn select_physical_device<'a>(&self, instance: &'a Arc<Instance>) -> Result<PhysicalDevice<'a>, String> {
+        let name = self.device_name.as_ref().map(|n| n.to_lowercase());
+        let found = PhysicalDevice::enumerate(instance).find(|dev| {
+            name.as_ref().map(|n| dev.name().to_lowercase().contains(n)).unwrap_or(true)
+                && self.device_type.map(|t| dev.ty() == t).unwrap_or(true)
+        });
+
+        match found {
+            Some(dev) => Ok(dev),
+            None => {
+                let available: Vec<String> = PhysicalDevice::enumerate(instance)
+                    .map(|dev| format!("{} ({:?})", dev.name(), dev.ty()))
+                    .collect();
+                Err(format!("No device matching name {:?} and type {:?}, available: {:?}",
+                            self.device_name, self.device_type, available))
+            }
+        }
+    }
+
+    /// `wait_for_gpu` if set, otherwise true on macOS (MoltenVK) and NVIDIA devices
+    pub fn waits_for_gpu(&self, physical: PhysicalDevice) -> bool {
+        self.wait_for_gpu.unwrap_or(cfg!(target_os = "macos") || physical.pci_vendor_id() == NVIDIA_VENDOR_ID)
+    }
+}
+
+/// Create Vulkan instance with engine info and given extensions
+pub fn create_instance(extensions: &InstanceExtensions) -> Result<Arc<Instance>, String> {
+    let app_info = ApplicationInfo {
+        application_name: Some(Cow::Borrowed(WINDOW_TITLE)),
+        application_version: Some(Version{
+            major: 0,
+            minor: 1,
+            patch: 0
+        }),
+        engine_name: Some(Cow::Borrowed(ENGINE_NAME)),
+        engine_version: Some(ENGINE_VER)
+    };
+
+    Instance::new(Some(&app_info), extensions, None).map_err(|e| format!("{:?}", e))
+}
+
 /// Contains backend information
 pub struct WindowInfo {
     pub event_loop: EventsLoop,
@@ -126,3 +613,72 @@ pub struct WindowInfo {
 impl WindowInfo {
     pub fn window(&self) -> &Window { self.surface.window() }
 }
+
+mod test {
+
+    #[test] fn test_settings_round_trip() {
+        use super::{ GameSettings, WindowMode, ColorMode };
+        use serializer::{ Data, Persistent };
+        use vulkano::{ instance::PhysicalDeviceType, swapchain::PresentMode };
+
+        let settings = GameSettings {
+            window_size: (1280, 720),
+            window_mode: WindowMode::Windowed(Some((10, 20))),
+            monitor: Some(1),
+            present_mode: PresentMode::Mailbox,
+            color_mode: ColorMode::Unorm,
+            swapchain_images: Some(3),
+            device_name: Some("llvmpipe".to_string()),
+            device_type: Some(PhysicalDeviceType::Cpu),
+            frames_in_flight: 3,
+            wait_for_gpu: Some(true),
+            .. GameSettings::default()
+        };
+
+        let json = serde_json::to_string(&settings.write()).unwrap();
+        let data: Data = serde_json::from_str(&json).unwrap();
+        let mut read = GameSettings::default();
+        read.read(data).unwrap();
+
+        assert_eq!(read.window_size, settings.window_size);
+        assert_eq!(read.window_mode, settings.window_mode);
+        assert_eq!(read.monitor, settings.monitor);
+        assert_eq!(read.present_mode, settings.present_mode);
+        assert_eq!(read.color_mode, settings.color_mode);
+        assert_eq!(read.swapchain_images, settings.swapchain_images);
+        assert_eq!(read.device_name, settings.device_name);
+        assert_eq!(read.device_type, settings.device_type);
+        assert_eq!(read.frames_in_flight, settings.frames_in_flight);
+        assert_eq!(read.wait_for_gpu, settings.wait_for_gpu);
+
+        // Unknown version is rejected
+        let mut future = GameSettings::default();
+        assert!(future.read(serializer::DataObject! { ver => 999u16 }).is_err());
+    }
+
+    #[test] fn test_settings_overrides() {
+        use super::{ GameSettings, WindowMode };
+
+        let mut settings = GameSettings::default();
+        settings.apply_args(&[
+            "--window-size=1024x768".to_string(),
+            "--window_mode=windowed:5,6".to_string(),
+            "--headless".to_string(),
+            "--device-name=none".to_string(),
+        ]).unwrap();
+        assert_eq!(settings.window_size, (1024, 768));
+        assert_eq!(settings.window_mode, WindowMode::Windowed(Some((5, 6))));
+        assert_eq!(settings.device_name, None);
+
+        assert!(settings.set_value("window_size", "big").is_err());
+        assert!(settings.set_value("no_such_key", "1").is_err());
+
+        // Unrelated variables with same prefix don't fail loading
+        settings.apply_vars(vec![
+            (format!("{}TEST_UNKNOWN_VARIABLE", super::SETTINGS_ENV_PREFIX), "1".to_string()),
+            (format!("{}MAX_TICKS_PER_FRAME", super::SETTINGS_ENV_PREFIX), "7".to_string()),
+            ("MAX_TICKS_PER_FRAME".to_string(), "9".to_string()),
+        ]).unwrap();
+        assert_eq!(settings.max_ticks_per_frame, 7);
+    }
+}
@@ -0,0 +1,296 @@
+
+// Stack of game states
+// Main loop drives single listener, which is stack of states (loading screen, menu, gameplay, pause menu...)
+// States are switched with `FrameRequest::PushState`, `PopState` and `ReplaceState`
+
+use std::path::PathBuf;
+use winit::{ VirtualKeyCode, MouseButton };
+use vulkano::sync::GpuFuture;
+
+use super::{ Frame, FrameRequest, GameListener };
+
+/// State given to `FrameRequest`
+pub struct GameState(Box<dyn GameListener>);
+impl GameState {
+    pub fn new<L: GameListener + 'static>(listener: L) -> Self { GameState(Box::new(listener)) }
+    pub fn from_box(listener: Box<dyn GameListener>) -> Self { GameState(listener) }
+}
+impl std::fmt::Debug for GameState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "GameState")
+    }
+}
+
+/// Stack bookkeeping, generic over state and context so it can be tested without `Frame`
+trait StackState<C> {
+    fn enter(&mut self, ctx: &mut C);
+    fn exit(&mut self, ctx: &mut C);
+    fn dimensions_changed(&mut self, ctx: &mut C, width: u32, height: u32);
+    fn is_overlay(&self) -> bool;
+}
+
+enum StateRequest<S> {
+    Push(S),
+    Pop,
+    Replace(S),
+}
+
+trait StackContext<S> {
+    /// First state request, in order they were made
+    fn take_state_request(&mut self) -> Option<StateRequest<S>>;
+    fn request_exit(&mut self);
+    fn set_top_state(&mut self, top: bool);
+}
+
+impl <'v> StackState<Frame<'v>> for Box<dyn GameListener> {
+    fn enter(&mut self, frame: &mut Frame<'v>) { GameListener::enter(self.as_mut(), frame) }
+    fn exit(&mut self, frame: &mut Frame<'v>) { GameListener::exit(self.as_mut(), frame) }
+    fn dimensions_changed(&mut self, frame: &mut Frame<'v>, width: u32, height: u32) {
+        GameListener::dimensions_changed(self.as_mut(), frame, width, height)
+    }
+    fn is_overlay(&self) -> bool { GameListener::is_overlay(self.as_ref()) }
+}
+
+impl <'v> StackContext<Box<dyn GameListener>> for Frame<'v> {
+    fn take_state_request(&mut self) -> Option<StateRequest<Box<dyn GameListener>>> {
+        let idx = self.requests.iter().position(|r| match r {
+            FrameRequest::PushState(_) | FrameRequest::PopState | FrameRequest::ReplaceState(_) => true,
+            _ => false,
+        })?;
+        match self.requests.remove(idx) {
+            FrameRequest::PushState(GameState(state)) => Some(StateRequest::Push(state)),
+            FrameRequest::ReplaceState(GameState(state)) => Some(StateRequest::Replace(state)),
+            _ => Some(StateRequest::Pop),
+        }
+    }
+    fn request_exit(&mut self) { self.request(FrameRequest::ExitApplication) }
+    fn set_top_state(&mut self, top: bool) { self.top_state = top; }
+}
+
+struct States<S> {
+    states: Vec<S>,
+    dimensions: Option<(u32, u32)>, // Given to pushed states
+}
+impl <S> States<S> {
+    fn new() -> Self { Self { states: vec![], dimensions: None } }
+
+    fn push<C>(&mut self, ctx: &mut C, mut state: S) where S: StackState<C> {
+        state.enter(ctx);
+        if let Some((w, h)) = self.dimensions { state.dimensions_changed(ctx, w, h); }
+        self.states.push(state);
+    }
+
+    fn pop<C>(&mut self, ctx: &mut C) where S: StackState<C>, C: StackContext<S> {
+        if let Some(mut state) = self.states.pop() {
+            state.exit(ctx);
+        }
+        if self.states.is_empty() { ctx.request_exit(); }
+    }
+
+    /// Apply state requests made by states, in order they were made
+    fn apply_requests<C>(&mut self, ctx: &mut C) where S: StackState<C>, C: StackContext<S> {
+        while let Some(request) = ctx.take_state_request() {
+            match request {
+                StateRequest::Push(state) => self.push(ctx, state),
+                StateRequest::Pop => self.pop(ctx),
+                StateRequest::Replace(state) => {
+                    if let Some(mut top) = self.states.pop() { top.exit(ctx); }
+                    self.push(ctx, state);
+                },
+            }
+        }
+    }
+
+    fn dimensions_changed<C>(&mut self, ctx: &mut C, width: u32, height: u32) where S: StackState<C>, C: StackContext<S> {
+        self.dimensions = Some((width, height));
+        for s in self.states.iter_mut() { s.dimensions_changed(ctx, width, height); }
+        self.apply_requests(ctx);
+    }
+
+    /// Index of lowest state that is still updated, every state above it is overlay
+    fn first_active<C>(&self) -> usize where S: StackState<C> {
+        let mut idx = self.states.len().saturating_sub(1);
+        while idx > 0 && self.states[idx].is_overlay() { idx -= 1; }
+        idx
+    }
+
+    /// Call `f` on every active state, bottom to top
+    fn for_active<C, F>(&mut self, ctx: &mut C, mut f: F)
+        where S: StackState<C>, C: StackContext<S>, F: FnMut(&mut S, &mut C)
+    {
+        let top = self.states.len().saturating_sub(1);
+        for i in self.first_active::<C>() .. self.states.len() {
+            ctx.set_top_state(i == top);
+            f(&mut self.states[i], ctx);
+        }
+        ctx.set_top_state(true);
+        self.apply_requests(ctx);
+    }
+
+    /// Call `f` on top state only
+    fn for_top<C, F>(&mut self, ctx: &mut C, f: F)
+        where S: StackState<C>, C: StackContext<S>, F: FnOnce(&mut S, &mut C)
+    {
+        if let Some(state) = self.states.last_mut() { f(state, ctx); }
+        self.apply_requests(ctx);
+    }
+
+    /// Call `f` on every state, bottom to top
+    fn for_all<C, F>(&mut self, ctx: &mut C, mut f: F)
+        where S: StackState<C>, C: StackContext<S>, F: FnMut(&mut S, &mut C)
+    {
+        for s in self.states.iter_mut() { f(s, ctx); }
+        self.apply_requests(ctx);
+    }
+}
+
+/// Input goes to top state only
+/// `update` and `fixed_update` also go to states under overlays, bottom first,
+/// check `Frame::is_top_state` before reading input in them
+/// Application exits then last state is popped
+pub struct StateStack {
+    states: States<Box<dyn GameListener>>,
+}
+impl StateStack {
+    /// Stack with single state, `enter` is called on it
+    pub fn new(frame: &mut Frame, first: Box<dyn GameListener>) -> Self {
+        let mut states = States::new();
+        states.push(frame, first);
+        states.apply_requests(frame);
+        Self { states }
+    }
+
+    pub fn len(&self) -> usize { self.states.states.len() }
+    pub fn is_empty(&self) -> bool { self.states.states.is_empty() }
+}
+
+impl GameListener for StateStack {
+    fn dimensions_changed(&mut self, frame: &mut Frame, width: u32, height: u32) {
+        self.states.dimensions_changed(frame, width, height);
+    }
+
+    fn fixed_update(&mut self, tick: f32, frame: &mut Frame) {
+        self.states.for_active(frame, |s, frame| s.fixed_update(tick, frame));
+    }
+
+    fn update(&mut self, delta: f32, frame: &mut Frame, future: Box<dyn GpuFuture>) -> Box<dyn GpuFuture> {
+        let mut future = Some(future);
+        self.states.for_active(frame, |s, frame| future = Some(s.update(delta, frame, future.take().unwrap())));
+        future.unwrap()
+    }
+
+    fn key_pressed(&mut self, frame: &mut Frame, keycode: VirtualKeyCode) { self.states.for_top(frame, |s, frame| s.key_pressed(frame, keycode)) }
+    fn key_released(&mut self, frame: &mut Frame, keycode: VirtualKeyCode) { self.states.for_top(frame, |s, frame| s.key_released(frame, keycode)) }
+
+    fn mouse_wheel(&mut self, frame: &mut Frame, x: f32, y: f32) { self.states.for_top(frame, |s, frame| s.mouse_wheel(frame, x, y)) }
+    fn mouse_wheel_pixels(&mut self, frame: &mut Frame, x: f32, y: f32) { self.states.for_top(frame, |s, frame| s.mouse_wheel_pixels(frame, x, y)) }
+
+    fn mouse_pressed(&mut self, frame: &mut Frame, button: MouseButton) { self.states.for_top(frame, |s, frame| s.mouse_pressed(frame, button)) }
+    fn mouse_released(&mut self, frame: &mut Frame, button: MouseButton) { self.states.for_top(frame, |s, frame| s.mouse_released(frame, button)) }
+    fn cursor_moved(&mut self, frame: &mut Frame, x: f32, y: f32) { self.states.for_top(frame, |s, frame| s.cursor_moved(frame, x, y)) }
+
+    fn received_character(&mut self, frame: &mut Frame, c: char) { self.states.for_top(frame, |s, frame| s.received_character(frame, c)) }
+
+    fn focus_changed(&mut self, frame: &mut Frame, focused: bool) { self.states.for_top(frame, |s, frame| s.focus_changed(frame, focused)) }
+    fn file_dropped(&mut self, frame: &mut Frame, path: PathBuf) { self.states.for_top(frame, |s, frame| s.file_dropped(frame, path)) }
+
+    /// Every state should know it is not drawn
+    fn minimised(&mut self, frame: &mut Frame, minimised: bool) {
+        self.states.for_all(frame, |s, frame| s.minimised(frame, minimised));
+    }
+}
+
+mod test {
+    use super::{ States, StackState, StackContext, StateRequest };
+
+    /// Context logging calls of states, requests are queued by test
+    #[derive(Default)]
+    struct Log {
+        entries: Vec<String>,
+        requests: Vec<StateRequest<Logged>>,
+        top: bool,
+        exit: bool,
+    }
+    impl StackContext<Logged> for Log {
+        fn take_state_request(&mut self) -> Option<StateRequest<Logged>> {
+            if self.requests.is_empty() { None } else { Some(self.requests.remove(0)) }
+        }
+        fn request_exit(&mut self) { self.exit = true; }
+        fn set_top_state(&mut self, top: bool) { self.top = top; }
+    }
+
+    struct Logged {
+        name: &'static str,
+        overlay: bool,
+    }
+    impl StackState<Log> for Logged {
+        fn enter(&mut self, log: &mut Log) { log.entries.push(format!("{}:enter", self.name)) }
+        fn exit(&mut self, log: &mut Log) { log.entries.push(format!("{}:exit", self.name)) }
+        fn dimensions_changed(&mut self, log: &mut Log, width: u32, height: u32) {
+            log.entries.push(format!("{}:dims:{}x{}", self.name, width, height))
+        }
+        fn is_overlay(&self) -> bool { self.overlay }
+    }
+
+    fn state(name: &'static str, overlay: bool) -> Logged { Logged { name, overlay } }
+
+    fn update(states: &mut States<Logged>, log: &mut Log) {
+        states.for_active(log, |s, log| {
+            let entry = format!("{}:update:{}", s.name, if log.top { "top" } else { "under" });
+            log.entries.push(entry);
+        });
+    }
+
+    #[test] fn test_state_stack_overlay() {
+        let mut log = Log::default();
+        let mut states = States::new();
+        states.push(&mut log, state("base", false));
+        states.dimensions_changed(&mut log, 4, 3);
+        update(&mut states, &mut log);
+
+        // Overlay gets dimensions on push, base is updated under it
+        states.for_top(&mut log, |_, log| log.requests.push(StateRequest::Push(state("pause", true))));
+        update(&mut states, &mut log);
+
+        states.for_top(&mut log, |_, log| log.requests.push(StateRequest::Pop));
+        update(&mut states, &mut log);
+
+        assert_eq!(log.entries, vec![
+            "base:enter", "base:dims:4x3", "base:update:top",
+            "pause:enter", "pause:dims:4x3",
+            "base:update:under", "pause:update:top",
+            "pause:exit", "base:update:top",
+        ]);
+        assert!(log.top);
+        assert!(!log.exit);
+    }
+
+    #[test] fn test_state_stack_replace_and_exit() {
+        let mut log = Log::default();
+        let mut states = States::new();
+        states.push(&mut log, state("base", false));
+
+        // Requests are applied in order they were made
+        states.for_top(&mut log, |_, log| {
+            log.requests.push(StateRequest::Push(state("menu", false)));
+            log.requests.push(StateRequest::Replace(state("game", false)));
+        });
+        // State which is not overlay stops updates of states under it
+        update(&mut states, &mut log);
+
+        // Popping last state exits
+        states.for_active(&mut log, |_, log| {
+            log.requests.push(StateRequest::Pop);
+            log.requests.push(StateRequest::Pop);
+        });
+
+        assert_eq!(log.entries, vec![
+            "base:enter",
+            "menu:enter", "menu:exit", "game:enter",
+            "game:update:top",
+            "game:exit", "base:exit",
+        ]);
+        assert!(log.exit);
+        assert!(states.states.is_empty());
+    }
+}
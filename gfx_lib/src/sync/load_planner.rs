@@ -0,0 +1,407 @@
+
+// Dependency aware asset loading
+// Jobs (images, OBJ files, atlases, meshes...) run on rayon once every dependency is done,
+// higher priority first. GPU uploads are started from `LoadPlanner::update` with per frame byte budget
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    marker::PhantomData,
+    panic::{ self, AssertUnwindSafe },
+    sync::{ Arc, mpsc::{ channel, Sender, Receiver } },
+};
+
+use super::{ Loader, LoaderError };
+
+pub type JobId = usize;
+
+type Value = Arc<dyn Any + Send + Sync>;
+type Task = Box<dyn FnOnce(&JobDeps) -> Result<Stage, String> + Send>;
+type Upload = Box<dyn FnOnce() -> Loader<Value> + Send>;
+
+/// Typed id of job added to `LoadPlanner`
+pub struct JobHandle<T> {
+    id: JobId,
+    _marker: PhantomData<fn() -> T>,
+}
+impl <T> JobHandle<T> {
+    pub fn id(&self) -> JobId { self.id }
+}
+impl <T> Clone for JobHandle<T> {
+    fn clone(&self) -> Self { Self { id: self.id, _marker: PhantomData } }
+}
+impl <T> Copy for JobHandle<T> {}
+
+/// Description of job: name shown in progress, priority, size estimate and dependencies
+pub struct LoadJob {
+    name: String,
+    priority: i32, // Higher starts first
+    bytes: u64, // Estimated size, used for progress
+    deps: Vec<JobId>,
+}
+impl LoadJob {
+    pub fn new<S: Into<String>>(name: S) -> Self { Self {
+        name: name.into(),
+        priority: 0,
+        bytes: 0,
+        deps: vec![],
+    } }
+
+    pub fn with_priority(mut self, priority: i32) -> Self { self.priority = priority; self }
+    pub fn with_bytes(mut self, bytes: u64) -> Self { self.bytes = bytes; self }
+
+    /// Job starts only after `dep` is done, and fails if `dep` fails
+    pub fn after<T>(mut self, dep: &JobHandle<T>) -> Self { self.deps.push(dep.id); self }
+}
+
+/// Result of CPU part of job
+pub enum JobStage<T> {
+    Done(T),
+    /// Value still has to be uploaded, `upload` is called from `LoadPlanner::update` on main thread
+    /// `bytes` are counted against per frame upload budget
+    Upload { bytes: u64, upload: Box<dyn FnOnce() -> Loader<T> + Send> },
+}
+impl <T: Send + Sync + 'static> JobStage<T> {
+    pub fn upload<F>(bytes: u64, upload: F) -> Self
+        where F: FnOnce() -> Loader<T> + Send + 'static
+    {
+        JobStage::Upload { bytes, upload: Box::new(upload) }
+    }
+
+    fn erase(self) -> Stage {
+        match self {
+            JobStage::Done(value) => Stage::Done(Arc::new(value)),
+            JobStage::Upload { bytes, upload } => Stage::Upload(bytes, Box::new(move || {
+                upload().map(|value| Arc::new(value) as Value)
+            })),
+        }
+    }
+}
+
+enum Stage {
+    Done(Value),
+    Upload(u64, Upload),
+}
+
+/// Values of dependencies, given to job
+pub struct JobDeps {
+    values: HashMap<JobId, Value>,
+}
+impl JobDeps {
+    /// Value of dependency, None if `handle` is not dependency of this job
+    pub fn get<T: 'static>(&self, handle: &JobHandle<T>) -> Option<&T> {
+        self.values.get(&handle.id).and_then(|v| v.downcast_ref())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Waiting, // For dependencies or free worker
+    Loading, // CPU part running
+    Uploading, // Waiting for upload budget or GPU
+    Done,
+    Failed(String),
+}
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        match self {
+            JobStatus::Done | JobStatus::Failed(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Aggregate progress of all jobs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadProgress {
+    pub items_done: usize, // Including failed
+    pub items_total: usize,
+    pub items_failed: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub current: Option<String>, // Name of highest priority running job
+}
+impl LoadProgress {
+    /// Done part in 0 ..= 1, by bytes if jobs have size estimates, otherwise by items
+    pub fn fraction(&self) -> f32 {
+        if self.bytes_total > 0 { self.bytes_done as f32 / self.bytes_total as f32 }
+        else if self.items_total > 0 { self.items_done as f32 / self.items_total as f32 }
+        else { 1.0 }
+    }
+    pub fn is_finished(&self) -> bool { self.items_done == self.items_total }
+}
+
+struct Job {
+    info: LoadJob,
+    status: JobStatus,
+    task: Option<Task>, // Taken when started
+    upload: Option<(u64, Upload)>, // Waiting for budget
+    uploading: Option<Loader<Value>>,
+    value: Option<Value>,
+}
+
+/// Schedules `LoadJob`s, `update` must be called regularly (once per frame) to make progress
+pub struct LoadPlanner {
+    jobs: Vec<Job>,
+    running: usize, // CPU parts on rayon
+    max_running: usize,
+    upload_budget: u64, // Bytes per `update`
+    sender: Sender<(JobId, Result<Stage, String>)>,
+    receiver: Receiver<(JobId, Result<Stage, String>)>,
+}
+impl LoadPlanner {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            jobs: vec![],
+            running: 0,
+            max_running: rayon::current_num_threads().max(1),
+            upload_budget: 64 * 1024 * 1024,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Max bytes uploads started in single `update`, single upload over budget still starts
+    pub fn set_upload_budget(&mut self, bytes: u64) { self.upload_budget = bytes; }
+
+    /// Add job, dependencies must be added before it
+    pub fn add<T, F>(&mut self, info: LoadJob, task: F) -> JobHandle<T>
+        where
+            T: Send + Sync + 'static,
+            F: FnOnce(&JobDeps) -> Result<JobStage<T>, String> + Send + 'static,
+    {
+        let id = self.jobs.len();
+        assert!(info.deps.iter().all(|d| *d < id), "Dependency of {:?} is not part of planner", info.name);
+        self.jobs.push(Job {
+            info,
+            status: JobStatus::Waiting,
+            task: Some(Box::new(move |deps| task(deps).map(JobStage::erase))),
+            upload: None,
+            uploading: None,
+            value: None,
+        });
+        JobHandle { id, _marker: PhantomData }
+    }
+
+    /// Add job with nothing to upload
+    pub fn add_cpu<T, F>(&mut self, info: LoadJob, task: F) -> JobHandle<T>
+        where
+            T: Send + Sync + 'static,
+            F: FnOnce(&JobDeps) -> Result<T, String> + Send + 'static,
+    {
+        self.add(info, move |deps| task(deps).map(JobStage::Done))
+    }
+
+    /// Track loader that is already running, counted as upload without size
+    pub fn add_loader<T>(&mut self, info: LoadJob, loader: Loader<T>) -> JobHandle<T>
+        where T: Send + Sync + 'static
+    {
+        self.add(info, move |_| Ok(JobStage::upload(0, move || loader)))
+    }
+
+    /// Collect finished jobs, start uploads within budget and start jobs with finished dependencies
+    pub fn update(&mut self) {
+        while let Ok((id, result)) = self.receiver.try_recv() {
+            self.running -= 1;
+            match result {
+                Ok(Stage::Done(value)) => self.finish(id, Ok(value)),
+                Ok(Stage::Upload(bytes, upload)) => {
+                    self.jobs[id].status = JobStatus::Uploading;
+                    self.jobs[id].upload = Some((bytes, upload));
+                },
+                Err(e) => self.finish(id, Err(e)),
+            }
+        }
+
+        self.poll_uploads();
+        self.start_uploads();
+        self.start_jobs();
+    }
+
+    fn finish(&mut self, id: JobId, result: Result<Value, String>) {
+        let job = &mut self.jobs[id];
+        match result {
+            Ok(value) => {
+                job.value = Some(value);
+                job.status = JobStatus::Done;
+            },
+            Err(e) => job.status = JobStatus::Failed(e),
+        }
+    }
+
+    fn poll_uploads(&mut self) {
+        for id in 0 .. self.jobs.len() {
+            let finished = match &self.jobs[id].uploading {
+                Some(loader) => loader.is_finished(),
+                None => false,
+            };
+            if !finished { continue; }
+
+            let result = self.jobs[id].uploading.take().unwrap().try_take().map_err(|e| match e {
+                LoaderError::Failed(e) => e,
+                e => e.to_string(),
+            });
+            self.finish(id, result);
+        }
+    }
+
+    fn start_uploads(&mut self) {
+        let mut spent = 0;
+        for id in self.by_priority() {
+            if self.jobs[id].upload.is_none() { continue; }
+            let bytes = self.jobs[id].upload.as_ref().unwrap().0;
+            if spent > 0 && spent + bytes > self.upload_budget { break; }
+            spent += bytes;
+
+            let (_, upload) = self.jobs[id].upload.take().unwrap();
+            match panic::catch_unwind(AssertUnwindSafe(upload)) {
+                Ok(loader) => self.jobs[id].uploading = Some(loader),
+                Err(_) => self.finish(id, Err("Upload panicked".into())),
+            }
+        }
+    }
+
+    fn start_jobs(&mut self) {
+        for id in self.by_priority() {
+            if self.running >= self.max_running { break; }
+            if self.jobs[id].status != JobStatus::Waiting { continue; }
+
+            // Failed dependency fails job, unfinished one keeps it waiting
+            let mut values = HashMap::new();
+            let mut failed = None;
+            let mut waiting = false;
+            for dep in self.jobs[id].info.deps.iter() {
+                let dep_job = &self.jobs[*dep];
+                match &dep_job.status {
+                    JobStatus::Done => { values.insert(*dep, dep_job.value.clone().unwrap()); },
+                    JobStatus::Failed(_) => failed = Some(dep_job.info.name.clone()),
+                    _ => waiting = true,
+                }
+            }
+            if let Some(name) = failed {
+                self.finish(id, Err(format!("Dependency {:?} failed", name)));
+                continue;
+            }
+            if waiting { continue; }
+
+            let job = &mut self.jobs[id];
+            job.status = JobStatus::Loading;
+            let task = job.task.take().unwrap();
+            let sender = self.sender.clone();
+            self.running += 1;
+            rayon::spawn(move || {
+                let deps = JobDeps { values };
+                let result = match panic::catch_unwind(AssertUnwindSafe(|| task(&deps))) {
+                    Ok(r) => r,
+                    Err(_) => Err("Job panicked".into()),
+                };
+                let _ = sender.send((id, result));
+            });
+        }
+    }
+
+    /// Unfinished jobs, highest priority first, then in order they were added
+    fn by_priority(&self) -> Vec<JobId> {
+        let mut ids: Vec<JobId> = (0 .. self.jobs.len())
+            .filter(|i| !self.jobs[*i].status.is_finished())
+            .collect();
+        ids.sort_by_key(|i| std::cmp::Reverse(self.jobs[*i].info.priority));
+        ids
+    }
+}
+/// Results and progress
+impl LoadPlanner {
+    pub fn status(&self, id: JobId) -> &JobStatus { &self.jobs[id].status }
+
+    /// Value of finished job
+    pub fn get<T: 'static>(&self, handle: &JobHandle<T>) -> Option<&T> {
+        self.jobs[handle.id].value.as_ref().and_then(|v| v.downcast_ref())
+    }
+
+    /// Value of finished job, or error with name of job
+    pub fn result<T: 'static>(&self, handle: &JobHandle<T>) -> Result<&T, String> {
+        let job = &self.jobs[handle.id];
+        match &job.status {
+            JobStatus::Failed(e) => Err(format!("Unable to load {}: {}", job.info.name, e)),
+            _ => self.get(handle).ok_or_else(|| format!("{} is not loaded", job.info.name)),
+        }
+    }
+
+    /// Name and error of every failed job
+    pub fn failures(&self) -> Vec<(&str, &str)> {
+        self.jobs.iter()
+            .filter_map(|j| match &j.status {
+                JobStatus::Failed(e) => Some((j.info.name.as_str(), e.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn is_finished(&self) -> bool { self.jobs.iter().all(|j| j.status.is_finished()) }
+
+    pub fn progress(&self) -> LoadProgress {
+        let mut progress = LoadProgress::default();
+        let mut current: Option<&Job> = None;
+        for job in self.jobs.iter() {
+            progress.items_total += 1;
+            progress.bytes_total += job.info.bytes;
+            match job.status {
+                JobStatus::Done | JobStatus::Failed(_) => {
+                    progress.items_done += 1;
+                    progress.bytes_done += job.info.bytes;
+                    if job.status != JobStatus::Done { progress.items_failed += 1; }
+                },
+                JobStatus::Loading | JobStatus::Uploading => {
+                    if current.map_or(true, |c| c.info.priority < job.info.priority) { current = Some(job); }
+                },
+                JobStatus::Waiting => (),
+            }
+        }
+        progress.current = current.map(|j| j.info.name.clone());
+        progress
+    }
+}
+
+mod test {
+
+    #[test] fn test_load_planner() {
+        use super::{ LoadPlanner, LoadJob, JobStage, JobStatus };
+        use crate::sync::Loader;
+        use std::time::{ Duration, Instant };
+
+        let mut planner = LoadPlanner::new();
+        planner.set_upload_budget(10);
+
+        let image = planner.add(LoadJob::new("image").with_bytes(100), |_| {
+            Ok(JobStage::upload(8, || Loader::with_closure(|| vec![1u8, 2, 3])))
+        });
+        let other = planner.add(LoadJob::new("other").with_bytes(100), |_| {
+            Ok(JobStage::upload(8, || Loader::ready(vec![4u8])))
+        });
+        let atlas = planner.add_cpu(LoadJob::new("atlas").with_priority(1).after(&image).after(&other), move |deps| {
+            Ok(deps.get(&image).unwrap().len() + deps.get(&other).unwrap().len())
+        });
+        let broken = planner.add_cpu::<u32, _>(LoadJob::new("broken"), |_| Err("Missing file".into()));
+        let object = planner.add_cpu(LoadJob::new("object").after(&broken), |_| Ok(0u32));
+
+        let start = Instant::now();
+        while !planner.is_finished() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            planner.update();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(planner.get(&atlas), Some(&4));
+        assert_eq!(planner.get(&image), Some(&vec![1, 2, 3]));
+        assert_eq!(*planner.status(broken.id()), JobStatus::Failed("Missing file".into()));
+        assert!(planner.get(&object).is_none());
+        assert_eq!(planner.result(&atlas), Ok(&4));
+        assert_eq!(planner.result(&broken), Err("Unable to load broken: Missing file".to_string()));
+        assert_eq!(planner.failures().len(), 2);
+
+        let progress = planner.progress();
+        assert_eq!((progress.items_done, progress.items_total, progress.items_failed), (5, 5, 2));
+        assert_eq!(progress.fraction(), 1.0);
+    }
+}
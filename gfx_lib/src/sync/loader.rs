@@ -3,22 +3,31 @@
 use vulkano::sync::GpuFuture;
 
 use std::{
-    ops::{ Deref, DerefMut },
-    sync::{ Arc, Mutex },
-    time::Duration,
+    cell::UnsafeCell,
+    future::Future,
+    marker::PhantomData,
+    ops::Deref,
+    panic::{ self, AssertUnwindSafe },
+    pin::Pin,
+    sync::{ Arc, Mutex, Condvar, atomic::{ AtomicBool, Ordering } },
+    task::{ Context, Poll, Waker, Wake },
+    time::{ Duration, Instant },
 };
-use std::sync::atomic::AtomicPtr;
-use std::mem::ManuallyDrop;
 
 /// Errors that `Loader<T>` can return
+#[derive(Clone, PartialEq)]
 pub enum LoaderError {
-    Timeout
+    Timeout,
+    Failed(String), // Job returned error or panicked
+    Cancelled,
 }
 impl std::error::Error for LoaderError {}
 impl std::fmt::Debug for LoaderError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         match self {
             LoaderError::Timeout => write!(f, "Timeout reached"),
+            LoaderError::Failed(e) => write!(f, "Loading failed: {}", e),
+            LoaderError::Cancelled => write!(f, "Loading was cancelled"),
         }
     }
 }
@@ -28,75 +37,246 @@ impl std::fmt::Display for LoaderError {
     }
 }
 
-/// Represents `vulkano::sync::GpuFuture` with resource which this future loads
-/// When cloned will represent same loader with cloned value and same ref to `ready` value
+/// State of `Loader<T>`, everything except `Loading` is final
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoaderStatus {
+    Loading,
+    Ready,
+    Failed(String),
+    Cancelled,
+}
+
+/// Given to loading jobs, long jobs should check it and return `LoaderError::Cancelled`
 #[derive(Clone)]
+pub struct CancelToken(Arc<AtomicBool>);
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool { self.0.load(Ordering::Acquire) }
+}
+
+/// Called with final status and value of loader, used by clones and combinators
+/// Consuming combinators take value out, clones copy it
+type Follower<T> = Box<dyn FnOnce(&LoaderStatus, &mut Option<T>) + Send>;
+
+struct State<T> {
+    status: LoaderStatus,
+    followers: Vec<Follower<T>>,
+    wakers: Vec<Waker>, // Tasks awaiting loader as `Future`
+}
+
+/// Shared between `Loader` and its job
+/// `value` is written once under `state` lock before status leaves `Loading`,
+/// after that it is only read, or moved out by `Loader::take` which holds `&mut Loader`
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    done: Condvar,
+    cancelled: Arc<AtomicBool>,
+    value: UnsafeCell<Option<T>>,
+}
+unsafe impl <T: Send> Send for Shared<T> {}
+unsafe impl <T: Send> Sync for Shared<T> {}
+
+impl <T> Shared<T> {
+    fn new(value: Option<T>) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(State { status: LoaderStatus::Loading, followers: vec![], wakers: vec![] }),
+            done: Condvar::new(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            value: UnsafeCell::new(value),
+        })
+    }
+
+    /// Set final status, `value` is stored only if there is no value yet
+    /// Ignored if loader already finished (was cancelled)
+    fn finish(&self, value: Option<T>, status: LoaderStatus) {
+        let mut state = self.state.lock().unwrap();
+        if state.status != LoaderStatus::Loading { return; }
+
+        let slot = unsafe { &mut *self.value.get() };
+        if slot.is_none() { *slot = value; }
+        state.status = status;
+
+        for f in state.followers.drain(..) {
+            f(&state.status, slot);
+        }
+        for w in state.wakers.drain(..) { w.wake(); }
+        self.done.notify_all();
+    }
+
+    /// Call `f` once loader is finished, right away if it already is
+    fn on_finish(&self, f: Follower<T>) {
+        let mut state = self.state.lock().unwrap();
+        if state.status == LoaderStatus::Loading {
+            state.followers.push(f);
+        } else {
+            f(&state.status, unsafe { &mut *self.value.get() });
+        }
+    }
+
+    fn is_cancelled(&self) -> bool { self.cancelled.load(Ordering::Acquire) }
+}
+
+/// Follower finishing `target` with value and status of source
+fn forward<T: Send + 'static>(target: Arc<Shared<T>>) -> Follower<T> {
+    Box::new(move |status, value| target.finish(value.take(), status.clone()))
+}
+
+/// Value which is loaded in background, by rayon job or GPU
+/// Clone of pending loader becomes ready together with original, with cloned value
 pub struct Loader<T> {
-    obj: Box<Option<T>>,
-    ready: Arc<Mutex<bool>>,
+    shared: Arc<Shared<T>>,
+    _marker: PhantomData<T>, // `&Loader` gives `&T`, so Loader is Sync only for Sync `T`
 }
 impl <T> Loader<T> {
 
-    /// Create loader from `GpuFuture`
+    /// Loader which is ready from the start
+    pub fn ready(obj: T) -> Self {
+        let loader = Self::pending(Some(obj));
+        loader.shared.finish(None, LoaderStatus::Ready);
+        loader
+    }
+
+    /// Loader which failed from the start
+    pub fn with_error<E: std::fmt::Display>(error: E) -> Self {
+        let loader = Self::pending(None);
+        loader.shared.finish(None, LoaderStatus::Failed(error.to_string()));
+        loader
+    }
+
+    fn pending(obj: Option<T>) -> Self {
+        Self { shared: Shared::new(obj), _marker: PhantomData }
+    }
+
+    /// Create loader from `GpuFuture`, ready when GPU finished it
+    /// `obj` is available through `snapshot` right away
     pub fn with_gpu_future<F>(obj: T, future: F) -> Self
-        where F: GpuFuture + Send + Sync + 'static
+        where
+            F: GpuFuture + Send + Sync + 'static,
+            T: Send + 'static,
     {
-        let ready = Arc::new(Mutex::new(false));
-        let r = ready.clone();
+        let loader = Self::pending(Some(obj));
+        let shared = loader.shared.clone();
         rayon::spawn(move || {
-            future.flush().unwrap();
-            *r.lock().unwrap() = true;
+            let status = match future.then_signal_fence_and_flush() {
+                Ok(f) => match f.wait(None) {
+                    Ok(()) => LoaderStatus::Ready,
+                    Err(e) => LoaderStatus::Failed(format!("{:?}", e)),
+                },
+                Err(e) => LoaderStatus::Failed(format!("{:?}", e)),
+            };
+            shared.finish(None, status);
         });
-        Self { obj: Box::new(Some(obj)), ready }
+        loader
     }
 
     /// Loader with function, ready after closure returns
-    pub fn with_closure<F>(mut func: F) -> Self
+    /// Panic inside closure fails the loader
+    pub fn with_closure<F>(func: F) -> Self
         where
             F: FnOnce() -> T + Send + 'static,
-            T: 'static,
+            T: Send + 'static,
     {
+        Self::with_task(move |_| Ok(func()))
+    }
 
-        let ready = Arc::new(Mutex::new(false));
-        let r = ready.clone();
-        let mut obj = Box::new(None);
-        let mut data = Self { obj, ready };
-
-        unsafe {
-            let mut ptr = AtomicPtr::new(data.obj.as_mut());
-            rayon::spawn(move || {
-//                std::thread::sleep(std::time::Duration::from_millis(3_000));
-                let value = func();
-                let v = &mut *ptr.into_inner();
-                *v = Some(value);
-                *r.lock().unwrap() = true;
-            });
-        }
+    /// Loader with fallible function, error is carried as `LoaderError::Failed`
+    /// Use `with_closure` returning `Result<T, E>` to keep typed error instead
+    pub fn with_try_closure<F, E>(func: F) -> Self
+        where
+            F: FnOnce() -> Result<T, E> + Send + 'static,
+            E: std::fmt::Display,
+            T: Send + 'static,
+    {
+        Self::with_task(move |_| func().map_err(|e| LoaderError::Failed(e.to_string())))
+    }
+
+    /// Loader with cancellable job, job is skipped if loader was cancelled before it started
+    pub fn with_task<F>(func: F) -> Self
+        where
+            F: FnOnce(&CancelToken) -> Result<T, LoaderError> + Send + 'static,
+            T: Send + 'static,
+    {
+        let loader = Self::pending(None);
+        let shared = loader.shared.clone();
+        let token = loader.cancel_token();
+        rayon::spawn(move || {
+            if token.is_cancelled() { return shared.finish(None, LoaderStatus::Cancelled); }
 
-        data
+            match panic::catch_unwind(AssertUnwindSafe(|| func(&token))) {
+                Ok(Ok(value)) => shared.finish(Some(value), LoaderStatus::Ready),
+                Ok(Err(LoaderError::Failed(e))) => shared.finish(None, LoaderStatus::Failed(e)),
+                Ok(Err(LoaderError::Timeout)) => shared.finish(None, LoaderStatus::Failed("Timeout reached".into())),
+                Ok(Err(LoaderError::Cancelled)) => shared.finish(None, LoaderStatus::Cancelled),
+                Err(payload) => shared.finish(None, LoaderStatus::Failed(panic_message(payload))),
+            }
+        });
+        loader
     }
 
+    /// Return true when loading successfully finished, otherwise false
+    pub fn is_ready(&self) -> bool { self.status() == LoaderStatus::Ready }
+    /// Return true when loading finished, failed or was cancelled
+    pub fn is_finished(&self) -> bool { self.status() != LoaderStatus::Loading }
+    pub fn status(&self) -> LoaderStatus { self.shared.state.lock().unwrap().status.clone() }
 
-    /// Return true when loading finished, otherwise false
-    pub fn is_ready(&self) -> bool { *self.ready.lock().unwrap() }
+    /// Ask job to stop, loader is cancelled right away, value job may still produce is dropped
+    pub fn cancel(&self) {
+        self.shared.cancelled.store(true, Ordering::Release);
+        self.shared.finish(None, LoaderStatus::Cancelled);
+    }
+    pub fn cancel_token(&self) -> CancelToken { CancelToken(self.shared.cancelled.clone()) }
 
-    /// Await until loading finishes, or return Error if timeout reached
-    /// TODO: Timeout currenly does nothing
+    /// Block until loading finishes, or return `LoaderError::Timeout` if timeout reached
     pub fn wait(&self, timeout: Option<Duration>) -> Result<(), LoaderError> {
-        while !*self.ready.lock().unwrap() {}
-        Ok(())
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut state = self.shared.state.lock().unwrap();
+        while state.status == LoaderStatus::Loading {
+            state = match deadline {
+                None => self.shared.done.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline { return Err(LoaderError::Timeout); }
+                    self.shared.done.wait_timeout(state, deadline - now).unwrap().0
+                },
+            };
+        }
+        match &state.status {
+            LoaderStatus::Ready => Ok(()),
+            LoaderStatus::Failed(e) => Err(LoaderError::Failed(e.clone())),
+            LoaderStatus::Cancelled => Err(LoaderError::Cancelled),
+            LoaderStatus::Loading => unreachable!(),
+        }
+    }
+
+    /// Ref to underlying data, blocks until loading finishes
+    pub fn try_get(&self) -> Result<&T, LoaderError> {
+        self.wait(None)?;
+        // Value is not written after status left `Loading`, and `take` needs `&mut self`
+        unsafe { (*self.shared.value.get()).as_ref() }.ok_or(LoaderError::Failed("Value was taken".into()))
     }
 
     /// Get ref to underlying data, will block until loading finishes
+    /// Panics if loading failed or was cancelled
     pub fn get_ref(&self) -> &T {
-        if !self.is_ready() { self.wait(None).unwrap() }
-        self.obj.as_ref().as_ref().unwrap()
+        match self.try_get() {
+            Ok(v) => v,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Move out underlying data, blocks until loading finishes, leaving loader empty
+    pub fn try_take(&mut self) -> Result<T, LoaderError> {
+        self.wait(None)?;
+        let _state = self.shared.state.lock().unwrap();
+        unsafe { (*self.shared.value.get()).take() }.ok_or(LoaderError::Failed("Value was taken".into()))
     }
 
     /// Unwraps loader, returning underlying data, leaving None
+    /// Panics if loading failed or was cancelled
     pub fn take(&mut self) -> T {
-        if !self.is_ready() { self.wait(None).unwrap() }
-        self.obj.take().unwrap()
+        match self.try_take() {
+            Ok(v) => v,
+            Err(e) => panic!("{}", e),
+        }
     }
 
     /// Unwraps value and drops loader
@@ -106,11 +286,221 @@ impl <T> Loader<T> {
 
 /// Specials for clone capable
 impl <T: Clone> Loader<T> {
-    /// Clone current value and return it
-    pub fn snapshot(&self) -> Option<T> { self.obj.as_ref().clone() }
+    /// Clone current value and return it, available before ready if loader was created with value
+    pub fn snapshot(&self) -> Option<T> {
+        let _state = self.shared.state.lock().unwrap();
+        unsafe { (*self.shared.value.get()).clone() }
+    }
+}
+
+/// Clone of pending loader is finished by original one
+impl <T: Clone + Send + 'static> Clone for Loader<T> {
+    fn clone(&self) -> Self {
+        let mut state = self.shared.state.lock().unwrap();
+        let value = unsafe { (*self.shared.value.get()).clone() };
+        let clone = Self::pending(value);
+
+        if state.status == LoaderStatus::Loading {
+            let shared = clone.shared.clone();
+            state.followers.push(Box::new(move |status, value| shared.finish(value.clone(), status.clone())));
+        } else {
+            clone.shared.finish(None, state.status.clone());
+        }
+        clone
+    }
+}
+
+/// Combinators, nothing is blocked while waiting for sources
+/// Functions run on rayon pool after source is ready, panic in them fails the loader
+/// Cancelling combined loader does not cancel its sources
+impl <T: Send + 'static> Loader<T> {
+
+    /// Loader driven by async block, which can `.await` other loaders
+    pub fn with_async<Fut>(future: Fut) -> Self
+        where Fut: Future<Output = Result<T, LoaderError>> + Send + 'static
+    {
+        let loader = Self::pending(None);
+        let task = Arc::new(AsyncTask {
+            future: Mutex::new(Some(Box::pin(future))),
+            shared: loader.shared.clone(),
+        });
+        rayon::spawn(move || task.poll());
+        loader
+    }
+
+    pub fn map<U, F>(self, func: F) -> Loader<U>
+        where
+            U: Send + 'static,
+            F: FnOnce(T) -> U + Send + 'static,
+    {
+        self.and_then(move |value| Loader::ready(func(value)))
+    }
+
+    /// Start next loader from value of this one
+    pub fn and_then<U, F>(self, func: F) -> Loader<U>
+        where
+            U: Send + 'static,
+            F: FnOnce(T) -> Loader<U> + Send + 'static,
+    {
+        let out = Loader::pending(None);
+        let target = out.shared.clone();
+        self.shared.on_finish(Box::new(move |status, value| match (status, value.take()) {
+            (LoaderStatus::Ready, Some(value)) => rayon::spawn(move || {
+                if target.is_cancelled() { return target.finish(None, LoaderStatus::Cancelled); }
+                match panic::catch_unwind(AssertUnwindSafe(|| func(value))) {
+                    Ok(next) => next.shared.on_finish(forward(target)),
+                    Err(payload) => target.finish(None, LoaderStatus::Failed(panic_message(payload))),
+                }
+            }),
+            (LoaderStatus::Ready, None) => target.finish(None, LoaderStatus::Failed("Value was taken".into())),
+            (status, _) => target.finish(None, status.clone()),
+        }));
+        out
+    }
+
+    /// Ready when both are ready, fails with first failed one
+    pub fn join<U: Send + 'static>(self, other: Loader<U>) -> Loader<(T, U)> {
+        let out = Loader::pending(None);
+        let parts = Arc::new(Mutex::new((None, None)));
+
+        let (target, p) = (out.shared.clone(), parts.clone());
+        self.shared.on_finish(Box::new(move |status, value| match (status, value.take()) {
+            (LoaderStatus::Ready, Some(value)) => {
+                let mut parts = p.lock().unwrap();
+                parts.0 = Some(value);
+                if parts.1.is_some() { target.finish(Some((parts.0.take().unwrap(), parts.1.take().unwrap())), LoaderStatus::Ready) }
+            },
+            (status, _) => target.finish(None, failed_status(status)),
+        }));
+
+        let (target, p) = (out.shared.clone(), parts);
+        other.shared.on_finish(Box::new(move |status, value| match (status, value.take()) {
+            (LoaderStatus::Ready, Some(value)) => {
+                let mut parts = p.lock().unwrap();
+                parts.1 = Some(value);
+                if parts.0.is_some() { target.finish(Some((parts.0.take().unwrap(), parts.1.take().unwrap())), LoaderStatus::Ready) }
+            },
+            (status, _) => target.finish(None, failed_status(status)),
+        }));
+
+        out
+    }
+
+    /// Ready when all are ready, values are in same order as loaders
+    pub fn join_all(loaders: Vec<Loader<T>>) -> Loader<Vec<T>> {
+        if loaders.is_empty() { return Loader::ready(vec![]); }
+
+        let out = Loader::pending(None);
+        let parts = Arc::new(Mutex::new((loaders.iter().map(|_| None).collect::<Vec<Option<T>>>(), loaders.len())));
+        for (i, l) in loaders.into_iter().enumerate() {
+            let (target, parts) = (out.shared.clone(), parts.clone());
+            l.shared.on_finish(Box::new(move |status, value| match (status, value.take()) {
+                (LoaderStatus::Ready, Some(value)) => {
+                    let mut parts = parts.lock().unwrap();
+                    parts.0[i] = Some(value);
+                    parts.1 -= 1;
+                    if parts.1 == 0 {
+                        let values = parts.0.drain(..).map(|v| v.unwrap()).collect();
+                        target.finish(Some(values), LoaderStatus::Ready);
+                    }
+                },
+                (status, _) => target.finish(None, failed_status(status)),
+            }));
+        }
+        out
+    }
+
+    /// Ready with index and value of first loader that got ready, rest are asked to cancel
+    /// Fails only if every loader failed
+    pub fn select(loaders: Vec<Loader<T>>) -> Loader<(usize, T)> {
+        if loaders.is_empty() { return Loader::pending(None).failed("No loaders to select from"); }
+
+        let out = Loader::pending(None);
+        let tokens: Arc<Vec<CancelToken>> = Arc::new(loaders.iter().map(|l| l.cancel_token()).collect());
+        let remaining = Arc::new(Mutex::new(loaders.len()));
+        for (i, l) in loaders.into_iter().enumerate() {
+            let (target, tokens, remaining) = (out.shared.clone(), tokens.clone(), remaining.clone());
+            l.shared.on_finish(Box::new(move |status, value| {
+                let mut remaining = remaining.lock().unwrap();
+                *remaining -= 1;
+                match (status, value.take()) {
+                    (LoaderStatus::Ready, Some(value)) => {
+                        target.finish(Some((i, value)), LoaderStatus::Ready);
+                        for t in tokens.iter() { t.0.store(true, Ordering::Release); }
+                    },
+                    (status, _) => if *remaining == 0 { target.finish(None, failed_status(status)) },
+                }
+            }));
+        }
+        out
+    }
+
+    fn failed(self, reason: &str) -> Self {
+        self.shared.finish(None, LoaderStatus::Failed(reason.into()));
+        self
+    }
+}
+
+/// Status given to combined loader then source did not produce value
+fn failed_status(status: &LoaderStatus) -> LoaderStatus {
+    match status {
+        LoaderStatus::Ready => LoaderStatus::Failed("Value was taken".into()),
+        s => s.clone(),
+    }
+}
+
+/// Polled on rayon pool, woken by loaders it awaits
+struct AsyncTask<T> {
+    future: Mutex<Option<Pin<Box<dyn Future<Output = Result<T, LoaderError>> + Send>>>>,
+    shared: Arc<Shared<T>>,
+}
+impl <T: Send + 'static> AsyncTask<T> {
+    fn poll(self: Arc<Self>) {
+        let mut slot = self.future.lock().unwrap();
+        let future = match slot.as_mut() {
+            Some(f) => f,
+            None => return, // Already finished, wake after completion
+        };
+        if self.shared.is_cancelled() {
+            *slot = None;
+            return self.shared.finish(None, LoaderStatus::Cancelled);
+        }
+
+        let waker = Waker::from(self.clone());
+        let mut cx = Context::from_waker(&waker);
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(&mut cx))) {
+            Ok(Poll::Pending) => return,
+            Ok(Poll::Ready(Ok(value))) => (Some(value), LoaderStatus::Ready),
+            Ok(Poll::Ready(Err(LoaderError::Failed(e)))) => (None, LoaderStatus::Failed(e)),
+            Ok(Poll::Ready(Err(LoaderError::Timeout))) => (None, LoaderStatus::Failed("Timeout reached".into())),
+            Ok(Poll::Ready(Err(LoaderError::Cancelled))) => (None, LoaderStatus::Cancelled),
+            Err(payload) => (None, LoaderStatus::Failed(panic_message(payload))),
+        };
+        *slot = None;
+        self.shared.finish(result.0, result.1);
+    }
+}
+impl <T: Send + 'static> Wake for AsyncTask<T> {
+    fn wake(self: Arc<Self>) { rayon::spawn(move || self.poll()); }
+}
+
+/// Awaiting loader takes its value
+impl <T> Unpin for Loader<T> {}
+impl <T> Future for Loader<T> {
+    type Output = Result<T, LoaderError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.status == LoaderStatus::Loading {
+                if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    state.wakers.push(cx.waker().clone());
+                }
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(self.get_mut().try_take())
+    }
 }
-/// To avoid writing to dealloc'ed memory, wait for loading to finish before actually dropping
-impl <T> Drop for Loader<T> { fn drop(&mut self) { self.wait(None).unwrap(); } }
 
 /// Deref for `T` in `Loader<T>`
 impl <T> Deref for Loader<T> {
@@ -119,8 +509,116 @@ impl <T> Deref for Loader<T> {
 }
 
 /// Create loader from `GpuFuture`
-impl <T, F: GpuFuture + Send + Sync + 'static> From<(T, F)> for Loader<T> {
+impl <T: Send + 'static, F: GpuFuture + Send + Sync + 'static> From<(T, F)> for Loader<T> {
     fn from(o: (T, F)) -> Self {
         Loader::with_gpu_future(o.0, o.1)
     }
 }
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Job panicked".to_string()
+    }
+}
+
+mod test {
+
+    #[test] fn test_loader_closure() {
+        use super::{ Loader, LoaderStatus };
+        use std::time::Duration;
+
+        let mut loader = Loader::with_closure(|| {
+            std::thread::sleep(Duration::from_millis(20));
+            vec![1, 2, 3]
+        });
+        let clone = loader.clone();
+        assert_eq!(loader.snapshot(), None);
+        loader.wait(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(loader.status(), LoaderStatus::Ready);
+        assert_eq!(*loader.get_ref(), vec![1, 2, 3]);
+        assert_eq!(loader.take(), vec![1, 2, 3]);
+        assert_eq!(clone.unwrap(), vec![1, 2, 3]);
+
+        assert_eq!(Loader::ready(5).unwrap(), 5);
+    }
+
+    #[test] fn test_loader_errors() {
+        use super::{ Loader, LoaderError };
+        use std::time::Duration;
+
+        let slow = Loader::with_closure(|| std::thread::sleep(Duration::from_millis(200)));
+        assert_eq!(slow.wait(Some(Duration::from_millis(1))), Err(LoaderError::Timeout));
+        slow.cancel();
+        assert_eq!(slow.wait(None), Err(LoaderError::Cancelled));
+
+        let panicked: Loader<u32> = Loader::with_closure(|| panic!("Broken file"));
+        assert_eq!(panicked.wait(Some(Duration::from_secs(5))), Err(LoaderError::Failed("Broken file".into())));
+        assert!(panicked.is_finished());
+        assert!(!panicked.is_ready());
+
+        let failed: Loader<u32> = Loader::with_try_closure(|| "x".parse::<u32>());
+        assert!(match failed.wait(None) { Err(LoaderError::Failed(_)) => true, _ => false });
+
+        let cancelled = Loader::with_task(|token| {
+            while !token.is_cancelled() { std::thread::sleep(Duration::from_millis(1)); }
+            Err(LoaderError::Cancelled)
+        });
+        cancelled.cancel();
+        assert_eq!(cancelled.wait(Some(Duration::from_secs(5))), Err(LoaderError::Cancelled));
+    }
+
+    #[test] fn test_loader_combinators() {
+        use super::{ Loader, LoaderError };
+        use std::time::Duration;
+
+        let timeout = Some(Duration::from_secs(5));
+        let slow = |v: u32, ms: u64| Loader::with_closure(move || { std::thread::sleep(Duration::from_millis(ms)); v });
+
+        let mapped = slow(2, 10).map(|v| v * 10).and_then(|v| slow(v + 1, 10));
+        mapped.wait(timeout).unwrap();
+        assert_eq!(mapped.unwrap(), 21);
+
+        let joined = slow(1, 20).join(Loader::ready("a"));
+        joined.wait(timeout).unwrap();
+        assert_eq!(joined.unwrap(), (1, "a"));
+
+        let all = Loader::join_all(vec![slow(1, 30), slow(2, 10), Loader::ready(3)]);
+        all.wait(timeout).unwrap();
+        assert_eq!(all.unwrap(), vec![1, 2, 3]);
+
+        let failed = Loader::join_all(vec![slow(1, 10), Loader::with_closure(|| panic!("Missing"))]);
+        assert_eq!(failed.wait(timeout), Err(LoaderError::Failed("Missing".into())));
+
+        let first = Loader::select(vec![slow(1, 300), slow(2, 5)]);
+        first.wait(timeout).unwrap();
+        assert_eq!(first.unwrap(), (1, 2));
+
+        let none = Loader::<u32>::select(vec![Loader::with_closure(|| panic!("A")), Loader::with_closure(|| panic!("B"))]);
+        assert!(match none.wait(timeout) { Err(LoaderError::Failed(_)) => true, _ => false });
+    }
+
+    #[test] fn test_loader_async() {
+        use super::{ Loader, LoaderError };
+        use std::time::Duration;
+
+        let a = Loader::with_closure(|| { std::thread::sleep(Duration::from_millis(10)); 2 });
+        let b = Loader::with_closure(|| 3);
+        let sum = Loader::with_async(async move {
+            let a = a.await?;
+            let b = b.await?;
+            Ok(a * b)
+        });
+        sum.wait(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(sum.unwrap(), 6);
+
+        let failed: Loader<u32> = Loader::with_async(async move {
+            let v: u32 = Loader::with_try_closure(|| "x".parse::<u32>()).await?;
+            Ok(v)
+        });
+        assert!(match failed.wait(Some(Duration::from_secs(5))) { Err(LoaderError::Failed(_)) => true, _ => false });
+    }
+}
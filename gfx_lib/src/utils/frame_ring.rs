@@ -0,0 +1,60 @@
+
+// Ring of per-frame resources
+// Buffers written by CPU every frame can't be reused while GPU still reads them from previous frames in flight
+
+/// Slots are reused only after `is_free` tells GPU is done with them, new slot is created if all are busy
+pub struct FrameRing<T> {
+    slots: Vec<T>,
+    current: usize,
+}
+impl <T> FrameRing<T> {
+    pub fn new(first: T) -> Self { Self {
+        slots: vec![first],
+        current: 0,
+    } }
+
+    pub fn current(&self) -> &T { &self.slots[self.current] }
+    pub fn current_mut(&mut self) -> &mut T { &mut self.slots[self.current] }
+
+    /// Switch to next free slot, starting after current one
+    pub fn next<F, C>(&mut self, is_free: F, create: C) -> &mut T
+        where
+            F: Fn(&T) -> bool,
+            C: FnOnce() -> T,
+    {
+        let len = self.slots.len();
+        match (1 ..= len).map(|i| (self.current + i) % len).find(|&i| is_free(&self.slots[i])) {
+            Some(i) => self.current = i,
+            None => {
+                self.slots.push(create());
+                self.current = len;
+            },
+        }
+        &mut self.slots[self.current]
+    }
+
+    /// Number of slots created so far, grows up to number of frames in flight
+    pub fn len(&self) -> usize { self.slots.len() }
+}
+
+mod test {
+
+    #[test] fn test_frame_ring() {
+        use super::FrameRing;
+
+        // (id, is busy)
+        let mut ring = FrameRing::new((0, false));
+        assert_eq!(ring.next(|s| !s.1, || (1, false)).0, 0); // Only slot is free
+        ring.current_mut().1 = true;
+
+        assert_eq!(ring.next(|s| !s.1, || (1, false)).0, 1); // Busy, new slot
+        ring.current_mut().1 = true;
+        assert_eq!(ring.next(|s| !s.1, || (2, false)).0, 2);
+        assert_eq!(ring.len(), 3);
+
+        // First slot finished on GPU
+        ring.slots[0].1 = false;
+        assert_eq!(ring.next(|s| !s.1, || (3, false)).0, 0);
+        assert_eq!(ring.len(), 3);
+    }
+}
@@ -1,4 +1,8 @@
 
+pub mod frame_ring;
+pub use frame_ring::FrameRing;
+
+
 
 /// Perform some operation *with* value, then drop it
 #[inline] pub fn with<T, F, R>(val: T, mut f: F) -> R where F: FnMut(T) -> R { f(val) }
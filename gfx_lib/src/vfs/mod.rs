@@ -0,0 +1,194 @@
+
+// Virtual file system
+// Asset paths are virtual (`data/test.obj`), resolved through mounted directories and pack archives
+// Mount added last is checked first, so directory mounted over pack overrides packed files
+
+use std::{
+    path::{ Path, PathBuf },
+};
+
+pub mod pack;
+pub use pack::{ PackArchive, PackWriter };
+
+pub enum VfsError {
+    NotFound(String),
+    Io(std::io::Error),
+    Corrupt(String), // Pack archive can't be read
+    ChecksumMismatch(String), // Packed file does not match its checksum
+}
+impl std::error::Error for VfsError {}
+impl std::fmt::Debug for VfsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            VfsError::NotFound(path) => write!(f, "No file for path: {}", path),
+            VfsError::Io(e) => write!(f, "IO error: {}", e),
+            VfsError::Corrupt(e) => write!(f, "Corrupt pack archive: {}", e),
+            VfsError::ChecksumMismatch(path) => write!(f, "Checksum mismatch of packed file: {}", path),
+        }
+    }
+}
+impl std::fmt::Display for VfsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        (self as &dyn std::fmt::Debug).fmt(f)
+    }
+}
+impl From<std::io::Error> for VfsError {
+    fn from(e: std::io::Error) -> Self { VfsError::Io(e) }
+}
+
+/// Files that can be mounted, paths given to it are relative to mount point and normalized
+pub trait VfsSource: Send + Sync {
+    /// Read whole file, `VfsError::NotFound` lets next mount try
+    fn read(&self, path: &str) -> Result<Vec<u8>, VfsError>;
+    fn exists(&self, path: &str) -> bool;
+    /// Path on disk, if file is a plain file, used to watch it for changes
+    fn real_path(&self, _path: &str) -> Option<PathBuf> { None }
+}
+
+/// Directory on disk
+pub struct DirSource {
+    root: PathBuf,
+}
+impl DirSource {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self { Self { root: root.into() } }
+}
+impl VfsSource for DirSource {
+    fn read(&self, path: &str) -> Result<Vec<u8>, VfsError> {
+        match std::fs::read(self.root.join(path)) {
+            Ok(bytes) => Ok(bytes),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Err(VfsError::NotFound(path.into())),
+            Err(e) => Err(e.into()),
+        }
+    }
+    fn exists(&self, path: &str) -> bool { self.root.join(path).is_file() }
+    fn real_path(&self, path: &str) -> Option<PathBuf> {
+        let p = self.root.join(path);
+        if p.is_file() { Some(p) } else { None }
+    }
+}
+
+/// Mounted sources, shared read only between loaders
+pub struct Vfs {
+    mounts: Vec<(String, Box<dyn VfsSource>)>, // Mount point, source
+}
+impl Vfs {
+    pub fn new() -> Self { Self { mounts: vec![] } }
+
+    /// Vfs with single directory mounted at root
+    pub fn directory(dir: &Path) -> Self {
+        let mut vfs = Self::new();
+        vfs.mount("", DirSource::new(dir));
+        vfs
+    }
+
+    /// Mount `source` at `point`, `""` is root
+    pub fn mount<S: VfsSource + 'static>(&mut self, point: &str, source: S) {
+        self.mounts.push((normalize(point), Box::new(source)));
+    }
+
+    /// Mounts containing `path`, last mounted first, with path relative to mount
+    fn resolve<'a>(&'a self, path: &'a str) -> impl Iterator<Item = (&'a dyn VfsSource, &'a str)> + 'a {
+        self.mounts.iter().rev().filter_map(move |(point, source)| {
+            let rel = if point.is_empty() { Some(path) }
+                else if path == point { Some("") }
+                else if path.starts_with(point.as_str()) && path[point.len() ..].starts_with('/') { Some(&path[point.len() + 1 ..]) }
+                else { None };
+            rel.map(|rel| (source.as_ref(), rel))
+        })
+    }
+
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, VfsError> {
+        let path = normalize(path);
+        for (source, rel) in self.resolve(&path) {
+            match source.read(rel) {
+                Err(VfsError::NotFound(_)) => continue,
+                result => return result,
+            }
+        }
+        Err(VfsError::NotFound(path.clone()))
+    }
+
+    pub fn read_to_string(&self, path: &str) -> Result<String, VfsError> {
+        String::from_utf8(self.read(path)?).map_err(|e| VfsError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        let path = normalize(path);
+        let found = self.resolve(&path).any(|(source, rel)| source.exists(rel));
+        found
+    }
+
+    /// Path on disk of file that would be read, None if it comes from pack
+    pub fn real_path(&self, path: &str) -> Option<PathBuf> {
+        let path = normalize(path);
+        for (source, rel) in self.resolve(&path) {
+            if source.exists(rel) { return source.real_path(rel); }
+        }
+        None
+    }
+}
+
+/// Forward slashes, no empty, `.` or leading `..` components
+pub fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = vec![];
+    for part in path.split(|c| c == '/' || c == '\\') {
+        match part {
+            "" | "." => (),
+            ".." => { parts.pop(); },
+            p => parts.push(p),
+        }
+    }
+    parts.join("/")
+}
+
+/// Directory of virtual path
+pub fn parent(path: &str) -> String {
+    let path = normalize(path);
+    match path.rfind('/') {
+        Some(i) => path[.. i].to_string(),
+        None => String::new(),
+    }
+}
+
+pub fn join(dir: &str, path: &str) -> String { normalize(&format!("{}/{}", dir, path)) }
+
+mod test {
+
+    #[test] fn test_paths() {
+        use super::{ normalize, parent, join };
+
+        assert_eq!(normalize("./data\\models//test.obj"), "data/models/test.obj");
+        assert_eq!(normalize("data/models/../test.mtl"), "data/test.mtl");
+        assert_eq!(parent("data/test.obj"), "data");
+        assert_eq!(parent("test.obj"), "");
+        assert_eq!(join("data", "../other/a.png"), "other/a.png");
+    }
+
+    #[test] fn test_mount_order() {
+        use super::{ Vfs, VfsError, DirSource, PackArchive, PackWriter };
+        use std::io::Cursor;
+
+        let mut writer = PackWriter::new(Cursor::new(vec![])).unwrap();
+        writer.add("a.txt", b"packed a", true).unwrap();
+        writer.add("b.txt", b"packed b", false).unwrap();
+        let pack = PackArchive::from_reader(Cursor::new(writer.finish().unwrap().into_inner())).unwrap();
+
+        let dir = std::env::temp_dir().join("gfx_lib_vfs_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "loose a").unwrap();
+
+        let mut vfs = Vfs::new();
+        vfs.mount("data", pack);
+        vfs.mount("data", DirSource::new(&dir));
+
+        // Directory mounted last overrides pack, missing files fall through
+        assert_eq!(vfs.read_to_string("data/a.txt").unwrap(), "loose a");
+        assert_eq!(vfs.read_to_string("./data/b.txt").unwrap(), "packed b");
+        assert!(vfs.real_path("data/b.txt").is_none());
+        assert_eq!(vfs.real_path("data/a.txt"), Some(dir.join("a.txt")));
+        assert!(match vfs.read("data/c.txt") { Err(VfsError::NotFound(_)) => true, _ => false });
+        assert!(!vfs.exists("a.txt"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
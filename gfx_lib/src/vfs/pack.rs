@@ -0,0 +1,266 @@
+
+// Pack archive
+// Single file with blobs after header and index at the end, all integers are little endian
+//   header: magic "GFXPACK\0", u32 version, u32 entry count, u64 index offset
+//   entry:  u16 path length, path, u64 offset, u64 stored size, u64 size, u8 flags, u32 crc32 of unpacked data
+
+use std::{
+    collections::HashMap,
+    io::{ Read, Write, Seek, SeekFrom },
+    path::Path,
+    sync::Mutex,
+};
+use flate2::{ Crc, Compression, read::DeflateDecoder, write::DeflateEncoder };
+
+use super::{ VfsSource, VfsError, normalize };
+
+const MAGIC: &[u8; 8] = b"GFXPACK\0";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 24;
+const FLAG_DEFLATE: u8 = 1;
+const MAX_DEFLATE_RATIO: u64 = 1032; // Deflate can't expand data more than this
+
+#[derive(Debug, Clone)]
+struct Entry {
+    offset: u64,
+    stored: u64, // Size in archive
+    size: u64, // Unpacked size
+    flags: u8,
+    crc: u32,
+}
+
+/// Read only pack archive, files are read and checked on demand
+pub struct PackArchive<R: Read + Seek + Send> {
+    reader: Mutex<R>,
+    entries: HashMap<String, Entry>,
+}
+impl PackArchive<std::io::BufReader<std::fs::File>> {
+    pub fn open(path: &Path) -> Result<Self, VfsError> {
+        Self::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+}
+impl <R: Read + Seek + Send> PackArchive<R> {
+    /// Reads index, blobs are left in reader
+    pub fn from_reader(mut reader: R) -> Result<Self, VfsError> {
+        let mut magic = [0u8; 8];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC { return Err(VfsError::Corrupt("not a pack archive".into())); }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION { return Err(VfsError::Corrupt(format!("unsupported version {}", version))); }
+        let count = read_u32(&mut reader)?;
+        let index = read_u64(&mut reader)?;
+
+        // Sizes are checked against archive length, so corrupt index can't allocate more than that
+        let length = reader.seek(SeekFrom::End(0))?;
+        if index < HEADER_SIZE || index > length { return Err(VfsError::Corrupt("index out of bounds".into())); }
+        reader.seek(SeekFrom::Start(index))?;
+        let mut entries = HashMap::new();
+        for _ in 0 .. count {
+            let len = read_u16(&mut reader)? as usize;
+            let mut path = vec![0u8; len];
+            reader.read_exact(&mut path)?;
+            let path = String::from_utf8(path).map_err(|_| VfsError::Corrupt("path is not utf-8".into()))?;
+            let entry = Entry {
+                offset: read_u64(&mut reader)?,
+                stored: read_u64(&mut reader)?,
+                size: read_u64(&mut reader)?,
+                flags: read_u8(&mut reader)?,
+                crc: read_u32(&mut reader)?,
+            };
+            if entry.offset < HEADER_SIZE || entry.offset.checked_add(entry.stored).map_or(true, |end| end > index) {
+                return Err(VfsError::Corrupt(format!("entry out of bounds: {}", path)));
+            }
+            let max_size = if entry.flags & FLAG_DEFLATE != 0 { entry.stored.saturating_mul(MAX_DEFLATE_RATIO) } else { entry.stored };
+            if entry.size > max_size {
+                return Err(VfsError::Corrupt(format!("entry size is too large: {}", path)));
+            }
+            entries.insert(path, entry);
+        }
+
+        Ok(Self { reader: Mutex::new(reader), entries })
+    }
+
+    /// Packed paths, unordered
+    pub fn paths(&self) -> impl Iterator<Item = &str> + '_ { self.entries.keys().map(|s| s.as_str()) }
+}
+impl <R: Read + Seek + Send> VfsSource for PackArchive<R> {
+    fn read(&self, path: &str) -> Result<Vec<u8>, VfsError> {
+        let entry = self.entries.get(path).ok_or_else(|| VfsError::NotFound(path.into()))?;
+
+        let mut stored = vec![0u8; entry.stored as usize];
+        {
+            let mut reader = self.reader.lock().unwrap();
+            reader.seek(SeekFrom::Start(entry.offset))?;
+            reader.read_exact(&mut stored)?;
+        }
+
+        let data = if entry.flags & FLAG_DEFLATE != 0 {
+            let mut data = Vec::with_capacity(entry.size as usize);
+            DeflateDecoder::new(stored.as_slice()).read_to_end(&mut data)
+                .map_err(|e| VfsError::Corrupt(format!("{}: {}", path, e)))?;
+            data
+        } else { stored };
+
+        if data.len() as u64 != entry.size || crc32(&data) != entry.crc {
+            return Err(VfsError::ChecksumMismatch(path.into()));
+        }
+        Ok(data)
+    }
+    fn exists(&self, path: &str) -> bool { self.entries.contains_key(path) }
+}
+
+/// Writes pack archive, `finish` must be called to write index
+pub struct PackWriter<W: Write + Seek> {
+    writer: W,
+    entries: Vec<(String, Entry)>,
+    offset: u64,
+}
+impl <W: Write + Seek> PackWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, VfsError> {
+        // Header is rewritten with real values in `finish`
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(&[0u8; HEADER_SIZE as usize])?;
+        Ok(Self { writer, entries: vec![], offset: HEADER_SIZE })
+    }
+
+    /// Add file, compressed data is stored only if it is smaller
+    pub fn add(&mut self, path: &str, data: &[u8], compress: bool) -> Result<(), VfsError> {
+        let path = normalize(path);
+        let packed = if compress {
+            let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+            encoder.write_all(data)?;
+            Some(encoder.finish()?).filter(|p| p.len() < data.len())
+        } else { None };
+
+        let (stored, flags) = match &packed {
+            Some(p) => (p.as_slice(), FLAG_DEFLATE),
+            None => (data, 0),
+        };
+        self.writer.write_all(stored)?;
+        let entry = Entry { offset: self.offset, stored: stored.len() as u64, size: data.len() as u64, flags, crc: crc32(data) };
+        self.offset += entry.stored;
+
+        // Later file with same path replaces earlier one
+        self.entries.retain(|(p, _)| *p != path);
+        self.entries.push((path, entry));
+        Ok(())
+    }
+
+    /// Add every file under `root`, paths are relative to it
+    pub fn add_dir(&mut self, root: &Path, compress: bool) -> Result<usize, VfsError> {
+        let mut count = 0;
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let mut children: Vec<_> = std::fs::read_dir(&dir)?.collect::<Result<_, _>>()?;
+            children.sort_by_key(|e| e.path());
+            for child in children {
+                let path = child.path();
+                if path.is_dir() { dirs.push(path); continue; }
+                let rel = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().into_owned();
+                self.add(&rel, &std::fs::read(&path)?, compress)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Write index and header, returns underlying writer
+    pub fn finish(mut self) -> Result<W, VfsError> {
+        let index = self.offset;
+        for (path, entry) in self.entries.iter() {
+            self.writer.write_all(&(path.len() as u16).to_le_bytes())?;
+            self.writer.write_all(path.as_bytes())?;
+            self.writer.write_all(&entry.offset.to_le_bytes())?;
+            self.writer.write_all(&entry.stored.to_le_bytes())?;
+            self.writer.write_all(&entry.size.to_le_bytes())?;
+            self.writer.write_all(&[entry.flags])?;
+            self.writer.write_all(&entry.crc.to_le_bytes())?;
+        }
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(MAGIC)?;
+        self.writer.write_all(&VERSION.to_le_bytes())?;
+        self.writer.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&index.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+fn read_u8<R: Read>(r: &mut R) -> std::io::Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+fn read_u16<R: Read>(r: &mut R) -> std::io::Result<u16> {
+    let mut b = [0u8; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+fn read_u32<R: Read>(r: &mut R) -> std::io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+fn read_u64<R: Read>(r: &mut R) -> std::io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+mod test {
+
+    #[test] fn test_pack_round_trip() {
+        use super::{ PackArchive, PackWriter };
+        use crate::vfs::{ VfsSource, VfsError };
+        use std::io::Cursor;
+
+        let text = "repeated text ".repeat(64);
+        let mut writer = PackWriter::new(Cursor::new(vec![])).unwrap();
+        writer.add("models/test.obj", text.as_bytes(), true).unwrap();
+        writer.add("./raw.bin", &[1, 2, 3], true).unwrap(); // Not smaller, stored raw
+        let mut bytes = writer.finish().unwrap().into_inner();
+
+        let pack = PackArchive::from_reader(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(pack.read("models/test.obj").unwrap(), text.as_bytes());
+        assert_eq!(pack.read("raw.bin").unwrap(), vec![1, 2, 3]);
+        assert!(pack.entries["models/test.obj"].stored < text.len() as u64);
+        assert!(!pack.exists("missing"));
+
+        // Flip byte of raw blob, it is last one before index
+        let raw = pack.entries["raw.bin"].offset as usize;
+        bytes[raw] ^= 0xff;
+        let pack = PackArchive::from_reader(Cursor::new(bytes)).unwrap();
+        assert!(match pack.read("raw.bin") { Err(VfsError::ChecksumMismatch(_)) => true, _ => false });
+
+        assert!(PackArchive::from_reader(Cursor::new(vec![0u8; 32])).is_err());
+    }
+
+    #[test] fn test_pack_corrupt_index() {
+        use super::{ PackArchive, PackWriter };
+        use std::io::Cursor;
+
+        let mut writer = PackWriter::new(Cursor::new(vec![])).unwrap();
+        writer.add("raw.bin", &[1, 2, 3], false).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+        // Index follows header and 3 byte blob, entry is u16 path length, 7 bytes of path, then offset, stored and size
+        let entry = 24 + 3 + 2 + 7;
+        let with = |at: usize, value: u64| {
+            let mut b = bytes.clone();
+            b[at .. at + 8].copy_from_slice(&value.to_le_bytes());
+            PackArchive::from_reader(Cursor::new(b))
+        };
+
+        assert!(with(entry, 24).is_ok());
+        assert!(with(entry + 8, u64::max_value()).is_err()); // Offset + stored overflows
+        assert!(with(entry + 16, 1 << 40).is_err()); // Unpacked size over stored size
+        assert!(with(16, 1 << 40).is_err()); // Index past end of archive
+    }
+}
@@ -0,0 +1,205 @@
+
+// Loading screen
+// Drives `LoadPlanner` while drawing progress bar, spinner, current item and optional logo with `Renderer2D`
+// Replaces itself with state built by `next` once every job is finished, or shows error `next` returned
+
+use std::{
+    io::Cursor,
+    sync::Arc,
+};
+use vulkano::{
+    format::Format,
+    image::{ ImageAccess, ImageViewAccess },
+    sync::GpuFuture,
+    sampler::SamplerCreationError,
+};
+
+use crate::{
+    main_processor::{ Frame, FrameRequest, GameListener, state_stack::GameState },
+    graphics::{
+        renderer_2d::{ Renderer2D, pixel_font },
+        object::ScreenInstance,
+        image::{ ImageContent, sampler_pool::SamplerParams },
+    },
+    sync::{ LoadPlanner, LoadProgress },
+};
+
+/// Builds state loading view switches to, failed jobs can be checked in `LoadPlanner`
+/// Error is shown by loading view, which stays on screen
+pub type NextState = Box<dyn FnOnce(&mut Frame, &LoadPlanner) -> Result<Box<dyn GameListener>, String>>;
+
+/// Number of spinner dots
+const SPINNER_DOTS: usize = 8;
+/// Size of font dot in pixels
+const TEXT_PIXEL: f32 = 2.0;
+/// Longer lines are cut
+const MAX_LINE_CHARS: usize = 64;
+const MAX_ERROR_LINES: usize = 4;
+/// Logo, progress bar, spinner and text lines
+const MAX_INSTANCES: usize = 3 + SPINNER_DOTS
+    + (MAX_ERROR_LINES + 1) * MAX_LINE_CHARS * pixel_font::GLYPH_WIDTH * pixel_font::GLYPH_HEIGHT;
+
+/// Loading screen state, push it (or give it as first listener) instead of blocking on loaders
+/// Current item is drawn under progress bar, and shown in window title if `with_window_title` is set
+pub struct LoadingView {
+    planner: LoadPlanner,
+    next: Option<NextState>,
+    renderer: Renderer2D,
+    logo: Option<ImageContent>,
+    window_title: Option<String>, // Restored when loading is done
+    current: Option<String>, // Item being loaded
+    error: Option<String>, // Returned by `next`, drawn instead of progress
+    shown_fraction: f32, // Drawn progress, eased towards real one
+    time: f32,
+}
+impl LoadingView {
+    pub fn new<F>(frame: &mut Frame, planner: LoadPlanner, next: F) -> Self
+        where F: FnOnce(&mut Frame, &LoadPlanner) -> Result<Box<dyn GameListener>, String> + 'static
+    {
+        let mut renderer = Renderer2D::new(frame.queue.clone(), ImageAccess::format(&frame.image), MAX_INSTANCES);
+        renderer.clear_color = [0.05, 0.05, 0.07, 1.0];
+        Self {
+            planner,
+            next: Some(Box::new(next)),
+            renderer,
+            logo: None,
+            window_title: None,
+            current: None,
+            error: None,
+            shown_fraction: 0.0,
+            time: 0.0,
+        }
+    }
+
+    /// Logo drawn above progress bar, PNG bytes, on error view is left without logo
+    pub fn with_logo(&mut self, frame: &mut Frame, bytes: Cursor<Vec<u8>>) -> Result<(), SamplerCreationError> {
+        self.logo = Some(ImageContent::new_with_bytes(
+            frame.queue.clone(),
+            frame.sampler_pool.with_params(SamplerParams::simple_repeat())?,
+            bytes,
+            Format::R8G8B8A8Srgb,
+        ));
+        Ok(())
+    }
+
+    /// Show current item in window title as `title - Loading item`
+    pub fn with_window_title<S: Into<String>>(mut self, title: S) -> Self {
+        self.window_title = Some(title.into());
+        self
+    }
+
+    pub fn progress(&self) -> LoadProgress { self.planner.progress() }
+
+    fn update_current(&mut self, frame: &mut Frame, progress: &LoadProgress) {
+        if progress.current == self.current { return; }
+        self.current = progress.current.clone();
+        if let Some(title) = &self.window_title {
+            frame.request(FrameRequest::SetWindowTitle(match &self.current {
+                Some(item) => format!("{} - Loading {}", title, item),
+                None => title.clone(),
+            }));
+        }
+    }
+
+    fn finish(&mut self, frame: &mut Frame) {
+        for (name, error) in self.planner.failures() {
+            println!("Unable to load {}: {}", name, error);
+        }
+        if let Some(title) = &self.window_title {
+            frame.request(FrameRequest::SetWindowTitle(title.clone()));
+        }
+        if let Some(next) = self.next.take() {
+            match next(frame, &self.planner) {
+                Ok(state) => frame.request(FrameRequest::ReplaceState(GameState::from_box(state))),
+                Err(e) => {
+                    println!("{}", e);
+                    self.error = Some(e);
+                },
+            }
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame, future: Box<dyn GpuFuture>) -> Box<dyn GpuFuture> {
+        let dims = ImageViewAccess::dimensions(&frame.image).width_height();
+        let (w, h) = (dims[0] as f32, dims[1] as f32);
+
+        let rect = |x: f32, y: f32, w: f32, h: f32, angle: f32, col: [f32; 4]| {
+            let mut instance = ScreenInstance::new();
+            instance.set_transform(x, y, w, h, cgmath::Rad(angle));
+            instance.set_color(col[0], col[1], col[2], col[3]);
+            instance
+        };
+
+        // Progress bar in lower third, spinner right of it
+        let (bar_w, bar_h) = (w * 0.5, 12.0);
+        let (bar_x, bar_y) = (w / 2.0, h * 0.7);
+        let mut shapes = vec![
+            rect(bar_x, bar_y, bar_w + 4.0, bar_h + 4.0, 0.0, [0.3, 0.3, 0.3, 1.0]),
+            rect(bar_x - bar_w / 2.0 * (1.0 - self.shown_fraction), bar_y, bar_w * self.shown_fraction, bar_h, 0.0, [0.9, 0.9, 0.9, 1.0]),
+        ];
+        // Current item under bar, or error lines in place of spinner once loading failed
+        let text_y = bar_y + bar_h / 2.0 + 12.0;
+        let line_h = (pixel_font::GLYPH_HEIGHT + 4) as f32 * TEXT_PIXEL;
+        let mut text = |line: &str, y: f32, col: [f32; 4]| {
+            shapes.extend(pixel_font::text_instances(line, w / 2.0 - pixel_font::text_width(line, TEXT_PIXEL) / 2.0, y, TEXT_PIXEL, col));
+        };
+        match &self.error {
+            Some(error) => {
+                let per_line = pixel_font::chars_fitting(w * 0.9, TEXT_PIXEL).min(MAX_LINE_CHARS).max(1);
+                let chars: Vec<char> = error.chars().collect();
+                for (i, line) in chars.chunks(per_line).take(MAX_ERROR_LINES).enumerate() {
+                    let line: String = line.iter().collect();
+                    text(&line, text_y + i as f32 * line_h, [1.0, 0.3, 0.3, 1.0]);
+                }
+            },
+            None => {
+                let fits = pixel_font::chars_fitting(bar_w, TEXT_PIXEL).min(MAX_LINE_CHARS);
+                let line = match &self.current {
+                    Some(item) => format!("Loading {}", item),
+                    None => "Loading".to_string(),
+                };
+                let line: String = line.chars().take(fits).collect();
+                text(&line, text_y, [0.7, 0.7, 0.7, 1.0]);
+
+                let spinner = (bar_x + bar_w / 2.0 + 30.0, bar_y);
+                for i in 0 .. SPINNER_DOTS {
+                    let angle = self.time * 4.0 + i as f32 / SPINNER_DOTS as f32 * std::f32::consts::PI * 2.0;
+                    let alpha = (i + 1) as f32 / SPINNER_DOTS as f32;
+                    shapes.push(rect(spinner.0 + angle.cos() * 12.0, spinner.1 + angle.sin() * 12.0, 4.0, 4.0, angle, [0.9, 0.9, 0.9, alpha]));
+                }
+            },
+        }
+
+        self.renderer.set_viewport_window(w, h);
+        self.renderer.begin(frame.image.clone());
+        if let Some(logo) = &mut self.logo {
+            // Not drawn until loaded, failed logo is just skipped
+            if logo.is_ready() {
+                let size = w.min(h) * 0.3;
+                let mut call = self.renderer.start_image_content(logo);
+                call.render_instance(rect(w / 2.0, h * 0.4, size, size, 0.0, [1.0; 4]));
+            }
+        }
+        {
+            let white = self.renderer.white_uniform();
+            let mut call = self.renderer.start_image_uniform(white);
+            call.render_instances_vec(shapes);
+        }
+        self.renderer.end_after(future)
+    }
+}
+
+impl GameListener for LoadingView {
+    fn update(&mut self, delta: f32, frame: &mut Frame, future: Box<dyn GpuFuture>) -> Box<dyn GpuFuture> {
+        self.time += delta;
+        self.planner.update();
+
+        let progress = self.planner.progress();
+        self.shown_fraction += (progress.fraction() - self.shown_fraction) * (delta * 10.0).min(1.0);
+        self.update_current(frame, &progress);
+
+        if self.planner.is_finished() && self.next.is_some() { self.finish(frame); }
+
+        self.render(frame, future)
+    }
+}
@@ -1,2 +1,2 @@
-
 mod loading_view;
+pub use loading_view::*;
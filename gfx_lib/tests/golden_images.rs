@@ -0,0 +1,323 @@
+
+// Golden image regression tests
+// Small scenes are rendered headless and compared against reference PNGs in `tests/golden`
+// Tests need Vulkan device (lavapipe / swiftshader in CI), so they are ignored by default
+// and fail instead of passing then device or reference is missing:
+//     cargo test -p gfx_lib --test golden_images -- --ignored
+// Run with `GOLDEN_BLESS=1` to write new references instead of comparing
+
+use std::{
+    io::Cursor,
+    path::{ Path, PathBuf },
+};
+use cgmath::{ Matrix4, SquareMatrix };
+use vulkano::{
+    format::Format,
+    instance::{ InstanceExtensions, PhysicalDevice },
+    sync::GpuFuture,
+};
+
+use gfx_lib::{
+    main_processor::{
+        GameListener, Frame,
+        settings::{ self, GameSettings },
+        headless::{ HeadlessRunner, HEADLESS_FORMAT },
+    },
+    graphics::{
+        Camera,
+        image::{
+            ImageContent, PNGData,
+            atlas::{ TextureAtlas, AtlasImageResolver },
+            sampler_pool::SamplerParams,
+            readback,
+        },
+        renderer_2d::Renderer2D,
+        renderer_3d::{
+            Renderer3D,
+            lighting_system::{ LightKind, ShadowKind },
+            mesh::{ Vertex3D, MaterialMeshSlice, MaterialData, ObjectInstance },
+        },
+        object::ScreenInstance,
+    },
+};
+
+/// Max allowed difference of any channel
+const CHANNEL_TOLERANCE: u8 = 3;
+const SCENE_DIMS: (u32, u32) = (128, 96);
+
+fn data_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../src/data").join(name)
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.png", name))
+}
+
+fn diff_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../target/golden-diff")
+}
+
+/// Software and hardware devices are both accepted
+fn require_vulkan() {
+    let available = match settings::create_instance(&InstanceExtensions::none()) {
+        Ok(instance) => PhysicalDevice::enumerate(&instance).next().is_some(),
+        Err(_) => false,
+    };
+    assert!(available, "No Vulkan device, golden image tests need one (install lavapipe)");
+}
+
+fn load_png(path: &Path) -> PNGData {
+    let bytes = std::fs::read(path).unwrap();
+    let (info, mut reader) = png::Decoder::new(Cursor::new(bytes)).read_info().unwrap();
+    assert_eq!(info.color_type, png::ColorType::RGBA, "Reference must be RGBA8: {:?}", path);
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data).unwrap();
+    PNGData::new((info.width, info.height), data)
+}
+
+/// Render single frame of listener and return output as RGBA8
+fn render_scene<F>(init_listener: F) -> PNGData
+    where F: FnMut(&mut Frame) -> Box<dyn GameListener>
+{
+    let settings = GameSettings {
+        window_size: SCENE_DIMS,
+        .. GameSettings::default()
+    };
+    let mut runner = HeadlessRunner::new(&settings, init_listener).unwrap();
+    runner.run(1).unwrap();
+    readback::read_image(runner.queue(), runner.image()).unwrap()
+        .to_rgba8().unwrap()
+}
+
+/// Compare with reference, dumping actual and diff images on failure
+fn assert_golden(name: &str, actual: &PNGData) {
+    let path = golden_path(name);
+
+    if std::env::var("GOLDEN_BLESS").map(|v| v == "1").unwrap_or(false) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        actual.save_png(&path).unwrap();
+        return;
+    }
+
+    if !path.exists() {
+        // Dumped output can be reviewed and committed as reference
+        let dir = diff_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        actual.save_png(&dir.join(format!("{}.actual.png", name))).unwrap();
+        panic!("No reference for \"{}\", run with GOLDEN_BLESS=1 to create it, output is in {:?}", name, dir);
+    }
+    let expected = load_png(&path);
+    assert_eq!(expected.dimensions, actual.dimensions, "Dimensions of \"{}\" differ", name);
+
+    let mut mismatched = 0;
+    let mut max_diff = 0;
+    let diff: Vec<u8> = expected.data.chunks_exact(4).zip(actual.data.chunks_exact(4))
+        .flat_map(|(e, a)| {
+            let d: Vec<u8> = (0 .. 4).map(|i| (e[i] as i16 - a[i] as i16).abs() as u8).collect();
+            let px_max = *d.iter().max().unwrap();
+            if px_max > CHANNEL_TOLERANCE { mismatched += 1; }
+            max_diff = max_diff.max(px_max);
+            // Amplify difference to make it visible, alpha is always opaque
+            vec![d[0].saturating_mul(8), d[1].saturating_mul(8), d[2].saturating_mul(8), 255]
+        })
+        .collect();
+
+    if mismatched > 0 {
+        let dir = diff_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        actual.save_png(&dir.join(format!("{}.actual.png", name))).unwrap();
+        PNGData::new(actual.dimensions, diff)
+            .save_png(&dir.join(format!("{}.diff.png", name))).unwrap();
+        panic!("\"{}\": {} pixels differ (max channel diff {}), see {:?}", name, mismatched, max_diff, dir);
+    }
+}
+
+/// Textured with icon, used by both 2D and 3D scenes
+fn build_atlas(frame: &mut Frame) -> TextureAtlas {
+    TextureAtlas::start()
+        .set_max_dims(512)
+        .set_padding(1, 1)
+        .set_background_color(1.0, 0.0, 1.0, 1.0)
+        .set_format(Format::R8G8B8A8Srgb)
+        .add_data("icon512.png", Cursor::new(std::fs::read(data_path("icon512.png")).unwrap())).unwrap()
+            .set_dim([256, 256]).next()
+        .add_data("icon128.png", Cursor::new(std::fs::read(data_path("icon128.png")).unwrap())).unwrap()
+            .next()
+        .build(frame).unwrap()
+        .unwrap()
+}
+
+/// Renderer3D scene with fixed camera
+struct Scene3D {
+    camera: Camera,
+    renderer: Renderer3D,
+}
+impl Scene3D {
+    fn new(frame: &mut Frame, with_object: bool, configure_lights: fn(&mut Renderer3D)) -> Self {
+        let mut renderer = Renderer3D::new(frame.queue.clone(), HEADLESS_FORMAT);
+        configure_lights(&mut renderer);
+
+        let floor_size = 10.0;
+        let floor_mesh = renderer.generate_mesh_from_data(vec![
+            Vertex3D::from_position(-floor_size, 0.0,-floor_size).uv(0.0, 0.0).normal(0.0, 1.0, 0.0),
+            Vertex3D::from_position(-floor_size, 0.0, floor_size).uv(0.0, 1.0).normal(0.0, 1.0, 0.0),
+            Vertex3D::from_position( floor_size, 0.0,-floor_size).uv(1.0, 0.0).normal(0.0, 1.0, 0.0),
+            Vertex3D::from_position( floor_size, 0.0, floor_size).uv(1.0, 1.0).normal(0.0, 1.0, 0.0),
+        ], Some(vec![0, 1, 2, 1, 3, 2]));
+
+        let mut floor = ObjectInstance::new(floor_mesh.unwrap());
+        floor.materials.push(MaterialMeshSlice {
+            vbo_slice: floor.mesh_data.get_vbo_slice(),
+            ibo_slice: Some(floor.mesh_data.get_ibo()),
+            material: {
+                let mut md = MaterialData::new();
+                md.set_diffuse(0.8, 0.8, 0.8);
+                md
+            }
+        });
+        floor.set_pos(0.0, -2.0, 0.0);
+        renderer.render_geometry.push(floor);
+
+        if with_object {
+            let atlas = build_atlas(frame);
+            let mut objects = gfx_lib::loader::obj::load_objects(&data_path("test.obj"), vec!["Plane"]).unwrap();
+            let object = renderer.generate_object(
+                objects.remove("Plane").unwrap(),
+                AtlasImageResolver::new(&atlas)
+            ).unwrap();
+            renderer.render_geometry.push(object);
+        }
+
+        let mut camera = Camera::new(Matrix4::identity());
+        camera.set_pos_arr([0.0, 1.0, -6.0]);
+
+        Self { camera, renderer }
+    }
+}
+impl GameListener for Scene3D {
+    fn dimensions_changed(&mut self, _frame: &mut Frame, width: u32, height: u32) {
+        self.camera.set_projection(cgmath::perspective(
+            cgmath::Deg(60.0), width as f32 / height as f32, 0.1, 100.0
+        ));
+    }
+
+    fn update(&mut self, _delta: f32, frame: &mut Frame, future: Box<dyn GpuFuture>) -> Box<dyn GpuFuture> {
+        self.renderer.set_view_projection(self.camera.get_view_projection());
+        self.renderer.render(future, frame.image.clone())
+    }
+}
+
+/// Renderer2D scene, draws `draw` instances with texture `content`
+struct Scene2D {
+    renderer: Renderer2D,
+    draw: Box<dyn FnMut(&mut Renderer2D)>,
+}
+impl GameListener for Scene2D {
+    fn dimensions_changed(&mut self, _frame: &mut Frame, _width: u32, _height: u32) {
+        self.renderer.set_viewport_window(1.0, 1.0);
+    }
+
+    fn update(&mut self, _delta: f32, frame: &mut Frame, future: Box<dyn GpuFuture>) -> Box<dyn GpuFuture> {
+        // Previous frame is already finished in headless mode
+        drop(future);
+        let now = Box::new(vulkano::sync::now(frame.queue.device().clone())) as Box<dyn GpuFuture + Send + Sync>;
+        self.renderer.begin(frame.image.clone());
+        (self.draw)(&mut self.renderer);
+        self.renderer.end(now)
+    }
+}
+
+#[test] #[ignore] fn golden_3d_ambient_point() {
+    require_vulkan();
+    let image = render_scene(|frame| Box::new(Scene3D::new(frame, false, |r| {
+        let ambient = r.create_light_source(LightKind::Ambient);
+        ambient.borrow_mut().active = true;
+        ambient.borrow_mut().col(0.2, 0.2, 0.2);
+
+        let point = r.create_light_source(LightKind::PointLight);
+        point.borrow_mut().active = true;
+        point.borrow_mut().pos(0.0, 2.0, 0.0);
+        point.borrow_mut().col(1.0, 0.5, 0.5);
+        point.borrow_mut().int(1.0);
+        point.borrow_mut().dist(10.0);
+    })));
+    assert_golden("3d_ambient_point", &image);
+}
+
+#[test] #[ignore] fn golden_3d_obj_cone_shadow() {
+    require_vulkan();
+    let image = render_scene(|frame| Box::new(Scene3D::new(frame, true, |r| {
+        let ambient = r.create_light_source(LightKind::Ambient);
+        ambient.borrow_mut().active = true;
+        ambient.borrow_mut().col(0.1, 0.1, 0.1);
+
+        let cone = r.create_light_source(LightKind::ConeWithShadow(
+            ShadowKind::Cone::with_projection(90.0, [256, 256])
+        ));
+        cone.borrow_mut().pos(1.0, 5.0, 5.0);
+        cone.borrow_mut().look_at(0.0, 0.0, 0.0);
+        cone.borrow_mut().int(1.0);
+        cone.borrow_mut().dist(20.0);
+    })));
+    assert_golden("3d_obj_cone_shadow", &image);
+}
+
+#[test] #[ignore] fn golden_2d_instances() {
+    require_vulkan();
+    let image = render_scene(|frame| {
+        let mut content = ImageContent::new_with_bytes(
+            frame.queue.clone(),
+            frame.sampler_pool.with_params(SamplerParams::simple_repeat()).unwrap(),
+            Cursor::new(std::fs::read(data_path("icon128.png")).unwrap()),
+            Format::R8G8B8A8Srgb,
+        );
+        content.flush();
+        Box::new(Scene2D {
+            renderer: Renderer2D::new(frame.queue.clone(), HEADLESS_FORMAT, 16),
+            draw: Box::new(move |renderer| {
+                let mut call = renderer.start_image_content(&mut content);
+                for i in 0 .. 4 {
+                    let mut inst = ScreenInstance::new();
+                    inst.set_transform(0.25 + (i % 2) as f32 * 0.5, 0.25 + (i / 2) as f32 * 0.5, 0.4, 0.4, cgmath::Deg(i as f32 * 15.0));
+                    inst.set_color(1.0, 1.0 - i as f32 * 0.25, 1.0, 1.0);
+                    call.render_instance(inst);
+                }
+                call.end_call();
+            }),
+        })
+    });
+    assert_golden("2d_instances", &image);
+}
+
+#[test] #[ignore] fn golden_2d_atlas() {
+    require_vulkan();
+    let image = render_scene(|frame| {
+        let mut atlas = build_atlas(frame);
+        Box::new(Scene2D {
+            renderer: Renderer2D::new(frame.queue.clone(), HEADLESS_FORMAT, 1),
+            draw: Box::new(move |renderer| {
+                let mut call = renderer.start_image_content(&mut atlas);
+                let mut inst = ScreenInstance::new();
+                inst.set_transform(0.5, 0.5, 1.0, 1.0, cgmath::Rad(0.0));
+                inst.set_color(1.0, 1.0, 1.0, 1.0);
+                call.render_instance(inst);
+                call.end_call();
+            }),
+        })
+    });
+    assert_golden("2d_atlas", &image);
+}
+
+#[test] #[ignore] fn atlas_has_mipmaps() {
+    use vulkano::image::ImageAccess;
+    require_vulkan();
+    let mut levels = 0;
+    render_scene(|frame| {
+        levels = build_atlas(frame).get_image().parent().mipmap_levels();
+        Box::new(Scene2D {
+            renderer: Renderer2D::new(frame.queue.clone(), HEADLESS_FORMAT, 1),
+            draw: Box::new(|_| ()),
+        })
+    });
+    assert!(levels > 1, "Atlas has {} mip levels", levels);
+}
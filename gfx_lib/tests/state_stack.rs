@@ -0,0 +1,110 @@
+
+// State stack tests
+// States are driven by headless runner with replayed input and log what they receive
+// Runner needs Vulkan device, so tests are ignored by default like golden image tests:
+//     cargo test -p gfx_lib --test state_stack -- --ignored
+
+use std::{ cell::RefCell, rc::Rc };
+use winit::VirtualKeyCode as Keys;
+use vulkano::sync::GpuFuture;
+
+use gfx_lib::main_processor::{
+    GameListener, Frame, FrameRequest,
+    settings::GameSettings,
+    headless::HeadlessRunner,
+    recording::{ InputRecording, InputEvent, InputPlayer },
+    state_stack::GameState,
+};
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+/// Logs every call, `on_key` reacts to pressed keys with requests
+struct Logged {
+    name: &'static str,
+    overlay: bool,
+    log: Log,
+    on_key: Box<dyn FnMut(&mut Frame, Keys, &Log)>,
+}
+impl Logged {
+    fn new<F>(name: &'static str, overlay: bool, log: &Log, on_key: F) -> Self
+        where F: FnMut(&mut Frame, Keys, &Log) + 'static
+    {
+        Self { name, overlay, log: log.clone(), on_key: Box::new(on_key) }
+    }
+    fn push(&self, entry: String) { self.log.borrow_mut().push(format!("{}:{}", self.name, entry)); }
+}
+impl GameListener for Logged {
+    fn update(&mut self, _delta: f32, frame: &mut Frame, future: Box<dyn GpuFuture>) -> Box<dyn GpuFuture> {
+        self.push(format!("update:{}", if frame.is_top_state() { "top" } else { "under" }));
+        future
+    }
+    fn key_pressed(&mut self, frame: &mut Frame, keycode: Keys) {
+        self.push(format!("key:{:?}", keycode));
+        (self.on_key)(frame, keycode, &self.log);
+    }
+    fn enter(&mut self, _frame: &mut Frame) { self.push("enter".into()) }
+    fn exit(&mut self, _frame: &mut Frame) { self.push("exit".into()) }
+    fn is_overlay(&self) -> bool { self.overlay }
+}
+
+/// Popped on Escape
+fn popped(name: &'static str, overlay: bool, log: &Log) -> Logged {
+    Logged::new(name, overlay, log, |frame, key, _| if key == Keys::Escape { frame.request(FrameRequest::PopState) })
+}
+
+/// Base state pushes `pushed` state on P and pops itself on Q, every frame presses one of `keys`
+fn run_stack(log: &Log, overlay: bool, keys: &[Keys]) -> u32 {
+    let base = Logged::new("base", false, log, move |frame, key, log| match key {
+        Keys::P => frame.request(FrameRequest::PushState(GameState::new(popped("pushed", overlay, log)))),
+        Keys::Q => frame.request(FrameRequest::PopState),
+        _ => (),
+    });
+
+    let mut recording = InputRecording::new((32, 32));
+    for key in keys.iter() {
+        recording.push_frame(1.0 / 60.0, &[InputEvent::Key(*key, true), InputEvent::Key(*key, false)]);
+    }
+
+    let settings = GameSettings {
+        window_size: (32, 32),
+        .. GameSettings::default()
+    };
+    let mut first = Some(base);
+    let mut runner = HeadlessRunner::new(&settings, |_| Box::new(first.take().unwrap()))
+        .expect("State stack tests need Vulkan device");
+    runner.set_replay(InputPlayer::new(recording));
+    runner.run(keys.len() as u32 + 5).unwrap()
+}
+
+fn entries(log: &Log) -> Vec<String> { log.borrow().clone() }
+
+#[test] #[ignore] fn test_overlay_push_pop() {
+    let log = Log::default();
+    let frames = run_stack(&log, true, &[Keys::P, Keys::A, Keys::Escape, Keys::A, Keys::Q]);
+
+    // Events go to top state only, overlay lets base state update under it
+    assert_eq!(entries(&log), vec![
+        "base:enter",
+        "base:update:top", "base:key:P", "pushed:enter",
+        "base:update:under", "pushed:update:top", "pushed:key:A",
+        "base:update:under", "pushed:update:top", "pushed:key:Escape", "pushed:exit",
+        "base:update:top", "base:key:A",
+        "base:update:top", "base:key:Q", "base:exit",
+    ]);
+    // Popping last state exits
+    assert_eq!(frames, 5);
+}
+
+#[test] #[ignore] fn test_state_push_pop() {
+    let log = Log::default();
+    run_stack(&log, false, &[Keys::P, Keys::A, Keys::Escape, Keys::Q]);
+
+    // State which is not overlay stops updates of states under it
+    assert_eq!(entries(&log), vec![
+        "base:enter",
+        "base:update:top", "base:key:P", "pushed:enter",
+        "pushed:update:top", "pushed:key:A",
+        "pushed:update:top", "pushed:key:Escape", "pushed:exit",
+        "base:update:top", "base:key:Q", "base:exit",
+    ]);
+}
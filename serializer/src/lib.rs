@@ -512,6 +512,11 @@ impl From<i128> for Data    { fn from(v: i128)  -> Self { Data::I128(v) } }
 impl From<f32> for Data     { fn from(v: f32)   -> Self { Data::F32(v) } }
 impl From<f64> for Data     { fn from(v: f64)   -> Self { Data::F64(v) } }
 impl From<&str> for Data    { fn from(v: &str)  -> Self { Data::String(v.into()) } }
+impl From<String> for Data  { fn from(v: String) -> Self { Data::String(v) } }
+// Optional, None => Data::None
+impl <T: Into<Data>> From<Option<T>> for Data {
+    fn from(v: Option<T>) -> Self { v.map(|v| v.into()).unwrap_or(Data::None) }
+}
 // Array
 impl From<Vec<Data>> for Data { fn from(v: Vec<Data>)  -> Self { Data::Array(v) } }
 
@@ -569,26 +574,31 @@ impl Data {
 
 /// Macro creates Data::Object
 #[allow(non_snake_case)]
+#[macro_export]
 macro_rules! DataObject {
-    ( $( $key:expr => $val:expr ),* ) => {
+    ( $( $key:expr => $val:expr ),* $(,)? ) => {{
         let mut tmp_map = std::collections::BTreeMap::new();
         $(
-            tmp_map.insert(stringify!($key).to_string(), $val.into());
+            tmp_map.insert(stringify!($key).to_string(), $crate::Data::from($val));
         )*
-        Data::Object(tmp_map)
-    }
+        $crate::Data::Object(tmp_map)
+    }}
 }
 
 
 /// Early Persistent serialization errors enum
 pub enum PersistentError {
-    UnableToDeserialize
+    UnableToDeserialize,
+    UnknownVersion(u16), // Data was written by newer or unknown version
+    InvalidField(String, DataObtainError), // Field exists, but has wrong type or value
 }
 impl std::error::Error for PersistentError {}
 impl std::fmt::Debug for PersistentError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         match self {
             PersistentError::UnableToDeserialize => write!(f, "Unable to deserialize"),
+            PersistentError::UnknownVersion(ver) => write!(f, "Unknown data version {}", ver),
+            PersistentError::InvalidField(key, e) => write!(f, "Invalid field \"{}\": {:?}", key, e),
         }
     }
 }
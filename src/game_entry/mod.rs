@@ -5,11 +5,16 @@ use std::{
     io::Cursor,
     iter::Iterator,
     sync::Arc,
-    path::Path,
+    path::{ Path, PathBuf },
+    time::{ SystemTime, UNIX_EPOCH },
 };
 use gfx_lib::{
     main_processor::{
-        GameListener, Frame, FrameRequest
+        GameListener, Frame, FrameRequest,
+        settings::{ self, WindowMode },
+        input_map::{ InputMap, Binding, AxisBinding },
+        profiler::ProfilerOverlay,
+        state_stack::GameState,
     },
     graphics::{
         Camera,
@@ -33,7 +38,10 @@ use gfx_lib::{
             ScreenVertex, ScreenInstance
         }
     },
-    sync::{ Loader, LoaderError },
+    sync::{ Loader, LoaderError, LoadPlanner, LoadJob, JobStage },
+    assets::hot_reload::{ HotReloader, Reloaded },
+    view::LoadingView,
+    vfs::{ Vfs, DirSource, PackArchive },
 };
 use std::ops::Deref;
 
@@ -41,51 +49,163 @@ use vulkano::{
     format::Format,
     sync::GpuFuture,
 };
-use vulkano::image::ImageAccess;
+use vulkano::image::{ ImageAccess, ImageViewAccess };
 
 mod ui_2d_pass;
+mod pause_state;
+
+/// Pack archive with contents of `src/data`, built with `pack_assets`
+const DATA_PACK: &str = "data.pack";
+/// Atlas entries, named as their files under `data/`, with scale
+const ATLAS_TEXTURES: [(&str, [f32; 2]); 1] = [("icon512.png", [0.7, 0.7])];
 
 /// Main Game Entry
 pub struct GameEntry {
     camera: Camera,
-//    atlas: TextureAtlas,
+    atlas: TextureAtlas,
+    plane_index: usize, // Index of object from test.obj in `renderer_3d.render_geometry`
+
+    vfs: Arc<Vfs>,
+    hot_reload: HotReloader,
+    atlas_textures: Vec<(PathBuf, &'static str)>, // Watched files of atlas entries
+    reloaded_atlas: Option<Loader<TextureAtlas>>, // Rebuilt with changed texture
+    reloaded_plane: Option<Loader<ObjectInstance>>,
 
     pass_2d: ui_2d_pass::UI2DPass,
 
     renderer_2d: Renderer2D,
     renderer_3d: Renderer3D,
+    profiler_overlay: ProfilerOverlay,
+    input: InputMap,
 
     time: f32, // Time sence beginning
     speed_mod: f32, // Cam Speed
     holding_mouse: bool, // Is currently holding mouse
+    borderless: bool, // Is currently in borderless window covering monitor
+    capturing: bool, // Is currently writing frame sequence
 }
 impl GameEntry {
-    pub fn new(init_frame: &mut Frame) -> Self {
+    /// Loading screen shown while atlas and OBJ are loaded, replaced by `GameEntry` after
+    pub fn loading_view(init_frame: &mut Frame) -> LoadingView {
+        let vfs = Arc::new(Self::mount_data());
+
+        // Window icon
+        match vfs.read("data/icon128.png") {
+            Ok(bytes) => match settings::load_icon(Cursor::new(bytes)) {
+                Ok(icon) => init_frame.request(FrameRequest::SetWindowIcon(Some(icon))),
+                Err(e) => println!("Unable to load icon: {}", e),
+            },
+            Err(e) => println!("Unable to load icon: {}", e),
+        }
 
-        // 2D UI Pass
-        let mut pass_2d = ui_2d_pass::UI2DPass::new(init_frame);
+        let mut planner = LoadPlanner::new();
+
+        // Atlas Test, failure is shown by loading view
+        let atlas = Self::load_atlas(init_frame, &vfs, None).unwrap_or_else(Loader::with_error);
+        let atlas = planner.add_loader(LoadJob::new("atlas").with_priority(1), atlas);
 
-        // Atlas Test
-        let img_bytes = include_bytes!("../data/icon512.png").to_vec();
-        let atlas = TextureAtlas::start()
+        let obj_vfs = vfs.clone();
+        let plane = planner.add_cpu(LoadJob::new("test.obj"), move |_| {
+            let mut objects = gfx_lib::loader::obj::load_objects_from(
+                &obj_vfs,
+                "data/test.obj",
+                vec!["Plane"]
+            ).map_err(|e| format!("{:?}", e))?;
+            objects.remove("Plane").ok_or_else(|| "No Plane object in test.obj".to_string())
+        });
+
+        let floor_size = 10.0;
+        let floor_mesh = planner.add_loader(LoadJob::new("floor"), Renderer3D::load_mesh(init_frame.queue.clone(), vec![
+            Vertex3D::from_position(-floor_size, 0.0,-floor_size).uv(0.0, 0.0).normal(0.0, 1.0, 0.0),
+            Vertex3D::from_position(-floor_size, 0.0, floor_size).uv(0.0, 1.0).normal(0.0, 1.0, 0.0),
+            Vertex3D::from_position( floor_size, 0.0,-floor_size).uv(1.0, 0.0).normal(0.0, 1.0, 0.0),
+            Vertex3D::from_position( floor_size, 0.0, floor_size).uv(1.0, 1.0).normal(0.0, 1.0, 0.0),
+        ], Some(vec![0, 1, 2, 1, 3, 2])));
+
+        // Plane materials are resolved from atlas
+        let queue = init_frame.queue.clone();
+        let plane_obj = planner.add(LoadJob::new("Plane").after(&atlas).after(&plane), move |deps| {
+            let resolver = AtlasImageResolver::new(deps.get(&atlas).ok_or("Atlas is not loaded")?);
+            let info = deps.get(&plane).ok_or("test.obj is not loaded")?.clone();
+            Ok(JobStage::upload(0, move || Renderer3D::load_object(queue, info, resolver)))
+        });
+
+        let logo = vfs.read("data/logo.png");
+        let mut view = LoadingView::new(init_frame, planner, move |frame, planner| {
+            Ok(Box::new(GameEntry::new(
+                frame,
+                vfs,
+                planner.result(&atlas)?.clone(),
+                planner.result(&floor_mesh)?.clone(),
+                planner.result(&plane_obj)?.clone(),
+            )?))
+        })
+            .with_window_title(settings::WINDOW_TITLE);
+
+        // Logo is optional, loading goes on without it
+        let logo = logo.map_err(|e| e.to_string())
+            .and_then(|bytes| view.with_logo(init_frame, Cursor::new(bytes)).map_err(|e| e.to_string()));
+        if let Err(e) = logo { println!("Unable to load logo: {}", e); }
+        view
+    }
+
+    /// Game data under `data/`, loose files in `src/data` override packed ones
+    fn mount_data() -> Vfs {
+        let mut vfs = Vfs::new();
+        if Path::new(DATA_PACK).is_file() {
+            match PackArchive::open(Path::new(DATA_PACK)) {
+                Ok(pack) => vfs.mount("data", pack),
+                Err(e) => println!("Unable to open {}: {}", DATA_PACK, e),
+            }
+        }
+        vfs.mount("data", DirSource::new("src/data"));
+        vfs
+    }
+
+    /// Atlas of `ATLAS_TEXTURES`, started on main thread as building needs frame
+    /// `reloaded` image replaces file of its entry, other entries are loaded from files
+    fn load_atlas(init_frame: &mut Frame, vfs: &Vfs, reloaded: Option<(&str, Arc<dyn ImageViewAccess + Send + Sync>)>)
+        -> Result<Loader<TextureAtlas>, String>
+    {
+        let mut builder = Self::atlas_builder();
+        for &(name, scl) in ATLAS_TEXTURES.iter() {
+            let entry = match &reloaded {
+                Some((reloaded_name, image)) if *reloaded_name == name => builder.add_image(name, image.clone()),
+                _ => {
+                    let path = format!("data/{}", name);
+                    let img_bytes = vfs.read(&path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+                    let image = ImageContent::load_image(init_frame.queue.clone(), Cursor::new(img_bytes), Format::R8G8B8A8Srgb)
+                        .map_err(|e| format!("Unable to decode {}: {}", path, e))?;
+                    builder.add_loader(name, image)
+                },
+            };
+            builder = entry.result().map_err(|e| e.to_string())?.set_scl(scl).next();
+        }
+        builder.build(init_frame).map_err(|e| format!("Unable to build atlas: {}", e))
+    }
+
+    fn atlas_builder() -> AtlasBuilder {
+        TextureAtlas::start()
             .set_max_dims(1024)
             .set_padding(1, 1)
             .set_background_color(1.0, 0.0, 1.0, 1.0)
-            .set_format(Format::R8G8B8A8Snorm)
-            .add_loader("icon512.png", ImageContent::load_image(
-                init_frame.queue.clone(),
-                Cursor::new(img_bytes),
-                Format::R8G8B8A8Srgb)
-            ).unwrap().set_scl([0.7, 0.7]).next()
-//            .add_data("icon512.png", Cursor::new(img_bytes)).unwrap().set_scl([0.5, 0.5]).next()
-            .build(init_frame).unwrap()
-            .unwrap();
+            .set_format(Format::R8G8B8A8Srgb)
+    }
+
+    /// Scene with loaded atlas, floor mesh and Plane object from test.obj
+    pub fn new(init_frame: &mut Frame, vfs: Arc<Vfs>, atlas: TextureAtlas, floor_mesh: Arc<dyn MeshAccess + Send + Sync>, plane: ObjectInstance)
+        -> Result<Self, String>
+    {
+
+        // 2D UI Pass
+        let mut pass_2d = ui_2d_pass::UI2DPass::new(init_frame, &vfs)?;
 
         // Transient image between renders and bake
 //        ImageContent::load_image()
 
         let mut renderer_2d = Renderer2D::new(init_frame.queue.clone(), Format::R8G8B8A8Snorm, 1000);
         let mut renderer_3d = Renderer3D::new(init_frame.queue.clone(), init_frame.image.format());
+        let profiler_overlay = ProfilerOverlay::new(init_frame.queue.clone(), init_frame.image.format());
         // Bake output onto output renderer and flip Y
 
         /* Setup lighting */ {
@@ -135,58 +255,135 @@ impl GameEntry {
 
         // Create objects
         let mut geom = {
-
-            let floor_size = 10.0;
-            let floor_mesh = renderer_3d.generate_mesh_from_data(vec![
-                Vertex3D::from_position(-floor_size, 0.0,-floor_size).uv(0.0, 0.0).normal(0.0, 1.0, 0.0),
-                Vertex3D::from_position(-floor_size, 0.0, floor_size).uv(0.0, 1.0).normal(0.0, 1.0, 0.0),
-                Vertex3D::from_position( floor_size, 0.0,-floor_size).uv(1.0, 0.0).normal(0.0, 1.0, 0.0),
-                Vertex3D::from_position( floor_size, 0.0, floor_size).uv(1.0, 1.0).normal(0.0, 1.0, 0.0),
-            ], Some(vec![0, 1, 2, 1, 3, 2]));
-
-            let mut floor_obj = ObjectInstance::new(floor_mesh.unwrap());
+            let sampler = init_frame.sampler_pool.with_params(SamplerParams::simple_repeat())
+                .map_err(|e| format!("Unable to create floor sampler: {}", e))?;
+            let mut floor_obj = ObjectInstance::new(floor_mesh);
             floor_obj.materials.push(MaterialMeshSlice {
                 vbo_slice: floor_obj.mesh_data.get_vbo_slice(),
                 ibo_slice: Some(floor_obj.mesh_data.get_ibo()),
                 material: {
                     let mut md = MaterialData::new();
-                    md.set_diffuse_texture_with_sampler(atlas.get_image(), init_frame.sampler_pool.with_params(SamplerParams::simple_repeat()));
+                    md.set_diffuse_texture_with_sampler(atlas.get_image(), sampler);
                     md
                 }
             });
             floor_obj.set_pos(0.0, -2.0, 0.0);
 
-            let mut object_data = gfx_lib::loader::obj::load_objects(
-                &Path::new("src/data/test.obj"),
-                vec!["Plane"]
-            ).unwrap();
-
-            let mut obj1 = renderer_3d.generate_object(
-                object_data.remove("Plane").unwrap(),
-                AtlasImageResolver::new(&atlas)
-            ).unwrap();
-
-            vec![floor_obj, obj1]
+            vec![floor_obj, plane]
         };
 
+        let plane_index = renderer_3d.render_geometry.len() + 1;
         for v in geom.drain(..) { renderer_3d.render_geometry.push(v) }
 
-        Self {
+        // Sources of assets are reloaded then changed on disk, packed ones are not watched
+        let mut hot_reload = HotReloader::new(init_frame.queue.clone(), 0.5);
+        let mut atlas_textures = vec![];
+        for &(name, _) in ATLAS_TEXTURES.iter() {
+            if let Some(path) = vfs.real_path(&format!("data/{}", name)) {
+                hot_reload.watch_texture(&path, Format::R8G8B8A8Srgb);
+                atlas_textures.push((path, name));
+            }
+        }
+        if let Some(path) = vfs.real_path("data/test.obj") { hot_reload.watch_object(&path, "Plane"); }
+        // Same arguments `main` layered settings with
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        hot_reload.watch_settings(Path::new(crate::SETTINGS_FILE), &args);
+
+        Ok(Self {
             camera: {
                 let mut c = Camera::new(Matrix4::identity());
                 c.pos[2] = -5.0;
                 c
             },
-//            atlas,
+            atlas,
+            plane_index,
+
+            vfs,
+            hot_reload,
+            atlas_textures,
+            reloaded_atlas: None,
+            reloaded_plane: None,
 
             pass_2d,
 
             renderer_2d,
             renderer_3d,
+            profiler_overlay,
+            input: Self::default_input(),
 
             time: 0.0,
             speed_mod: 0.0,
             holding_mouse: false,
+            borderless: false,
+            capturing: false,
+        })
+    }
+
+    /// Default bindings of camera and window controls
+    fn default_input() -> InputMap {
+        let mut input = InputMap::new();
+        input
+            .bind_axis("move_forward", AxisBinding::keys(Keys::W, Keys::S))
+            .bind_axis("move_right", AxisBinding::keys(Keys::A, Keys::D))
+            .bind_axis("move_up", AxisBinding::keys(Keys::F, Keys::R))
+            .bind_axis("look_x", AxisBinding::mouse_x(40.0))
+            .bind_axis("look_y", AxisBinding::mouse_y(40.0))
+            .bind_action("sprint", Binding::key(Keys::LShift))
+            .bind_action("exit", Binding::key(Keys::Escape))
+            .bind_action("hold_cursor", Binding::key(Keys::F1))
+            .bind_action("pause", Binding::key(Keys::P))
+            .bind_action("profiler", Binding::key(Keys::F3))
+            .bind_action("screenshot", Binding::key(Keys::F12))
+            .bind_action("capture", Binding::key(Keys::F12).with_shift())
+            .bind_action("borderless", Binding::key(Keys::F11))
+            .bind_action("borderless", Binding::key(Keys::Return).with_alt());
+        input
+    }
+
+    /// Start rebuilding resources from reloaded files, and swap in ones that finished
+    fn hot_reload(&mut self, delta: f32, frame: &mut Frame) {
+        for (path, reloaded) in self.hot_reload.update(delta) {
+            println!("Reloaded {:?}", path);
+            match reloaded {
+                Reloaded::Texture(image) => {
+                    let name = match self.atlas_textures.iter().find(|(p, _)| *p == path) {
+                        Some(&(_, name)) => name,
+                        None => {
+                            println!("{:?} is not an atlas texture", path);
+                            continue;
+                        },
+                    };
+                    // Atlas is rebuilt with new image, materials are moved onto it once it is ready
+                    match Self::load_atlas(frame, &self.vfs, Some((name, image))) {
+                        Ok(atlas) => self.reloaded_atlas = Some(atlas),
+                        Err(e) => println!("{}", e),
+                    }
+                },
+                Reloaded::Object(_, info) => {
+                    self.reloaded_plane = Some(self.renderer_3d.generate_object(info, AtlasImageResolver::new(&self.atlas)));
+                },
+                Reloaded::Settings(settings) => {
+                    for r in settings.runtime_requests() { frame.request(r) }
+                },
+            }
+        }
+
+        if self.reloaded_atlas.as_ref().map_or(false, |l| l.is_finished()) {
+            match self.reloaded_atlas.take().unwrap().try_take() {
+                Ok(atlas) => {
+                    for obj in self.renderer_3d.render_geometry.iter_mut() {
+                        for slice in obj.materials.iter_mut() { self.atlas.rebind(&atlas, &mut slice.material); }
+                    }
+                    self.atlas = atlas;
+                },
+                Err(e) => println!("Unable to rebuild atlas: {}", e),
+            }
+        }
+        if self.reloaded_plane.as_ref().map_or(false, |l| l.is_finished()) {
+            match self.reloaded_plane.take().unwrap().try_take() {
+                Ok(plane) => self.renderer_3d.render_geometry[self.plane_index].swap_geometry(plane),
+                Err(e) => println!("Unable to rebuild test.obj: {}", e),
+            }
         }
     }
 
@@ -211,18 +408,59 @@ impl GameListener for GameEntry {
         self.time += delta;
 //        println!("FPS: {}", 1.0 / delta);
 
-        /* Process Camera Movement */ {
-            let forward = if frame.key_state(Keys::W) { 1.0 } else if frame.key_state(Keys::S) { -1.0 } else { 0.0 };
-            let right = if frame.key_state(Keys::A) { 1.0 } else if frame.key_state(Keys::D) { -1.0 } else { 0.0 };
-            let up = if frame.key_state(Keys::F) { 1.0 } else if frame.key_state(Keys::R) { -1.0 } else { 0.0 };
+        self.hot_reload(delta, frame);
 
-            let sprint = if frame.key_state(Keys::LShift) { 2.0 } else { 1.0 };
+        /* Process Window Controls */ if frame.is_top_state() {
+            if self.input.pressed_this_frame(frame, "exit") {
+                frame.request(FrameRequest::SaveSettings);
+                frame.request(FrameRequest::ExitApplication)
+            }
+            if self.input.pressed_this_frame(frame, "hold_cursor") {
+                self.holding_mouse = !self.holding_mouse;
+                frame.request(FrameRequest::HoldCursor(Some(self.holding_mouse)))
+            }
+            if self.input.pressed_this_frame(frame, "pause") {
+                frame.request(FrameRequest::PushState(GameState::new(pause_state::PauseState::new())))
+            }
+            if self.input.pressed_this_frame(frame, "profiler") {
+                self.profiler_overlay.visible = !self.profiler_overlay.visible;
+            }
+            if self.input.pressed_this_frame(frame, "capture") {
+                self.capturing = !self.capturing;
+                frame.request(if self.capturing {
+                    FrameRequest::StartCapture(PathBuf::from("capture"), 30.0)
+                } else {
+                    FrameRequest::StopCapture
+                })
+            }
+            if self.input.pressed_this_frame(frame, "screenshot") {
+                // Milliseconds too, so screenshots taken within one second don't overwrite each other
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                frame.request(FrameRequest::Screenshot(PathBuf::from(
+                    format!("screenshot_{}_{:03}.png", now.as_secs(), now.subsec_millis())
+                )))
+            }
+            // Borderless, as winit 0.19 can't switch video mode for exclusive fullscreen
+            if self.input.pressed_this_frame(frame, "borderless") {
+                self.borderless = !self.borderless;
+                frame.request(FrameRequest::SetWindowMode(
+                    if self.borderless { WindowMode::Borderless } else { WindowMode::Windowed(None) }
+                ))
+            }
+        }
+
+        /* Process Camera Movement */ if frame.is_top_state() {
+            let forward = self.input.axis(frame, "move_forward");
+            let right = self.input.axis(frame, "move_right");
+            let up = self.input.axis(frame, "move_up");
+
+            let sprint = if self.input.is_down(frame, "sprint") { 2.0 } else { 1.0 };
             let speed = 1.05f32.powf(self.speed_mod) * delta * sprint * 2.0;
             self.camera.move_by(-forward * speed,  -right * speed, -up * speed);
 
             if self.holding_mouse {
-                let spd = frame.cursor_spd();
-                self.camera.rotate_by( spd[1] * 40.0, spd[0] * 40.0, 0.0);
+                let look = [self.input.axis(frame, "look_x"), self.input.axis(frame, "look_y")];
+                self.camera.rotate_by(look[1], look[0], 0.0);
             }
         }
 
@@ -231,6 +469,10 @@ impl GameListener for GameEntry {
 
         self.renderer_3d.set_view_projection(self.camera.get_view_projection());
         future = self.renderer_3d.render(future, frame.image.clone());
+        self.renderer_3d.report(frame.profiler());
+
+        let image = frame.image.clone();
+        future = self.profiler_overlay.render(frame.profiler(), image, future);
 
 //        future = self.renderer_3d.render(future, self.transient_image.clone());
 //        future = {
@@ -241,19 +483,6 @@ impl GameListener for GameEntry {
         future
     }
 
-    fn key_pressed(&mut self, frame: &mut Frame, keycode: Keys) {
-        use Keys::*;
-        match keycode {
-            Escape => frame.request(FrameRequest::ExitApplication),
-//            F2 => self.async_2d = !self.async_2d,
-            F1 => {
-                self.holding_mouse = !self.holding_mouse;
-                frame.request(FrameRequest::HoldCursor(Some(self.holding_mouse)))
-            },
-            _ => (),
-        }
-    }
-
     fn mouse_wheel(&mut self, frame: &mut Frame, x: f32, y: f32) {
         self.speed_mod += y;
     }
@@ -0,0 +1,39 @@
+use winit::VirtualKeyCode as Keys;
+use gfx_lib::main_processor::{ GameListener, Frame, FrameRequest };
+use vulkano::sync::GpuFuture;
+
+// Pause overlay, game is still rendered under it but clock is stopped
+
+const CLOSE_KEYS: [Keys; 2] = [Keys::P, Keys::Escape];
+
+/// Closes on release of P or Escape pressed while it is open,
+/// so key which opened it is ignored and state under it never sees closing press
+pub struct PauseState {
+    close_pressed: bool,
+}
+impl PauseState {
+    pub fn new() -> Self { Self { close_pressed: false } }
+}
+impl GameListener for PauseState {
+
+    fn enter(&mut self, frame: &mut Frame) {
+        frame.request(FrameRequest::PauseClock(Some(true)))
+    }
+
+    fn exit(&mut self, frame: &mut Frame) {
+        frame.request(FrameRequest::PauseClock(Some(false)))
+    }
+
+    fn is_overlay(&self) -> bool { true }
+
+    fn update(&mut self, _delta: f32, frame: &mut Frame, future: Box<dyn GpuFuture>) -> Box<dyn GpuFuture> {
+        if frame.is_top_state() {
+            let keyboard = frame.keyboard();
+            if CLOSE_KEYS.iter().any(|k| keyboard.pressed_this_frame(*k)) { self.close_pressed = true; }
+            if self.close_pressed && CLOSE_KEYS.iter().any(|k| keyboard.released_this_frame(*k)) {
+                frame.request(FrameRequest::PopState);
+            }
+        }
+        future
+    }
+}
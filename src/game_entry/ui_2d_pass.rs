@@ -7,6 +7,7 @@ use std::{
 };
 use gfx_lib::{
     main_processor::Frame,
+    vfs::Vfs,
     graphics::{
         image::{
             ImageContent,
@@ -36,11 +37,13 @@ pub struct UI2DPass {
 }
 impl UI2DPass {
 
-    pub fn new(frame: &mut Frame) -> Self {
+    pub fn new(frame: &mut Frame, vfs: &Vfs) -> Result<Self, String> {
+        let bytes = vfs.read("data/icon512.png").map_err(|e| format!("Unable to read data/icon512.png: {}", e))?;
         let image = ImageContent::new_with_bytes(
             frame.queue.clone(),
-            frame.sampler_pool.with_params(SamplerParams::simple_repeat()),
-            Cursor::new(include_bytes!("../data/icon512.png").to_vec()),
+            frame.sampler_pool.with_params(SamplerParams::simple_repeat())
+                .map_err(|e| format!("Unable to create UI sampler: {}", e))?,
+            Cursor::new(bytes),
             Format::R8G8B8A8Srgb,
         );
 
@@ -80,11 +83,11 @@ impl UI2DPass {
 //            cache.append(instance).unwrap();
 //        } }
 
-        Self {
+        Ok(Self {
             image,
             cache,
             output: renderer_2d_att,
-        }
+        })
     }
 
 
@@ -3,11 +3,14 @@ use std::cell::{RefCell};
 use std::sync::Arc;
 use serializer::Peek;
 use std::ops::Mul;
+use std::path::Path;
 
 use gfx_lib;
 
 mod game_entry;
 
+const SETTINGS_FILE: &str = "settings.json";
+
 fn main() { start(); }
 
 fn start() {
@@ -15,15 +18,34 @@ fn start() {
         settings::{ GameSettings, WindowMode }
     };
 
-    let settings = GameSettings {
-//        window_mode: WindowMode::Borderless,
-        .. GameSettings::default()
+    // Settings file, then `INSOMNIA_*` environment and `--key=value` arguments on top
+    // `--record-input=file.json` records session, `--replay-input=file.json` plays it back
+    let args: Vec<String> = std::env::args().collect();
+    let settings = match GameSettings::load_layered(Path::new(SETTINGS_FILE), &args[1 ..]) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Unable to load settings, using defaults: {}", e);
+            GameSettings::default()
+        }
+    };
+
+    // `--headless N` renders N frames without window
+    let headless = args.iter().position(|a| a == "--headless")
+        .map(|i| args.get(i + 1).and_then(|n| n.parse::<u32>().ok()).unwrap_or(1));
+
+    let result = match headless {
+        Some(frames) => gfx_lib::main_processor::start_headless_with_settings_and_listener(
+            settings,
+            frames,
+            |frame| { Box::new(game_entry::GameEntry::loading_view(frame)) }
+        ),
+        None => gfx_lib::main_processor::start_with_settings_and_listener(
+            settings,
+            |frame| { Box::new(game_entry::GameEntry::loading_view(frame)) }
+        ),
     };
 
-    match gfx_lib::main_processor::start_with_settings_and_listener(
-        settings,
-        |frame| { Box::new(game_entry::GameEntry::new(frame)) }
-    ) {
+    match result {
         Ok(_) => (),
         Err(err) => println!("Finished with error: {}", err),
     }